@@ -1,27 +1,380 @@
-use fafka::{server, DEFAULT_PORT};
+use fafka::server::{self, BrokerConfig, Properties, Source};
 
+use futures::stream::{self, Stream};
+use std::env;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
 use structopt::StructOpt;
 use tokio::net::TcpListener;
 use tokio::signal;
+use tracing::{error, info, warn};
 
 #[tokio::main]
-pub async fn main() -> std::io::Result<()> {
+pub async fn main() -> io::Result<()> {
     // enable logging
     // see https://docs.rs/tracing for more info
     tracing_subscriber::fmt::try_init().unwrap();
 
     let cli = Cli::from_args();
-    let port = cli.port.as_deref().unwrap_or(DEFAULT_PORT);
 
-    // Bind a TCP listener
-    let listener = TcpListener::bind(&format!("127.0.0.1:{}", port)).await?;
+    let (properties, broker_config) =
+        load_config(&cli).map_err(|msg| io::Error::new(io::ErrorKind::InvalidInput, msg))?;
+    for (key, value) in broker_config.values() {
+        match properties.source(&key) {
+            Some(source) => info!(%key, %value, %source, "config"),
+            None => info!(%key, %value, "config"),
+        }
+    }
 
-    server::run(listener, signal::ctrl_c()).await
+    // Bind a TCP listener for each listener
+    let mut listeners = vec![];
+    for (listener_config, endpoint) in broker_config.listener_configs() {
+        let host = if endpoint.host.is_empty() {
+            "0.0.0.0"
+        } else {
+            &endpoint.host
+        };
+        let listener = TcpListener::bind((host, endpoint.port)).await?;
+        listeners.push((listener_config, listener));
+    }
+
+    let config = broker_config.server_config();
+    if !cli.add_scram.is_empty() {
+        let credentials = server::CredentialStore::open(&config.log_dir)?;
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+        for (mechanism, user) in &cli.add_scram {
+            let password = read_password(&mut stdin, user)?;
+            let iterations = server::ScramMechanism::MIN_ITERATIONS;
+            let credential = server::ScramCredential::new(*mechanism, &password, iterations);
+            credentials.upsert(user, *mechanism, credential)?;
+        }
+    }
+
+    let reload = reload_on_hangup(cli, broker_config)?;
+    server::run(listeners, config, reload, signal::ctrl_c()).await
+}
+
+// Read the config file, overridden by the environment, itself overridden by
+// flags.
+fn load_config(cli: &Cli) -> Result<(Properties, BrokerConfig), String> {
+    let mut properties = Properties::default();
+    if let Some(path) = &cli.config {
+        properties.load_file(path)?;
+    }
+    properties.load_env(env::vars());
+    for (key, value, flag) in cli.overrides() {
+        properties.set(key, value, Source::Flag(flag.into()));
+    }
+    let broker_config = BrokerConfig::from_properties(&properties)?;
+    Ok((properties, broker_config))
+}
+
+// Load the configuration again every time the process receives SIGHUP. The
+// keys which cannot be changed at runtime keep their value until the broker
+// restarts.
+#[cfg(unix)]
+fn reload_on_hangup(
+    cli: Cli,
+    current: BrokerConfig,
+) -> io::Result<impl Stream<Item = server::Config>> {
+    use std::collections::HashMap;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let hangup = signal(SignalKind::hangup())?;
+    Ok(stream::unfold(
+        (hangup, cli, current),
+        |(mut hangup, cli, current)| async move {
+            loop {
+                hangup.recv().await?;
+                let (properties, config) = match load_config(&cli) {
+                    Ok(loaded) => loaded,
+                    Err(err) => {
+                        error!(cause = %err, "failed to reload configuration");
+                        continue;
+                    }
+                };
+                let old: HashMap<_, _> = current.values().into_iter().collect();
+                for (key, value) in config.values() {
+                    if old.get(&key) == Some(&value) {
+                        continue;
+                    }
+                    let source = properties.source(&key).map(ToString::to_string);
+                    if server::DYNAMIC_KEYS.contains(&key.as_str()) {
+                        info!(%key, %value, ?source, "config changed");
+                    } else {
+                        warn!(%key, %value, ?source, "config change requires a restart");
+                    }
+                }
+                return Some((config.server_config(), (hangup, cli, config)));
+            }
+        },
+    ))
 }
 
+#[cfg(not(unix))]
+fn reload_on_hangup(
+    _cli: Cli,
+    _current: BrokerConfig,
+) -> io::Result<impl Stream<Item = server::Config>> {
+    Ok(stream::pending())
+}
+
+/// Flags override the config keys given in parentheses, see the keys for
+/// the format of their values.
 #[derive(StructOpt, Debug)]
 #[structopt(name = "fafka", version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"), about = "A kafka-like broker.")]
 struct Cli {
+    /// Properties file holding the configuration of the broker, as
+    /// `key=value` lines. Keys are overridden by `FAFKA_*` environment
+    /// variables, e.g. `FAFKA_NUM_PARTITIONS` for `num.partitions`, and by
+    /// flags. The file is read again on SIGHUP, to change the keys which can
+    /// be changed at runtime.
+    #[structopt(name = "config", long = "--config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Override a config key, as `key=value`.
+    #[structopt(name = "override", long = "--override", parse(try_from_str = parse_override))]
+    overrides: Vec<(String, String)>,
+
+    /// Port of the listener, when no listener is configured (`port`).
     #[structopt(name = "port", long = "--port")]
     port: Option<String>,
+
+    /// Directory holding the partitions (`log.dirs`).
+    #[structopt(name = "log-dir", long = "--log-dir")]
+    log_dir: Option<String>,
+
+    /// Comma-separated listeners, as `NAME://host:port`, e.g.
+    /// `INTERNAL://0.0.0.0:9092,EXTERNAL://0.0.0.0:9093` (`listeners`).
+    #[structopt(name = "listeners", long = "--listeners")]
+    listeners: Option<String>,
+
+    /// Comma-separated addresses clients should connect to, e.g.
+    /// `EXTERNAL://broker.example.com:19093` (`advertised.listeners`).
+    #[structopt(name = "advertised-listeners", long = "--advertised-listeners")]
+    advertised_listeners: Option<String>,
+
+    /// Comma-separated security protocols of the listeners, as
+    /// `NAME:PROTOCOL`, e.g. `INTERNAL:PLAINTEXT,EXTERNAL:SASL_SSL`
+    /// (`listener.security.protocol.map`).
+    #[structopt(
+        name = "listener-security-protocol-map",
+        long = "--listener-security-protocol-map"
+    )]
+    listener_security_protocol_map: Option<String>,
+
+    /// Unix domain socket to accept connections on as well, whose clients
+    /// are the user of their process (`unix.socket.path`).
+    #[structopt(name = "unix-socket-path", long = "--unix-socket-path")]
+    unix_socket_path: Option<String>,
+
+    /// Port to serve metrics on over HTTP, at `/metrics` in the Prometheus
+    /// text format (`metrics.port`).
+    #[structopt(name = "metrics-port", long = "--metrics-port")]
+    metrics_port: Option<String>,
+
+    /// Host to serve metrics on, the host of the broker by default
+    /// (`metrics.host`).
+    #[structopt(name = "metrics-host", long = "--metrics-host")]
+    metrics_host: Option<String>,
+
+    /// Maximum number of connections open at the same time
+    /// (`max.connections`).
+    #[structopt(name = "max-connections", long = "--max-connections")]
+    max_connections: Option<String>,
+
+    /// Maximum number of connections open at the same time from an IP
+    /// address (`max.connections.per.ip`).
+    #[structopt(name = "max-connections-per-ip", long = "--max-connections-per-ip")]
+    max_connections_per_ip: Option<String>,
+
+    /// Comma-separated maximum numbers of connections of specific IP
+    /// addresses, as `<ip>:<count>` (`max.connections.per.ip.overrides`).
+    #[structopt(
+        name = "max-connections-per-ip-overrides",
+        long = "--max-connections-per-ip-overrides"
+    )]
+    max_connections_per_ip_overrides: Option<String>,
+
+    /// Maximum number of connections an IP address may create per second
+    /// (`max.connection.creation.rate`).
+    #[structopt(
+        name = "max-connection-creation-rate",
+        long = "--max-connection-creation-rate"
+    )]
+    max_connection_creation_rate: Option<String>,
+
+    /// PEM file holding the certificate chain of the broker
+    /// (`ssl.certificate.location`).
+    #[structopt(name = "tls-cert", long = "--tls-cert")]
+    tls_cert: Option<String>,
+
+    /// PEM file holding the private key of the broker (`ssl.key.location`).
+    #[structopt(name = "tls-key", long = "--tls-key")]
+    tls_key: Option<String>,
+
+    /// PEM file holding the CA certificates client certificates are
+    /// verified against (`ssl.truststore.location`).
+    #[structopt(name = "tls-client-ca", long = "--tls-client-ca")]
+    tls_client_ca: Option<String>,
+
+    /// Whether clients authenticate with a certificate: none, requested or
+    /// required (`ssl.client.auth`).
+    #[structopt(name = "tls-client-auth", long = "--tls-client-auth")]
+    tls_client_auth: Option<String>,
+
+    /// Comma-separated SASL mechanisms clients of the SASL_PLAINTEXT and
+    /// SASL_SSL listeners authenticate with, among PLAIN, SCRAM-SHA-256 and
+    /// SCRAM-SHA-512 (`sasl.enabled.mechanisms`).
+    #[structopt(name = "sasl-enabled-mechanisms", long = "--sasl-enabled-mechanisms")]
+    sasl_enabled_mechanisms: Option<String>,
+
+    /// Create or update the SCRAM credential of a user before starting, as
+    /// `<mechanism>=<user>`, e.g. `SCRAM-SHA-256=alice`. The passwords are
+    /// read from the standard input, one line for each user in the order of
+    /// the flags, so that they do not show in the command line.
+    #[structopt(name = "add-scram", long = "--add-scram", parse(try_from_str = parse_scram_user))]
+    add_scram: Vec<(server::ScramMechanism, String)>,
+
+    /// Authorize requests with the ACLs managed by CreateAcls and DeleteAcls
+    /// requests (`authorizer.enabled`).
+    #[structopt(name = "authorizer", long = "--authorizer")]
+    authorizer: bool,
+
+    /// Semicolon-separated principals allowed every operation, e.g.
+    /// `User:admin;User:CN=broker` (`super.users`).
+    #[structopt(name = "super-users", long = "--super-users")]
+    super_users: Option<String>,
+
+    /// Allow every operation on resources without any ACL
+    /// (`allow.everyone.if.no.acl.found`).
+    #[structopt(
+        name = "allow-everyone-if-no-acl-found",
+        long = "--allow-everyone-if-no-acl-found"
+    )]
+    allow_everyone_if_no_acl_found: bool,
+}
+
+impl Cli {
+    // Config keys set by flags, with their value and the flag setting them.
+    // `--override` comes last, so that it wins over the other flags.
+    fn overrides(&self) -> Vec<(String, String, &'static str)> {
+        let flags = [
+            ("port", &self.port, "--port"),
+            ("log.dirs", &self.log_dir, "--log-dir"),
+            ("listeners", &self.listeners, "--listeners"),
+            (
+                "advertised.listeners",
+                &self.advertised_listeners,
+                "--advertised-listeners",
+            ),
+            (
+                "listener.security.protocol.map",
+                &self.listener_security_protocol_map,
+                "--listener-security-protocol-map",
+            ),
+            (
+                "unix.socket.path",
+                &self.unix_socket_path,
+                "--unix-socket-path",
+            ),
+            ("metrics.port", &self.metrics_port, "--metrics-port"),
+            ("metrics.host", &self.metrics_host, "--metrics-host"),
+            (
+                "max.connections",
+                &self.max_connections,
+                "--max-connections",
+            ),
+            (
+                "max.connections.per.ip",
+                &self.max_connections_per_ip,
+                "--max-connections-per-ip",
+            ),
+            (
+                "max.connections.per.ip.overrides",
+                &self.max_connections_per_ip_overrides,
+                "--max-connections-per-ip-overrides",
+            ),
+            (
+                "max.connection.creation.rate",
+                &self.max_connection_creation_rate,
+                "--max-connection-creation-rate",
+            ),
+            ("ssl.certificate.location", &self.tls_cert, "--tls-cert"),
+            ("ssl.key.location", &self.tls_key, "--tls-key"),
+            (
+                "ssl.truststore.location",
+                &self.tls_client_ca,
+                "--tls-client-ca",
+            ),
+            (
+                "ssl.client.auth",
+                &self.tls_client_auth,
+                "--tls-client-auth",
+            ),
+            (
+                "sasl.enabled.mechanisms",
+                &self.sasl_enabled_mechanisms,
+                "--sasl-enabled-mechanisms",
+            ),
+            ("super.users", &self.super_users, "--super-users"),
+        ];
+        let switches = [
+            ("authorizer.enabled", self.authorizer, "--authorizer"),
+            (
+                "allow.everyone.if.no.acl.found",
+                self.allow_everyone_if_no_acl_found,
+                "--allow-everyone-if-no-acl-found",
+            ),
+        ];
+
+        let mut overrides = vec![];
+        for (key, value, flag) in flags.iter() {
+            if let Some(value) = value {
+                overrides.push((key.to_string(), value.clone(), *flag));
+            }
+        }
+        for (key, enabled, flag) in switches.iter() {
+            if *enabled {
+                overrides.push((key.to_string(), "true".into(), *flag));
+            }
+        }
+        for (key, value) in &self.overrides {
+            overrides.push((key.clone(), value.clone(), "--override"));
+        }
+        overrides
+    }
+}
+
+fn parse_override(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid override {:?}, expected key=value", s))?;
+    Ok((key.trim().into(), value.trim().into()))
+}
+
+fn parse_scram_user(s: &str) -> Result<(server::ScramMechanism, String), String> {
+    let invalid = || format!("invalid SCRAM user {:?}, expected <mechanism>=<user>", s);
+    let (mechanism, user) = s.split_once('=').ok_or_else(invalid)?;
+    let mechanism = server::ScramMechanism::from_name(mechanism)
+        .ok_or_else(|| format!("invalid SCRAM mechanism {:?}", mechanism))?;
+    // Passwords are not taken from the command line, where other users of
+    // the machine can read them.
+    if user.is_empty() || user.contains(':') {
+        return Err(invalid());
+    }
+    Ok((mechanism, user.into()))
+}
+
+// Read the password of the SCRAM user `user` from a line of `input`.
+fn read_password(input: &mut impl BufRead, user: &str) -> io::Result<String> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("no password for SCRAM user {} on the standard input", user),
+        ));
+    }
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
 }
@@ -1,3 +1,36 @@
-fn main() {
-    println!("Hello, world!");
+use fafka::store::upgrade;
+
+use std::path::PathBuf;
+use std::process;
+use structopt::StructOpt;
+
+pub fn main() {
+    // enable logging
+    // see https://docs.rs/tracing for more info
+    tracing_subscriber::fmt::try_init().unwrap();
+
+    match Cli::from_args() {
+        Cli::UpgradeLogDir { log_dir } => match upgrade::upgrade_log_dir(&log_dir) {
+            Ok(report) => println!(
+                "{} segment(s) upgraded, {} already up to date",
+                report.upgraded.len(),
+                report.up_to_date
+            ),
+            Err(err) => {
+                eprintln!("failed to upgrade {}: {}", log_dir.display(), err);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "fafka-cli", version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"), about = "Administration tools for fafka.")]
+enum Cli {
+    /// Rewrite every segment of a log directory with the current on-disk
+    /// format. The broker must be stopped.
+    UpgradeLogDir {
+        #[structopt(parse(from_os_str))]
+        log_dir: PathBuf,
+    },
 }
@@ -7,6 +7,6 @@
 extern crate test;
 
 pub mod server;
-mod store;
+pub mod store;
 
 pub const DEFAULT_PORT: &str = "9092";
@@ -0,0 +1,314 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use tracing::info;
+
+use super::{
+    AccessControlEntry, AclBinding, AclBindingFilter, AclOperation, AclPermissionType, Action,
+    Authorizer, PatternType, ResourcePattern, WILDCARD,
+};
+use crate::server::persist;
+use crate::server::principal::Principal;
+
+/// Name of the file holding the ACLs, in the log directory.
+const FILE_NAME: &str = "acls";
+
+type Acls = BTreeMap<ResourcePattern, BTreeSet<AccessControlEntry>>;
+
+/// Authorizer checking the ACLs of resources, persisted in a file of the log
+/// directory.
+///
+/// An operation is allowed when an ACL of the resource allows it and none
+/// denies it. ACLs apply to a resource if their pattern is its name, the `*`
+/// wildcard, or a prefix of its name. Super users are allowed everything.
+#[derive(Debug)]
+pub struct AclAuthorizer {
+    path: PathBuf,
+    super_users: Vec<Principal>,
+    /// Whether operations on resources without any ACL are allowed
+    /// (`allow.everyone.if.no.acl.found`).
+    allow_everyone_if_no_acl_found: bool,
+    acls: RwLock<Acls>,
+}
+
+impl AclAuthorizer {
+    /// Open the ACLs stored in `dir`, creating the directory if needed.
+    pub fn open(
+        dir: impl AsRef<Path>,
+        super_users: Vec<Principal>,
+        allow_everyone_if_no_acl_found: bool,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let path = dir.as_ref().join(FILE_NAME);
+
+        let acls: Acls = persist::load(&path)?;
+        info!(path = %path.display(), resources = acls.len(), "loaded ACLs");
+
+        Ok(Self {
+            path,
+            super_users,
+            allow_everyone_if_no_acl_found,
+            acls: RwLock::new(acls),
+        })
+    }
+}
+
+impl Authorizer for AclAuthorizer {
+    fn authorize(&self, principal: &Principal, host: IpAddr, action: &Action) -> bool {
+        if self.super_users.contains(principal) {
+            return true;
+        }
+
+        let acls = self.acls.read().unwrap();
+        let pattern = |name: &str, pattern_type| ResourcePattern {
+            resource_type: action.resource_type,
+            name: name.into(),
+            pattern_type,
+        };
+        let name = &action.name;
+        let mut patterns = vec![
+            pattern(name, PatternType::Literal),
+            pattern(WILDCARD, PatternType::Literal),
+        ];
+        patterns.extend(
+            name.char_indices()
+                .map(|(i, c)| pattern(&name[..i + c.len_utf8()], PatternType::Prefixed)),
+        );
+        let entries: Vec<_> = patterns
+            .iter()
+            .filter_map(|pattern| acls.get(pattern))
+            .flatten()
+            .collect();
+        if entries.is_empty() {
+            return self.allow_everyone_if_no_acl_found;
+        }
+
+        let principal = principal.to_string();
+        let wildcard_principal = format!("{}:{}", Principal::USER_TYPE, WILDCARD);
+        let host = host.to_string();
+        let applying: Vec<_> = entries
+            .into_iter()
+            .filter(|entry| {
+                (entry.principal == principal || entry.principal == wildcard_principal)
+                    && (entry.host == host || entry.host == WILDCARD)
+            })
+            .collect();
+
+        // Implied operations only apply to ALLOW entries, denying to read a
+        // topic does not deny to describe it.
+        let denied = applying.iter().any(|entry| {
+            entry.permission_type == AclPermissionType::Deny
+                && (entry.operation == action.operation || entry.operation == AclOperation::All)
+        });
+        !denied
+            && applying.iter().any(|entry| {
+                entry.permission_type == AclPermissionType::Allow
+                    && entry.operation.implies(action.operation)
+            })
+    }
+
+    fn create_acls(&self, bindings: &[AclBinding]) -> io::Result<()> {
+        let mut acls = self.acls.write().unwrap();
+        let mut updated = acls.clone();
+        for binding in bindings {
+            updated
+                .entry(binding.pattern.clone())
+                .or_default()
+                .insert(binding.entry.clone());
+        }
+        persist::save(&self.path, &updated)?;
+        *acls = updated;
+        Ok(())
+    }
+
+    fn describe_acls(&self, filter: &AclBindingFilter) -> Vec<AclBinding> {
+        let acls = self.acls.read().unwrap();
+        bindings(&acls)
+            .filter(|binding| filter.matches(binding))
+            .collect()
+    }
+
+    fn delete_acls(&self, filters: &[AclBindingFilter]) -> io::Result<Vec<Vec<AclBinding>>> {
+        let mut acls = self.acls.write().unwrap();
+        let deleted: Vec<Vec<_>> = filters
+            .iter()
+            .map(|filter| {
+                bindings(&acls)
+                    .filter(|binding| filter.matches(binding))
+                    .collect()
+            })
+            .collect();
+        if deleted.iter().all(Vec::is_empty) {
+            return Ok(deleted);
+        }
+
+        let mut updated = acls.clone();
+        for binding in deleted.iter().flatten() {
+            if let Some(entries) = updated.get_mut(&binding.pattern) {
+                entries.remove(&binding.entry);
+                if entries.is_empty() {
+                    updated.remove(&binding.pattern);
+                }
+            }
+        }
+        persist::save(&self.path, &updated)?;
+        *acls = updated;
+        Ok(deleted)
+    }
+}
+
+fn bindings(acls: &Acls) -> impl Iterator<Item = AclBinding> + '_ {
+    acls.iter().flat_map(|(pattern, entries)| {
+        entries.iter().map(move |entry| AclBinding {
+            pattern: pattern.clone(),
+            entry: entry.clone(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::authorizer::ResourceType;
+
+    fn binding(
+        name: &str,
+        pattern_type: PatternType,
+        principal: &str,
+        operation: AclOperation,
+        permission_type: AclPermissionType,
+    ) -> AclBinding {
+        AclBinding {
+            pattern: ResourcePattern {
+                resource_type: ResourceType::Topic,
+                name: name.into(),
+                pattern_type,
+            },
+            entry: AccessControlEntry {
+                principal: principal.into(),
+                host: WILDCARD.into(),
+                operation,
+                permission_type,
+            },
+        }
+    }
+
+    #[test]
+    fn test_authorize() {
+        let dir = tempfile::tempdir().unwrap();
+        let admin = Principal::user("admin");
+        let authorizer = AclAuthorizer::open(dir.path(), vec![admin.clone()], false).unwrap();
+        authorizer
+            .create_acls(&[
+                binding(
+                    "events",
+                    PatternType::Prefixed,
+                    "User:alice",
+                    AclOperation::Read,
+                    AclPermissionType::Allow,
+                ),
+                binding(
+                    "events-secret",
+                    PatternType::Literal,
+                    "User:alice",
+                    AclOperation::Read,
+                    AclPermissionType::Deny,
+                ),
+                binding(
+                    WILDCARD,
+                    PatternType::Literal,
+                    "User:*",
+                    AclOperation::Write,
+                    AclPermissionType::Allow,
+                ),
+            ])
+            .unwrap();
+
+        let host = "127.0.0.1".parse().unwrap();
+        let alice = Principal::user("alice");
+        let bob = Principal::user("bob");
+        let topic = |operation, name| Action::new(operation, ResourceType::Topic, name);
+
+        assert!(authorizer.authorize(&alice, host, &topic(AclOperation::Read, "events-1")));
+        assert!(authorizer.authorize(&alice, host, &topic(AclOperation::Describe, "events")));
+        assert!(!authorizer.authorize(&alice, host, &topic(AclOperation::Read, "event")));
+        assert!(!authorizer.authorize(&alice, host, &topic(AclOperation::Read, "events-secret")));
+        assert!(authorizer.authorize(&alice, host, &topic(AclOperation::Write, "events-secret")));
+        assert!(!authorizer.authorize(&bob, host, &topic(AclOperation::Read, "events-1")));
+        assert!(authorizer.authorize(&bob, host, &topic(AclOperation::Write, "logs")));
+        assert!(authorizer.authorize(&admin, host, &topic(AclOperation::Delete, "logs")));
+
+        // No ACL applies to groups.
+        let group = Action::new(AclOperation::Read, ResourceType::Group, "consumers");
+        assert!(!authorizer.authorize(&alice, host, &group));
+        let authorizer = AclAuthorizer::open(dir.path(), vec![], true).unwrap();
+        assert!(authorizer.authorize(&alice, host, &group));
+        assert!(!authorizer.authorize(&bob, host, &topic(AclOperation::Read, "events-1")));
+    }
+
+    #[test]
+    fn test_host() {
+        let dir = tempfile::tempdir().unwrap();
+        let authorizer = AclAuthorizer::open(dir.path(), vec![], false).unwrap();
+        let mut allowed = binding(
+            "events",
+            PatternType::Literal,
+            "User:alice",
+            AclOperation::All,
+            AclPermissionType::Allow,
+        );
+        allowed.entry.host = "10.0.0.1".into();
+        authorizer.create_acls(&[allowed]).unwrap();
+
+        let alice = Principal::user("alice");
+        let action = Action::new(AclOperation::Read, ResourceType::Topic, "events");
+        assert!(authorizer.authorize(&alice, "10.0.0.1".parse().unwrap(), &action));
+        assert!(!authorizer.authorize(&alice, "10.0.0.2".parse().unwrap(), &action));
+    }
+
+    #[test]
+    fn test_delete_acls() {
+        let dir = tempfile::tempdir().unwrap();
+        let authorizer = AclAuthorizer::open(dir.path(), vec![], false).unwrap();
+        let read = binding(
+            "events",
+            PatternType::Literal,
+            "User:alice",
+            AclOperation::Read,
+            AclPermissionType::Allow,
+        );
+        let write = binding(
+            "events",
+            PatternType::Literal,
+            "User:bob",
+            AclOperation::Write,
+            AclPermissionType::Allow,
+        );
+        authorizer
+            .create_acls(&[read.clone(), write.clone()])
+            .unwrap();
+
+        let filter = AclBindingFilter {
+            principal: Some("User:alice".into()),
+            ..AclBindingFilter::any()
+        };
+        let none = AclBindingFilter {
+            principal: Some("User:carol".into()),
+            ..AclBindingFilter::any()
+        };
+        assert_eq!(
+            authorizer.delete_acls(&[filter, none]).unwrap(),
+            vec![vec![read], vec![]]
+        );
+
+        let authorizer = AclAuthorizer::open(dir.path(), vec![], false).unwrap();
+        assert_eq!(
+            authorizer.describe_acls(&AclBindingFilter::any()),
+            vec![write]
+        );
+    }
+}
@@ -0,0 +1,388 @@
+//! Authorization of requests.
+//!
+//! Every request handler checks that the principal of the connection may
+//! perform an operation on the resources of the request, such as reading a
+//! topic or altering the cluster. The `Authorizer` trait makes the decision;
+//! `AclAuthorizer` bases it on access control lists (ACLs) managed with the
+//! CreateAcls, DescribeAcls and DeleteAcls requests, like the authorizer of
+//! Kafka.
+
+mod acl;
+
+pub use acl::AclAuthorizer;
+
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+use super::principal::Principal;
+
+/// Name of the single cluster resource.
+pub const CLUSTER_NAME: &str = "kafka-cluster";
+
+/// Resource name, principal name and host matching any value in ACLs.
+pub const WILDCARD: &str = "*";
+
+/// Decides whether principals may perform operations on resources.
+pub trait Authorizer: fmt::Debug + Send + Sync {
+    /// Whether `principal`, connected from `host`, may perform `action`.
+    fn authorize(&self, principal: &Principal, host: IpAddr, action: &Action) -> bool;
+
+    /// Add `bindings`. Bindings that already exist are kept as they are.
+    fn create_acls(&self, bindings: &[AclBinding]) -> io::Result<()>;
+
+    /// Every binding matching `filter`.
+    fn describe_acls(&self, filter: &AclBindingFilter) -> Vec<AclBinding>;
+
+    /// Delete the bindings matching any of `filters`, returning the bindings
+    /// deleted by each filter.
+    fn delete_acls(&self, filters: &[AclBindingFilter]) -> io::Result<Vec<Vec<AclBinding>>>;
+}
+
+/// An operation on a resource.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Action {
+    pub operation: AclOperation,
+    pub resource_type: ResourceType,
+    pub name: String,
+}
+
+impl Action {
+    pub fn new(operation: AclOperation, resource_type: ResourceType, name: &str) -> Self {
+        Self {
+            operation,
+            resource_type,
+            name: name.into(),
+        }
+    }
+
+    /// `operation` on the cluster.
+    pub fn cluster(operation: AclOperation) -> Self {
+        Self::new(operation, ResourceType::Cluster, CLUSTER_NAME)
+    }
+}
+
+/// Defines an enum of the protocol, encoded as an `i8`. Values `ANY` and
+/// `MATCH` are only used in filters.
+macro_rules! protocol_enum {
+    (
+        $(#[$meta:meta])*
+        $name:ident { $($(#[$variant_meta:meta])* $variant:ident = $code:expr,)* }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant = $code,)*
+        }
+
+        impl $name {
+            pub fn code(self) -> i8 {
+                self as i8
+            }
+
+            pub fn from_code(code: i8) -> Option<Self> {
+                match code {
+                    $($code => Some($name::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+protocol_enum! {
+    /// Type of the resources ACLs apply to.
+    ResourceType {
+        Any = 1,
+        Topic = 2,
+        Group = 3,
+        Cluster = 4,
+        TransactionalId = 5,
+        DelegationToken = 6,
+        User = 7,
+    }
+}
+
+protocol_enum! {
+    /// How the resource name of an ACL is matched.
+    PatternType {
+        /// Filters: any pattern type, with the same name.
+        Any = 1,
+        /// Filters: every pattern matching the name, wildcards and prefixes
+        /// included.
+        Match = 2,
+        /// The name of the resource, or `*` for every resource.
+        Literal = 3,
+        /// A prefix of the name of the resource.
+        Prefixed = 4,
+    }
+}
+
+protocol_enum! {
+    /// Operations on resources.
+    AclOperation {
+        Any = 1,
+        All = 2,
+        Read = 3,
+        Write = 4,
+        Create = 5,
+        Delete = 6,
+        Alter = 7,
+        Describe = 8,
+        ClusterAction = 9,
+        DescribeConfigs = 10,
+        AlterConfigs = 11,
+        IdempotentWrite = 12,
+        CreateTokens = 13,
+        DescribeTokens = 14,
+    }
+}
+
+protocol_enum! {
+    /// Whether an ACL allows or denies the operation.
+    AclPermissionType {
+        Any = 1,
+        Deny = 2,
+        Allow = 3,
+    }
+}
+
+impl AclOperation {
+    /// Whether an ACL for this operation applies to `operation`. Allowing to
+    /// read, write, delete or alter a resource also allows to describe it,
+    /// and allowing to alter its configuration allows to describe it.
+    fn implies(self, operation: AclOperation) -> bool {
+        use AclOperation::*;
+
+        self == All
+            || self == operation
+            || (operation == Describe && [Read, Write, Delete, Alter].contains(&self))
+            || (operation == DescribeConfigs && self == AlterConfigs)
+    }
+}
+
+/// Resources an ACL applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ResourcePattern {
+    pub resource_type: ResourceType,
+    pub name: String,
+    /// `Literal` or `Prefixed`.
+    pub pattern_type: PatternType,
+}
+
+/// Principals, hosts and operation an ACL applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct AccessControlEntry {
+    /// Principal, e.g. `User:alice`, or `User:*` for every user.
+    pub principal: String,
+    /// IP address the principal connects from, or `*` for every host.
+    pub host: String,
+    pub operation: AclOperation,
+    pub permission_type: AclPermissionType,
+}
+
+/// An ACL: an entry applying to the resources of a pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct AclBinding {
+    pub pattern: ResourcePattern,
+    pub entry: AccessControlEntry,
+}
+
+impl AclBinding {
+    /// Why the binding cannot be created, if it cannot.
+    pub fn validate(&self) -> Result<(), String> {
+        let ResourcePattern {
+            resource_type,
+            name,
+            pattern_type,
+        } = &self.pattern;
+        if *resource_type == ResourceType::Any {
+            return Err("resource type must not be ANY".into());
+        }
+        if ![PatternType::Literal, PatternType::Prefixed].contains(pattern_type) {
+            return Err("pattern type must be LITERAL or PREFIXED".into());
+        }
+        if name.is_empty() {
+            return Err("resource name must not be empty".into());
+        }
+        if *resource_type == ResourceType::Cluster && name != CLUSTER_NAME {
+            return Err(format!("cluster resource must be named {}", CLUSTER_NAME));
+        }
+
+        let entry = &self.entry;
+        if entry.principal.parse::<Principal>().is_err() {
+            return Err(format!("invalid principal {:?}", entry.principal));
+        }
+        if entry.host != WILDCARD && entry.host.parse::<IpAddr>().is_err() {
+            return Err(format!("invalid host {:?}", entry.host));
+        }
+        if entry.operation == AclOperation::Any {
+            return Err("operation must not be ANY".into());
+        }
+        if entry.permission_type == AclPermissionType::Any {
+            return Err("permission type must not be ANY".into());
+        }
+        Ok(())
+    }
+}
+
+/// Selects ACLs to describe or delete. Fields set to `None` or `Any` match
+/// any value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclBindingFilter {
+    pub resource_type: ResourceType,
+    pub name: Option<String>,
+    pub pattern_type: PatternType,
+    pub principal: Option<String>,
+    pub host: Option<String>,
+    pub operation: AclOperation,
+    pub permission_type: AclPermissionType,
+}
+
+impl AclBindingFilter {
+    /// Filter matching every ACL.
+    pub fn any() -> Self {
+        Self {
+            resource_type: ResourceType::Any,
+            name: None,
+            pattern_type: PatternType::Any,
+            principal: None,
+            host: None,
+            operation: AclOperation::Any,
+            permission_type: AclPermissionType::Any,
+        }
+    }
+
+    pub fn matches(&self, binding: &AclBinding) -> bool {
+        self.matches_pattern(&binding.pattern) && self.matches_entry(&binding.entry)
+    }
+
+    fn matches_pattern(&self, pattern: &ResourcePattern) -> bool {
+        if self.resource_type != ResourceType::Any && self.resource_type != pattern.resource_type {
+            return false;
+        }
+        let name = match &self.name {
+            Some(name) => name,
+            None => {
+                return self.pattern_type == PatternType::Any
+                    || self.pattern_type == PatternType::Match
+                    || self.pattern_type == pattern.pattern_type
+            }
+        };
+        match self.pattern_type {
+            PatternType::Any => *name == pattern.name,
+            PatternType::Match => match pattern.pattern_type {
+                PatternType::Prefixed => name.starts_with(&pattern.name),
+                _ => *name == pattern.name || pattern.name == WILDCARD,
+            },
+            pattern_type => pattern_type == pattern.pattern_type && *name == pattern.name,
+        }
+    }
+
+    fn matches_entry(&self, entry: &AccessControlEntry) -> bool {
+        self.principal
+            .as_ref()
+            .is_none_or(|p| *p == entry.principal)
+            && self.host.as_ref().is_none_or(|h| *h == entry.host)
+            && (self.operation == AclOperation::Any || self.operation == entry.operation)
+            && (self.permission_type == AclPermissionType::Any
+                || self.permission_type == entry.permission_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(pattern_type: PatternType, name: &str) -> AclBinding {
+        AclBinding {
+            pattern: ResourcePattern {
+                resource_type: ResourceType::Topic,
+                name: name.into(),
+                pattern_type,
+            },
+            entry: AccessControlEntry {
+                principal: "User:alice".into(),
+                host: WILDCARD.into(),
+                operation: AclOperation::Read,
+                permission_type: AclPermissionType::Allow,
+            },
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let literal = binding(PatternType::Literal, "events");
+        let wildcard = binding(PatternType::Literal, WILDCARD);
+        let prefixed = binding(PatternType::Prefixed, "ev");
+
+        let filter = |pattern_type, name: Option<&str>| AclBindingFilter {
+            pattern_type,
+            name: name.map(String::from),
+            ..AclBindingFilter::any()
+        };
+        let matching = |filter: AclBindingFilter| -> Vec<bool> {
+            vec![
+                filter.matches(&literal),
+                filter.matches(&wildcard),
+                filter.matches(&prefixed),
+            ]
+        };
+        assert_eq!(matching(AclBindingFilter::any()), [true, true, true]);
+        assert_eq!(
+            matching(filter(PatternType::Any, Some("events"))),
+            [true, false, false]
+        );
+        assert_eq!(
+            matching(filter(PatternType::Match, Some("events"))),
+            [true, true, true]
+        );
+        assert_eq!(
+            matching(filter(PatternType::Prefixed, None)),
+            [false, false, true]
+        );
+        assert_eq!(
+            matching(filter(PatternType::Literal, Some("ev"))),
+            [false, false, false]
+        );
+
+        let filter = AclBindingFilter {
+            resource_type: ResourceType::Group,
+            ..AclBindingFilter::any()
+        };
+        assert_eq!(matching(filter), [false, false, false]);
+        let filter = AclBindingFilter {
+            principal: Some("User:bob".into()),
+            ..AclBindingFilter::any()
+        };
+        assert_eq!(matching(filter), [false, false, false]);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(binding(PatternType::Literal, "events").validate().is_ok());
+        assert!(binding(PatternType::Match, "events").validate().is_err());
+        assert!(binding(PatternType::Prefixed, "").validate().is_err());
+
+        let mut invalid = binding(PatternType::Literal, "events");
+        invalid.entry.principal = "alice".into();
+        assert!(invalid.validate().is_err());
+        let mut invalid = binding(PatternType::Literal, "events");
+        invalid.entry.host = "localhost".into();
+        assert!(invalid.validate().is_err());
+        let mut invalid = binding(PatternType::Literal, "events");
+        invalid.pattern.resource_type = ResourceType::Cluster;
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_implied_operations() {
+        assert!(AclOperation::All.implies(AclOperation::Write));
+        assert!(AclOperation::Read.implies(AclOperation::Describe));
+        assert!(AclOperation::AlterConfigs.implies(AclOperation::DescribeConfigs));
+        assert!(!AclOperation::Describe.implies(AclOperation::Read));
+        assert!(!AclOperation::Write.implies(AclOperation::Read));
+    }
+}
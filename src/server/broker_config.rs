@@ -0,0 +1,1001 @@
+//! Configuration of the broker, as written in a properties file.
+//!
+//! Every setting of the broker is a key of the properties format of Kafka,
+//! e.g. `max.connections=1000`. Values are read from a file, then from
+//! `FAFKA_*` environment variables, then from command-line flags, each
+//! overriding the previous ones. `BrokerConfig` parses and validates them
+//! all at startup, so that mistakes are reported along with the key and
+//! where its value comes from.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::warn;
+
+use super::endpoint::{Endpoint, ListenerConfig, SecurityProtocol};
+use super::principal::Principal;
+use super::tls::{ClientAuth, TlsConfig};
+#[cfg(unix)]
+use super::unix;
+use super::{is_wildcard, Config};
+
+/// Prefix of the environment variables overriding config keys. The rest of
+/// the variable is the key, upper case with dots replaced by underscores,
+/// e.g. `FAFKA_MAX_CONNECTIONS` for `max.connections`.
+pub const ENV_PREFIX: &str = "FAFKA_";
+
+// Keys of specific listeners are `listener.name.<name>.<key>`, e.g.
+// `listener.name.INTERNAL.max.connections`.
+const LISTENER_PREFIX: &str = "listener.name.";
+const MAX_CONNECTIONS_SUFFIX: &str = ".max.connections";
+
+/// Where the value of a key comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    File {
+        path: PathBuf,
+        line: usize,
+    },
+    Env(String),
+    /// A command-line flag, e.g. `--max-connections`.
+    Flag(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::File { path, line } => write!(f, "{}:{}", path.display(), line),
+            Source::Env(var) => write!(f, "environment variable {}", var),
+            Source::Flag(flag) => write!(f, "flag {}", flag),
+        }
+    }
+}
+
+/// Raw values of config keys, along with where they come from. Setting a
+/// key again overrides its value.
+#[derive(Debug, Clone, Default)]
+pub struct Properties {
+    values: BTreeMap<String, (String, Source)>,
+}
+
+impl Properties {
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<String>, source: Source) {
+        self.values.insert(key.into(), (value.into(), source));
+    }
+
+    /// Where the value of `key` comes from, `None` if it is not set.
+    pub fn source(&self, key: &str) -> Option<&Source> {
+        self.values.get(key).map(|(_, source)| source)
+    }
+
+    /// Set the keys of the properties file at `path`: one `key=value` per
+    /// line, blank lines and lines starting with `#` or `!` being ignored.
+    pub fn load_file(&mut self, path: &Path) -> Result<(), String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+                continue;
+            }
+            let source = Source::File {
+                path: path.to_owned(),
+                line: i + 1,
+            };
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{}: expected key=value", source))?;
+            self.set(key.trim(), value.trim(), source);
+        }
+        Ok(())
+    }
+
+    /// Set the keys of the `FAFKA_*` variables among `vars`. Variables which
+    /// match no key, e.g. `FAFKA_HOME`, are left out with a warning. Listener
+    /// names keep their case and underscores, e.g.
+    /// `FAFKA_LISTENER_NAME_SASL_SSL_MAX_CONNECTIONS` sets
+    /// `listener.name.SASL_SSL.max.connections`.
+    pub fn load_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) {
+        let config = BrokerConfig::default();
+        for (var, value) in vars {
+            if let Some(key) = var.strip_prefix(ENV_PREFIX) {
+                let key =
+                    env_listener_key(key).unwrap_or_else(|| key.to_lowercase().replace('_', "."));
+                if config.get(&key).is_none() {
+                    warn!(variable = %var, "environment variable matches no config key");
+                    continue;
+                }
+                self.set(key, value, Source::Env(var));
+            }
+        }
+    }
+}
+
+/// Typed configuration of the broker.
+#[derive(Debug, Clone)]
+pub struct BrokerConfig {
+    /// Listeners and the address they are bound to (`listeners`). When
+    /// empty, the broker listens on `host_name:port`.
+    pub listeners: Vec<Endpoint>,
+
+    /// Addresses clients should connect to, for the listeners not
+    /// advertised at the address they are bound to
+    /// (`advertised.listeners`).
+    pub advertised_listeners: Vec<Endpoint>,
+
+    /// Security protocol of the listeners by name
+    /// (`listener.security.protocol.map`). Listeners named after a
+    /// security protocol use it by default.
+    pub listener_security_protocol_map: BTreeMap<String, SecurityProtocol>,
+
+    /// Host the broker listens on when `listeners` is empty (`host.name`).
+    pub host_name: String,
+
+    /// Port the broker listens on when `listeners` is empty (`port`). The
+    /// security protocol of the listener depends on whether TLS and SASL
+    /// are configured.
+    pub port: u16,
+
+    /// Host metrics are served on (`metrics.host`). Defaults to
+    /// `host_name`.
+    pub metrics_host: Option<String>,
+
+    /// PEM file holding the certificate chain of the broker
+    /// (`ssl.certificate.location`).
+    pub ssl_certificate_location: Option<PathBuf>,
+
+    /// PEM file holding the private key of the broker (`ssl.key.location`).
+    pub ssl_key_location: Option<PathBuf>,
+
+    /// PEM file holding the CA certificates client certificates are
+    /// verified against (`ssl.truststore.location`).
+    pub ssl_truststore_location: Option<PathBuf>,
+
+    /// Whether clients authenticate with a certificate (`ssl.client.auth`).
+    pub ssl_client_auth: ClientAuth,
+
+    /// Configuration of the server. Its TLS configuration is built from the
+    /// `ssl.*` keys.
+    pub server: Config,
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        Self {
+            listeners: vec![],
+            advertised_listeners: vec![],
+            listener_security_protocol_map: BTreeMap::new(),
+            host_name: "127.0.0.1".into(),
+            port: 9092,
+            metrics_host: None,
+            ssl_certificate_location: None,
+            ssl_key_location: None,
+            ssl_truststore_location: None,
+            ssl_client_auth: ClientAuth::None,
+            server: Config::default(),
+        }
+    }
+}
+
+/// A config key, and how to parse and format its value.
+struct Key {
+    name: &'static str,
+    set: fn(&mut BrokerConfig, &str) -> Result<(), String>,
+    get: fn(&BrokerConfig) -> String,
+}
+
+macro_rules! key {
+    ($name:expr, $config:ident => $field:expr, $parse:expr, $format:expr) => {
+        Key {
+            name: $name,
+            set: |$config, value| {
+                $field = $parse(value)?;
+                Ok(())
+            },
+            get: |$config| $format(&$field),
+        }
+    };
+    ($name:expr, $config:ident => $field:expr) => {
+        key!($name, $config => $field, parse, ToString::to_string)
+    };
+}
+
+const KEYS: &[Key] = &[
+    key!("broker.id", c => c.server.broker_id),
+    key!("cluster.id", c => c.server.cluster_id),
+    key!("listeners", c => c.listeners, parse_list, format_list),
+    key!("advertised.listeners", c => c.advertised_listeners, parse_list, format_list),
+    key!(
+        "listener.security.protocol.map",
+        c => c.listener_security_protocol_map,
+        parse_protocol_map,
+        format_protocol_map
+    ),
+    key!("host.name", c => c.host_name),
+    key!("port", c => c.port),
+    key!(
+        "unix.socket.path",
+        c => c.server.unix_socket_path,
+        parse_optional,
+        format_path
+    ),
+    key!("unix.socket.mode", c => c.server.unix_socket_mode, parse_mode, format_mode),
+    key!("metrics.port", c => c.server.metrics_port, parse_optional, format_optional),
+    key!("metrics.host", c => c.metrics_host, parse_optional, format_optional),
+    key!("log.dirs", c => c.server.log_dir, parse_log_dirs, format_log_dirs),
+    key!("log.segment.bytes", c => c.server.log.segment_bytes),
+    key!("log.roll.ms", c => c.server.log.segment_ms),
+    key!("log.roll.jitter.ms", c => c.server.log.segment_jitter_ms),
+    key!("log.preallocate", c => c.server.log.preallocate),
+    // Keys are set in order, so that `log.retention.ms` wins over
+    // `log.retention.minutes`, which wins over `log.retention.hours`.
+    key!(
+        "log.retention.hours",
+        c => c.server.log.retention_ms,
+        |value| parse_retention_in(value, HOUR_MS),
+        |ms: &Option<u64>| format_retention(&ms.map(|ms| ms / HOUR_MS))
+    ),
+    key!(
+        "log.retention.minutes",
+        c => c.server.log.retention_ms,
+        |value| parse_retention_in(value, MINUTE_MS),
+        |ms: &Option<u64>| format_retention(&ms.map(|ms| ms / MINUTE_MS))
+    ),
+    key!("log.retention.ms", c => c.server.log.retention_ms, parse_retention, format_retention),
+    key!(
+        "log.retention.bytes",
+        c => c.server.log.retention_bytes,
+        parse_retention,
+        format_retention
+    ),
+    key!(
+        "log.retention.check.interval.ms",
+        c => c.server.log_retention_check_interval,
+        parse_ms,
+        format_ms
+    ),
+    key!("log.message.timestamp.type", c => c.server.message_timestamp_type),
+    key!("num.partitions", c => c.server.num_partitions),
+    key!("auto.create.topics.enable", c => c.server.auto_create_topics),
+    key!("message.max.bytes", c => c.server.max_message_bytes),
+    key!("max.connections", c => c.server.max_connections),
+    key!("max.connections.per.ip", c => c.server.max_connections_per_ip),
+    key!(
+        "max.connections.per.ip.overrides",
+        c => c.server.max_connections_per_ip_overrides,
+        parse_connections_overrides,
+        format_connections_overrides
+    ),
+    key!(
+        "max.connection.creation.rate",
+        c => c.server.max_connection_creation_rate,
+        parse_optional,
+        format_optional
+    ),
+    key!("socket.request.max.bytes", c => c.server.max_request_size),
+    key!("connections.max.idle.ms", c => c.server.connections_max_idle, parse_ms, format_ms),
+    key!(
+        "connections.write.stall.timeout.ms",
+        c => c.server.write_stall_timeout,
+        parse_ms,
+        format_ms
+    ),
+    key!("shutdown.timeout.ms", c => c.server.shutdown_timeout, parse_ms, format_ms),
+    key!("max.in.flight.requests.per.connection", c => c.server.max_in_flight_requests),
+    key!(
+        "ssl.certificate.location",
+        c => c.ssl_certificate_location,
+        parse_optional,
+        format_path
+    ),
+    key!("ssl.key.location", c => c.ssl_key_location, parse_optional, format_path),
+    key!(
+        "ssl.truststore.location",
+        c => c.ssl_truststore_location,
+        parse_optional,
+        format_path
+    ),
+    key!("ssl.client.auth", c => c.ssl_client_auth),
+    key!(
+        "sasl.enabled.mechanisms",
+        c => c.server.sasl_enabled_mechanisms,
+        parse_list,
+        format_list
+    ),
+    key!("authorizer.enabled", c => c.server.authorizer_enabled),
+    key!(
+        "super.users",
+        c => c.server.super_users,
+        parse_super_users,
+        format_super_users
+    ),
+    key!(
+        "allow.everyone.if.no.acl.found",
+        c => c.server.allow_everyone_if_no_acl_found
+    ),
+    key!("quota.window.num", c => c.server.quota_window_num),
+    key!(
+        "quota.window.size.seconds",
+        c => c.server.quota_window_size,
+        |value| parse(value).map(Duration::from_secs),
+        |size: &Duration| size.as_secs().to_string()
+    ),
+];
+
+impl BrokerConfig {
+    /// Names of every key, in the order they are logged.
+    pub fn keys() -> impl Iterator<Item = &'static str> {
+        KEYS.iter().map(|key| key.name)
+    }
+
+    /// Parse and validate `properties`, keys which are not set keeping
+    /// their default value.
+    pub fn from_properties(properties: &Properties) -> Result<Self, String> {
+        let mut config = Self::default();
+        for (name, (value, source)) in &properties.values {
+            if config.get(name).is_none() {
+                return Err(format!("unknown config key {} ({})", name, source));
+            }
+            config.set(name, value).map_err(|err| {
+                format!(
+                    "invalid value {:?} for {} ({}): {}",
+                    value, name, source, err
+                )
+            })?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parse `value` and set the key `name` to it, without validating the
+    /// resulting config.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if let Some(listener) = listener_max_connections_key(name) {
+            let max = parse(value)?;
+            let limits = &mut self.server.listener_max_connections;
+            limits.insert(listener.into(), max);
+            return Ok(());
+        }
+        let key = KEYS
+            .iter()
+            .find(|key| key.name == name)
+            .ok_or_else(|| format!("unknown config key {}", name))?;
+        (key.set)(self, value)
+    }
+
+    /// Value of the key `name`, formatted as in properties files.
+    pub fn get(&self, name: &str) -> Option<String> {
+        if let Some(listener) = listener_max_connections_key(name) {
+            let max = self.server.listener_max_connections.get(listener);
+            return Some(format_optional(&max));
+        }
+        KEYS.iter()
+            .find(|key| key.name == name)
+            .map(|key| (key.get)(self))
+    }
+
+    /// Every key with its value, formatted as in properties files. Keys of
+    /// specific listeners come last, when they are set.
+    pub fn values(&self) -> Vec<(String, String)> {
+        let mut values: Vec<_> = KEYS
+            .iter()
+            .map(|key| (key.name.to_string(), (key.get)(self)))
+            .collect();
+        let mut limits: Vec<_> = self.server.listener_max_connections.iter().collect();
+        limits.sort();
+        for (listener, max) in limits {
+            let name = format!("{}{}{}", LISTENER_PREFIX, listener, MAX_CONNECTIONS_SUFFIX);
+            values.push((name, max.to_string()));
+        }
+        values
+    }
+
+    /// Configuration of the server, TLS included.
+    pub fn server_config(&self) -> Config {
+        let tls = match (&self.ssl_certificate_location, &self.ssl_key_location) {
+            (Some(cert), Some(key)) => Some(TlsConfig {
+                client_ca_path: self.ssl_truststore_location.clone(),
+                client_auth: self.ssl_client_auth,
+                ..TlsConfig::new(cert, key)
+            }),
+            _ => None,
+        };
+        let metrics_host = self.metrics_host.as_ref().unwrap_or(&self.host_name);
+        Config {
+            tls,
+            metrics_host: metrics_host.clone(),
+            ..self.server.clone()
+        }
+    }
+
+    /// Configuration and bind address of every listener.
+    pub fn listener_configs(&self) -> Vec<(ListenerConfig, Endpoint)> {
+        self.endpoints()
+            .into_iter()
+            .map(|endpoint| {
+                let name = &endpoint.listener_name;
+                let mut listener = ListenerConfig::new(name.clone(), self.security_protocol(name));
+                let advertised = self
+                    .advertised_listeners
+                    .iter()
+                    .find(|advertised| advertised.listener_name == *name);
+                if let Some(advertised) = advertised {
+                    listener.advertised_host = Some(advertised.host.clone());
+                    listener.advertised_port = Some(advertised.port);
+                }
+                (listener, endpoint)
+            })
+            .collect()
+    }
+
+    // Listeners, or the one on `host_name:port`, named after its security
+    // protocol.
+    fn endpoints(&self) -> Vec<Endpoint> {
+        if !self.listeners.is_empty() {
+            return self.listeners.clone();
+        }
+        let tls = self.ssl_certificate_location.is_some();
+        let sasl = !self.server.sasl_enabled_mechanisms.is_empty();
+        let protocol = match (tls, sasl) {
+            (false, false) => SecurityProtocol::Plaintext,
+            (true, false) => SecurityProtocol::Ssl,
+            (false, true) => SecurityProtocol::SaslPlaintext,
+            (true, true) => SecurityProtocol::SaslSsl,
+        };
+        vec![Endpoint {
+            listener_name: protocol.name().into(),
+            host: self.host_name.clone(),
+            port: self.port,
+        }]
+    }
+
+    // Security protocol of the listener `name`, the plaintext protocol if it
+    // has none, which `validate` rules out.
+    fn security_protocol(&self, name: &str) -> SecurityProtocol {
+        self.listener_security_protocol_map
+            .get(name)
+            .copied()
+            .or_else(|| name.parse().ok())
+            .unwrap_or(SecurityProtocol::Plaintext)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let endpoints = self.endpoints();
+        for (i, endpoint) in endpoints.iter().enumerate() {
+            let name = &endpoint.listener_name;
+            if endpoints[..i].iter().any(|e| e.listener_name == *name) {
+                return Err(format!("duplicate listener {} in listeners", name));
+            }
+            if !self.listener_security_protocol_map.contains_key(name)
+                && name.parse::<SecurityProtocol>().is_err()
+            {
+                return Err(format!(
+                    "listener {} has no security protocol in listener.security.protocol.map",
+                    name
+                ));
+            }
+
+            let protocol = self.security_protocol(name);
+            if protocol.is_tls()
+                && (self.ssl_certificate_location.is_none() || self.ssl_key_location.is_none())
+            {
+                return Err(format!(
+                    "{} listener {} requires ssl.certificate.location and ssl.key.location",
+                    protocol, name
+                ));
+            }
+            if protocol.is_sasl() && self.server.sasl_enabled_mechanisms.is_empty() {
+                return Err(format!(
+                    "{} listener {} requires sasl.enabled.mechanisms",
+                    protocol, name
+                ));
+            }
+        }
+        for listener in self.server.listener_max_connections.keys() {
+            #[cfg(unix)]
+            if *listener == unix::LISTENER_NAME && self.server.unix_socket_path.is_some() {
+                continue;
+            }
+            if !endpoints.iter().any(|e| e.listener_name == *listener) {
+                return Err(format!(
+                    "{}{}{}: listener {} is not in listeners",
+                    LISTENER_PREFIX, listener, MAX_CONNECTIONS_SUFFIX, listener
+                ));
+            }
+        }
+        for advertised in &self.advertised_listeners {
+            let name = &advertised.listener_name;
+            if !endpoints.iter().any(|e| e.listener_name == *name) {
+                return Err(format!("advertised listener {} is not in listeners", name));
+            }
+            if is_wildcard(&advertised.host) {
+                return Err(format!(
+                    "advertised listener {} cannot use the wildcard address {:?}",
+                    name, advertised.host
+                ));
+            }
+        }
+
+        if self.ssl_client_auth != ClientAuth::None && self.ssl_truststore_location.is_none() {
+            return Err(format!(
+                "ssl.client.auth={} requires ssl.truststore.location",
+                self.ssl_client_auth
+            ));
+        }
+        validate_server(&self.server)
+    }
+}
+
+/// Check the values of the keys of the server configuration which must be
+/// positive.
+pub(super) fn validate_server(server: &Config) -> Result<(), String> {
+    let positive = [
+        ("max.connections", server.max_connections as u64),
+        (
+            "max.connections.per.ip",
+            server.max_connections_per_ip as u64,
+        ),
+        ("num.partitions", server.num_partitions.max(0) as u64),
+        ("log.segment.bytes", server.log.segment_bytes as u64),
+        (
+            "log.retention.check.interval.ms",
+            server.log_retention_check_interval.as_millis() as u64,
+        ),
+        ("message.max.bytes", server.max_message_bytes as u64),
+        ("socket.request.max.bytes", server.max_request_size as u64),
+        ("quota.window.num", server.quota_window_num as u64),
+        (
+            "quota.window.size.seconds",
+            server.quota_window_size.as_secs(),
+        ),
+    ];
+    for (name, value) in positive.iter() {
+        if *value == 0 {
+            return Err(format!("{} must be positive", name));
+        }
+    }
+    for (listener, max) in &server.listener_max_connections {
+        if *max == 0 {
+            return Err(format!(
+                "{}{}{} must be positive",
+                LISTENER_PREFIX, listener, MAX_CONNECTIONS_SUFFIX
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Key of the variable `var`, without its prefix, when it sets the
+// `max.connections` of a listener.
+fn env_listener_key(var: &str) -> Option<String> {
+    let to_env = |key: &str| key.to_uppercase().replace('.', "_");
+    let (prefix, suffix) = (to_env(LISTENER_PREFIX), to_env(MAX_CONNECTIONS_SUFFIX));
+    if var.len() <= prefix.len() + suffix.len()
+        || !var.is_char_boundary(prefix.len())
+        || !var.is_char_boundary(var.len() - suffix.len())
+        || !var[..prefix.len()].eq_ignore_ascii_case(&prefix)
+        || !var[var.len() - suffix.len()..].eq_ignore_ascii_case(&suffix)
+    {
+        return None;
+    }
+    let listener = &var[prefix.len()..var.len() - suffix.len()];
+    Some(format!(
+        "{}{}{}",
+        LISTENER_PREFIX, listener, MAX_CONNECTIONS_SUFFIX
+    ))
+}
+
+// Name of the listener of a `listener.name.<name>.max.connections` key.
+fn listener_max_connections_key(name: &str) -> Option<&str> {
+    name.strip_prefix(LISTENER_PREFIX)?
+        .strip_suffix(MAX_CONNECTIONS_SUFFIX)
+        .filter(|listener| !listener.is_empty())
+}
+
+fn parse<T>(value: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|err: T::Err| err.to_string())
+}
+
+// Empty values leave optional keys unset.
+fn parse_optional<T>(value: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if value.is_empty() {
+        return Ok(None);
+    }
+    parse(value).map(Some)
+}
+
+fn format_optional<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map_or_else(String::new, ToString::to_string)
+}
+
+fn format_path(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map_or_else(String::new, |path| path.display().to_string())
+}
+
+// Octal file permissions, such as `660`.
+fn parse_mode(value: &str) -> Result<u32, String> {
+    match u32::from_str_radix(value, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!(
+            "invalid mode {:?}, expected octal permissions",
+            value
+        )),
+    }
+}
+
+fn format_mode(mode: &u32) -> String {
+    format!("{:o}", mode)
+}
+
+const MINUTE_MS: u64 = 60 * 1000;
+const HOUR_MS: u64 = 60 * MINUTE_MS;
+
+// Retention limits, `-1` for no limit.
+fn parse_retention(value: &str) -> Result<Option<u64>, String> {
+    if value.trim() == "-1" {
+        return Ok(None);
+    }
+    value.parse().map(Some).map_err(|_| {
+        format!(
+            "invalid limit {:?}, expected -1 or a non-negative number",
+            value
+        )
+    })
+}
+
+// Retention time limits in `unit` milliseconds.
+fn parse_retention_in(value: &str, unit: u64) -> Result<Option<u64>, String> {
+    parse_retention(value).map(|limit| limit.map(|limit| limit.saturating_mul(unit)))
+}
+
+fn format_retention(limit: &Option<u64>) -> String {
+    limit.map_or_else(|| "-1".into(), |limit| limit.to_string())
+}
+
+fn parse_ms(value: &str) -> Result<Duration, String> {
+    parse(value).map(Duration::from_millis)
+}
+
+fn format_ms(duration: &Duration) -> String {
+    duration.as_millis().to_string()
+}
+
+// Comma-separated values.
+fn parse_list<T>(value: &str) -> Result<Vec<T>, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(parse)
+        .collect()
+}
+
+fn format_list<T: ToString>(values: &[T]) -> String {
+    let values: Vec<_> = values.iter().map(ToString::to_string).collect();
+    values.join(",")
+}
+
+fn parse_log_dirs(value: &str) -> Result<PathBuf, String> {
+    let dirs: Vec<PathBuf> = parse_list(value)?;
+    match dirs.as_slice() {
+        [dir] => Ok(dir.clone()),
+        [] => Err("no log directory".into()),
+        _ => Err("only one log directory is supported".into()),
+    }
+}
+
+fn format_log_dirs(dir: &Path) -> String {
+    dir.display().to_string()
+}
+
+// Comma-separated `NAME:PROTOCOL`.
+fn parse_protocol_map(value: &str) -> Result<BTreeMap<String, SecurityProtocol>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let (name, protocol) = item
+                .split_once(':')
+                .ok_or_else(|| format!("invalid {:?}, expected NAME:PROTOCOL", item))?;
+            Ok((name.into(), protocol.parse()?))
+        })
+        .collect()
+}
+
+fn format_protocol_map(map: &BTreeMap<String, SecurityProtocol>) -> String {
+    let items: Vec<_> = map
+        .iter()
+        .map(|(name, protocol)| format!("{}:{}", name, protocol))
+        .collect();
+    items.join(",")
+}
+
+// Comma-separated `ip:count`.
+fn parse_connections_overrides(value: &str) -> Result<HashMap<IpAddr, usize>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let invalid = || format!("invalid {:?}, expected <ip>:<count>", item);
+            let (ip, count) = item.rsplit_once(':').ok_or_else(invalid)?;
+            Ok((
+                ip.parse().map_err(|_| invalid())?,
+                count.parse().map_err(|_| invalid())?,
+            ))
+        })
+        .collect()
+}
+
+fn format_connections_overrides(overrides: &HashMap<IpAddr, usize>) -> String {
+    let mut items: Vec<_> = overrides
+        .iter()
+        .map(|(ip, count)| format!("{}:{}", ip, count))
+        .collect();
+    items.sort();
+    items.join(",")
+}
+
+// Semicolon-separated principals, as they may contain commas.
+fn parse_super_users(value: &str) -> Result<Vec<Principal>, String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(parse)
+        .collect()
+}
+
+fn format_super_users(users: &[Principal]) -> String {
+    let users: Vec<_> = users.iter().map(ToString::to_string).collect();
+    users.join(";")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Mechanism;
+
+    fn flag(flag: &str) -> Source {
+        Source::Flag(flag.into())
+    }
+
+    #[test]
+    fn test_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.properties");
+        fs::write(
+            &path,
+            "# Listeners\n\
+             listeners=INTERNAL://0.0.0.0:9092, EXTERNAL://:9093\n\
+             listener.security.protocol.map=INTERNAL:PLAINTEXT,EXTERNAL:SASL_PLAINTEXT\n\
+             advertised.listeners=EXTERNAL://broker.example.com:19093\n\
+             \n\
+             sasl.enabled.mechanisms=SCRAM-SHA-256\n\
+             max.connections = 100\n\
+             listener.name.EXTERNAL.max.connections=10\n\
+             num.partitions=3\n\
+             log.retention.hours=24\n\
+             log.retention.bytes=1073741824\n",
+        )
+        .unwrap();
+
+        let mut properties = Properties::default();
+        properties.load_file(&path).unwrap();
+        properties.load_env(vec![
+            ("FAFKA_NUM_PARTITIONS".into(), "6".into()),
+            ("FAFKA_LOG_ROLL_MS".into(), "1000".into()),
+            ("FAFKA_LOG_RETENTION_MS".into(), "3600000".into()),
+            ("FAFKA_HOME".into(), "/opt/fafka".into()),
+            ("HOME".into(), "/root".into()),
+        ]);
+        properties.set("log.roll.ms", "2000", flag("--override"));
+        let config = BrokerConfig::from_properties(&properties).unwrap();
+
+        assert_eq!(config.server.max_connections, 100);
+        assert_eq!(
+            config.server.listener_max_connections,
+            vec![("EXTERNAL".to_string(), 10)].into_iter().collect()
+        );
+        assert_eq!(config.server.num_partitions, 6);
+        assert_eq!(config.server.log.segment_ms, 2000);
+        // `log.retention.ms` wins over `log.retention.hours`.
+        assert_eq!(config.server.log.retention_ms, Some(60 * 60 * 1000));
+        assert_eq!(config.server.log.retention_bytes, Some(1 << 30));
+        assert_eq!(properties.source("home"), None);
+        assert_eq!(
+            config.server.sasl_enabled_mechanisms,
+            vec![Mechanism::Scram(crate::server::ScramMechanism::Sha256)]
+        );
+        assert_eq!(
+            properties.source("max.connections"),
+            Some(&Source::File {
+                path: path.clone(),
+                line: 7
+            })
+        );
+        assert_eq!(
+            properties.source("num.partitions"),
+            Some(&Source::Env("FAFKA_NUM_PARTITIONS".into()))
+        );
+
+        let listeners = config.listener_configs();
+        assert_eq!(listeners.len(), 2);
+        let (internal, endpoint) = &listeners[0];
+        assert_eq!(
+            *internal,
+            ListenerConfig::new("INTERNAL", SecurityProtocol::Plaintext)
+        );
+        assert_eq!(endpoint.host, "0.0.0.0");
+        let (external, endpoint) = &listeners[1];
+        assert_eq!(external.security_protocol, SecurityProtocol::SaslPlaintext);
+        assert_eq!(
+            external.advertised_host.as_deref(),
+            Some("broker.example.com")
+        );
+        assert_eq!(external.advertised_port, Some(19093));
+        assert_eq!(endpoint.port, 9093);
+
+        // Formatted values parse back to the same config.
+        let mut formatted = Properties::default();
+        for (key, value) in config.values() {
+            formatted.set(key, value, flag("--override"));
+        }
+        let reparsed = BrokerConfig::from_properties(&formatted).unwrap();
+        assert_eq!(reparsed.values(), config.values());
+    }
+
+    #[test]
+    fn test_env_listener_keys() {
+        let mut properties = Properties::default();
+        properties.set(
+            "listeners",
+            "EXTERNAL://:9093,INTERNAL_CLIENTS://:9094",
+            flag("--listeners"),
+        );
+        properties.set(
+            "listener.security.protocol.map",
+            "EXTERNAL:PLAINTEXT,INTERNAL_CLIENTS:PLAINTEXT",
+            flag("--override"),
+        );
+        properties.load_env(vec![
+            (
+                "FAFKA_LISTENER_NAME_EXTERNAL_MAX_CONNECTIONS".into(),
+                "10".into(),
+            ),
+            (
+                "FAFKA_LISTENER_NAME_INTERNAL_CLIENTS_MAX_CONNECTIONS".into(),
+                "20".into(),
+            ),
+            ("FAFKA_LISTENER_NAME_MAX_CONNECTIONS".into(), "30".into()),
+        ]);
+        let config = BrokerConfig::from_properties(&properties).unwrap();
+
+        assert_eq!(
+            config.server.listener_max_connections,
+            vec![
+                ("EXTERNAL".to_string(), 10),
+                ("INTERNAL_CLIENTS".to_string(), 20)
+            ]
+            .into_iter()
+            .collect()
+        );
+        assert_eq!(
+            properties.source("listener.name.INTERNAL_CLIENTS.max.connections"),
+            Some(&Source::Env(
+                "FAFKA_LISTENER_NAME_INTERNAL_CLIENTS_MAX_CONNECTIONS".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_default_listener() {
+        let mut properties = Properties::default();
+        properties.set("port", "9000", flag("--port"));
+        properties.set("ssl.certificate.location", "broker.pem", flag("--tls-cert"));
+        properties.set("ssl.key.location", "broker.key", flag("--tls-key"));
+        let config = BrokerConfig::from_properties(&properties).unwrap();
+
+        let listeners = config.listener_configs();
+        assert_eq!(
+            listeners,
+            vec![(
+                ListenerConfig::new("SSL", SecurityProtocol::Ssl),
+                "SSL://127.0.0.1:9000".parse().unwrap()
+            )]
+        );
+        assert!(config.server_config().tls.is_some());
+        assert_eq!(config.server_config().metrics_host, "127.0.0.1");
+
+        // Metrics are served on the host of the broker by default.
+        properties.set("host.name", "10.0.0.1", flag("--override"));
+        let config = BrokerConfig::from_properties(&properties).unwrap();
+        assert_eq!(config.server_config().metrics_host, "10.0.0.1");
+        properties.set("metrics.host", "0.0.0.0", flag("--override"));
+        let config = BrokerConfig::from_properties(&properties).unwrap();
+        assert_eq!(config.server_config().metrics_host, "0.0.0.0");
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |pairs: &[(&str, &str)]| {
+            let mut properties = Properties::default();
+            for (key, value) in pairs {
+                properties.set(*key, *value, flag("--override"));
+            }
+            BrokerConfig::from_properties(&properties).unwrap_err()
+        };
+
+        assert_eq!(
+            error(&[("log.cleaner.enable", "true")]),
+            "unknown config key log.cleaner.enable (flag --override)"
+        );
+        assert_eq!(
+            error(&[("log.retention.ms", "-2")]),
+            "invalid value \"-2\" for log.retention.ms (flag --override): \
+             invalid limit \"-2\", expected -1 or a non-negative number"
+        );
+        assert!(error(&[("max.connections", "many")])
+            .starts_with("invalid value \"many\" for max.connections (flag --override)"));
+        assert_eq!(
+            error(&[("unix.socket.mode", "999")]),
+            "invalid value \"999\" for unix.socket.mode (flag --override): \
+             invalid mode \"999\", expected octal permissions"
+        );
+        assert_eq!(
+            error(&[("listeners", "INTERNAL://:9092")]),
+            "listener INTERNAL has no security protocol in listener.security.protocol.map"
+        );
+        assert_eq!(
+            error(&[("listeners", "SASL_SSL://:9092")]),
+            "SASL_SSL listener SASL_SSL requires ssl.certificate.location and ssl.key.location"
+        );
+        assert_eq!(
+            error(&[("advertised.listeners", "EXTERNAL://example.com:9092")]),
+            "advertised listener EXTERNAL is not in listeners"
+        );
+        assert_eq!(
+            error(&[("advertised.listeners", "PLAINTEXT://0.0.0.0:9092")]),
+            "advertised listener PLAINTEXT cannot use the wildcard address \"0.0.0.0\""
+        );
+        assert_eq!(
+            error(&[("advertised.listeners", "PLAINTEXT://[::]:9092")]),
+            "advertised listener PLAINTEXT cannot use the wildcard address \"::\""
+        );
+        assert_eq!(
+            error(&[("listener.name.INTERNAL.max.connections", "10")]),
+            "listener.name.INTERNAL.max.connections: listener INTERNAL is not in listeners"
+        );
+        assert_eq!(
+            error(&[("listener.name.PLAINTEXT.max.connections", "0")]),
+            "listener.name.PLAINTEXT.max.connections must be positive"
+        );
+        assert_eq!(
+            error(&[("log.dirs", "/data/1,/data/2")]),
+            "invalid value \"/data/1,/data/2\" for log.dirs (flag --override): \
+             only one log directory is supported"
+        );
+        assert_eq!(
+            error(&[("num.partitions", "0")]),
+            "num.partitions must be positive"
+        );
+    }
+}
@@ -1,14 +1,36 @@
-// use crate::frame::{self, Frame};
+use std::fmt;
+use std::io::{self, Cursor};
 
-// use bytes::{Buf, BytesMut};
-use tokio::io::BufWriter;
-use tokio::net::TcpStream;
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::time::{self, Duration, Instant};
 
-/// Send and receive `Frame` values from a remote peer.
+use super::error::{Error, Result};
+use super::frame::{Request, Response};
+
+/// Size of the prefix holding the size of a frame.
+const SIZE_PREFIX: usize = 4;
+
+/// Maximum number of bytes reserved at once for the rest of a frame. Larger
+/// frames grow the buffer as their bytes arrive, so that a peer announcing a
+/// large frame cannot make the broker allocate memory it never fills.
+const MAX_RESERVE: usize = 1024 * 1024;
+
+/// A byte stream connecting the broker to a client, such as a `TcpStream`
+/// or a TLS stream.
+pub trait Socket: AsyncRead + AsyncWrite + Send + Unpin + fmt::Debug {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + fmt::Debug> Socket for T {}
+
+/// Send and receive frames from a remote peer.
 ///
 /// When implementing networking protocols, a message on that protocol is
 /// often composed of several smaller messages known as frames. The purpose of
-/// `Connection` is to read and write frames on the underlying `TcpStream`.
+/// `Connection` is to read and write frames on the underlying `Socket`.
+///
+/// Every frame is prefixed with its size, encoded as a big-endian `i32`.
+/// Request frames start with a `RequestHeader`, response frames with the
+/// correlation id of the request they answer.
 ///
 /// To read frames, the `Connection` uses an internal buffer, which is filled
 /// up until there are enough bytes to create a full frame. Once this happens,
@@ -18,25 +40,288 @@ use tokio::net::TcpStream;
 /// The contents of the write buffer are then written to the socket.
 #[derive(Debug)]
 pub struct Connection {
-    // The `TcpStream`. It is decorated with a `BufWriter`, which provides write
+    // The `Socket`. It is decorated with a `BufWriter`, which provides write
     // level buffering. The `BufWriter` implementation provided by Tokio is
     // sufficient for our needs.
-    stream: BufWriter<TcpStream>,
+    stream: BufWriter<Box<dyn Socket>>,
+
     // The buffer for reading frames.
-    // buffer: BytesMut,
+    buffer: BytesMut,
+
+    // Frames larger than this are rejected before being buffered, so that a
+    // peer cannot make the broker allocate an arbitrary amount of memory.
+    max_frame_size: usize,
+
+    // Writes making no progress for this long fail, so that a peer that does
+    // not read its responses cannot hold the connection forever.
+    write_timeout: Duration,
+
+    // Last time bytes were read from or written to the socket.
+    last_activity: Instant,
 }
 
 impl Connection {
     /// Create a new `Connection`, backed by `socket`. Read and write buffers
     /// are initialized.
-    pub fn new(socket: TcpStream) -> Connection {
+    pub fn new(
+        socket: impl Socket + 'static,
+        max_frame_size: usize,
+        write_timeout: Duration,
+    ) -> Connection {
         Connection {
-            stream: BufWriter::new(socket),
-            // Default to a 4KB read buffer. For the use case of mini redis,
-            // this is fine. However, real applications will want to tune this
-            // value to their specific use case. There is a high likelihood that
-            // a larger read buffer will work better.
-            // buffer: BytesMut::with_capacity(4 * 1024),
+            stream: BufWriter::new(Box::new(socket)),
+            // Default to a 4KB read buffer. Most requests are small, and the
+            // buffer grows to the size of larger frames when needed.
+            buffer: BytesMut::with_capacity(4 * 1024),
+            max_frame_size,
+            write_timeout,
+            last_activity: Instant::now(),
+        }
+    }
+
+    /// Last time bytes were read from or written to the socket.
+    pub fn last_activity(&self) -> Instant {
+        self.last_activity
+    }
+
+    /// Read a single request frame from the underlying stream.
+    ///
+    /// The function waits until it has retrieved enough data to parse a
+    /// frame. Any data remaining in the read buffer after the frame has been
+    /// parsed is kept there for the next call to `read_frame`.
+    ///
+    /// # Returns
+    ///
+    /// On success, the received frame is returned. If the `Socket`
+    /// is closed in a way that doesn't break a frame in half, it returns
+    /// `None`. Otherwise, an error is returned.
+    ///
+    /// # Cancel safety
+    ///
+    /// The method is cancel safe: bytes read before it is cancelled stay in
+    /// the read buffer.
+    pub async fn read_frame(&mut self) -> Result<Option<Request>> {
+        loop {
+            // Attempt to parse a frame from the buffered data. If enough data
+            // has been buffered, the frame is returned.
+            if let Some(frame) = self.parse_frame()? {
+                return Ok(Some(frame));
+            }
+
+            // There is not enough buffered data to read a frame. Attempt to
+            // read more data from the socket.
+            //
+            // On success, the number of bytes is returned. `0` indicates "end
+            // of stream".
+            let n = self.stream.read_buf(&mut self.buffer).await?;
+            self.last_activity = Instant::now();
+            if n == 0 {
+                // The remote closed the connection. For this to be a clean
+                // shutdown, there should be no data in the read buffer. If
+                // there is, this means that the peer closed the socket while
+                // sending a frame.
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                } else {
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::ConnectionReset,
+                        "connection reset by peer",
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Tries to parse a frame from the buffer. If the buffer contains enough
+    /// data, the frame is returned and the data removed from the buffer. If
+    /// not enough data has been buffered yet, `Ok(None)` is returned. If the
+    /// buffered data does not represent a valid frame, `Err` is returned.
+    fn parse_frame(&mut self) -> Result<Option<Request>> {
+        if self.buffer.len() < SIZE_PREFIX {
+            return Ok(None);
+        }
+
+        let size = Cursor::new(&self.buffer[..]).get_i32();
+        if size < 0 {
+            return Err(Error::InvalidRequest(format!(
+                "negative frame size {}",
+                size
+            )));
+        }
+        if size as usize > self.max_frame_size {
+            return Err(Error::RequestTooLarge {
+                size: size as usize,
+                max: self.max_frame_size,
+            });
+        }
+
+        let len = SIZE_PREFIX + size as usize;
+        if self.buffer.len() < len {
+            // Make room for the rest of the frame instead of growing the
+            // buffer on every read, up to `MAX_RESERVE` at once.
+            self.buffer
+                .reserve((len - self.buffer.len()).min(MAX_RESERVE));
+            return Ok(None);
+        }
+
+        self.buffer.advance(SIZE_PREFIX);
+        let frame = self.buffer.split_to(size as usize).freeze();
+        Request::parse(frame).map(Some)
+    }
+
+    /// Write a single response frame to the underlying stream.
+    ///
+    /// Fails with `io::ErrorKind::TimedOut` when the socket accepts no byte
+    /// for `write_timeout`, i.e. the send buffer does not drain because the
+    /// peer does not read. Slow peers making progress are not affected.
+    pub async fn write_frame(&mut self, response: &Response) -> io::Result<()> {
+        let size = 4 + response.body.len();
+        let mut header = [0; 8];
+        header[..4].copy_from_slice(&(size as i32).to_be_bytes());
+        header[4..].copy_from_slice(&response.correlation_id.to_be_bytes());
+        self.write_all(&header).await?;
+        self.write_all(&response.body).await?;
+
+        // Ensure the encoded frame is written to the socket. The calls above
+        // are to the buffered stream and writes. Calling `flush` writes the
+        // remaining contents of the buffer to the socket.
+        let flushed = time::timeout(self.write_timeout, self.stream.flush()).await;
+        flushed.map_err(|_| self.write_stalled())??;
+        self.last_activity = Instant::now();
+        Ok(())
+    }
+
+    // Write all of `buf` to the buffered stream. Every write must make
+    // progress within `write_timeout`.
+    async fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let written = time::timeout(self.write_timeout, self.stream.write(buf)).await;
+            let n = written.map_err(|_| self.write_stalled())??;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.last_activity = Instant::now();
+            buf = &buf[n..];
         }
+        Ok(())
+    }
+
+    fn write_stalled(&self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("no byte written for {:?}", self.write_timeout),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::{BufMut, Bytes};
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn connect(max_frame_size: usize) -> (Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let write_timeout = Duration::from_millis(100);
+        (
+            Connection::new(socket, max_frame_size, write_timeout),
+            client,
+        )
+    }
+
+    fn request_frame(correlation_id: i32, body: &[u8]) -> Vec<u8> {
+        let mut frame = vec![];
+        frame.put_i32(10 + body.len() as i32);
+        frame.put_i16(1);
+        frame.put_i16(4);
+        frame.put_i32(correlation_id);
+        frame.put_i16(-1);
+        frame.put_slice(body);
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_read_frames() {
+        let (mut connection, mut client) = connect(1024).await;
+
+        // Two frames sent at once, the second split across writes.
+        let first = request_frame(1, b"first");
+        let second = request_frame(2, b"second");
+        client
+            .write_all(&[&first[..], &second[..3]].concat())
+            .await
+            .unwrap();
+
+        let request = connection.read_frame().await.unwrap().unwrap();
+        assert_eq!(request.header.correlation_id, 1);
+        assert_eq!(request.body, Bytes::from_static(b"first"));
+
+        client.write_all(&second[3..]).await.unwrap();
+        let request = connection.read_frame().await.unwrap().unwrap();
+        assert_eq!(request.header.correlation_id, 2);
+        assert_eq!(request.body, Bytes::from_static(b"second"));
+
+        drop(client);
+        assert!(connection.read_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_truncated_frame() {
+        let (mut connection, mut client) = connect(1024).await;
+
+        let frame = request_frame(1, b"body");
+        client.write_all(&frame[..frame.len() - 1]).await.unwrap();
+        drop(client);
+
+        assert!(connection.read_frame().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_max_frame_size() {
+        let (mut connection, mut client) = connect(16).await;
+
+        client.write_i32(i32::MAX).await.unwrap();
+        assert!(matches!(
+            connection.read_frame().await,
+            Err(Error::RequestTooLarge { max: 16, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_write_stall() {
+        let (mut connection, _client) = connect(1024).await;
+
+        // The client does not read: once the socket buffers are full, the
+        // write makes no progress and times out.
+        let response = Response {
+            correlation_id: 1,
+            body: Bytes::from(vec![0; 64 * 1024 * 1024]),
+        };
+        let err = connection.write_frame(&response).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        // The connection was last active when the last bytes were written
+        assert!(connection.last_activity().elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_write_frame() {
+        let (mut connection, mut client) = connect(1024).await;
+
+        let response = Response {
+            correlation_id: 7,
+            body: Bytes::from_static(b"response"),
+        };
+        connection.write_frame(&response).await.unwrap();
+
+        let mut buf = vec![0; 16];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf[..4], &12_i32.to_be_bytes());
+        assert_eq!(&buf[4..8], &7_i32.to_be_bytes());
+        assert_eq!(&buf[8..], b"response");
     }
 }
@@ -0,0 +1,236 @@
+//! Limits on the connections of each client IP address.
+//!
+//! A client reconnecting in a loop, or opening connections without closing
+//! them, could otherwise take every connection the listener accepts and
+//! lock the other clients out. The listener checks each accepted connection
+//! against the limits of its IP address, and closes it right away when they
+//! are exceeded. Limits may be changed while the broker runs, connections
+//! already open are kept.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+use super::quota::Rate;
+
+/// Open connections and connection creation rate of each IP address.
+///
+/// The quotas are a wrapper around an `Arc`, clones share the same counts.
+#[derive(Debug, Clone)]
+pub struct ConnectionQuotas {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    limits: Mutex<Limits>,
+    window_size: Duration,
+    window_num: u32,
+    hosts: Mutex<HashMap<IpAddr, Host>>,
+}
+
+#[derive(Debug)]
+struct Limits {
+    max_connections_per_ip: usize,
+    max_connections_per_ip_overrides: HashMap<IpAddr, usize>,
+    max_connection_creation_rate: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+struct Host {
+    connections: usize,
+    /// Connections created, rejected ones included so that a client
+    /// reconnecting in a loop stays rejected.
+    creations: Rate,
+}
+
+/// Why a connection was rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rejection {
+    /// The IP address already has `max` connections open.
+    TooManyConnections { max: usize },
+
+    /// The IP address creates connections faster than `max` per second.
+    CreationRateExceeded { max: f64 },
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::TooManyConnections { max } => {
+                write!(f, "more than {} connections open", max)
+            }
+            Rejection::CreationRateExceeded { max } => {
+                write!(f, "more than {} connections created per second", max)
+            }
+        }
+    }
+}
+
+/// A connection counted against the limits of its IP address, until it is
+/// dropped.
+#[derive(Debug)]
+pub struct IpConnection {
+    quotas: ConnectionQuotas,
+    ip: IpAddr,
+}
+
+impl ConnectionQuotas {
+    /// Quotas allowing `max_connections_per_ip` open connections to each IP
+    /// address but the ones with an override, and
+    /// `max_connection_creation_rate` new connections per second, measured
+    /// over `window_num` windows of `window_size`.
+    pub fn new(
+        max_connections_per_ip: usize,
+        max_connections_per_ip_overrides: HashMap<IpAddr, usize>,
+        max_connection_creation_rate: Option<f64>,
+        window_size: Duration,
+        window_num: u32,
+    ) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                limits: Mutex::new(Limits {
+                    max_connections_per_ip,
+                    max_connections_per_ip_overrides,
+                    max_connection_creation_rate,
+                }),
+                window_size,
+                window_num: window_num.max(2),
+                hosts: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Replace the limits. Addresses over the new limits keep their open
+    /// connections, but cannot open new ones until they are back under.
+    pub fn reconfigure(
+        &self,
+        max_connections_per_ip: usize,
+        max_connections_per_ip_overrides: HashMap<IpAddr, usize>,
+        max_connection_creation_rate: Option<f64>,
+    ) {
+        *self.shared.limits.lock().unwrap() = Limits {
+            max_connections_per_ip,
+            max_connections_per_ip_overrides,
+            max_connection_creation_rate,
+        };
+    }
+
+    /// Count a new connection from `ip`, unless it exceeds the limits of
+    /// the address.
+    pub fn open(&self, ip: IpAddr) -> Result<IpConnection, Rejection> {
+        let shared = &*self.shared;
+        let limits = shared.limits.lock().unwrap();
+        let now = Instant::now();
+        let window = shared.window_size * shared.window_num;
+        let mut hosts = shared.hosts.lock().unwrap();
+        if !hosts.contains_key(&ip) {
+            // Forget about the addresses without connections which have
+            // not connected for a whole window.
+            hosts.retain(|_, host| host.connections > 0 || !host.creations.is_idle(now, window));
+        }
+        let host = hosts.entry(ip).or_default();
+
+        if let Some(max) = limits.max_connection_creation_rate {
+            host.creations.record(1.0, now, shared.window_size, window);
+            let (total, elapsed) =
+                host.creations
+                    .measure(now, shared.window_size, shared.window_num);
+            if total / elapsed.as_secs_f64() > max {
+                return Err(Rejection::CreationRateExceeded { max });
+            }
+        }
+
+        let max = limits
+            .max_connections_per_ip_overrides
+            .get(&ip)
+            .copied()
+            .unwrap_or(limits.max_connections_per_ip);
+        if host.connections >= max {
+            return Err(Rejection::TooManyConnections { max });
+        }
+        host.connections += 1;
+
+        Ok(IpConnection {
+            quotas: self.clone(),
+            ip,
+        })
+    }
+
+    /// Number of open connections from `ip`.
+    pub fn connections(&self, ip: IpAddr) -> usize {
+        let hosts = self.shared.hosts.lock().unwrap();
+        hosts.get(&ip).map_or(0, |host| host.connections)
+    }
+}
+
+impl Drop for IpConnection {
+    fn drop(&mut self) {
+        let mut hosts = self.quotas.shared.hosts.lock().unwrap();
+        if let Some(host) = hosts.get_mut(&self.ip) {
+            host.connections -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_max_connections_per_ip() {
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let admin: IpAddr = "10.0.0.1".parse().unwrap();
+        let overrides = vec![(admin, 3)].into_iter().collect();
+        let quotas = ConnectionQuotas::new(2, overrides, None, Duration::from_secs(1), 11);
+
+        let first = quotas.open(local).unwrap();
+        let _second = quotas.open(local).unwrap();
+        assert_eq!(
+            quotas.open(local).unwrap_err(),
+            Rejection::TooManyConnections { max: 2 }
+        );
+        let _admin: Vec<_> = (0..3).map(|_| quotas.open(admin).unwrap()).collect();
+        assert!(quotas.open(admin).is_err());
+
+        // Closing a connection makes room for another one.
+        drop(first);
+        assert_eq!(quotas.connections(local), 1);
+        let third = quotas.open(local).unwrap();
+
+        // Open connections are kept when the limit is lowered.
+        quotas.reconfigure(1, HashMap::new(), None);
+        assert_eq!(quotas.connections(local), 2);
+        assert!(quotas.open(admin).is_err());
+        drop(third);
+        assert!(quotas.open(local).is_err());
+        quotas.reconfigure(3, HashMap::new(), None);
+        assert!(quotas.open(local).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_max_connection_creation_rate() {
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let other: IpAddr = "127.0.0.2".parse().unwrap();
+        let window_size = Duration::from_millis(50);
+        let quotas = ConnectionQuotas::new(usize::MAX, HashMap::new(), Some(20.0), window_size, 11);
+
+        // Half a second worth of connections are allowed at once, closed
+        // or not.
+        for _ in 0..10 {
+            quotas.open(local).unwrap();
+        }
+        assert_eq!(
+            quotas.open(local).unwrap_err(),
+            Rejection::CreationRateExceeded { max: 20.0 }
+        );
+        assert_eq!(quotas.connections(local), 0);
+        assert!(quotas.open(other).is_ok());
+
+        // Creations are forgotten once out of the window.
+        tokio::time::sleep(window_size * 11).await;
+        assert!(quotas.open(local).is_ok());
+    }
+}
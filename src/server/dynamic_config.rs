@@ -0,0 +1,815 @@
+//! Configuration changed while the broker runs.
+//!
+//! Some keys of the broker, and the configuration of each topic, can be
+//! changed with IncrementalAlterConfigs requests, without restarting the
+//! broker or reconnecting clients. Changes are persisted in a file of the
+//! log directory, and applied again when the broker restarts. They override
+//! the configuration the broker was started with, which may itself be
+//! reloaded, e.g. when the properties file changes.
+//!
+//! New values are applied to the partitions, which roll and delete their
+//! segments accordingly, and to the connection limits. Requests read the values of
+//! their topic as they are processed.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, Semaphore};
+use tracing::info;
+
+use super::broker_config::{validate_server, BrokerConfig};
+use super::connection_quotas::ConnectionQuotas;
+use super::persist;
+use super::protocol::describe_configs::ConfigSource;
+use super::Config;
+use crate::store::LogManager;
+
+/// Name of the file holding the changed configuration, in the log
+/// directory.
+const FILE_NAME: &str = "configs";
+
+/// Broker keys which can be changed while the broker runs.
+pub const DYNAMIC_KEYS: &[&str] = &[
+    "log.segment.bytes",
+    "log.roll.ms",
+    "log.roll.jitter.ms",
+    "log.preallocate",
+    "log.retention.ms",
+    "log.retention.bytes",
+    "log.message.timestamp.type",
+    "message.max.bytes",
+    "num.partitions",
+    "auto.create.topics.enable",
+    "max.connections",
+    "max.connections.per.ip",
+    "max.connections.per.ip.overrides",
+    "max.connection.creation.rate",
+];
+
+/// Topic keys, with the broker key providing their default value.
+const TOPIC_KEYS: &[(&str, &str)] = &[
+    ("segment.bytes", "log.segment.bytes"),
+    ("segment.ms", "log.roll.ms"),
+    ("segment.jitter.ms", "log.roll.jitter.ms"),
+    ("preallocate", "log.preallocate"),
+    ("retention.ms", "log.retention.ms"),
+    ("retention.bytes", "log.retention.bytes"),
+    ("message.timestamp.type", "log.message.timestamp.type"),
+    ("max.message.bytes", "message.max.bytes"),
+];
+
+/// Keys whose value is a comma-separated list, which items can be appended
+/// to and subtracted from.
+const LIST_KEYS: &[&str] = &["max.connections.per.ip.overrides"];
+
+/// Resource a configuration belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigResource {
+    Broker,
+    Topic(String),
+}
+
+/// Change of a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlterOp {
+    Set(String),
+    /// Remove the value, the key goes back to its default.
+    Delete,
+    /// Add the items of a comma-separated list to the value of a list key.
+    Append(String),
+    /// Remove the items of a comma-separated list from the value of a list
+    /// key.
+    Subtract(String),
+}
+
+/// A value of a key, and where it comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigValue {
+    pub name: String,
+    pub value: String,
+    pub source: ConfigSource,
+}
+
+/// Why the configuration of a resource cannot be described or altered.
+#[derive(Debug)]
+pub enum ConfigError {
+    UnknownTopic(String),
+
+    /// The key is unknown, cannot be changed at runtime, or the value is
+    /// invalid.
+    InvalidConfig(String),
+
+    /// The changes could not be persisted or applied.
+    Io(io::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnknownTopic(topic) => write!(f, "unknown topic {}", topic),
+            ConfigError::InvalidConfig(msg) => f.write_str(msg),
+            ConfigError::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+/// Values set at runtime, by key, as written in properties files.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Overrides {
+    broker: BTreeMap<String, String>,
+    topics: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Configuration of the broker, changed at runtime.
+///
+/// The configuration is a wrapper around an `Arc`, clones share the same
+/// values.
+#[derive(Debug, Clone)]
+pub struct DynamicConfig {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    path: PathBuf,
+    logs: LogManager,
+    connection_quotas: ConnectionQuotas,
+    limit_connections: Arc<Semaphore>,
+    /// Number of connections `limit_connections` allows, all of its
+    /// permits being given back.
+    max_connections: std::sync::Mutex<usize>,
+    /// Permits of `limit_connections` to take back as connections close,
+    /// after `max_connections` was lowered below the open connections.
+    owed_connections: AtomicUsize,
+    /// Held while changes are applied, so that they are applied in the
+    /// order they are made.
+    changes: Mutex<()>,
+    state: RwLock<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Configuration the broker was started or reloaded with.
+    base: Config,
+    overrides: Overrides,
+    /// `base` with the broker overrides.
+    broker: Arc<Config>,
+    /// `broker` with the overrides of each topic which has some.
+    topics: HashMap<String, Arc<Config>>,
+}
+
+impl DynamicConfig {
+    /// Load the changes stored in the log directory of `base`, and apply
+    /// them to the partitions of `logs`, to `connection_quotas` and to
+    /// `limit_connections`, the semaphore of the connections of the broker,
+    /// which has `base.max_connections` permits.
+    pub async fn open(
+        base: Config,
+        logs: LogManager,
+        connection_quotas: ConnectionQuotas,
+        limit_connections: Arc<Semaphore>,
+    ) -> io::Result<Self> {
+        let path = base.log_dir.join(FILE_NAME);
+        let overrides: Overrides = persist::load(&path)?;
+        let state = State::new(base, overrides).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid file {}: {}", path.display(), err),
+            )
+        })?;
+        info!(
+            path = %path.display(),
+            broker = state.overrides.broker.len(),
+            topics = state.overrides.topics.len(),
+            "loaded dynamic configs"
+        );
+
+        let config = Self {
+            shared: Arc::new(Shared {
+                path,
+                logs,
+                connection_quotas,
+                limit_connections,
+                max_connections: std::sync::Mutex::new(state.base.max_connections),
+                owed_connections: AtomicUsize::new(0),
+                changes: Mutex::new(()),
+                state: RwLock::new(state),
+            }),
+        };
+        config.apply().await?;
+        Ok(config)
+    }
+
+    /// Configuration of the broker.
+    pub fn config(&self) -> Arc<Config> {
+        self.shared.state.read().unwrap().broker.clone()
+    }
+
+    /// Configuration of `topic`, the one of the broker with the values set
+    /// for the topic.
+    pub fn topic_config(&self, topic: &str) -> Arc<Config> {
+        let state = self.shared.state.read().unwrap();
+        state.topics.get(topic).unwrap_or(&state.broker).clone()
+    }
+
+    /// Every key of `resource` which can be changed at runtime, with its
+    /// values by order of precedence, the first one being in effect.
+    pub fn describe(
+        &self,
+        resource: &ConfigResource,
+    ) -> Result<Vec<Vec<ConfigValue>>, ConfigError> {
+        let state = self.shared.state.read().unwrap();
+        match resource {
+            ConfigResource::Broker => Ok(DYNAMIC_KEYS
+                .iter()
+                .map(|name| state.broker_values(name))
+                .collect()),
+            ConfigResource::Topic(topic) => {
+                self.check_topic(topic)?;
+                let overrides = state.overrides.topics.get(topic);
+                Ok(TOPIC_KEYS
+                    .iter()
+                    .map(|(name, broker_key)| {
+                        let value = overrides.and_then(|overrides| overrides.get(*name));
+                        let topic_value = value.map(|value| ConfigValue {
+                            name: name.to_string(),
+                            value: value.clone(),
+                            source: ConfigSource::DynamicTopic,
+                        });
+                        topic_value
+                            .into_iter()
+                            .chain(state.broker_values(broker_key))
+                            .collect()
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    /// Apply `ops` to the keys of `resource`, all of them or none. When
+    /// `validate_only`, the changes are checked but not applied.
+    pub async fn alter(
+        &self,
+        resource: &ConfigResource,
+        ops: &[(String, AlterOp)],
+        validate_only: bool,
+    ) -> Result<(), ConfigError> {
+        let _changes = self.shared.changes.lock().await;
+        let (base, mut overrides) = {
+            let state = self.shared.state.read().unwrap();
+            (state.base.clone(), state.overrides.clone())
+        };
+
+        let (values, current) = match resource {
+            ConfigResource::Broker => (&mut overrides.broker, self.config()),
+            ConfigResource::Topic(topic) => {
+                self.check_topic(topic)?;
+                let values = overrides.topics.entry(topic.clone()).or_default();
+                (values, self.topic_config(topic))
+            }
+        };
+        for (name, op) in ops {
+            let key = match resource {
+                ConfigResource::Broker => DYNAMIC_KEYS.iter().find(|key| *key == name),
+                ConfigResource::Topic(_) => TOPIC_KEYS
+                    .iter()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, broker_key)| broker_key),
+            };
+            let key = key.ok_or_else(|| {
+                let known = BrokerConfig::default().get(name).is_some();
+                let msg = match resource {
+                    ConfigResource::Broker if known => {
+                        format!("{} cannot be changed without restarting", name)
+                    }
+                    _ => format!("unknown config key {}", name),
+                };
+                ConfigError::InvalidConfig(msg)
+            })?;
+
+            let value = match op {
+                AlterOp::Set(value) => value.clone(),
+                AlterOp::Delete => {
+                    values.remove(name);
+                    continue;
+                }
+                AlterOp::Append(items) | AlterOp::Subtract(items) => {
+                    if !LIST_KEYS.contains(key) {
+                        let msg = format!("{} is not a list", name);
+                        return Err(ConfigError::InvalidConfig(msg));
+                    }
+                    let value = values
+                        .get(name)
+                        .cloned()
+                        .unwrap_or_else(|| get(&current, key));
+                    alter_list(&value, items, matches!(op, AlterOp::Append(_)))
+                }
+            };
+            let mut config = BrokerConfig {
+                server: (*current).clone(),
+                ..BrokerConfig::default()
+            };
+            config
+                .set(key, &value)
+                .and_then(|()| validate_server(&config.server))
+                .map_err(|err| {
+                    let msg = format!("invalid value {:?} for {}: {}", value, name, err);
+                    ConfigError::InvalidConfig(msg)
+                })?;
+            values.insert(name.clone(), value);
+        }
+        overrides.topics.retain(|_, values| !values.is_empty());
+
+        let state = State::new(base, overrides).map_err(ConfigError::InvalidConfig)?;
+        if validate_only {
+            return Ok(());
+        }
+        persist::save(&self.shared.path, &state.overrides).map_err(ConfigError::Io)?;
+        *self.shared.state.write().unwrap() = state;
+        self.apply().await.map_err(ConfigError::Io)
+    }
+
+    /// Replace the configuration the broker was started with, keeping the
+    /// values set at runtime. Only the keys which can be changed at runtime
+    /// are taken into account.
+    pub async fn reload(&self, config: Config) -> Result<(), String> {
+        let _changes = self.shared.changes.lock().await;
+        let (base, overrides) = {
+            let state = self.shared.state.read().unwrap();
+            (state.base.clone(), state.overrides.clone())
+        };
+        let values = DYNAMIC_KEYS.iter().map(|name| (name, get(&config, name)));
+        let state = State::new(with_values(&base, values)?, overrides)?;
+        *self.shared.state.write().unwrap() = state;
+        self.apply().await.map_err(|err| err.to_string())
+    }
+
+    // Apply the current configuration to the partitions and the connection
+    // limits.
+    async fn apply(&self) -> io::Result<()> {
+        let (broker, topics) = {
+            let state = self.shared.state.read().unwrap();
+            let topics: HashMap<_, _> = state
+                .topics
+                .iter()
+                .map(|(topic, config)| (topic.clone(), config.log.clone()))
+                .collect();
+            (state.broker.clone(), topics)
+        };
+        self.shared.connection_quotas.reconfigure(
+            broker.max_connections_per_ip,
+            broker.max_connections_per_ip_overrides.clone(),
+            broker.max_connection_creation_rate,
+        );
+        self.resize_connections(broker.max_connections);
+        self.shared
+            .logs
+            .reconfigure(broker.log.clone(), topics)
+            .await
+            .map_err(|err| io::Error::other(err.to_string()))
+    }
+
+    // Give `limit_connections` as many permits as `max_connections`. The
+    // permits of open connections cannot be taken back right away: the
+    // connections are kept, and the permits are owed until they close, so
+    // that no connection is accepted until the broker is back under the
+    // limit. Raising the limit again first cancels what is owed.
+    fn resize_connections(&self, max_connections: usize) {
+        let limit = &self.shared.limit_connections;
+        let owed = &self.shared.owed_connections;
+        let mut current = self.shared.max_connections.lock().unwrap();
+        if max_connections > *current {
+            let added = max_connections - *current;
+            let cancelled = owed
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |owed| {
+                    Some(owed.saturating_sub(added))
+                })
+                .unwrap()
+                .min(added);
+            limit.add_permits(added - cancelled);
+        } else if max_connections < *current {
+            let excess = *current - max_connections;
+            let held = excess - limit.forget_permits(excess);
+            if held > 0 && owed.fetch_add(held, Ordering::SeqCst) == 0 {
+                tokio::spawn(take_back_connections(self.shared.clone()));
+            }
+        }
+        *current = max_connections;
+    }
+
+    fn check_topic(&self, topic: &str) -> Result<(), ConfigError> {
+        if self.shared.logs.topics().contains_key(topic) {
+            Ok(())
+        } else {
+            Err(ConfigError::UnknownTopic(topic.into()))
+        }
+    }
+}
+
+impl State {
+    fn new(base: Config, overrides: Overrides) -> Result<Self, String> {
+        let broker = Arc::new(with_values(&base, &overrides.broker)?);
+        let topics = overrides
+            .topics
+            .iter()
+            .map(|(topic, values)| {
+                let values = values.iter().filter_map(|(name, value)| {
+                    let (_, broker_key) = TOPIC_KEYS.iter().find(|(key, _)| key == name)?;
+                    Some((broker_key.to_string(), value.clone()))
+                });
+                let config = with_values(&broker, values)?;
+                Ok((topic.clone(), Arc::new(config)))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            base,
+            overrides,
+            broker,
+            topics,
+        })
+    }
+
+    // Values of the broker key `name`: set at runtime, in the
+    // configuration of the broker, and by default.
+    fn broker_values(&self, name: &str) -> Vec<ConfigValue> {
+        let value = |value: String, source| ConfigValue {
+            name: name.into(),
+            value,
+            source,
+        };
+        let mut values = vec![];
+        if let Some(dynamic) = self.overrides.broker.get(name) {
+            values.push(value(dynamic.clone(), ConfigSource::DynamicBroker));
+        }
+        let default = get(&Config::default(), name);
+        let base = get(&self.base, name);
+        if base != default {
+            values.push(value(base, ConfigSource::StaticBroker));
+        }
+        values.push(value(default, ConfigSource::Default));
+        values
+    }
+}
+
+// Take back the permits `shared` owes to its `limit_connections`, one at a
+// time as connections close. A permit acquired once nothing is owed any
+// more, the limit having been raised meanwhile, is given back.
+async fn take_back_connections(shared: Arc<Shared>) {
+    while shared.owed_connections.load(Ordering::SeqCst) > 0 {
+        // The semaphore is never closed.
+        let permit = shared.limit_connections.acquire().await.unwrap();
+        let paid =
+            shared
+                .owed_connections
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |owed| {
+                    owed.checked_sub(1)
+                });
+        if paid.is_ok() {
+            permit.forget();
+        }
+    }
+}
+
+// `config` with the broker keys of `values` set.
+fn with_values<K, V>(
+    config: &Config,
+    values: impl IntoIterator<Item = (K, V)>,
+) -> Result<Config, String>
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let mut broker_config = BrokerConfig {
+        server: config.clone(),
+        ..BrokerConfig::default()
+    };
+    for (name, value) in values {
+        let (name, value) = (name.as_ref(), value.as_ref());
+        broker_config
+            .set(name, value)
+            .map_err(|err| format!("invalid value {:?} for {}: {}", value, name, err))?;
+    }
+    validate_server(&broker_config.server)?;
+    Ok(broker_config.server)
+}
+
+// Value of the broker key `name` in `config`.
+fn get(config: &Config, name: &str) -> String {
+    let broker_config = BrokerConfig {
+        server: config.clone(),
+        ..BrokerConfig::default()
+    };
+    broker_config.get(name).unwrap_or_default()
+}
+
+// Add the comma-separated `items` to the list `value`, or remove them.
+fn alter_list(value: &str, items: &str, append: bool) -> String {
+    let split = |list: &str| -> Vec<String> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect()
+    };
+    let mut list = split(value);
+    for item in split(items) {
+        if append && !list.contains(&item) {
+            list.push(item);
+        } else if !append {
+            list.retain(|other| *other != item);
+        }
+    }
+    list.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::TimestampType;
+    use std::time::Duration;
+    use tokio::time;
+
+    async fn open(base: Config) -> (DynamicConfig, LogManager, ConnectionQuotas) {
+        let limit_connections = Arc::new(Semaphore::new(base.max_connections));
+        open_with_limit(base, limit_connections).await
+    }
+
+    async fn open_with_limit(
+        base: Config,
+        limit_connections: Arc<Semaphore>,
+    ) -> (DynamicConfig, LogManager, ConnectionQuotas) {
+        let logs = LogManager::open(&base.log_dir, base.log.clone()).unwrap();
+        let quotas = ConnectionQuotas::new(
+            base.max_connections_per_ip,
+            HashMap::new(),
+            None,
+            Duration::from_secs(1),
+            11,
+        );
+        let config = DynamicConfig::open(base, logs.clone(), quotas.clone(), limit_connections)
+            .await
+            .unwrap();
+        (config, logs, quotas)
+    }
+
+    fn set(name: &str, value: &str) -> (String, AlterOp) {
+        (name.into(), AlterOp::Set(value.into()))
+    }
+
+    #[tokio::test]
+    async fn test_alter_and_reopen() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let base = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            max_message_bytes: 2048,
+            ..Config::default()
+        };
+        let (config, logs, quotas) = open(base.clone()).await;
+        logs.create_topic("events", 1).unwrap();
+        let events = ConfigResource::Topic("events".into());
+
+        let broker_ops = [
+            set("max.connections.per.ip", "1"),
+            set("log.segment.bytes", "4096"),
+        ];
+        config
+            .alter(&ConfigResource::Broker, &broker_ops, false)
+            .await
+            .unwrap();
+        let topic_ops = [
+            set("message.timestamp.type", "LogAppendTime"),
+            set("segment.bytes", "1024"),
+            set("retention.bytes", "4096"),
+        ];
+        config.alter(&events, &topic_ops, false).await.unwrap();
+
+        assert_eq!(config.config().log.segment_bytes, 4096);
+        assert_eq!(config.config().max_message_bytes, 2048);
+        let topic = config.topic_config("events");
+        assert_eq!(topic.message_timestamp_type, TimestampType::LogAppendTime);
+        assert_eq!(topic.log.segment_bytes, 1024);
+        assert_eq!(logs.config("events").segment_bytes, 1024);
+        assert_eq!(logs.config("other").segment_bytes, 4096);
+        assert_eq!(logs.config("events").retention_bytes, Some(4096));
+        assert_eq!(logs.config("other").retention_bytes, None);
+        let local = "127.0.0.1".parse().unwrap();
+        let _connection = quotas.open(local).unwrap();
+        assert!(quotas.open(local).is_err());
+
+        // Values by order of precedence.
+        let described = config.describe(&events).unwrap();
+        let segment_bytes = &described[0];
+        assert_eq!(
+            segment_bytes
+                .iter()
+                .map(|value| (value.name.as_str(), value.value.as_str(), value.source))
+                .collect::<Vec<_>>(),
+            vec![
+                ("segment.bytes", "1024", ConfigSource::DynamicTopic),
+                ("log.segment.bytes", "4096", ConfigSource::DynamicBroker),
+                ("log.segment.bytes", "1073741824", ConfigSource::Default),
+            ]
+        );
+        let described = config.describe(&ConfigResource::Broker).unwrap();
+        let max_message_bytes = described
+            .iter()
+            .find(|values| values[0].name == "message.max.bytes")
+            .unwrap();
+        assert_eq!(max_message_bytes[0].source, ConfigSource::StaticBroker);
+        assert_eq!(max_message_bytes[0].value, "2048");
+
+        // Changes survive restarts.
+        drop((config, logs));
+        let (config, _, _) = open(base).await;
+        assert_eq!(config.config().log.segment_bytes, 4096);
+        assert_eq!(config.topic_config("events").log.segment_bytes, 1024);
+
+        let ops = [("segment.bytes".into(), AlterOp::Delete)];
+        config.alter(&events, &ops, false).await.unwrap();
+        assert_eq!(config.topic_config("events").log.segment_bytes, 4096);
+        assert_eq!(
+            config.topic_config("events").message_timestamp_type,
+            TimestampType::LogAppendTime
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_changes() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let base = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let (config, _logs, _) = open(base).await;
+        let error = |resource: ConfigResource, ops: &[(String, AlterOp)]| {
+            let config = config.clone();
+            let ops = ops.to_vec();
+            async move {
+                config
+                    .alter(&resource, &ops, false)
+                    .await
+                    .unwrap_err()
+                    .to_string()
+            }
+        };
+
+        assert_eq!(
+            error(ConfigResource::Broker, &[set("log.dirs", "/data")]).await,
+            "log.dirs cannot be changed without restarting"
+        );
+        assert_eq!(
+            error(ConfigResource::Broker, &[set("retention.ms", "10")]).await,
+            "unknown config key retention.ms"
+        );
+        assert_eq!(
+            error(ConfigResource::Topic("nope".into()), &[]).await,
+            "unknown topic nope"
+        );
+        // Nothing is applied when one of the changes is invalid.
+        let ops = [set("num.partitions", "3"), set("message.max.bytes", "0")];
+        assert_eq!(
+            error(ConfigResource::Broker, &ops).await,
+            "invalid value \"0\" for message.max.bytes: message.max.bytes must be positive"
+        );
+        assert_eq!(config.config().num_partitions, 1);
+
+        let ops = [("log.segment.bytes".into(), AlterOp::Append("1".into()))];
+        assert_eq!(
+            error(ConfigResource::Broker, &ops).await,
+            "log.segment.bytes is not a list"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_and_reload() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let base = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let (config, _logs, _) = open(base.clone()).await;
+
+        let key = "max.connections.per.ip.overrides";
+        let ops = [(key.into(), AlterOp::Append("10.0.0.1:5, 10.0.0.2:6".into()))];
+        config
+            .alter(&ConfigResource::Broker, &ops, false)
+            .await
+            .unwrap();
+        let ops = [(key.into(), AlterOp::Subtract("10.0.0.1:5".into()))];
+        config
+            .alter(&ConfigResource::Broker, &ops, false)
+            .await
+            .unwrap();
+        let overrides = &config.config().max_connections_per_ip_overrides;
+        assert_eq!(overrides.len(), 1);
+        assert_eq!(overrides[&"10.0.0.2".parse().unwrap()], 6);
+
+        // Values set at runtime win over the reloaded configuration.
+        let ops = [set("num.partitions", "3")];
+        config
+            .alter(&ConfigResource::Broker, &ops, true)
+            .await
+            .unwrap();
+        assert_eq!(config.config().num_partitions, 1);
+        config
+            .alter(&ConfigResource::Broker, &ops, false)
+            .await
+            .unwrap();
+        let reloaded = Config {
+            num_partitions: 6,
+            auto_create_topics: false,
+            max_connections: 10,
+            ..base
+        };
+        config.reload(reloaded).await.unwrap();
+        assert_eq!(config.config().num_partitions, 3);
+        assert!(!config.config().auto_create_topics);
+        assert_eq!(config.config().max_connections, 10);
+    }
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let base = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            max_connections: 4,
+            ..Config::default()
+        };
+        let limit = Arc::new(Semaphore::new(base.max_connections));
+        let (config, _logs, _) = open_with_limit(base, limit.clone()).await;
+        limit.acquire_many(3).await.unwrap().forget();
+
+        config
+            .alter(
+                &ConfigResource::Broker,
+                &[set("max.connections", "6")],
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(limit.available_permits(), 3);
+
+        // Open connections are kept, and their permits taken back as they
+        // close.
+        config
+            .alter(
+                &ConfigResource::Broker,
+                &[set("max.connections", "2")],
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(limit.available_permits(), 0);
+        limit.add_permits(1);
+        time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(limit.available_permits(), 0);
+        limit.add_permits(2);
+        time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(limit.available_permits(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_max_connections_raised_while_owed() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let base = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            max_connections: 4,
+            ..Config::default()
+        };
+        let limit = Arc::new(Semaphore::new(base.max_connections));
+        let (config, _logs, _) = open_with_limit(base, limit.clone()).await;
+        limit.acquire_many(3).await.unwrap().forget();
+
+        // One permit is owed by the open connections, and cancelled when the
+        // limit is raised again.
+        config
+            .alter(
+                &ConfigResource::Broker,
+                &[set("max.connections", "2")],
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(limit.available_permits(), 0);
+        config
+            .alter(
+                &ConfigResource::Broker,
+                &[set("max.connections", "6")],
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(limit.available_permits(), 3);
+        time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(limit.available_permits(), 3);
+
+        limit.add_permits(3);
+        time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(limit.available_permits(), 6);
+    }
+}
@@ -0,0 +1,181 @@
+//! Named listeners of the broker.
+//!
+//! The broker may listen on several addresses, e.g. one for the clients of
+//! the internal network and one for the outside world. Each listener has a
+//! name, a security protocol, and the address advertised to its clients in
+//! Metadata responses, which may differ from the address it is bound to,
+//! e.g. behind NAT or in containers.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How the connections of a listener are secured
+/// (`listener.security.protocol.map`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SecurityProtocol {
+    /// Neither encrypted nor authenticated.
+    Plaintext,
+    /// Encrypted with TLS, clients may authenticate with a certificate.
+    Ssl,
+    /// Clients authenticate with SASL, not encrypted.
+    SaslPlaintext,
+    /// Encrypted with TLS, clients authenticate with SASL.
+    SaslSsl,
+}
+
+impl SecurityProtocol {
+    pub const ALL: [SecurityProtocol; 4] = [
+        SecurityProtocol::Plaintext,
+        SecurityProtocol::Ssl,
+        SecurityProtocol::SaslPlaintext,
+        SecurityProtocol::SaslSsl,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SecurityProtocol::Plaintext => "PLAINTEXT",
+            SecurityProtocol::Ssl => "SSL",
+            SecurityProtocol::SaslPlaintext => "SASL_PLAINTEXT",
+            SecurityProtocol::SaslSsl => "SASL_SSL",
+        }
+    }
+
+    /// Whether connections are encrypted with TLS.
+    pub fn is_tls(self) -> bool {
+        self == SecurityProtocol::Ssl || self == SecurityProtocol::SaslSsl
+    }
+
+    /// Whether clients authenticate with SASL.
+    pub fn is_sasl(self) -> bool {
+        self == SecurityProtocol::SaslPlaintext || self == SecurityProtocol::SaslSsl
+    }
+}
+
+impl FromStr for SecurityProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|protocol| protocol.name() == s)
+            .ok_or_else(|| {
+                let expected = "expected PLAINTEXT, SSL, SASL_PLAINTEXT or SASL_SSL";
+                format!("invalid security protocol {:?}, {}", s, expected)
+            })
+    }
+}
+
+impl fmt::Display for SecurityProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Address of a listener, written `NAME://host:port` in `listeners` and
+/// `advertised.listeners`. IPv6 hosts are enclosed in brackets. The host
+/// may be empty to bind to every interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub listener_name: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid listener {:?}, expected NAME://host:port", s);
+        let (listener_name, address) = s.split_once("://").ok_or_else(invalid)?;
+        let (host, port) = address.rsplit_once(':').ok_or_else(invalid)?;
+        let host = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+        if listener_name.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            listener_name: listener_name.into(),
+            host: host.into(),
+            port: port.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "{}://[{}]:{}", self.listener_name, self.host, self.port)
+        } else {
+            write!(f, "{}://{}:{}", self.listener_name, self.host, self.port)
+        }
+    }
+}
+
+/// Configuration of a listener, served by `server::run` along with the
+/// socket it is bound to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerConfig {
+    /// Name of the listener, e.g. `INTERNAL`, unique to the broker.
+    pub name: String,
+
+    pub security_protocol: SecurityProtocol,
+
+    /// Host clients of the listener should connect to. Defaults to the host
+    /// the listener is bound to, or to the name of the machine when it is
+    /// bound to every interface. It cannot be the wildcard address.
+    pub advertised_host: Option<String>,
+
+    /// Port clients of the listener should connect to. Defaults to the port
+    /// the listener is bound to.
+    pub advertised_port: Option<u16>,
+}
+
+impl ListenerConfig {
+    /// Listener advertised at the address it is bound to.
+    pub fn new(name: impl Into<String>, security_protocol: SecurityProtocol) -> Self {
+        Self {
+            name: name.into(),
+            security_protocol,
+            advertised_host: None,
+            advertised_port: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_endpoint() {
+        let endpoint: Endpoint = "INTERNAL://0.0.0.0:9092".parse().unwrap();
+        assert_eq!(
+            endpoint,
+            Endpoint {
+                listener_name: "INTERNAL".into(),
+                host: "0.0.0.0".into(),
+                port: 9092,
+            }
+        );
+        let endpoint: Endpoint = "EXTERNAL://[::1]:9093".parse().unwrap();
+        assert_eq!(endpoint.host, "::1");
+        assert_eq!(endpoint.to_string(), "EXTERNAL://[::1]:9093");
+        let endpoint: Endpoint = "PLAINTEXT://:9092".parse().unwrap();
+        assert_eq!(endpoint.host, "");
+
+        assert!("0.0.0.0:9092".parse::<Endpoint>().is_err());
+        assert!("://0.0.0.0:9092".parse::<Endpoint>().is_err());
+        assert!("INTERNAL://0.0.0.0".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn test_parse_security_protocol() {
+        for protocol in SecurityProtocol::ALL.iter() {
+            assert_eq!(protocol.name().parse(), Ok(*protocol));
+        }
+        assert!("SASL".parse::<SecurityProtocol>().is_err());
+    }
+}
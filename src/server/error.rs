@@ -0,0 +1,184 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use crate::store::StoreError;
+
+/// Error codes sent to clients in responses.
+///
+/// The values are the ones used by the Kafka protocol, see
+/// https://kafka.apache.org/protocol#protocol_error_codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i16)]
+pub enum ErrorCode {
+    UnknownServerError = -1,
+    None = 0,
+    OffsetOutOfRange = 1,
+    CorruptMessage = 2,
+    UnknownTopicOrPartition = 3,
+    MessageTooLarge = 10,
+    InvalidTopicException = 17,
+    InvalidRequiredAcks = 21,
+    TopicAuthorizationFailed = 29,
+    ClusterAuthorizationFailed = 31,
+    InvalidConfig = 40,
+    UnsupportedSaslMechanism = 33,
+    IllegalSaslState = 34,
+    UnsupportedVersion = 35,
+    InvalidRequest = 42,
+    SecurityDisabled = 54,
+    KafkaStorageError = 56,
+    SaslAuthenticationFailed = 58,
+    UnsupportedCompressionType = 76,
+    InvalidRecord = 87,
+    ResourceNotFound = 91,
+    DuplicateResource = 92,
+    UnacceptableCredential = 93,
+}
+
+impl ErrorCode {
+    pub fn code(self) -> i16 {
+        self as i16
+    }
+}
+
+/// Errors raised while serving a request.
+///
+/// Every error maps to an `ErrorCode` so that handlers can report it to the
+/// client instead of closing the connection.
+#[derive(Debug)]
+pub enum Error {
+    /// The store failed to serve the request.
+    Store(StoreError),
+
+    /// The request targets a topic or a partition the broker does not host.
+    UnknownTopicOrPartition,
+
+    /// The record batch failed validation, e.g. its CRC does not match.
+    CorruptMessage(String),
+
+    /// The records are valid but rejected by the broker, e.g. their offsets
+    /// are not contiguous.
+    InvalidRecord(String),
+
+    /// The record batch is larger than the broker accepts.
+    MessageTooLarge,
+
+    /// The record batch uses a compression codec the broker does not support.
+    UnsupportedCompressionType,
+
+    /// The `acks` of a produce request is not 0, 1 or -1.
+    InvalidRequiredAcks(i16),
+
+    /// The request is malformed.
+    InvalidRequest(String),
+
+    /// The request is larger than the broker accepts. The connection is
+    /// closed, as the request cannot be skipped without reading it.
+    RequestTooLarge { size: usize, max: usize },
+
+    /// The client requested a SASL mechanism the broker does not enable.
+    UnsupportedSaslMechanism(String),
+
+    /// The request is not expected at this point of the SASL exchange.
+    IllegalSaslState(String),
+
+    /// The client failed to authenticate.
+    SaslAuthenticationFailed(String),
+
+    /// The principal of the connection may not perform the operation on the
+    /// topic.
+    TopicAuthorizationFailed,
+
+    /// The principal of the connection may not perform the operation on the
+    /// cluster.
+    ClusterAuthorizationFailed,
+
+    /// An I/O error occurred on the connection.
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Protocol error code reported to the client for this error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Store(err) => match err {
+                StoreError::SegmentFull => ErrorCode::MessageTooLarge,
+                StoreError::OffsetOutOfRange(_) => ErrorCode::OffsetOutOfRange,
+                StoreError::CorruptRecord(_) => ErrorCode::CorruptMessage,
+                StoreError::InvalidOffset { .. } => ErrorCode::CorruptMessage,
+                StoreError::IndexMismatch(_) => ErrorCode::KafkaStorageError,
+                StoreError::InvalidTopic(_) => ErrorCode::InvalidTopicException,
+                StoreError::UnsupportedFormat(_) => ErrorCode::KafkaStorageError,
+                StoreError::InvalidRequest(_) => ErrorCode::InvalidRequest,
+                StoreError::Io(_) => ErrorCode::KafkaStorageError,
+            },
+            Error::UnknownTopicOrPartition => ErrorCode::UnknownTopicOrPartition,
+            Error::CorruptMessage(_) => ErrorCode::CorruptMessage,
+            Error::InvalidRecord(_) => ErrorCode::InvalidRecord,
+            Error::MessageTooLarge => ErrorCode::MessageTooLarge,
+            Error::UnsupportedCompressionType => ErrorCode::UnsupportedCompressionType,
+            Error::InvalidRequiredAcks(_) => ErrorCode::InvalidRequiredAcks,
+            Error::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            Error::RequestTooLarge { .. } => ErrorCode::InvalidRequest,
+            Error::UnsupportedSaslMechanism(_) => ErrorCode::UnsupportedSaslMechanism,
+            Error::IllegalSaslState(_) => ErrorCode::IllegalSaslState,
+            Error::SaslAuthenticationFailed(_) => ErrorCode::SaslAuthenticationFailed,
+            Error::TopicAuthorizationFailed => ErrorCode::TopicAuthorizationFailed,
+            Error::ClusterAuthorizationFailed => ErrorCode::ClusterAuthorizationFailed,
+            Error::Io(_) => ErrorCode::UnknownServerError,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Store(err) => err.fmt(f),
+            Error::UnknownTopicOrPartition => write!(f, "unknown topic or partition"),
+            Error::CorruptMessage(msg) => write!(f, "corrupt message: {}", msg),
+            Error::InvalidRecord(msg) => write!(f, "invalid record: {}", msg),
+            Error::MessageTooLarge => write!(f, "message is too large"),
+            Error::UnsupportedCompressionType => write!(f, "unsupported compression type"),
+            Error::InvalidRequiredAcks(acks) => write!(f, "invalid required acks {}", acks),
+            Error::InvalidRequest(msg) => write!(f, "invalid request: {}", msg),
+            Error::RequestTooLarge { size, max } => write!(
+                f,
+                "request of {} bytes is larger than the maximum of {}",
+                size, max
+            ),
+            Error::UnsupportedSaslMechanism(mechanism) => {
+                write!(f, "unsupported SASL mechanism {}", mechanism)
+            }
+            Error::IllegalSaslState(msg) => write!(f, "illegal SASL state: {}", msg),
+            Error::SaslAuthenticationFailed(msg) => write!(f, "authentication failed: {}", msg),
+            Error::TopicAuthorizationFailed => write!(f, "not authorized to access topic"),
+            Error::ClusterAuthorizationFailed => write!(f, "not authorized to access cluster"),
+            Error::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Store(err) => Some(err),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<StoreError> for Error {
+    fn from(err: StoreError) -> Self {
+        Error::Store(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
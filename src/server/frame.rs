@@ -0,0 +1,102 @@
+use bytes::Bytes;
+
+use super::error::Result;
+use super::protocol::codec::{get_i16, get_i32, get_nullable_string};
+
+/// Header sent at the beginning of every request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestHeader {
+    /// Identifies the API of the request.
+    pub api_key: i16,
+    /// Version of the API used to encode the request.
+    pub api_version: i16,
+    /// Set by the client and echoed in the response, which lets the client
+    /// match responses with requests.
+    pub correlation_id: i32,
+    /// Logical name of the client, used in logs and quotas.
+    pub client_id: Option<String>,
+}
+
+/// A request frame: the header and the encoded request body.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub header: RequestHeader,
+    pub body: Bytes,
+}
+
+/// A response frame: the correlation id of the request and the encoded
+/// response body.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub correlation_id: i32,
+    pub body: Bytes,
+}
+
+impl Request {
+    /// Parse a request from a frame, without its size prefix.
+    ///
+    /// The client id is never encoded as a compact string, even in the
+    /// header of flexible requests. The tagged fields ending the header of
+    /// flexible requests are left in the body.
+    pub fn parse(mut frame: Bytes) -> Result<Request> {
+        let header = RequestHeader {
+            api_key: get_i16(&mut frame)?,
+            api_version: get_i16(&mut frame)?,
+            correlation_id: get_i32(&mut frame)?,
+            client_id: get_nullable_string(&mut frame, false)?,
+        };
+
+        Ok(Request {
+            header,
+            body: frame,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BufMut;
+
+    #[test]
+    fn test_parse() {
+        let mut frame = vec![];
+        frame.put_i16(18);
+        frame.put_i16(3);
+        frame.put_i32(42);
+        frame.put_i16(4);
+        frame.put_slice(b"kcat");
+        frame.put_slice(b"body");
+
+        let request = Request::parse(Bytes::from(frame)).unwrap();
+        assert_eq!(
+            request.header,
+            RequestHeader {
+                api_key: 18,
+                api_version: 3,
+                correlation_id: 42,
+                client_id: Some("kcat".into()),
+            }
+        );
+        assert_eq!(request.body, Bytes::from_static(b"body"));
+    }
+
+    #[test]
+    fn test_parse_null_client_id() {
+        let mut frame = vec![];
+        frame.put_i16(0);
+        frame.put_i16(0);
+        frame.put_i32(1);
+        frame.put_i16(-1);
+
+        let request = Request::parse(Bytes::from(frame)).unwrap();
+        assert_eq!(request.header.client_id, None);
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn test_parse_incomplete() {
+        let frame = Bytes::from_static(&[0, 1, 0, 0, 0]);
+        assert!(Request::parse(frame).is_err());
+    }
+}
@@ -0,0 +1,361 @@
+//! ACLs: authorization of requests, and the DescribeAcls, CreateAcls and
+//! DeleteAcls APIs.
+
+use bytes::Bytes;
+use std::sync::Arc;
+use tokio::time::Duration;
+use tracing::{debug, info};
+
+use crate::server::authorizer::{
+    AccessControlEntry, AclBinding, AclBindingFilter, AclOperation, AclPermissionType, Action,
+    Authorizer, PatternType, ResourcePattern, ResourceType, CLUSTER_NAME,
+};
+use crate::server::error::{Error, ErrorCode, Result};
+use crate::server::frame::RequestHeader;
+use crate::server::protocol::create_acls::{
+    AclCreationResult, CreateAclsRequest, CreateAclsResponse,
+};
+use crate::server::protocol::delete_acls::{
+    DeleteAclsFilterResult, DeleteAclsMatchingAcl, DeleteAclsRequest, DeleteAclsResponse,
+};
+use crate::server::protocol::describe_acls::{
+    AclDescription, DescribeAclsRequest, DescribeAclsResource, DescribeAclsResponse,
+};
+use crate::server::protocol::{self, ApiKey};
+
+use super::{throttle_time_ms, Context, ResultError};
+
+impl Context {
+    /// Whether the principal of the connection may perform `operation` on
+    /// the resource named `name`. Everything is allowed when authorization
+    /// is disabled.
+    pub(super) fn authorize(
+        &self,
+        operation: AclOperation,
+        resource_type: ResourceType,
+        name: &str,
+    ) -> bool {
+        let authorizer = match &self.authorizer {
+            Some(authorizer) => authorizer,
+            None => return true,
+        };
+        let action = Action::new(operation, resource_type, name);
+        let allowed = authorizer.authorize(&self.principal, self.peer_addr.ip(), &action);
+        if !allowed {
+            debug!(principal = %self.principal, ?action, "denied");
+        }
+        allowed
+    }
+
+    // Whether `topic` may be created, which is allowed by creating topics
+    // on the cluster or by creating this topic.
+    pub(super) fn authorize_create_topic(&self, topic: &str) -> bool {
+        self.authorize(AclOperation::Create, ResourceType::Cluster, CLUSTER_NAME)
+            || self.authorize(AclOperation::Create, ResourceType::Topic, topic)
+    }
+
+    // Authorizer managing the ACLs, if the principal may perform `operation`
+    // on the cluster.
+    fn acl_authorizer(
+        &self,
+        operation: AclOperation,
+    ) -> std::result::Result<&Arc<dyn Authorizer>, ResultError> {
+        let authorizer = self.authorizer.as_ref().ok_or_else(|| {
+            let msg = "no authorizer is configured".into();
+            (ErrorCode::SecurityDisabled, msg)
+        })?;
+        if !self.authorize(operation, ResourceType::Cluster, CLUSTER_NAME) {
+            let err = Error::ClusterAuthorizationFailed;
+            return Err((err.code(), err.to_string()));
+        }
+        Ok(authorizer)
+    }
+
+    /// Describe the ACLs matching the filter of the request, grouped by
+    /// resource pattern.
+    pub(super) fn describe_acls(
+        &self,
+        header: &RequestHeader,
+        body: Bytes,
+        throttle: Duration,
+    ) -> Result<Bytes> {
+        let key = ApiKey::DescribeAcls;
+        let request: DescribeAclsRequest = protocol::decode_request(key, header, body)?;
+
+        let res = self
+            .acl_authorizer(AclOperation::Describe)
+            .and_then(|authorizer| {
+                let filter = acl_filter(
+                    request.resource_type_filter,
+                    request.resource_name_filter,
+                    request.pattern_type_filter,
+                    request.principal_filter,
+                    request.host_filter,
+                    request.operation,
+                    request.permission_type,
+                )
+                .map_err(|msg| (ErrorCode::InvalidRequest, msg))?;
+                Ok(authorizer.describe_acls(&filter))
+            });
+
+        let mut response = DescribeAclsResponse {
+            throttle_time_ms: throttle_time_ms(throttle),
+            error_code: ErrorCode::None,
+            error_message: None,
+            resources: vec![],
+        };
+        match res {
+            // Bindings are sorted by pattern, so that the ones of a pattern
+            // are next to each other.
+            Ok(bindings) => {
+                for AclBinding { pattern, entry } in bindings {
+                    let acl = AclDescription {
+                        principal: entry.principal,
+                        host: entry.host,
+                        operation: entry.operation.code(),
+                        permission_type: entry.permission_type.code(),
+                    };
+                    match response.resources.last_mut() {
+                        Some(resource)
+                            if resource.resource_type == pattern.resource_type.code()
+                                && resource.resource_name == pattern.name
+                                && resource.pattern_type == pattern.pattern_type.code() =>
+                        {
+                            resource.acls.push(acl)
+                        }
+                        _ => response.resources.push(DescribeAclsResource {
+                            resource_type: pattern.resource_type.code(),
+                            resource_name: pattern.name,
+                            pattern_type: pattern.pattern_type.code(),
+                            acls: vec![acl],
+                        }),
+                    }
+                }
+            }
+            Err((error_code, msg)) => {
+                response.error_code = error_code;
+                response.error_message = Some(msg);
+            }
+        }
+        Ok(protocol::encode_response(key, header, &response))
+    }
+
+    /// Create the ACLs of the request.
+    ///
+    /// Errors are reported per ACL: invalid ACLs do not prevent the valid
+    /// ones from being created.
+    pub(super) fn create_acls(
+        &self,
+        header: &RequestHeader,
+        body: Bytes,
+        throttle: Duration,
+    ) -> Result<Bytes> {
+        let key = ApiKey::CreateAcls;
+        let request: CreateAclsRequest = protocol::decode_request(key, header, body)?;
+
+        let mut results: Vec<std::result::Result<Option<AclBinding>, ResultError>> = request
+            .creations
+            .into_iter()
+            .map(|creation| {
+                let binding = AclBinding {
+                    pattern: ResourcePattern {
+                        resource_type: enum_code(
+                            creation.resource_type,
+                            "resource type",
+                            ResourceType::from_code,
+                        )?,
+                        name: creation.resource_name,
+                        pattern_type: enum_code(
+                            creation.resource_pattern_type,
+                            "pattern type",
+                            PatternType::from_code,
+                        )?,
+                    },
+                    entry: AccessControlEntry {
+                        principal: creation.principal,
+                        host: creation.host,
+                        operation: enum_code(
+                            creation.operation,
+                            "operation",
+                            AclOperation::from_code,
+                        )?,
+                        permission_type: enum_code(
+                            creation.permission_type,
+                            "permission type",
+                            AclPermissionType::from_code,
+                        )?,
+                    },
+                };
+                binding.validate()?;
+                Ok(Some(binding))
+            })
+            .map(|res| res.map_err(|msg| (ErrorCode::InvalidRequest, msg)))
+            .collect();
+
+        match self.acl_authorizer(AclOperation::Alter) {
+            Ok(authorizer) => {
+                let bindings: Vec<_> = results
+                    .iter_mut()
+                    .filter_map(|res| res.as_mut().ok()?.take())
+                    .collect();
+                if !bindings.is_empty() {
+                    match authorizer.create_acls(&bindings) {
+                        Ok(()) => {
+                            info!(acls = ?bindings, principal = %self.principal, "created ACLs")
+                        }
+                        Err(err) => {
+                            let err = (ErrorCode::KafkaStorageError, err.to_string());
+                            for res in results.iter_mut().filter(|res| res.is_ok()) {
+                                *res = Err(err.clone());
+                            }
+                        }
+                    }
+                }
+            }
+            Err(err) => results = results.iter().map(|_| Err(err.clone())).collect(),
+        }
+
+        let results = results
+            .into_iter()
+            .map(|res| match res {
+                Ok(_) => AclCreationResult {
+                    error_code: ErrorCode::None,
+                    error_message: None,
+                },
+                Err((error_code, msg)) => AclCreationResult {
+                    error_code,
+                    error_message: Some(msg),
+                },
+            })
+            .collect();
+
+        let response = CreateAclsResponse {
+            throttle_time_ms: throttle_time_ms(throttle),
+            results,
+        };
+        Ok(protocol::encode_response(key, header, &response))
+    }
+
+    /// Delete the ACLs matching the filters of the request, reporting the
+    /// ACLs deleted by each filter.
+    pub(super) fn delete_acls(
+        &self,
+        header: &RequestHeader,
+        body: Bytes,
+        throttle: Duration,
+    ) -> Result<Bytes> {
+        let key = ApiKey::DeleteAcls;
+        let request: DeleteAclsRequest = protocol::decode_request(key, header, body)?;
+
+        let mut results: Vec<std::result::Result<Vec<AclBinding>, ResultError>> = vec![];
+        let mut filters = vec![];
+        for filter in request.filters {
+            let filter = acl_filter(
+                filter.resource_type_filter,
+                filter.resource_name_filter,
+                filter.pattern_type_filter,
+                filter.principal_filter,
+                filter.host_filter,
+                filter.operation,
+                filter.permission_type,
+            );
+            match filter {
+                Ok(filter) => {
+                    filters.push(filter);
+                    results.push(Ok(vec![]));
+                }
+                Err(msg) => results.push(Err((ErrorCode::InvalidRequest, msg))),
+            }
+        }
+
+        let deleted = self
+            .acl_authorizer(AclOperation::Alter)
+            .and_then(|authorizer| {
+                authorizer
+                    .delete_acls(&filters)
+                    .map_err(|err| (ErrorCode::KafkaStorageError, err.to_string()))
+            });
+        match deleted {
+            Ok(deleted) => {
+                let acls: Vec<_> = deleted.iter().flatten().collect();
+                if !acls.is_empty() {
+                    info!(?acls, principal = %self.principal, "deleted ACLs");
+                }
+                let mut deleted = deleted.into_iter();
+                for res in results.iter_mut().filter(|res| res.is_ok()) {
+                    *res = Ok(deleted.next().unwrap_or_default());
+                }
+            }
+            Err(err) => results = results.iter().map(|_| Err(err.clone())).collect(),
+        }
+
+        let filter_results = results
+            .into_iter()
+            .map(|res| match res {
+                Ok(bindings) => DeleteAclsFilterResult {
+                    error_code: ErrorCode::None,
+                    error_message: None,
+                    matching_acls: bindings
+                        .into_iter()
+                        .map(|AclBinding { pattern, entry }| DeleteAclsMatchingAcl {
+                            error_code: ErrorCode::None,
+                            error_message: None,
+                            resource_type: pattern.resource_type.code(),
+                            resource_name: pattern.name,
+                            pattern_type: pattern.pattern_type.code(),
+                            principal: entry.principal,
+                            host: entry.host,
+                            operation: entry.operation.code(),
+                            permission_type: entry.permission_type.code(),
+                        })
+                        .collect(),
+                },
+                Err((error_code, msg)) => DeleteAclsFilterResult {
+                    error_code,
+                    error_message: Some(msg),
+                    matching_acls: vec![],
+                },
+            })
+            .collect();
+
+        let response = DeleteAclsResponse {
+            throttle_time_ms: throttle_time_ms(throttle),
+            filter_results,
+        };
+        Ok(protocol::encode_response(key, header, &response))
+    }
+}
+
+// Value of an enum of the ACL APIs, e.g. a resource type, from its code.
+fn enum_code<T>(
+    code: i8,
+    what: &str,
+    from_code: fn(i8) -> Option<T>,
+) -> std::result::Result<T, String> {
+    from_code(code).ok_or_else(|| format!("unknown {} {}", what, code))
+}
+
+// Filter of a DescribeAcls or DeleteAcls request.
+fn acl_filter(
+    resource_type: i8,
+    name: Option<String>,
+    pattern_type: i8,
+    principal: Option<String>,
+    host: Option<String>,
+    operation: i8,
+    permission_type: i8,
+) -> std::result::Result<AclBindingFilter, String> {
+    Ok(AclBindingFilter {
+        resource_type: enum_code(resource_type, "resource type", ResourceType::from_code)?,
+        name,
+        pattern_type: enum_code(pattern_type, "pattern type", PatternType::from_code)?,
+        principal,
+        host,
+        operation: enum_code(operation, "operation", AclOperation::from_code)?,
+        permission_type: enum_code(
+            permission_type,
+            "permission type",
+            AclPermissionType::from_code,
+        )?,
+    })
+}
@@ -0,0 +1,30 @@
+//! ApiVersions: the APIs and versions supported by the broker.
+
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::server::error::{ErrorCode, Result};
+use crate::server::frame::RequestHeader;
+use crate::server::protocol::api_versions::{ApiVersionsRequest, ApiVersionsResponse};
+use crate::server::protocol::{self, ApiKey};
+
+use super::{unsupported_version, Context};
+
+impl Context {
+    /// Answer an ApiVersions request with the APIs supported by the broker.
+    pub(super) fn api_versions(&self, header: &RequestHeader, body: Bytes) -> Result<Bytes> {
+        let key = ApiKey::ApiVersions;
+
+        // Clients may try a version newer than the ones the broker supports,
+        // and retry with a supported version.
+        if !key.supports(header.api_version) {
+            return Ok(unsupported_version(header).body);
+        }
+
+        let request: ApiVersionsRequest = protocol::decode_request(key, header, body)?;
+        debug!(?request);
+
+        let response = ApiVersionsResponse::supported(ErrorCode::None);
+        Ok(protocol::encode_response(key, header, &response))
+    }
+}
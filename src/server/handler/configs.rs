@@ -0,0 +1,247 @@
+//! DescribeConfigs and IncrementalAlterConfigs: broker and topic configs.
+
+use bytes::Bytes;
+use tokio::time::Duration;
+use tracing::info;
+
+use crate::server::authorizer::{AclOperation, ResourceType, CLUSTER_NAME};
+use crate::server::dynamic_config::{AlterOp, ConfigError, ConfigResource, ConfigValue};
+use crate::server::error::{Error, ErrorCode, Result};
+use crate::server::frame::RequestHeader;
+use crate::server::protocol::describe_configs::{
+    DescribeConfigsRequest, DescribeConfigsResourceResult, DescribeConfigsResponse,
+    DescribeConfigsResult, DescribeConfigsSynonym, RESOURCE_BROKER, RESOURCE_TOPIC,
+};
+use crate::server::protocol::incremental_alter_configs::{
+    AlterConfigsResourceResponse, AlterableConfig, IncrementalAlterConfigsRequest,
+    IncrementalAlterConfigsResponse, OP_APPEND, OP_DELETE, OP_SET, OP_SUBTRACT,
+};
+use crate::server::protocol::{self, ApiKey};
+
+use super::{throttle_time_ms, Context, ResultError};
+
+impl Context {
+    /// Describe the configuration of brokers and topics. Only the keys which
+    /// can be changed at runtime are described.
+    ///
+    /// Errors are reported per resource.
+    pub(super) fn describe_configs(
+        &self,
+        header: &RequestHeader,
+        body: Bytes,
+        throttle: Duration,
+    ) -> Result<Bytes> {
+        let key = ApiKey::DescribeConfigs;
+        let DescribeConfigsRequest {
+            resources,
+            include_synonyms,
+            ..
+        } = protocol::decode_request(key, header, body)?;
+
+        let results = resources
+            .into_iter()
+            .map(|resource| {
+                let res = self
+                    .config_resource(
+                        resource.resource_type,
+                        &resource.resource_name,
+                        AclOperation::DescribeConfigs,
+                    )
+                    .and_then(|config_resource| {
+                        self.dynamic_config
+                            .describe(&config_resource)
+                            .map_err(config_error)
+                    });
+                let (error_code, error_message, configs) = match res {
+                    Ok(described) => {
+                        let configs = described
+                            .into_iter()
+                            .filter(|values| match &resource.configuration_keys {
+                                Some(keys) => keys.contains(&values[0].name),
+                                None => true,
+                            })
+                            .map(|values| config_result(values, include_synonyms))
+                            .collect();
+                        (ErrorCode::None, None, configs)
+                    }
+                    Err((error_code, msg)) => (error_code, Some(msg), vec![]),
+                };
+                DescribeConfigsResult {
+                    error_code,
+                    error_message,
+                    resource_type: resource.resource_type,
+                    resource_name: resource.resource_name,
+                    configs,
+                }
+            })
+            .collect();
+
+        let response = DescribeConfigsResponse {
+            throttle_time_ms: throttle_time_ms(throttle),
+            results,
+        };
+        Ok(protocol::encode_response(key, header, &response))
+    }
+
+    /// Change some keys of brokers and topics.
+    ///
+    /// Errors are reported per resource: the changes of a resource are only
+    /// applied if all of them are valid.
+    pub(super) async fn incremental_alter_configs(
+        &self,
+        header: &RequestHeader,
+        body: Bytes,
+        throttle: Duration,
+    ) -> Result<Bytes> {
+        let key = ApiKey::IncrementalAlterConfigs;
+        let IncrementalAlterConfigsRequest {
+            resources,
+            validate_only,
+        } = protocol::decode_request(key, header, body)?;
+
+        let mut responses = Vec::with_capacity(resources.len());
+        for resource in resources {
+            let res = self.config_resource(
+                resource.resource_type,
+                &resource.resource_name,
+                AclOperation::AlterConfigs,
+            );
+            let res = match res.and_then(|r| Ok((r, alter_ops(&resource.configs)?))) {
+                Ok((config_resource, ops)) => self
+                    .dynamic_config
+                    .alter(&config_resource, &ops, validate_only)
+                    .await
+                    .map(|()| {
+                        if !validate_only {
+                            info!(resource = ?config_resource, ?ops, principal = %self.principal, "altered configs");
+                        }
+                    })
+                    .map_err(config_error),
+                Err(err) => Err(err),
+            };
+            let (error_code, error_message) = match res {
+                Ok(()) => (ErrorCode::None, None),
+                Err((error_code, msg)) => (error_code, Some(msg)),
+            };
+            responses.push(AlterConfigsResourceResponse {
+                error_code,
+                error_message,
+                resource_type: resource.resource_type,
+                resource_name: resource.resource_name,
+            });
+        }
+
+        let response = IncrementalAlterConfigsResponse {
+            throttle_time_ms: throttle_time_ms(throttle),
+            responses,
+        };
+        Ok(protocol::encode_response(key, header, &response))
+    }
+
+    // Resource of a DescribeConfigs or IncrementalAlterConfigs request, if
+    // the principal may perform `operation` on it. The broker is named
+    // after its id, or empty for the default configuration of every broker,
+    // which is the same thing for a single broker.
+    fn config_resource(
+        &self,
+        resource_type: i8,
+        name: &str,
+        operation: AclOperation,
+    ) -> std::result::Result<ConfigResource, ResultError> {
+        match resource_type {
+            RESOURCE_BROKER => {
+                if !name.is_empty() && name != self.config.broker_id.to_string() {
+                    return Err((
+                        ErrorCode::InvalidRequest,
+                        format!("unknown broker {}", name),
+                    ));
+                }
+                if !self.authorize(operation, ResourceType::Cluster, CLUSTER_NAME) {
+                    let err = Error::ClusterAuthorizationFailed;
+                    return Err((err.code(), err.to_string()));
+                }
+                Ok(ConfigResource::Broker)
+            }
+            RESOURCE_TOPIC => {
+                if !self.authorize(operation, ResourceType::Topic, name) {
+                    let err = Error::TopicAuthorizationFailed;
+                    return Err((err.code(), err.to_string()));
+                }
+                Ok(ConfigResource::Topic(name.into()))
+            }
+            _ => Err((
+                ErrorCode::InvalidRequest,
+                format!("unsupported resource type {}", resource_type),
+            )),
+        }
+    }
+}
+
+// Config of a DescribeConfigs response, from the values of a key by order
+// of precedence.
+fn config_result(
+    values: Vec<ConfigValue>,
+    include_synonyms: bool,
+) -> DescribeConfigsResourceResult {
+    let synonyms = match include_synonyms {
+        true => values
+            .iter()
+            .map(|value| DescribeConfigsSynonym {
+                name: value.name.clone(),
+                value: Some(value.value.clone()),
+                source: value.source,
+            })
+            .collect(),
+        false => vec![],
+    };
+    let ConfigValue {
+        name,
+        value,
+        source,
+    } = values.into_iter().next().unwrap();
+    DescribeConfigsResourceResult {
+        name,
+        value: Some(value),
+        read_only: false,
+        config_source: source,
+        is_sensitive: false,
+        synonyms,
+    }
+}
+
+// Changes of the configs of an IncrementalAlterConfigs resource. Each key
+// is changed at most once.
+fn alter_ops(
+    configs: &[AlterableConfig],
+) -> std::result::Result<Vec<(String, AlterOp)>, ResultError> {
+    let invalid = |msg: String| Err((ErrorCode::InvalidRequest, msg));
+    let mut ops = Vec::with_capacity(configs.len());
+    for (i, config) in configs.iter().enumerate() {
+        if configs[..i].iter().any(|c| c.name == config.name) {
+            return invalid(format!("{} is changed more than once", config.name));
+        }
+        let value = config.value.clone();
+        let op = match (config.config_operation, value) {
+            (OP_DELETE, _) => AlterOp::Delete,
+            (OP_SET, Some(value)) => AlterOp::Set(value),
+            (OP_APPEND, Some(value)) => AlterOp::Append(value),
+            (OP_SUBTRACT, Some(value)) => AlterOp::Subtract(value),
+            (OP_SET, None) | (OP_APPEND, None) | (OP_SUBTRACT, None) => {
+                return invalid(format!("no value for {}", config.name))
+            }
+            (op, _) => return invalid(format!("unknown config operation {}", op)),
+        };
+        ops.push((config.name.clone(), op));
+    }
+    Ok(ops)
+}
+
+// Error code and message of a config which cannot be described or altered.
+fn config_error(err: ConfigError) -> ResultError {
+    let code = match &err {
+        ConfigError::UnknownTopic(_) => ErrorCode::UnknownTopicOrPartition,
+        ConfigError::InvalidConfig(_) => ErrorCode::InvalidConfig,
+        ConfigError::Io(_) => ErrorCode::KafkaStorageError,
+    };
+    (code, err.to_string())
+}
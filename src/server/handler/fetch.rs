@@ -0,0 +1,231 @@
+//! Fetch: read records, waiting for them in the fetch purgatory.
+
+use bytes::{Bytes, BytesMut};
+use tokio::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::server::authorizer::{AclOperation, ResourceType};
+use crate::server::error::{ErrorCode, Result};
+use crate::server::frame::RequestHeader;
+use crate::server::protocol::fetch::{
+    FetchPartition, FetchRequest, FetchResponse, FetchableTopicResponse, PartitionData,
+};
+use crate::server::protocol::records;
+use crate::server::protocol::{self, ApiKey};
+use crate::server::purgatory::DelayedOperation;
+use crate::server::quota::QuotaType;
+use crate::store::partition::Partition;
+use crate::store::TopicPartition;
+
+use super::{percent, throttle_time_ms, Context};
+
+impl Context {
+    /// Read records for a Fetch request.
+    ///
+    /// When less than `min_bytes` are available, the request is parked in
+    /// the fetch purgatory until records are appended to one of its
+    /// partitions or `max_wait_ms` elapses. Errors are answered right away.
+    ///
+    /// Only the time spent reading partitions counts as handler time for
+    /// request quotas, not the time spent waiting for records.
+    pub(super) async fn fetch(
+        &self,
+        header: &RequestHeader,
+        body: Bytes,
+        throttle: &mut Duration,
+    ) -> Result<Bytes> {
+        let key = ApiKey::Fetch;
+        let request: FetchRequest = protocol::decode_request(key, header, body)?;
+        let max_wait = Duration::from_millis(request.max_wait_ms.max(0) as u64);
+
+        let fetch = DelayedFetch {
+            context: self,
+            deadline: Instant::now() + max_wait,
+            request,
+            busy: Duration::ZERO,
+        };
+        let (responses, busy) = self.fetch_purgatory.complete(fetch).await;
+
+        let mut size = 0;
+        for topic in &responses {
+            let topic_size: usize = topic
+                .partitions
+                .iter()
+                .map(|data| data.records.as_ref().map_or(0, Bytes::len))
+                .sum();
+            if topic_size > 0 {
+                self.metrics.record_bytes_out(&topic.name, topic_size);
+            }
+            size += topic_size;
+        }
+        self.record_usage(QuotaType::RequestPercentage, header, percent(busy));
+        let fetch_throttle = self.record_usage(QuotaType::ConsumerByteRate, header, size as f64);
+        *throttle = (*throttle).max(fetch_throttle);
+
+        let response = FetchResponse {
+            throttle_time_ms: throttle_time_ms(*throttle),
+            error_code: ErrorCode::None,
+            session_id: 0,
+            responses,
+        };
+        Ok(protocol::encode_response(key, header, &response))
+    }
+
+    // Read every partition of a Fetch request, returning the responses, the
+    // number of bytes read and whether reading any partition failed.
+    async fn read_partitions(
+        &self,
+        request: &FetchRequest,
+    ) -> (Vec<FetchableTopicResponse>, usize, bool) {
+        let mut remaining = request.max_bytes.max(0) as usize;
+        let mut size = 0;
+        let mut failed = false;
+
+        let mut responses = Vec::with_capacity(request.topics.len());
+        for topic in &request.topics {
+            let authorized = self.authorize(AclOperation::Read, ResourceType::Topic, &topic.name);
+            let mut partitions = Vec::with_capacity(topic.partitions.len());
+            for fetch in &topic.partitions {
+                if !authorized {
+                    failed = true;
+                    partitions.push(PartitionData::error(
+                        fetch.index,
+                        ErrorCode::TopicAuthorizationFailed,
+                    ));
+                    continue;
+                }
+
+                let tp = TopicPartition::new(topic.name.as_str(), fetch.index);
+                let partition = match self.logs.get(&tp) {
+                    Some(partition) => partition,
+                    None => {
+                        failed = true;
+                        partitions.push(PartitionData::error(
+                            fetch.index,
+                            ErrorCode::UnknownTopicOrPartition,
+                        ));
+                        continue;
+                    }
+                };
+
+                let data = match self.read_partition(&partition, fetch, remaining).await {
+                    Ok(data) => data,
+                    Err(err) => {
+                        debug!(%tp, cause = %err, "fetch failed");
+                        PartitionData::error(fetch.index, err.code())
+                    }
+                };
+                if data.error_code != ErrorCode::None {
+                    failed = true;
+                }
+                let read = data.records.as_ref().map_or(0, |r| r.len());
+                remaining = remaining.saturating_sub(read);
+                size += read;
+                partitions.push(data);
+            }
+            responses.push(FetchableTopicResponse {
+                name: topic.name.clone(),
+                partitions,
+            });
+        }
+
+        (responses, size, failed)
+    }
+
+    // Read the records of a partition from the fetch offset, up to the
+    // partition limit and `max_bytes`. Once `max_bytes` is exhausted, other
+    // partitions return no record.
+    async fn read_partition(
+        &self,
+        partition: &Partition,
+        fetch: &FetchPartition,
+        max_bytes: usize,
+    ) -> Result<PartitionData> {
+        let high_watermark = partition.end_offset() as i64;
+        let log_start_offset = partition.start_offset() as i64;
+        let mut data = PartitionData {
+            index: fetch.index,
+            error_code: ErrorCode::None,
+            high_watermark,
+            last_stable_offset: last_stable_offset(partition),
+            log_start_offset,
+            records: None,
+        };
+
+        if fetch.fetch_offset < log_start_offset || fetch.fetch_offset > high_watermark {
+            data.error_code = ErrorCode::OffsetOutOfRange;
+            return Ok(data);
+        }
+        let max_bytes = max_bytes.min(fetch.partition_max_bytes.max(0) as usize);
+        if max_bytes == 0 {
+            data.records = Some(Bytes::new());
+            return Ok(data);
+        }
+
+        let max_bytes = max_bytes.min(u32::MAX as usize) as u32;
+        let (entries, stored) = partition
+            .read_bytes(fetch.fetch_offset as u32, max_bytes)
+            .await?;
+
+        let mut buf = BytesMut::new();
+        for batch in records::batches_from_stored(&entries, Bytes::from(stored))? {
+            batch.encode(&mut buf);
+        }
+        data.records = Some(buf.freeze());
+        Ok(data)
+    }
+}
+
+/// A Fetch request waiting for `min_bytes` to be available in its
+/// partitions.
+struct DelayedFetch<'a> {
+    context: &'a Context,
+    request: FetchRequest,
+    deadline: Instant,
+    /// Time spent reading partitions so far.
+    busy: Duration,
+}
+
+impl DelayedOperation for DelayedFetch<'_> {
+    type Key = TopicPartition;
+    /// The responses, and the time spent reading partitions.
+    type Output = (Vec<FetchableTopicResponse>, Duration);
+
+    fn keys(&self) -> Vec<TopicPartition> {
+        self.request
+            .topics
+            .iter()
+            .flat_map(|topic| {
+                topic
+                    .partitions
+                    .iter()
+                    .map(move |fetch| TopicPartition::new(topic.name.as_str(), fetch.index))
+            })
+            .collect()
+    }
+
+    fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    async fn try_complete(&mut self) -> Option<Self::Output> {
+        let started = Instant::now();
+        let (responses, size, failed) = self.context.read_partitions(&self.request).await;
+        self.busy += started.elapsed();
+        let min_bytes = self.request.min_bytes.max(0) as usize;
+        (failed || size >= min_bytes || Instant::now() >= self.deadline)
+            .then_some((responses, self.busy))
+    }
+
+    async fn on_expiration(&mut self) -> Self::Output {
+        let started = Instant::now();
+        let responses = self.context.read_partitions(&self.request).await.0;
+        (responses, self.busy + started.elapsed())
+    }
+}
+
+// Offset up to which records are stable, i.e. not part of an open
+// transaction. There are no transactions, so every record is stable.
+pub(super) fn last_stable_offset(partition: &Partition) -> i64 {
+    partition.end_offset() as i64
+}
@@ -0,0 +1,114 @@
+//! ListOffsets: look up offsets by timestamp.
+
+use bytes::Bytes;
+use tokio::time::Duration;
+use tracing::debug;
+
+use crate::server::authorizer::{AclOperation, ResourceType};
+use crate::server::error::{Error, ErrorCode, Result};
+use crate::server::frame::RequestHeader;
+use crate::server::protocol::list_offsets::{
+    IsolationLevel, ListOffsetsPartitionResponse, ListOffsetsRequest, ListOffsetsResponse,
+    ListOffsetsTopicResponse, EARLIEST_TIMESTAMP, LATEST_TIMESTAMP, MAX_TIMESTAMP,
+};
+use crate::server::protocol::{self, ApiKey};
+use crate::store::TopicPartition;
+
+use super::fetch::last_stable_offset;
+use super::{throttle_time_ms, Context};
+
+impl Context {
+    /// Look up the offsets of partitions at the requested timestamps.
+    ///
+    /// Besides the earliest, latest and max timestamp lookups, timestamps
+    /// are compared to the time at which records were appended, which is the
+    /// one kept in the index, whatever the timestamp type of the records.
+    pub(super) async fn list_offsets(
+        &self,
+        header: &RequestHeader,
+        body: Bytes,
+        throttle: Duration,
+    ) -> Result<Bytes> {
+        let key = ApiKey::ListOffsets;
+        let request: ListOffsetsRequest = protocol::decode_request(key, header, body)?;
+
+        let mut topics = Vec::with_capacity(request.topics.len());
+        for topic in request.topics {
+            let authorized =
+                self.authorize(AclOperation::Describe, ResourceType::Topic, &topic.name);
+            let mut partitions = Vec::with_capacity(topic.partitions.len());
+            for lookup in topic.partitions {
+                let index = lookup.partition_index;
+                let tp = TopicPartition::new(topic.name.as_str(), index);
+                let res = if authorized {
+                    self.list_offset(&tp, lookup.timestamp, request.isolation_level)
+                        .await
+                } else {
+                    Err(Error::TopicAuthorizationFailed)
+                };
+                let response = res.unwrap_or_else(|err| {
+                    debug!(%tp, cause = %err, "list offsets failed");
+                    ListOffsetsPartitionResponse::error(index, err.code())
+                });
+                partitions.push(response);
+            }
+            topics.push(ListOffsetsTopicResponse {
+                name: topic.name,
+                partitions,
+            });
+        }
+
+        let response = ListOffsetsResponse {
+            throttle_time_ms: throttle_time_ms(throttle),
+            topics,
+        };
+        Ok(protocol::encode_response(key, header, &response))
+    }
+
+    async fn list_offset(
+        &self,
+        tp: &TopicPartition,
+        timestamp: i64,
+        isolation_level: IsolationLevel,
+    ) -> Result<ListOffsetsPartitionResponse> {
+        let partition = self.logs.get(tp).ok_or(Error::UnknownTopicOrPartition)?;
+
+        // Consumers reading committed records must not see offsets past the
+        // last stable offset.
+        let end_offset = match isolation_level {
+            IsolationLevel::ReadUncommitted => partition.end_offset() as i64,
+            IsolationLevel::ReadCommitted => last_stable_offset(&partition),
+        };
+
+        let (timestamp, offset) = match timestamp {
+            LATEST_TIMESTAMP => (-1, end_offset),
+            EARLIEST_TIMESTAMP => (-1, partition.start_offset() as i64),
+            timestamp => {
+                let entry = match timestamp {
+                    MAX_TIMESTAMP => partition.max_timestamp().await?,
+                    timestamp if timestamp < 0 => {
+                        return Err(Error::InvalidRequest(format!(
+                            "invalid timestamp {}",
+                            timestamp
+                        )))
+                    }
+                    timestamp => partition.find_timestamp(timestamp).await?,
+                };
+                match entry {
+                    Some(entry) if (entry.offset as i64) < end_offset => {
+                        (entry.timestamp, entry.offset as i64)
+                    }
+                    _ => (-1, -1),
+                }
+            }
+        };
+
+        Ok(ListOffsetsPartitionResponse {
+            partition_index: tp.partition,
+            error_code: ErrorCode::None,
+            timestamp,
+            offset,
+            leader_epoch: 0,
+        })
+    }
+}
@@ -0,0 +1,100 @@
+//! Metadata: describe the brokers and the topics.
+
+use bytes::Bytes;
+use tokio::time::Duration;
+
+use crate::server::authorizer::{AclOperation, ResourceType};
+use crate::server::error::{ErrorCode, Result};
+use crate::server::frame::RequestHeader;
+use crate::server::protocol::metadata::{
+    MetadataRequest, MetadataResponse, MetadataResponseBroker, MetadataResponsePartition,
+    MetadataResponseTopic,
+};
+use crate::server::protocol::{self, ApiKey};
+
+use super::{throttle_time_ms, Context};
+
+impl Context {
+    /// Describe the brokers of the cluster and the requested topics, or
+    /// every topic the principal may describe if none is requested.
+    ///
+    /// Requested topics that do not exist are created when both the request
+    /// and the broker allow it.
+    pub(super) fn metadata(
+        &self,
+        header: &RequestHeader,
+        body: Bytes,
+        throttle: Duration,
+    ) -> Result<Bytes> {
+        let key = ApiKey::Metadata;
+        let request: MetadataRequest = protocol::decode_request(key, header, body)?;
+        let auto_create =
+            request.allow_auto_topic_creation && self.dynamic_config.config().auto_create_topics;
+
+        let names = request.topics.unwrap_or_else(|| {
+            let mut names = self.metadata.topics();
+            names.retain(|name| self.authorize(AclOperation::Describe, ResourceType::Topic, name));
+            names
+        });
+        let topics = names
+            .into_iter()
+            .map(|name| self.describe_topic(name, auto_create))
+            .collect();
+
+        let brokers = self
+            .metadata
+            .brokers(&self.listener.name)
+            .into_iter()
+            .map(|broker| MetadataResponseBroker {
+                node_id: broker.node_id,
+                host: broker.host,
+                port: broker.port,
+                rack: broker.rack,
+            })
+            .collect();
+
+        let response = MetadataResponse {
+            throttle_time_ms: throttle_time_ms(throttle),
+            brokers,
+            cluster_id: Some(self.metadata.cluster_id().into()),
+            controller_id: self.metadata.controller_id(),
+            topics,
+        };
+        Ok(protocol::encode_response(key, header, &response))
+    }
+
+    fn describe_topic(&self, name: String, auto_create: bool) -> MetadataResponseTopic {
+        if !self.authorize(AclOperation::Describe, ResourceType::Topic, &name) {
+            return MetadataResponseTopic::error(name, ErrorCode::TopicAuthorizationFailed);
+        }
+        if auto_create
+            && self.metadata.partitions(&name).is_none()
+            && self.authorize_create_topic(&name)
+        {
+            if let Err(err) = self.create_topic(&name) {
+                return MetadataResponseTopic::error(name, err.code());
+            }
+        }
+
+        let partitions = match self.metadata.partitions(&name) {
+            Some(partitions) => partitions,
+            None => return MetadataResponseTopic::error(name, ErrorCode::UnknownTopicOrPartition),
+        };
+        MetadataResponseTopic {
+            error_code: ErrorCode::None,
+            name,
+            is_internal: false,
+            partitions: partitions
+                .into_iter()
+                .map(|partition| MetadataResponsePartition {
+                    error_code: ErrorCode::None,
+                    partition_index: partition.index,
+                    leader_id: partition.leader,
+                    leader_epoch: partition.leader_epoch,
+                    replica_nodes: partition.replicas,
+                    isr_nodes: partition.isr,
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,1525 @@
+mod acls;
+mod api_versions;
+mod configs;
+mod fetch;
+mod list_offsets;
+mod metadata;
+mod produce;
+mod quotas;
+mod sasl;
+mod scram;
+
+use bytes::BytesMut;
+use futures::stream::{FuturesOrdered, StreamExt};
+use std::io;
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch, Semaphore};
+use tokio::time::{self, Duration, Instant};
+use tracing::{debug, info, instrument, warn};
+
+use super::authorizer::Authorizer;
+use super::connection::Connection;
+use super::connection_quotas::IpConnection;
+use super::dynamic_config::DynamicConfig;
+use super::endpoint::ListenerConfig;
+use super::error::{Error, ErrorCode, Result};
+use super::frame::{Request, RequestHeader, Response};
+use super::listener::PeerAddr;
+use super::metadata::MetadataCache;
+use super::metrics::{ActiveConnection, Metrics};
+use super::principal::Principal;
+use super::protocol::api_versions::ApiVersionsResponse;
+use super::protocol::{ApiKey, Encode};
+use super::purgatory::Purgatory;
+use super::quota::{QuotaManager, QuotaType};
+use super::sasl::CredentialStore;
+use super::shutdown::Shutdown;
+use super::Config;
+use crate::store::partition::Partition;
+use crate::store::{LogManager, TopicPartition};
+use sasl::SaslState;
+
+/// State shared by the requests of a connection, used to apply them.
+///
+/// Several requests of a connection may be in flight at once: they only
+/// borrow the context, while the handler keeps reading and writing frames.
+#[derive(Debug)]
+pub struct Context {
+    /// Shared partitions handle.
+    ///
+    /// When a request is received from `connection`, it is applied to the
+    /// partitions of `logs`. The messages of each API are defined in the
+    /// `protocol` module.
+    pub logs: LogManager,
+
+    /// Broker-wide metadata cache, describing the brokers and the topics.
+    pub metadata: MetadataCache,
+
+    /// Listener the client connected to. Its security protocol tells
+    /// whether the client authenticates, and brokers are advertised to the
+    /// client at the address of their listener of the same name.
+    pub listener: Arc<ListenerConfig>,
+
+    /// SCRAM credentials clients authenticate with.
+    pub credentials: CredentialStore,
+
+    /// Decides which requests the principal may perform. `None` when
+    /// authorization is disabled, in which case everything is allowed.
+    pub authorizer: Option<Arc<dyn Authorizer>>,
+
+    /// Quotas clients are throttled with, and their usage.
+    pub quotas: QuotaManager,
+
+    /// Fetch requests parked until records are appended to one of their
+    /// partitions.
+    pub fetch_purgatory: Purgatory<TopicPartition>,
+
+    /// Server configuration, as the broker was started with.
+    pub config: Arc<Config>,
+
+    /// Configuration of the broker and of the topics, with the values
+    /// changed at runtime. Keys in `DYNAMIC_KEYS` are read from here.
+    pub dynamic_config: DynamicConfig,
+
+    /// Identity of the client, established when it connected or
+    /// authenticated.
+    pub principal: Principal,
+
+    /// Address of the peer. Its IP address is the host ACLs match.
+    pub peer_addr: PeerAddr,
+
+    /// Broker metrics, recorded as requests are processed.
+    pub metrics: Metrics,
+}
+
+/// Per-connection handler. Reads requests from `connection` and applies them
+/// with `context`.
+#[derive(Debug)]
+pub struct Handler {
+    /// State used to apply requests.
+    pub context: Context,
+
+    /// The TCP connection decorated with the protocol encoder / decoder
+    /// implemented using a buffered `TcpStream`.
+    ///
+    /// When `Listener` receives an inbound connection, the `TcpStream` is
+    /// passed to `Connection::new`, which initializes the associated buffers.
+    /// `Connection` allows the handler to operate at the "frame" level and keep
+    /// the byte level protocol parsing details encapsulated in `Connection`.
+    pub connection: Connection,
+
+    /// Max connection semaphore.
+    ///
+    /// When the handler is dropped, a permit is returned to this semaphore. If
+    /// the listener is waiting for connections to close, it will be notified of
+    /// the newly available permit and resume accepting connections.
+    pub limit_connections: Arc<Semaphore>,
+
+    /// Max connection semaphore of the listener, given a permit back as
+    /// well.
+    pub limit_listener_connections: Arc<Semaphore>,
+
+    /// Counts the connection against the limits of the IP address of the
+    /// peer. Dropped, and thus released, with the handler.
+    pub _ip_connection: IpConnection,
+
+    /// Counts the connection in the metrics of its listener until the
+    /// handler is dropped.
+    pub _active_connection: ActiveConnection,
+
+    /// Listen for shutdown notifications.
+    ///
+    /// A wrapper around the `broadcast::Receiver` paired with the sender in
+    /// `Listener`. The connection handler processes requests from the
+    /// connection until the peer disconnects **or** a shutdown notification is
+    /// received from `shutdown`. In the latter case, any in-flight work being
+    /// processed for the peer is continued until it reaches a safe state, at
+    /// which point the connection is terminated.
+    pub shutdown: Shutdown,
+
+    /// Not used directly. Instead, when `Handler` is dropped...?
+    pub _shutdown_complete: mpsc::Sender<()>,
+}
+
+impl Handler {
+    /// Process a single connection.
+    ///
+    /// Request frames are read from the socket and processed. Responses are
+    /// written back to the socket.
+    ///
+    /// Requests are pipelined: up to `max_in_flight_requests` requests are
+    /// read ahead and processed concurrently, so that a long polling fetch
+    /// does not hold back the requests sent after it. Responses are still
+    /// written in the order the requests were received, which is what
+    /// clients expect when matching them by correlation id.
+    ///
+    /// Connections are closed when they are idle for `connections_max_idle`,
+    /// when they send a request larger than `max_request_size`, or when
+    /// their responses stall for `write_stall_timeout`, so that misbehaving
+    /// peers do not hold memory and a connection permit forever.
+    ///
+    /// When the security protocol of the listener uses SASL, clients
+    /// authenticate first, see `authenticate`.
+    ///
+    /// When the shutdown signal is received, no further request is read, but
+    /// the requests in flight, such as produce requests, are still processed
+    /// and answered before the connection is terminated.
+    #[instrument(skip(self))]
+    pub async fn run(&mut self) -> Result<()> {
+        let max_in_flight = self.context.config.max_in_flight_requests.max(1);
+        let max_idle = self.context.config.connections_max_idle;
+        let peer_addr = self.context.peer_addr;
+
+        if self.context.listener.security_protocol.is_sasl() {
+            match self.authenticate().await? {
+                Some(principal) => {
+                    debug!(peer = %peer_addr, %principal, "authenticated");
+                    self.context.principal = principal;
+                }
+                None => return Ok(()),
+            }
+        }
+
+        // Borrow the fields separately, so that in-flight requests can
+        // borrow the context while frames are read and written.
+        let context = &self.context;
+        let connection = &mut self.connection;
+        let shutdown = &mut self.shutdown;
+
+        // Number of produce requests applied so far, see `apply_in_order`.
+        let (produced, _) = watch::channel(0);
+        // Requests being processed. `FuturesOrdered` yields their results in
+        // the order they were pushed, whichever completes first.
+        let mut in_flight = FuturesOrdered::new();
+        let mut produce_requests = 0;
+        let mut reading = true;
+
+        // Read new request frames and write the responses of completed ones,
+        // until the peer closes the socket or the shutdown signal is received.
+        loop {
+            // No further request is read and every response was written.
+            // There is no further work to do and the task can be terminated.
+            if !reading && in_flight.is_empty() {
+                return Ok(());
+            }
+
+            let idle_deadline = connection.last_activity() + max_idle;
+
+            tokio::select! {
+                // Read a new frame only when there is room for one more
+                // request in flight.
+                res = connection.read_frame(), if reading && in_flight.len() < max_in_flight => {
+                    // If `None` is returned from `read_frame()` then the peer
+                    // closed the socket. Requests already read are still
+                    // answered.
+                    let request = match res {
+                        Ok(Some(request)) => request,
+                        Ok(None) => {
+                            reading = false;
+                            continue;
+                        }
+                        Err(err @ Error::RequestTooLarge { .. }) => {
+                            warn!(peer = %peer_addr, cause = %err, "closing connection");
+                            return Ok(());
+                        }
+                        Err(err) => return Err(err),
+                    };
+
+                    // Logs the request header. `tracing` provides structured
+                    // logging, so information is "logged" as key-value pairs.
+                    debug!(header = ?request.header);
+
+                    let ticket = if request.header.api_key == ApiKey::Produce as i16 {
+                        produce_requests += 1;
+                        Some(produce_requests - 1)
+                    } else {
+                        None
+                    };
+                    in_flight.push_back(context.apply_in_order(request, ticket, &produced));
+                }
+                Some(res) = in_flight.next() => {
+                    // Some requests, such as produce requests with `acks=0`,
+                    // are not answered.
+                    if let Some(response) = res? {
+                        match connection.write_frame(&response).await {
+                            Ok(()) => {}
+                            Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                                warn!(peer = %peer_addr, cause = %err, "closing connection whose responses are not read");
+                                return Ok(());
+                            }
+                            Err(err) => return Err(err.into()),
+                        }
+                    }
+                }
+                // Requests in flight, such as long polling fetches, keep the
+                // connection busy. Reading a frame may have been active since
+                // the deadline was computed, so it is checked again.
+                _ = time::sleep_until(idle_deadline), if in_flight.is_empty() => {
+                    if connection.last_activity() + max_idle <= Instant::now() {
+                        info!(peer = %peer_addr, idle = ?max_idle, "closing idle connection");
+                        return Ok(());
+                    }
+                }
+                _ = shutdown.recv(), if !shutdown.is_shutdown() => {
+                    // If a shutdown signal is received, stop reading. The
+                    // task terminates once the requests in flight are
+                    // answered. Fetch requests waiting for records are woken
+                    // up by the purgatory.
+                    reading = false;
+                }
+            }
+        }
+    }
+
+    /// Run the SASL exchange of the connection. Returns the principal of
+    /// the client, or `None` when the connection is to be closed.
+    ///
+    /// Requests are processed one at a time until the client is
+    /// authenticated. Only ApiVersions requests, then a SaslHandshake
+    /// request and the SaslAuthenticate requests of the exchange are
+    /// accepted: other requests close the connection right away, and
+    /// failures once their response is written.
+    async fn authenticate(&mut self) -> Result<Option<Principal>> {
+        let max_idle = self.context.config.connections_max_idle;
+        let mut state = SaslState::Handshake;
+
+        loop {
+            let idle_deadline = self.connection.last_activity() + max_idle;
+            let request = tokio::select! {
+                res = self.connection.read_frame() => match res {
+                    Ok(Some(request)) => request,
+                    Ok(None) => return Ok(None),
+                    Err(err @ Error::RequestTooLarge { .. }) => {
+                        warn!(peer = %self.context.peer_addr, cause = %err, "closing connection");
+                        return Ok(None);
+                    }
+                    Err(err) => return Err(err),
+                },
+                _ = time::sleep_until(idle_deadline) => {
+                    info!(peer = %self.context.peer_addr, idle = ?max_idle, "closing idle connection");
+                    return Ok(None);
+                }
+                _ = self.shutdown.recv() => return Ok(None),
+            };
+            debug!(header = ?request.header);
+
+            let response = match ApiKey::from_i16(request.header.api_key) {
+                Some(ApiKey::ApiVersions) => self.context.apply(request).await?,
+                Some(ApiKey::SaslHandshake) | Some(ApiKey::SaslAuthenticate) => {
+                    Some(self.context.sasl(request, &mut state).await?)
+                }
+                _ => {
+                    warn!(
+                        peer = %self.context.peer_addr,
+                        api_key = request.header.api_key,
+                        "closing connection sending requests before authenticating"
+                    );
+                    return Ok(None);
+                }
+            };
+            if let Some(response) = response {
+                self.connection.write_frame(&response).await?;
+            }
+
+            match state {
+                SaslState::Authenticated(principal) => return Ok(Some(principal)),
+                SaslState::Failed(err) => {
+                    warn!(peer = %self.context.peer_addr, cause = %err, "authentication failed");
+                    return Ok(None);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Context {
+    /// Apply `request`. Produce requests are given consecutive tickets and
+    /// applied one at a time, in ticket order, so that the records produced
+    /// on a connection are appended in the order they were sent even when
+    /// several produce requests are in flight.
+    async fn apply_in_order(
+        &self,
+        request: Request,
+        ticket: Option<u64>,
+        produced: &watch::Sender<u64>,
+    ) -> Result<Option<Response>> {
+        let ticket = match ticket {
+            Some(ticket) => ticket,
+            None => return self.apply(request).await,
+        };
+
+        // The sender outlives every request, so waiting cannot fail.
+        let _ = produced.subscribe().wait_for(|n| *n == ticket).await;
+        let res = self.apply(request).await;
+        produced.send_replace(ticket + 1);
+        res
+    }
+
+    /// Process a request and build the response to send back to the peer.
+    ///
+    /// Requests for an API or a version the broker does not support cannot
+    /// be decoded. They are answered with an `UnsupportedVersion` error in a
+    /// version 0 ApiVersions response, the one response every client can
+    /// parse, listing the versions the broker supports. The connection stays
+    /// open, and the client may retry with a supported version.
+    async fn apply(&self, request: Request) -> Result<Option<Response>> {
+        let Request { header, body } = request;
+
+        let key = match ApiKey::from_i16(header.api_key) {
+            Some(key) if key == ApiKey::ApiVersions || key.supports(header.api_version) => key,
+            _ => return Ok(Some(unsupported_version(&header))),
+        };
+
+        // Clients over their request quota are throttled, given the time
+        // spent on their previous requests. Clients may send requests before
+        // knowing the broker or authenticating, whatever their quota.
+        let started = Instant::now();
+        let quota_exempt = matches!(
+            key,
+            ApiKey::ApiVersions | ApiKey::SaslHandshake | ApiKey::SaslAuthenticate
+        );
+        let mut throttle = match quota_exempt {
+            true => Duration::ZERO,
+            false => self.record_usage(QuotaType::RequestPercentage, &header, 0.0),
+        };
+
+        let body = match key {
+            ApiKey::Produce => self.produce(&header, body, &mut throttle).await?,
+            ApiKey::Fetch => Some(self.fetch(&header, body, &mut throttle).await?),
+            ApiKey::ListOffsets => Some(self.list_offsets(&header, body, throttle).await?),
+            ApiKey::Metadata => Some(self.metadata(&header, body, throttle)?),
+            ApiKey::ApiVersions => Some(self.api_versions(&header, body)?),
+            // The client is already authenticated, or SASL is not enabled.
+            ApiKey::SaslHandshake | ApiKey::SaslAuthenticate => {
+                let request = Request { header, body };
+                let mut state = SaslState::Authenticated(self.principal.clone());
+                return self.sasl(request, &mut state).await.map(Some);
+            }
+            ApiKey::DescribeUserScramCredentials => {
+                Some(self.describe_user_scram_credentials(&header, body, throttle)?)
+            }
+            ApiKey::AlterUserScramCredentials => {
+                Some(self.alter_user_scram_credentials(&header, body, throttle)?)
+            }
+            ApiKey::DescribeAcls => Some(self.describe_acls(&header, body, throttle)?),
+            ApiKey::CreateAcls => Some(self.create_acls(&header, body, throttle)?),
+            ApiKey::DeleteAcls => Some(self.delete_acls(&header, body, throttle)?),
+            ApiKey::DescribeClientQuotas => {
+                Some(self.describe_client_quotas(&header, body, throttle)?)
+            }
+            ApiKey::AlterClientQuotas => Some(self.alter_client_quotas(&header, body, throttle)?),
+            ApiKey::DescribeConfigs => Some(self.describe_configs(&header, body, throttle)?),
+            ApiKey::IncrementalAlterConfigs => Some(
+                self.incremental_alter_configs(&header, body, throttle)
+                    .await?,
+            ),
+        };
+
+        // Fetches record their handler time themselves.
+        if !quota_exempt && key != ApiKey::Fetch {
+            let busy = percent(started.elapsed());
+            self.record_usage(QuotaType::RequestPercentage, &header, busy);
+        }
+        self.metrics.record_request(key, started.elapsed());
+        // The response is delayed as well as sent with the throttle time, so
+        // that clients ignoring it are throttled too. Responses are written
+        // in order, which holds back the next requests of the connection.
+        if !throttle.is_zero() {
+            debug!(principal = %self.principal, client_id = ?header.client_id, ?throttle, "throttled");
+            time::sleep(throttle).await;
+        }
+
+        Ok(body.map(|body| Response {
+            correlation_id: header.correlation_id,
+            body,
+        }))
+    }
+}
+
+/// Error reported for one of the results of a request, such as the SCRAM
+/// credentials of a user or an ACL.
+type ResultError = (ErrorCode, String);
+
+// Response to a request for an API or a version the broker does not support:
+// an `UnsupportedVersion` error in a version 0 ApiVersions response, which
+// every client can parse.
+fn unsupported_version(header: &RequestHeader) -> Response {
+    let mut buf = BytesMut::new();
+    ApiVersionsResponse::supported(ErrorCode::UnsupportedVersion).encode(&mut buf, 0);
+    Response {
+        correlation_id: header.correlation_id,
+        body: buf.freeze(),
+    }
+}
+
+// Throttle time of a response, in milliseconds.
+fn throttle_time_ms(throttle: Duration) -> i32 {
+    throttle.as_millis().min(i32::MAX as u128) as i32
+}
+
+// Handler time, in percents of a second, the unit of request quotas.
+fn percent(busy: Duration) -> f64 {
+    busy.as_secs_f64() * 100.0
+}
+
+impl Context {
+    // Partition `tp`. Its topic is created if it does not exist, topics are
+    // created automatically and the principal may create it.
+    fn partition(&self, tp: &TopicPartition) -> Result<Partition> {
+        if let Some(partition) = self.logs.get(tp) {
+            return Ok(partition);
+        }
+
+        if self.dynamic_config.config().auto_create_topics
+            && self.metadata.partitions(&tp.topic).is_none()
+            && self.authorize_create_topic(&tp.topic)
+        {
+            self.create_topic(&tp.topic)?;
+        }
+        self.logs.get(tp).ok_or(Error::UnknownTopicOrPartition)
+    }
+
+    // Create `topic` with the default number of partitions, and add it to
+    // the metadata cache.
+    fn create_topic(&self, topic: &str) -> Result<()> {
+        let num_partitions = self.dynamic_config.config().num_partitions;
+        self.logs.create_topic(topic, num_partitions)?;
+        let num_partitions = self.logs.topics().get(topic).copied().unwrap_or(0);
+        self.metadata.update_topic(topic, num_partitions);
+        Ok(())
+    }
+}
+
+impl Drop for Handler {
+    fn drop(&mut self) {
+        // Add a permit back to the semaphores.
+        //
+        // Doing so unblocks the listener if the max number of
+        // connections has been reached.
+        //
+        // This is done in a `Drop` implementation in order to guarantee that
+        // the permit is added even if the task handling the connection panics.
+        // If `add_permit` was called at the end of the `run` function and some
+        // bug causes a panic. The permit would never be returned to the
+        // semaphore.
+        self.limit_listener_connections.add_permits(1);
+        self.limit_connections.add_permits(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::authorizer::{AclOperation, AclPermissionType, PatternType, ResourceType};
+    use crate::server::endpoint::SecurityProtocol;
+    use crate::server::protocol;
+    use crate::server::quota::QuotaEntity;
+    use crate::server::sasl::{Mechanism, ScramCredential, ScramMechanism};
+    use crate::server::{self, protocol::codec::*};
+    use bytes::{BufMut, Bytes};
+    use futures::stream;
+    use std::net::SocketAddr;
+    use tempfile::tempdir;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::oneshot;
+
+    // Start a server with a single listener, whose clients authenticate
+    // when SASL mechanisms are enabled.
+    async fn start_server(config: Config) -> (SocketAddr, oneshot::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let protocol = if config.sasl_enabled_mechanisms.is_empty() {
+            SecurityProtocol::Plaintext
+        } else {
+            SecurityProtocol::SaslPlaintext
+        };
+        let listener_config = ListenerConfig::new(protocol.name(), protocol);
+        let (stop, stopped) = oneshot::channel();
+        tokio::spawn(server::run(
+            vec![(listener_config, listener)],
+            config,
+            stream::pending(),
+            stopped,
+        ));
+        (addr, stop)
+    }
+
+    async fn send(
+        stream: &mut (impl AsyncWrite + Unpin),
+        api_key: ApiKey,
+        version: i16,
+        id: i32,
+        body: &[u8],
+    ) {
+        let mut frame = BytesMut::new();
+        frame.put_i32(10 + body.len() as i32);
+        frame.put_i16(api_key as i16);
+        frame.put_i16(version);
+        frame.put_i32(id);
+        frame.put_i16(-1);
+        frame.put_slice(body);
+        stream.write_all(&frame).await.unwrap();
+    }
+
+    async fn recv(stream: &mut (impl AsyncRead + Unpin)) -> (i32, Bytes) {
+        let size = stream.read_i32().await.unwrap();
+        let mut frame = vec![0; size as usize];
+        stream.read_exact(&mut frame).await.unwrap();
+        let mut frame = Bytes::from(frame);
+        (get_i32(&mut frame).unwrap(), frame)
+    }
+
+    // Fetch v4 request for partition 0 of `topic` from offset 0.
+    fn fetch_request(topic: &str, max_wait_ms: i32) -> BytesMut {
+        let mut body = BytesMut::new();
+        body.put_i32(-1);
+        body.put_i32(max_wait_ms);
+        body.put_i32(1);
+        body.put_i32(1024);
+        body.put_i8(0);
+        put_array(&mut body, &[topic], false, |buf, topic| {
+            put_string(buf, topic, false);
+            put_array(buf, &[0], false, |buf, partition| {
+                buf.put_i32(*partition);
+                buf.put_i64(0);
+                buf.put_i32(1024);
+            });
+        });
+        body
+    }
+
+    #[tokio::test]
+    async fn test_pipelining() {
+        let tmp_dir = tempdir().unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let (addr, _stop) = start_server(config).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        // Create the topic, then send a long polling fetch on the empty
+        // partition followed by an ApiVersions request.
+        let mut body = BytesMut::new();
+        put_array(&mut body, &["events"], false, |buf, topic| {
+            put_string(buf, topic, false)
+        });
+        body.put_i8(1);
+        send(&mut stream, ApiKey::Metadata, 4, 1, &body).await;
+        assert_eq!(recv(&mut stream).await.0, 1);
+
+        let started = Instant::now();
+        send(
+            &mut stream,
+            ApiKey::Fetch,
+            4,
+            2,
+            &fetch_request("events", 300),
+        )
+        .await;
+        send(&mut stream, ApiKey::ApiVersions, 0, 3, &[]).await;
+
+        // Responses come in request order, the ApiVersions response after
+        // the fetch even though it completed first.
+        assert_eq!(recv(&mut stream).await.0, 2);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert_eq!(recv(&mut stream).await.0, 3);
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_version() {
+        let tmp_dir = tempdir().unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let (addr, _stop) = start_server(config).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        // Unknown APIs and unsupported versions are answered with an error,
+        // and the connection stays open.
+        let code = ErrorCode::UnsupportedVersion.code();
+        send(&mut stream, ApiKey::Metadata, 99, 1, &[]).await;
+        let (id, mut body) = recv(&mut stream).await;
+        assert_eq!((id, get_i16(&mut body).unwrap()), (1, code));
+
+        let mut frame = BytesMut::new();
+        frame.put_i32(10);
+        frame.put_i16(999);
+        frame.put_i16(0);
+        frame.put_i32(2);
+        frame.put_i16(-1);
+        stream.write_all(&frame).await.unwrap();
+        let (id, mut body) = recv(&mut stream).await;
+        assert_eq!((id, get_i16(&mut body).unwrap()), (2, code));
+
+        send(&mut stream, ApiKey::ApiVersions, 0, 3, &[]).await;
+        let (id, mut body) = recv(&mut stream).await;
+        assert_eq!((id, get_i16(&mut body).unwrap()), (3, 0));
+    }
+
+    #[tokio::test]
+    async fn test_max_in_flight_requests() {
+        let tmp_dir = tempdir().unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            max_in_flight_requests: 2,
+            ..Config::default()
+        };
+        let (addr, _stop) = start_server(config).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        // Fetches of an unknown partition are answered right away, the
+        // third request is read once the first response is written.
+        for id in 0..6 {
+            send(&mut stream, ApiKey::Fetch, 4, id, &fetch_request("nope", 0)).await;
+        }
+        for id in 0..6 {
+            assert_eq!(recv(&mut stream).await.0, id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection() {
+        let tmp_dir = tempdir().unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            connections_max_idle: Duration::from_millis(200),
+            ..Config::default()
+        };
+        let (addr, _stop) = start_server(config).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        // A long polling fetch keeps the connection busy past the idle
+        // timeout.
+        let mut body = BytesMut::new();
+        put_array(&mut body, &["events"], false, |buf, topic| {
+            put_string(buf, topic, false)
+        });
+        body.put_i8(1);
+        send(&mut stream, ApiKey::Metadata, 4, 1, &body).await;
+        assert_eq!(recv(&mut stream).await.0, 1);
+        let body = fetch_request("events", 400);
+        send(&mut stream, ApiKey::Fetch, 4, 2, &body).await;
+        assert_eq!(recv(&mut stream).await.0, 2);
+
+        // Once idle, the connection is closed
+        let started = Instant::now();
+        assert_eq!(stream.read(&mut [0; 1]).await.unwrap(), 0);
+        assert!(started.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        let tmp_dir = tempdir().unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener_config = ListenerConfig::new("PLAINTEXT", SecurityProtocol::Plaintext);
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(server::run(
+            vec![(listener_config, listener)],
+            config,
+            stream::pending(),
+            stopped,
+        ));
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        let mut body = BytesMut::new();
+        put_array(&mut body, &["events"], false, |buf, topic| {
+            put_string(buf, topic, false)
+        });
+        body.put_i8(1);
+        send(&mut stream, ApiKey::Metadata, 4, 1, &body).await;
+        assert_eq!(recv(&mut stream).await.0, 1);
+
+        // The long polling fetch in flight is answered before the connection
+        // is closed.
+        let body = fetch_request("events", 10_000);
+        send(&mut stream, ApiKey::Fetch, 4, 2, &body).await;
+        time::sleep(Duration::from_millis(100)).await;
+        let started = Instant::now();
+        stop.send(()).unwrap();
+        assert_eq!(recv(&mut stream).await.0, 2);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(stream.read(&mut [0; 1]).await.unwrap(), 0);
+
+        // The partitions were closed cleanly.
+        server.await.unwrap().unwrap();
+        assert!(tmp_dir.path().join(".clean_shutdown").exists());
+    }
+
+    #[tokio::test]
+    async fn test_advertised_listeners() {
+        let tmp_dir = tempdir().unwrap();
+        let credentials = CredentialStore::open(tmp_dir.path()).unwrap();
+        let credential = ScramCredential::new(ScramMechanism::Sha256, "secret", 4096);
+        credentials
+            .upsert("alice", ScramMechanism::Sha256, credential)
+            .unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            sasl_enabled_mechanisms: vec![Mechanism::Plain],
+            ..Config::default()
+        };
+        let internal = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let internal_addr = internal.local_addr().unwrap();
+        let external = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let external_addr = external.local_addr().unwrap();
+        let external_config = ListenerConfig {
+            advertised_host: Some("broker.example.com".into()),
+            advertised_port: Some(19093),
+            ..ListenerConfig::new("EXTERNAL", SecurityProtocol::SaslPlaintext)
+        };
+        let listeners = vec![
+            (
+                ListenerConfig::new("INTERNAL", SecurityProtocol::Plaintext),
+                internal,
+            ),
+            (external_config, external),
+        ];
+        let (_stop, stopped) = oneshot::channel::<()>();
+        tokio::spawn(server::run(listeners, config, stream::pending(), stopped));
+
+        // Host and port of the single broker of a Metadata v1 response.
+        async fn advertised_broker(stream: &mut TcpStream) -> (String, i32) {
+            let mut body = BytesMut::new();
+            body.put_i32(0);
+            send(stream, ApiKey::Metadata, 1, 1, &body).await;
+            let (_, mut body) = recv(stream).await;
+            assert_eq!(get_i32(&mut body).unwrap(), 1);
+            get_i32(&mut body).unwrap();
+            let host = get_string(&mut body, false).unwrap();
+            (host, get_i32(&mut body).unwrap())
+        }
+
+        // Each listener advertises its own address.
+        let mut stream = TcpStream::connect(internal_addr).await.unwrap();
+        assert_eq!(
+            advertised_broker(&mut stream).await,
+            ("127.0.0.1".into(), internal_addr.port() as i32)
+        );
+
+        // Only the clients of the external listener authenticate.
+        let mut stream = TcpStream::connect(external_addr).await.unwrap();
+        send(&mut stream, ApiKey::Metadata, 1, 1, &0_i32.to_be_bytes()).await;
+        assert_eq!(stream.read(&mut [0; 1]).await.unwrap(), 0);
+
+        let mut stream = TcpStream::connect(external_addr).await.unwrap();
+        let mut body = BytesMut::new();
+        put_string(&mut body, "PLAIN", false);
+        send(&mut stream, ApiKey::SaslHandshake, 1, 1, &body).await;
+        recv(&mut stream).await;
+        let mut body = BytesMut::new();
+        put_bytes(&mut body, b"\0alice\0secret", false);
+        send(&mut stream, ApiKey::SaslAuthenticate, 0, 2, &body).await;
+        let (_, mut body) = recv(&mut stream).await;
+        assert_eq!(get_i16(&mut body).unwrap(), 0);
+        assert_eq!(
+            advertised_broker(&mut stream).await,
+            ("broker.example.com".into(), 19093)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_connections_per_ip() {
+        let tmp_dir = tempdir().unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            max_connections_per_ip: 1,
+            ..Config::default()
+        };
+        let (addr, _stop) = start_server(config).await;
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        send(&mut first, ApiKey::ApiVersions, 0, 1, &[]).await;
+        assert_eq!(recv(&mut first).await.0, 1);
+
+        // The second connection is closed right away.
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(second.read(&mut [0; 1]).await.unwrap(), 0);
+
+        // Once the first one is closed, the client may connect again.
+        drop(first);
+        time::sleep(Duration::from_millis(50)).await;
+        let mut third = TcpStream::connect(addr).await.unwrap();
+        send(&mut third, ApiKey::ApiVersions, 0, 2, &[]).await;
+        assert_eq!(recv(&mut third).await.0, 2);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_advertised_wildcard() {
+        let tmp_dir = tempdir().unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let listener_config = ListenerConfig::new("PLAINTEXT", SecurityProtocol::Plaintext);
+        let (_stop, stopped) = oneshot::channel::<()>();
+        tokio::spawn(server::run(
+            vec![(listener_config, listener)],
+            config,
+            stream::pending(),
+            stopped,
+        ));
+
+        // The listener is advertised at the name of the machine.
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        send(&mut stream, ApiKey::Metadata, 1, 1, &0_i32.to_be_bytes()).await;
+        let (_, mut body) = recv(&mut stream).await;
+        assert_eq!(get_i32(&mut body).unwrap(), 1);
+        get_i32(&mut body).unwrap();
+        let host = get_string(&mut body, false).unwrap();
+        assert_eq!(host, server::hostname().unwrap());
+        assert_eq!(get_i32(&mut body).unwrap(), port as i32);
+    }
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let tmp_dir = tempdir().unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            max_connections: 2,
+            listener_max_connections: vec![("INTERNAL".to_string(), 1)].into_iter().collect(),
+            ..Config::default()
+        };
+        let internal = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let internal_addr = internal.local_addr().unwrap();
+        let external = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let external_addr = external.local_addr().unwrap();
+        let listeners = vec![
+            (
+                ListenerConfig::new("INTERNAL", SecurityProtocol::Plaintext),
+                internal,
+            ),
+            (
+                ListenerConfig::new("EXTERNAL", SecurityProtocol::Plaintext),
+                external,
+            ),
+        ];
+        let (_stop, stopped) = oneshot::channel::<()>();
+        tokio::spawn(server::run(listeners, config, stream::pending(), stopped));
+
+        // Whether the connection is accepted, and answers requests.
+        async fn served(stream: &mut TcpStream) -> bool {
+            send(stream, ApiKey::ApiVersions, 0, 1, &[]).await;
+            time::timeout(Duration::from_millis(100), recv(stream))
+                .await
+                .is_ok()
+        }
+
+        // The limit of the listener is reached first.
+        let mut internal = TcpStream::connect(internal_addr).await.unwrap();
+        assert!(served(&mut internal).await);
+        let mut waiting = TcpStream::connect(internal_addr).await.unwrap();
+        assert!(!served(&mut waiting).await);
+
+        // The limit of the broker is shared by both listeners.
+        let mut external = TcpStream::connect(external_addr).await.unwrap();
+        assert!(served(&mut external).await);
+        let mut waiting = TcpStream::connect(external_addr).await.unwrap();
+        assert!(!served(&mut waiting).await);
+
+        drop(external);
+        assert_eq!(recv(&mut waiting).await.0, 1);
+    }
+
+    #[tokio::test]
+    async fn test_request_too_large() {
+        let tmp_dir = tempdir().unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            max_request_size: 64,
+            ..Config::default()
+        };
+        let (addr, _stop) = start_server(config).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        stream.write_i32(i32::MAX).await.unwrap();
+        assert_eq!(stream.read(&mut [0; 1]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sasl_authentication() {
+        let tmp_dir = tempdir().unwrap();
+        let credentials = CredentialStore::open(tmp_dir.path()).unwrap();
+        let credential = ScramCredential::new(ScramMechanism::Sha256, "secret", 4096);
+        credentials
+            .upsert("alice", ScramMechanism::Sha256, credential)
+            .unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            sasl_enabled_mechanisms: vec![
+                Mechanism::Plain,
+                Mechanism::Scram(ScramMechanism::Sha256),
+            ],
+            ..Config::default()
+        };
+        let (addr, _stop) = start_server(config).await;
+
+        let handshake = |mechanism: &str| {
+            let mut body = BytesMut::new();
+            put_string(&mut body, mechanism, false);
+            body
+        };
+        let authenticate = |token: &[u8]| {
+            let mut body = BytesMut::new();
+            put_bytes(&mut body, token, false);
+            body
+        };
+        let mut metadata = BytesMut::new();
+        metadata.put_i32(-1);
+        metadata.put_i8(0);
+
+        // Requests other than ApiVersions close the connection until the
+        // client authenticates.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        send(&mut stream, ApiKey::ApiVersions, 0, 1, &[]).await;
+        assert_eq!(recv(&mut stream).await.0, 1);
+        send(&mut stream, ApiKey::Metadata, 4, 2, &metadata).await;
+        assert_eq!(stream.read(&mut [0; 1]).await.unwrap(), 0);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        send(
+            &mut stream,
+            ApiKey::SaslHandshake,
+            1,
+            1,
+            &handshake("PLAIN"),
+        )
+        .await;
+        let (_, mut body) = recv(&mut stream).await;
+        assert_eq!(get_i16(&mut body).unwrap(), 0);
+        let mechanisms = get_array(&mut body, false, |buf| get_string(buf, false)).unwrap();
+        assert_eq!(mechanisms, vec!["PLAIN", "SCRAM-SHA-256"]);
+        let body = authenticate(b"\0alice\0secret");
+        send(&mut stream, ApiKey::SaslAuthenticate, 0, 2, &body).await;
+        let (_, mut body) = recv(&mut stream).await;
+        assert_eq!(get_i16(&mut body).unwrap(), 0);
+
+        // Once authenticated, the exchange cannot start again.
+        send(&mut stream, ApiKey::Metadata, 4, 3, &metadata).await;
+        assert_eq!(recv(&mut stream).await.0, 3);
+        send(
+            &mut stream,
+            ApiKey::SaslHandshake,
+            1,
+            4,
+            &handshake("PLAIN"),
+        )
+        .await;
+        let (_, mut body) = recv(&mut stream).await;
+        assert_eq!(
+            get_i16(&mut body).unwrap(),
+            ErrorCode::IllegalSaslState.code()
+        );
+
+        // Failures are answered, then the connection is closed.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        send(
+            &mut stream,
+            ApiKey::SaslHandshake,
+            1,
+            1,
+            &handshake("GSSAPI"),
+        )
+        .await;
+        let (_, mut body) = recv(&mut stream).await;
+        assert_eq!(
+            get_i16(&mut body).unwrap(),
+            ErrorCode::UnsupportedSaslMechanism.code()
+        );
+        assert_eq!(stream.read(&mut [0; 1]).await.unwrap(), 0);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        send(
+            &mut stream,
+            ApiKey::SaslHandshake,
+            1,
+            1,
+            &handshake("PLAIN"),
+        )
+        .await;
+        recv(&mut stream).await;
+        let body = authenticate(b"\0alice\0wrong");
+        send(&mut stream, ApiKey::SaslAuthenticate, 0, 2, &body).await;
+        let (_, mut body) = recv(&mut stream).await;
+        assert_eq!(
+            get_i16(&mut body).unwrap(),
+            ErrorCode::SaslAuthenticationFailed.code()
+        );
+        assert_eq!(stream.read(&mut [0; 1]).await.unwrap(), 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_unix_socket() {
+        use tokio::net::UnixStream;
+
+        let tmp_dir = tempdir().unwrap();
+        let path = tmp_dir.path().join("broker.sock");
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            unix_socket_path: Some(path.clone()),
+            authorizer_enabled: true,
+            super_users: vec![Principal::user(format!("uid={},gid={}", uid, gid))],
+            ..Config::default()
+        };
+        let (addr, stop) = start_server(config).await;
+        let mut metadata = BytesMut::new();
+        put_array(&mut metadata, &["events"], false, |buf, topic| {
+            put_string(buf, topic, false)
+        });
+        metadata.put_i8(1);
+
+        // The socket is bound once the partitions are loaded.
+        while !path.exists() {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // Clients of the socket are the user of their process, a super user
+        // here, while TCP clients are anonymous.
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        send(&mut stream, ApiKey::Metadata, 4, 1, &metadata).await;
+        assert_eq!(metadata_topic_error(recv(&mut stream).await.1), 0);
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        send(&mut stream, ApiKey::Metadata, 4, 1, &metadata).await;
+        assert_eq!(
+            metadata_topic_error(recv(&mut stream).await.1),
+            ErrorCode::TopicAuthorizationFailed.code()
+        );
+
+        // The socket is removed once the server is shut down
+        stop.send(()).unwrap();
+        time::sleep(Duration::from_millis(200)).await;
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        // Pick a free port for the metrics.
+        let port = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let tmp_dir = tempdir().unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            metrics_port: Some(port),
+            ..Config::default()
+        };
+        let (addr, _stop) = start_server(config).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        let mut body = BytesMut::new();
+        put_array(&mut body, &["events"], false, |buf, topic| {
+            put_string(buf, topic, false)
+        });
+        body.put_i8(1);
+        send(&mut stream, ApiKey::Metadata, 4, 1, &body).await;
+        recv(&mut stream).await;
+        send(
+            &mut stream,
+            ApiKey::Fetch,
+            4,
+            2,
+            &fetch_request("events", 0),
+        )
+        .await;
+        recv(&mut stream).await;
+
+        let mut http = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        http.write_all(b"GET /metrics HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        http.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        for line in [
+            r#"fafka_request_duration_seconds_count{api="Fetch"} 1"#,
+            r#"fafka_request_duration_seconds_count{api="Metadata"} 1"#,
+            r#"fafka_connections_active{listener="PLAINTEXT"} 1"#,
+            r#"fafka_log_end_offset{topic="events",partition="0"} 0"#,
+        ]
+        .iter()
+        {
+            assert!(response.lines().any(|l| l == *line), "{}", line);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_alter_user_scram_credentials() {
+        let tmp_dir = tempdir().unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let (addr, _stop) = start_server(config).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        // Delete a credential carol does not have, upsert a credential of
+        // alice twice, and one of bob.
+        let salted_password = ScramMechanism::Sha512.salted_password(b"secret", b"salt", 4096);
+        let mut body = BytesMut::new();
+        put_empty_tagged_fields(&mut body);
+        put_unsigned_varint(&mut body, 2);
+        put_string(&mut body, "carol", true);
+        body.put_i8(1);
+        put_empty_tagged_fields(&mut body);
+        put_array(&mut body, &["alice", "alice", "bob"], true, |buf, user| {
+            put_string(buf, user, true);
+            buf.put_i8(2);
+            buf.put_i32(4096);
+            put_bytes(buf, b"salt", true);
+            put_bytes(buf, &salted_password, true);
+            put_empty_tagged_fields(buf);
+        });
+        put_empty_tagged_fields(&mut body);
+        send(&mut stream, ApiKey::AlterUserScramCredentials, 0, 1, &body).await;
+
+        let (_, mut body) = recv(&mut stream).await;
+        skip_tagged_fields(&mut body).unwrap();
+        assert_eq!(get_i32(&mut body).unwrap(), 0);
+        let results = get_array(&mut body, true, |buf| {
+            let user = get_string(buf, true)?;
+            let error_code = get_i16(buf)?;
+            get_nullable_string(buf, true)?;
+            skip_tagged_fields(buf)?;
+            Ok((user, error_code))
+        })
+        .unwrap();
+        assert_eq!(
+            results,
+            vec![
+                ("carol".into(), ErrorCode::ResourceNotFound.code()),
+                ("alice".into(), ErrorCode::DuplicateResource.code()),
+                ("bob".into(), 0),
+            ]
+        );
+
+        // Only the credential of bob is stored, derived from the salted
+        // password.
+        let credentials = CredentialStore::open(tmp_dir.path()).unwrap();
+        assert_eq!(credentials.users(), vec!["bob".to_string()]);
+        let credential = credentials.get("bob", ScramMechanism::Sha512).unwrap();
+        assert!(credential.verify(ScramMechanism::Sha512, "secret"));
+    }
+
+    // Error code of the first topic of a Metadata v4 response.
+    fn metadata_topic_error(mut body: Bytes) -> i16 {
+        get_i32(&mut body).unwrap();
+        get_array(&mut body, false, |buf| {
+            get_i32(buf)?;
+            get_string(buf, false)?;
+            get_i32(buf)?;
+            get_nullable_string(buf, false)
+        })
+        .unwrap();
+        get_nullable_string(&mut body, false).unwrap();
+        get_i32(&mut body).unwrap();
+        get_i32(&mut body).unwrap();
+        get_i16(&mut body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_authorization() {
+        let tmp_dir = tempdir().unwrap();
+        let credentials = CredentialStore::open(tmp_dir.path()).unwrap();
+        for user in ["admin", "bob"].iter() {
+            let credential = ScramCredential::new(ScramMechanism::Sha256, "secret", 4096);
+            credentials
+                .upsert(user, ScramMechanism::Sha256, credential)
+                .unwrap();
+        }
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            sasl_enabled_mechanisms: vec![Mechanism::Plain],
+            authorizer_enabled: true,
+            super_users: vec![Principal::user("admin")],
+            ..Config::default()
+        };
+        let (addr, _stop) = start_server(config).await;
+
+        let connect = |user: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let mut body = BytesMut::new();
+            put_string(&mut body, "PLAIN", false);
+            send(&mut stream, ApiKey::SaslHandshake, 1, 1, &body).await;
+            recv(&mut stream).await;
+            let mut body = BytesMut::new();
+            put_bytes(&mut body, format!("\0{}\0secret", user).as_bytes(), false);
+            send(&mut stream, ApiKey::SaslAuthenticate, 0, 2, &body).await;
+            let (_, mut body) = recv(&mut stream).await;
+            assert_eq!(get_i16(&mut body).unwrap(), 0);
+            stream
+        };
+        let mut metadata = BytesMut::new();
+        put_array(&mut metadata, &["events"], false, |buf, topic| {
+            put_string(buf, topic, false)
+        });
+        metadata.put_i8(1);
+        let mut create_acls = BytesMut::new();
+        put_array(&mut create_acls, &["User:bob"], false, |buf, principal| {
+            buf.put_i8(ResourceType::Topic.code());
+            put_string(buf, "ev", false);
+            buf.put_i8(PatternType::Prefixed.code());
+            put_string(buf, principal, false);
+            put_string(buf, "*", false);
+            buf.put_i8(AclOperation::Write.code());
+            buf.put_i8(AclPermissionType::Allow.code());
+        });
+        let mut any = BytesMut::new();
+        any.put_i8(ResourceType::Any.code());
+        put_nullable_string(&mut any, None, false);
+        any.put_i8(PatternType::Any.code());
+        put_nullable_string(&mut any, None, false);
+        put_nullable_string(&mut any, None, false);
+        any.put_i8(AclOperation::Any.code());
+        any.put_i8(AclPermissionType::Any.code());
+
+        // Super users may do anything, including creating topics.
+        let mut admin = connect("admin").await;
+        send(&mut admin, ApiKey::Metadata, 4, 3, &metadata).await;
+        assert_eq!(metadata_topic_error(recv(&mut admin).await.1), 0);
+
+        // Without any ACL, other users may not even describe the topic nor
+        // manage ACLs.
+        let mut bob = connect("bob").await;
+        send(&mut bob, ApiKey::Metadata, 4, 3, &metadata).await;
+        assert_eq!(
+            metadata_topic_error(recv(&mut bob).await.1),
+            ErrorCode::TopicAuthorizationFailed.code()
+        );
+        send(&mut bob, ApiKey::CreateAcls, 1, 4, &create_acls).await;
+        let (_, mut body) = recv(&mut bob).await;
+        get_i32(&mut body).unwrap();
+        assert_eq!(get_i32(&mut body).unwrap(), 1);
+        assert_eq!(
+            get_i16(&mut body).unwrap(),
+            ErrorCode::ClusterAuthorizationFailed.code()
+        );
+
+        // Allowing to write to topics prefixed with `ev` allows to describe
+        // `events`.
+        send(&mut admin, ApiKey::CreateAcls, 1, 4, &create_acls).await;
+        let (_, mut body) = recv(&mut admin).await;
+        get_i32(&mut body).unwrap();
+        assert_eq!(get_i32(&mut body).unwrap(), 1);
+        assert_eq!(get_i16(&mut body).unwrap(), 0);
+        send(&mut bob, ApiKey::Metadata, 4, 5, &metadata).await;
+        assert_eq!(metadata_topic_error(recv(&mut bob).await.1), 0);
+
+        send(&mut admin, ApiKey::DescribeAcls, 1, 5, &any).await;
+        let (_, mut body) = recv(&mut admin).await;
+        get_i32(&mut body).unwrap();
+        assert_eq!(get_i16(&mut body).unwrap(), 0);
+        get_nullable_string(&mut body, false).unwrap();
+        assert_eq!(get_i32(&mut body).unwrap(), 1);
+        assert_eq!(get_i8(&mut body).unwrap(), ResourceType::Topic.code());
+        assert_eq!(get_string(&mut body, false).unwrap(), "ev");
+
+        let mut delete_acls = BytesMut::new();
+        delete_acls.put_i32(1);
+        delete_acls.put_slice(&any);
+        send(&mut admin, ApiKey::DeleteAcls, 1, 6, &delete_acls).await;
+        let (_, mut body) = recv(&mut admin).await;
+        get_i32(&mut body).unwrap();
+        assert_eq!(get_i32(&mut body).unwrap(), 1);
+        assert_eq!(get_i16(&mut body).unwrap(), 0);
+        get_nullable_string(&mut body, false).unwrap();
+        assert_eq!(get_i32(&mut body).unwrap(), 1);
+        send(&mut bob, ApiKey::Metadata, 4, 7, &metadata).await;
+        assert_eq!(
+            metadata_topic_error(recv(&mut bob).await.1),
+            ErrorCode::TopicAuthorizationFailed.code()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alter_client_quotas() {
+        let tmp_dir = tempdir().unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let (addr, _stop) = start_server(config).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        // Set a quota of alice, and one of bob with an unknown key.
+        let entries = [("alice", "producer_byte_rate"), ("bob", "unknown")];
+        let mut body = BytesMut::new();
+        put_array(&mut body, &entries, false, |buf, (user, key)| {
+            put_array(buf, &[*user], false, |buf, user| {
+                put_string(buf, QuotaEntity::USER, false);
+                put_nullable_string(buf, Some(user), false);
+            });
+            put_array(buf, &[*key], false, |buf, key| {
+                put_string(buf, key, false);
+                buf.put_f64(1024.0);
+                buf.put_i8(0);
+            });
+        });
+        body.put_i8(0);
+        send(&mut stream, ApiKey::AlterClientQuotas, 0, 1, &body).await;
+
+        let (_, mut body) = recv(&mut stream).await;
+        assert_eq!(get_i32(&mut body).unwrap(), 0);
+        let results = get_array(&mut body, false, |buf| {
+            let error_code = get_i16(buf)?;
+            get_nullable_string(buf, false)?;
+            let entity = get_array(buf, false, |buf| {
+                get_string(buf, false)?;
+                get_nullable_string(buf, false)
+            })?;
+            Ok((error_code, entity))
+        })
+        .unwrap();
+        assert_eq!(
+            results,
+            vec![
+                (0, vec![Some("alice".into())]),
+                (ErrorCode::InvalidRequest.code(), vec![Some("bob".into())]),
+            ]
+        );
+
+        // Describe the quotas of every user.
+        let mut body = BytesMut::new();
+        put_array(
+            &mut body,
+            &[QuotaEntity::USER],
+            false,
+            |buf, entity_type| {
+                put_string(buf, entity_type, false);
+                buf.put_i8(protocol::describe_client_quotas::MATCH_ANY);
+                put_nullable_string(buf, None, false);
+            },
+        );
+        body.put_i8(0);
+        send(&mut stream, ApiKey::DescribeClientQuotas, 0, 2, &body).await;
+
+        let (_, mut body) = recv(&mut stream).await;
+        assert_eq!(get_i32(&mut body).unwrap(), 0);
+        assert_eq!(get_i16(&mut body).unwrap(), 0);
+        get_nullable_string(&mut body, false).unwrap();
+        assert_eq!(get_i32(&mut body).unwrap(), 1);
+        assert_eq!(get_i32(&mut body).unwrap(), 1);
+        assert_eq!(get_string(&mut body, false).unwrap(), QuotaEntity::USER);
+        assert_eq!(
+            get_nullable_string(&mut body, false).unwrap(),
+            Some("alice".into())
+        );
+        assert_eq!(get_i32(&mut body).unwrap(), 1);
+        assert_eq!(get_string(&mut body, false).unwrap(), "producer_byte_rate");
+        assert_eq!(get_f64(&mut body).unwrap(), 1024.0);
+
+        // The quota is persisted.
+        let quotas = QuotaManager::open(tmp_dir.path(), Duration::from_secs(1), 11).unwrap();
+        assert_eq!(quotas.describe().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_alter_configs() {
+        let tmp_dir = tempdir().unwrap();
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let (addr, _stop) = start_server(config).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        let create_topic = |topic: &'static str| {
+            let mut body = BytesMut::new();
+            put_array(&mut body, &[topic], false, |buf, topic| {
+                put_string(buf, topic, false)
+            });
+            body.put_i8(1);
+            body
+        };
+        send(&mut stream, ApiKey::Metadata, 4, 1, &create_topic("events")).await;
+        recv(&mut stream).await;
+
+        // Change the number of partitions of new topics and the segments of
+        // a topic, along with invalid changes.
+        use protocol::describe_configs::{RESOURCE_BROKER, RESOURCE_TOPIC};
+        let resources = [
+            (RESOURCE_BROKER, "0", "num.partitions", "3"),
+            (RESOURCE_TOPIC, "events", "segment.bytes", "1024"),
+            (RESOURCE_BROKER, "", "log.dirs", "/data"),
+            (RESOURCE_TOPIC, "nope", "segment.bytes", "1024"),
+        ];
+        let mut body = BytesMut::new();
+        put_array(&mut body, &resources, false, |buf, resource| {
+            let (resource_type, resource_name, name, value) = resource;
+            buf.put_i8(*resource_type);
+            put_string(buf, resource_name, false);
+            put_array(buf, &[(name, value)], false, |buf, (name, value)| {
+                put_string(buf, name, false);
+                buf.put_i8(protocol::incremental_alter_configs::OP_SET);
+                put_nullable_string(buf, Some(value), false);
+            });
+        });
+        put_bool(&mut body, false);
+        send(&mut stream, ApiKey::IncrementalAlterConfigs, 0, 2, &body).await;
+
+        let (_, mut body) = recv(&mut stream).await;
+        assert_eq!(get_i32(&mut body).unwrap(), 0);
+        let error_codes = get_array(&mut body, false, |buf| {
+            let error_code = get_i16(buf)?;
+            get_nullable_string(buf, false)?;
+            get_i8(buf)?;
+            get_string(buf, false)?;
+            Ok(error_code)
+        })
+        .unwrap();
+        assert_eq!(
+            error_codes,
+            vec![
+                0,
+                0,
+                ErrorCode::InvalidConfig.code(),
+                ErrorCode::UnknownTopicOrPartition.code()
+            ]
+        );
+
+        // Topics are created with the new number of partitions.
+        send(&mut stream, ApiKey::Metadata, 4, 3, &create_topic("later")).await;
+        recv(&mut stream).await;
+        assert!(tmp_dir.path().join("later-2").is_dir());
+
+        // Describe the segment size of the topic.
+        let mut body = BytesMut::new();
+        put_array(&mut body, &["events"], false, |buf, topic| {
+            buf.put_i8(RESOURCE_TOPIC);
+            put_string(buf, topic, false);
+            put_array(buf, &["segment.bytes"], false, |buf, key| {
+                put_string(buf, key, false)
+            });
+        });
+        put_bool(&mut body, false);
+        send(&mut stream, ApiKey::DescribeConfigs, 1, 4, &body).await;
+
+        let (_, mut body) = recv(&mut stream).await;
+        assert_eq!(get_i32(&mut body).unwrap(), 0);
+        assert_eq!(get_i32(&mut body).unwrap(), 1);
+        assert_eq!(get_i16(&mut body).unwrap(), 0);
+        get_nullable_string(&mut body, false).unwrap();
+        assert_eq!(get_i8(&mut body).unwrap(), RESOURCE_TOPIC);
+        assert_eq!(get_string(&mut body, false).unwrap(), "events");
+        assert_eq!(get_i32(&mut body).unwrap(), 1);
+        assert_eq!(get_string(&mut body, false).unwrap(), "segment.bytes");
+        assert_eq!(
+            get_nullable_string(&mut body, false).unwrap(),
+            Some("1024".into())
+        );
+        assert!(!get_bool(&mut body).unwrap());
+        assert_eq!(get_i8(&mut body).unwrap(), 1);
+    }
+}
@@ -0,0 +1,132 @@
+//! Produce: append record batches to partitions.
+
+use bytes::Bytes;
+use tokio::time::Duration;
+use tracing::debug;
+
+use crate::server::authorizer::{AclOperation, ResourceType};
+use crate::server::error::{Error, ErrorCode, Result};
+use crate::server::frame::RequestHeader;
+use crate::server::protocol::produce::{
+    PartitionProduceData, PartitionProduceResponse, ProduceRequest, ProduceResponse,
+    TopicProduceResponse,
+};
+use crate::server::protocol::records::{RecordBatch, TimestampType};
+use crate::server::protocol::{self, ApiKey};
+use crate::server::quota::QuotaType;
+use crate::store::partition::Partition;
+use crate::store::segment::{AppendInfo, Record as StoreRecord};
+use crate::store::TopicPartition;
+
+use super::{throttle_time_ms, Context};
+
+impl Context {
+    /// Append the records of a Produce request to their partitions.
+    ///
+    /// Errors are reported per partition: a partition whose records are
+    /// rejected does not prevent the other ones from being appended. The
+    /// broker holds the only replica of every partition, so `acks=-1` is
+    /// satisfied as soon as the records are appended, like `acks=1`, and the
+    /// timeout of the request is not used. Requests with `acks=0` are not
+    /// answered.
+    pub(super) async fn produce(
+        &self,
+        header: &RequestHeader,
+        body: Bytes,
+        throttle: &mut Duration,
+    ) -> Result<Option<Bytes>> {
+        let key = ApiKey::Produce;
+        let request: ProduceRequest = protocol::decode_request(key, header, body)?;
+        let size: usize = request
+            .topics
+            .iter()
+            .flat_map(|topic| &topic.partitions)
+            .map(|data| data.records.as_ref().map_or(0, Bytes::len))
+            .sum();
+
+        let mut responses = Vec::with_capacity(request.topics.len());
+        for topic in request.topics {
+            let timestamp_type = self
+                .dynamic_config
+                .topic_config(&topic.name)
+                .message_timestamp_type;
+            let mut partitions = Vec::with_capacity(topic.partitions.len());
+            for data in topic.partitions {
+                let index = data.index;
+                let result = match request.acks {
+                    -1..=1 => self.append(&topic.name, data).await,
+                    acks => Err(Error::InvalidRequiredAcks(acks)),
+                };
+
+                partitions.push(match result {
+                    Ok((info, partition)) => PartitionProduceResponse {
+                        index,
+                        error_code: ErrorCode::None,
+                        base_offset: info.base_offset as i64,
+                        log_append_time_ms: match timestamp_type {
+                            TimestampType::CreateTime => -1,
+                            TimestampType::LogAppendTime => info.log_append_time,
+                        },
+                        log_start_offset: partition.start_offset() as i64,
+                        error_message: None,
+                    },
+                    Err(err) => {
+                        debug!(topic = %topic.name, partition = index, cause = %err, "produce failed");
+                        PartitionProduceResponse::error(index, err.code(), Some(err.to_string()))
+                    }
+                });
+            }
+            responses.push(TopicProduceResponse {
+                name: topic.name,
+                partitions,
+            });
+        }
+
+        let produce_throttle = self.record_usage(QuotaType::ProducerByteRate, header, size as f64);
+        *throttle = (*throttle).max(produce_throttle);
+        if request.acks == 0 {
+            return Ok(None);
+        }
+
+        let response = ProduceResponse {
+            responses,
+            throttle_time_ms: throttle_time_ms(*throttle),
+        };
+        Ok(Some(protocol::encode_response(key, header, &response)))
+    }
+
+    // Validate the record batches produced to a partition and append their
+    // records.
+    async fn append(
+        &self,
+        topic: &str,
+        data: PartitionProduceData,
+    ) -> Result<(AppendInfo, Partition)> {
+        if !self.authorize(AclOperation::Write, ResourceType::Topic, topic) {
+            return Err(Error::TopicAuthorizationFailed);
+        }
+        let tp = TopicPartition::new(topic, data.index);
+        let partition = self.partition(&tp)?;
+
+        let records = data
+            .records
+            .ok_or_else(|| Error::InvalidRecord("null records".into()))?;
+        let config = self.dynamic_config.topic_config(topic);
+        if records.len() > config.max_message_bytes {
+            return Err(Error::MessageTooLarge);
+        }
+
+        let timestamp_type = config.message_timestamp_type;
+        let size = records.len();
+        let records = RecordBatch::decode_all(records)?
+            .iter()
+            .flat_map(|batch| batch.records.iter())
+            .map(|record| StoreRecord::new(record.encode_stored(timestamp_type)))
+            .collect();
+
+        let info = partition.append(records).await?;
+        self.metrics.record_bytes_in(topic, size);
+        self.fetch_purgatory.check_and_complete(&tp);
+        Ok((info, partition))
+    }
+}
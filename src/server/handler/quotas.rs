@@ -0,0 +1,281 @@
+//! Client quotas: usage of the clients, and the DescribeClientQuotas and
+//! AlterClientQuotas APIs.
+
+use bytes::Bytes;
+use tokio::time::Duration;
+use tracing::info;
+
+use crate::server::authorizer::{AclOperation, ResourceType, CLUSTER_NAME};
+use crate::server::error::{Error, ErrorCode, Result};
+use crate::server::frame::RequestHeader;
+use crate::server::protocol::alter_client_quotas::{
+    AlterClientQuotasRequest, AlterClientQuotasResponse, EntryResult, OpData,
+};
+use crate::server::protocol::describe_client_quotas::{
+    ComponentFilter, DescribeClientQuotasRequest, DescribeClientQuotasResponse, EntityData,
+    EntryData, ValueData, MATCH_ANY, MATCH_DEFAULT, MATCH_EXACT,
+};
+use crate::server::protocol::{self, ApiKey};
+use crate::server::quota::{EntityName, QuotaEntity, QuotaType};
+
+use super::{throttle_time_ms, Context};
+
+impl Context {
+    // Record `value` to the usage of the client of the request, returning
+    // how long the client is throttled for. Clients are identified by the
+    // name of their principal and their client id.
+    pub(super) fn record_usage(
+        &self,
+        quota_type: QuotaType,
+        header: &RequestHeader,
+        value: f64,
+    ) -> Duration {
+        let client_id = header.client_id.as_deref().unwrap_or("");
+        self.quotas
+            .record(quota_type, &self.principal.name, client_id, value)
+    }
+
+    /// Describe the quotas of the entities matching the filter of the
+    /// request.
+    pub(super) fn describe_client_quotas(
+        &self,
+        header: &RequestHeader,
+        body: Bytes,
+        throttle: Duration,
+    ) -> Result<Bytes> {
+        let key = ApiKey::DescribeClientQuotas;
+        let request: DescribeClientQuotasRequest = protocol::decode_request(key, header, body)?;
+
+        let res = if self.authorize(
+            AclOperation::DescribeConfigs,
+            ResourceType::Cluster,
+            CLUSTER_NAME,
+        ) {
+            validate_quota_filter(&request.components)
+                .map_err(|msg| (ErrorCode::InvalidRequest, msg))
+        } else {
+            let err = Error::ClusterAuthorizationFailed;
+            Err((err.code(), err.to_string()))
+        };
+
+        let response = match res {
+            Ok(()) => {
+                let entries = self
+                    .quotas
+                    .describe()
+                    .into_iter()
+                    .filter(|(entity, _)| {
+                        matches_quota_filter(entity, &request.components, request.strict)
+                    })
+                    .map(|(entity, quotas)| EntryData {
+                        entity: entity_data(&entity),
+                        values: quotas
+                            .into_iter()
+                            .map(|(quota_type, value)| ValueData {
+                                key: quota_type.name().into(),
+                                value,
+                            })
+                            .collect(),
+                    })
+                    .collect();
+                DescribeClientQuotasResponse {
+                    throttle_time_ms: throttle_time_ms(throttle),
+                    error_code: ErrorCode::None,
+                    error_message: None,
+                    entries: Some(entries),
+                }
+            }
+            Err((error_code, msg)) => DescribeClientQuotasResponse {
+                throttle_time_ms: throttle_time_ms(throttle),
+                error_code,
+                error_message: Some(msg),
+                entries: None,
+            },
+        };
+        Ok(protocol::encode_response(key, header, &response))
+    }
+
+    /// Set or remove the quotas of entities.
+    ///
+    /// Errors are reported per entity: the changes of an entity are only
+    /// applied if all of them are valid.
+    pub(super) fn alter_client_quotas(
+        &self,
+        header: &RequestHeader,
+        body: Bytes,
+        throttle: Duration,
+    ) -> Result<Bytes> {
+        let key = ApiKey::AlterClientQuotas;
+        let AlterClientQuotasRequest {
+            entries,
+            validate_only,
+        } = protocol::decode_request(key, header, body)?;
+        let authorized = self.authorize(
+            AclOperation::AlterConfigs,
+            ResourceType::Cluster,
+            CLUSTER_NAME,
+        );
+
+        let entries = entries
+            .into_iter()
+            .map(|entry| {
+                let res = if authorized {
+                    quota_changes(&entry.entity, &entry.ops)
+                        .map_err(|msg| (ErrorCode::InvalidRequest, msg))
+                } else {
+                    let err = Error::ClusterAuthorizationFailed;
+                    Err((err.code(), err.to_string()))
+                };
+                let res = res.and_then(|(entity, changes)| {
+                    if validate_only {
+                        return Ok(());
+                    }
+                    self.quotas
+                        .alter(&entity, &changes)
+                        .map_err(|err| (ErrorCode::KafkaStorageError, err.to_string()))?;
+                    info!(?entity, ?changes, principal = %self.principal, "altered quotas");
+                    Ok(())
+                });
+                let (error_code, error_message) = match res {
+                    Ok(()) => (ErrorCode::None, None),
+                    Err((error_code, msg)) => (error_code, Some(msg)),
+                };
+                EntryResult {
+                    error_code,
+                    error_message,
+                    entity: entry.entity,
+                }
+            })
+            .collect();
+
+        let response = AlterClientQuotasResponse {
+            throttle_time_ms: throttle_time_ms(throttle),
+            entries,
+        };
+        Ok(protocol::encode_response(key, header, &response))
+    }
+}
+
+// Check that the components of a DescribeClientQuotas filter are of known
+// entity types, at most once each, and have a name when matched exactly.
+fn validate_quota_filter(components: &[ComponentFilter]) -> std::result::Result<(), String> {
+    for (i, component) in components.iter().enumerate() {
+        let entity_type = &component.entity_type;
+        if entity_type != QuotaEntity::USER && entity_type != QuotaEntity::CLIENT_ID {
+            return Err(format!("unknown entity type {:?}", entity_type));
+        }
+        if components[..i]
+            .iter()
+            .any(|c| c.entity_type == *entity_type)
+        {
+            return Err(format!(
+                "entity type {:?} is filtered more than once",
+                entity_type
+            ));
+        }
+        match component.match_type {
+            MATCH_EXACT if component.match_name.is_none() => {
+                return Err("exact match without a name".into())
+            }
+            MATCH_EXACT | MATCH_DEFAULT | MATCH_ANY => {}
+            match_type => return Err(format!("unknown match type {}", match_type)),
+        }
+    }
+    Ok(())
+}
+
+// Whether `entity` matches every component of a validated filter. Strict
+// filters also exclude entities with other components.
+fn matches_quota_filter(
+    entity: &QuotaEntity,
+    components: &[ComponentFilter],
+    strict: bool,
+) -> bool {
+    let matches = |name: &Option<EntityName>, entity_type: &str| {
+        let component = match components.iter().find(|c| c.entity_type == entity_type) {
+            Some(component) => component,
+            None => return !strict || name.is_none(),
+        };
+        match (name, component.match_type) {
+            (Some(EntityName::Name(name)), MATCH_EXACT) => {
+                component.match_name.as_ref() == Some(name)
+            }
+            (Some(EntityName::Default), MATCH_DEFAULT) => true,
+            (Some(EntityName::Name(_)), MATCH_ANY) => true,
+            _ => false,
+        }
+    };
+    matches(&entity.user, QuotaEntity::USER) && matches(&entity.client_id, QuotaEntity::CLIENT_ID)
+}
+
+// Components of `entity`, a `None` name standing for the default entity.
+fn entity_data(entity: &QuotaEntity) -> Vec<EntityData> {
+    let components = [
+        (QuotaEntity::USER, &entity.user),
+        (QuotaEntity::CLIENT_ID, &entity.client_id),
+    ];
+    components
+        .iter()
+        .filter_map(|(entity_type, name)| {
+            let entity_name = match name.as_ref()? {
+                EntityName::Default => None,
+                EntityName::Name(name) => Some(name.clone()),
+            };
+            Some(EntityData {
+                entity_type: entity_type.to_string(),
+                entity_name,
+            })
+        })
+        .collect()
+}
+
+// Entity and quota changes of an AlterClientQuotas entry, a change without a
+// value removing the quota.
+fn quota_changes(
+    components: &[EntityData],
+    ops: &[OpData],
+) -> std::result::Result<(QuotaEntity, Vec<QuotaChange>), String> {
+    let mut entity = QuotaEntity {
+        user: None,
+        client_id: None,
+    };
+    for component in components {
+        let name = match &component.entity_name {
+            Some(name) => EntityName::Name(name.clone()),
+            None => EntityName::Default,
+        };
+        let slot = match component.entity_type.as_str() {
+            QuotaEntity::USER => &mut entity.user,
+            QuotaEntity::CLIENT_ID => &mut entity.client_id,
+            entity_type => return Err(format!("unknown entity type {:?}", entity_type)),
+        };
+        if slot.replace(name).is_some() {
+            let msg = format!("duplicate entity type {:?}", component.entity_type);
+            return Err(msg);
+        }
+    }
+    if entity.user.is_none() && entity.client_id.is_none() {
+        return Err("empty entity".into());
+    }
+
+    let mut changes: Vec<QuotaChange> = vec![];
+    for op in ops {
+        let quota_type =
+            QuotaType::from_name(&op.key).ok_or_else(|| format!("unknown quota {:?}", op.key))?;
+        if changes.iter().any(|(t, _)| *t == quota_type) {
+            return Err(format!("quota {} is altered more than once", quota_type));
+        }
+        let valid = op.remove || (op.value.is_finite() && op.value > 0.0);
+        if !valid {
+            return Err(format!(
+                "invalid value {} for quota {}",
+                op.value, quota_type
+            ));
+        }
+        changes.push((quota_type, (!op.remove).then_some(op.value)));
+    }
+    Ok((entity, changes))
+}
+
+/// Change of a quota, `None` removing it.
+type QuotaChange = (QuotaType, Option<f64>);
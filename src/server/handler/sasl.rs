@@ -0,0 +1,114 @@
+//! SaslHandshake and SaslAuthenticate: authentication of connections.
+
+use crate::server::error::{Error, ErrorCode, Result};
+use crate::server::frame::{Request, Response};
+use crate::server::principal::Principal;
+use crate::server::protocol::sasl_authenticate::{
+    SaslAuthenticateRequest, SaslAuthenticateResponse,
+};
+use crate::server::protocol::sasl_handshake::{SaslHandshakeRequest, SaslHandshakeResponse};
+use crate::server::protocol::{self, ApiKey};
+use crate::server::sasl::{Authenticator, Mechanism, Step};
+
+use super::{unsupported_version, Context};
+
+impl Context {
+    /// Process a SaslHandshake or a SaslAuthenticate request, moving the
+    /// SASL exchange to its next `state`.
+    ///
+    /// Requests that do not match the state are answered with an
+    /// `IllegalSaslState` error, and fail the exchange.
+    pub(super) async fn sasl(&self, request: Request, state: &mut SaslState) -> Result<Response> {
+        let Request { header, body } = request;
+        let key = match ApiKey::from_i16(header.api_key) {
+            Some(key) if key.supports(header.api_version) => key,
+            _ => return Ok(unsupported_version(&header)),
+        };
+        let enabled = &self.config.sasl_enabled_mechanisms;
+        let handshake_response = |error_code| SaslHandshakeResponse {
+            error_code,
+            mechanisms: enabled.iter().map(|m| m.name().to_string()).collect(),
+        };
+
+        let body = match (key, std::mem::replace(state, SaslState::Handshake)) {
+            (ApiKey::SaslHandshake, SaslState::Handshake) => {
+                let request: SaslHandshakeRequest = protocol::decode_request(key, &header, body)?;
+                let mechanism = request
+                    .mechanism
+                    .parse::<Mechanism>()
+                    .ok()
+                    .filter(|mechanism| enabled.contains(mechanism));
+                let response = match mechanism {
+                    Some(mechanism) => {
+                        *state = SaslState::Authenticate(Authenticator::new(
+                            mechanism,
+                            &self.credentials,
+                        ));
+                        handshake_response(ErrorCode::None)
+                    }
+                    None => {
+                        *state =
+                            SaslState::Failed(Error::UnsupportedSaslMechanism(request.mechanism));
+                        handshake_response(ErrorCode::UnsupportedSaslMechanism)
+                    }
+                };
+                protocol::encode_response(key, &header, &response)
+            }
+            (ApiKey::SaslAuthenticate, SaslState::Authenticate(mut authenticator)) => {
+                let request: SaslAuthenticateRequest =
+                    protocol::decode_request(key, &header, body)?;
+                let (auth_bytes, next) = match authenticator.step(&request.auth_bytes).await {
+                    Ok(Step::Challenge(token)) => (token, SaslState::Authenticate(authenticator)),
+                    Ok(Step::Done {
+                        response,
+                        principal,
+                    }) => (response, SaslState::Authenticated(principal)),
+                    Err(err) => (vec![], SaslState::Failed(err)),
+                };
+                let response = match &next {
+                    SaslState::Failed(err) => {
+                        SaslAuthenticateResponse::error(err.code(), err.to_string())
+                    }
+                    _ => SaslAuthenticateResponse {
+                        error_code: ErrorCode::None,
+                        error_message: None,
+                        auth_bytes: auth_bytes.into(),
+                        session_lifetime_ms: 0,
+                    },
+                };
+                *state = next;
+                protocol::encode_response(key, &header, &response)
+            }
+            (key, _) => {
+                let err = Error::IllegalSaslState(format!("unexpected {:?} request", key));
+                let body = if key == ApiKey::SaslHandshake {
+                    let response = handshake_response(err.code());
+                    protocol::encode_response(key, &header, &response)
+                } else {
+                    let response = SaslAuthenticateResponse::error(err.code(), err.to_string());
+                    protocol::encode_response(key, &header, &response)
+                };
+                *state = SaslState::Failed(err);
+                body
+            }
+        };
+
+        Ok(Response {
+            correlation_id: header.correlation_id,
+            body,
+        })
+    }
+}
+
+/// State of the SASL exchange of a connection.
+#[derive(Debug)]
+pub(super) enum SaslState {
+    /// Waiting for the SaslHandshake request picking the mechanism.
+    Handshake,
+    /// Waiting for the next token of the exchange.
+    Authenticate(Authenticator),
+    Authenticated(Principal),
+    /// The exchange failed, the connection is closed once the error is
+    /// sent.
+    Failed(Error),
+}
@@ -0,0 +1,233 @@
+//! DescribeUserScramCredentials and AlterUserScramCredentials: SCRAM
+//! credentials of the users.
+
+use bytes::Bytes;
+use tokio::time::Duration;
+use tracing::info;
+
+use crate::server::authorizer::{AclOperation, ResourceType, CLUSTER_NAME};
+use crate::server::error::{Error, ErrorCode, Result};
+use crate::server::frame::RequestHeader;
+use crate::server::protocol::alter_user_scram_credentials::{
+    AlterUserScramCredentialsRequest, AlterUserScramCredentialsResponse,
+    AlterUserScramCredentialsResult, ScramCredentialUpsertion,
+};
+use crate::server::protocol::describe_user_scram_credentials::{
+    CredentialInfo, DescribeUserScramCredentialsRequest, DescribeUserScramCredentialsResponse,
+    DescribeUserScramCredentialsResult,
+};
+use crate::server::protocol::{self, ApiKey};
+use crate::server::sasl::{ScramCredential, ScramMechanism};
+
+use super::{throttle_time_ms, Context, ResultError};
+
+impl Context {
+    /// Describe the SCRAM credentials of the requested users, or of every
+    /// user if none is requested. Only the mechanisms and iterations are
+    /// described.
+    pub(super) fn describe_user_scram_credentials(
+        &self,
+        header: &RequestHeader,
+        body: Bytes,
+        throttle: Duration,
+    ) -> Result<Bytes> {
+        let key = ApiKey::DescribeUserScramCredentials;
+        let request: DescribeUserScramCredentialsRequest =
+            protocol::decode_request(key, header, body)?;
+
+        if !self.authorize(AclOperation::Describe, ResourceType::Cluster, CLUSTER_NAME) {
+            let err = Error::ClusterAuthorizationFailed;
+            let response = DescribeUserScramCredentialsResponse {
+                throttle_time_ms: throttle_time_ms(throttle),
+                error_code: err.code(),
+                error_message: Some(err.to_string()),
+                results: vec![],
+            };
+            return Ok(protocol::encode_response(key, header, &response));
+        }
+
+        let (users, all) = match request.users {
+            Some(users) => (users, false),
+            None => (self.credentials.users(), true),
+        };
+        let mut results: Vec<DescribeUserScramCredentialsResult> = vec![];
+        for user in users {
+            if let Some(result) = results.iter_mut().find(|r| r.user == user) {
+                result.error_code = ErrorCode::DuplicateResource;
+                result.error_message = Some("user is described more than once".into());
+                result.credential_infos.clear();
+                continue;
+            }
+
+            let credential_infos: Vec<_> = self
+                .credentials
+                .describe(&user)
+                .into_iter()
+                .map(|(mechanism, iterations)| CredentialInfo {
+                    mechanism: mechanism.type_code(),
+                    iterations: iterations as i32,
+                })
+                .collect();
+            let (error_code, error_message) = if credential_infos.is_empty() && !all {
+                let msg = "user has no credential";
+                (ErrorCode::ResourceNotFound, Some(msg.into()))
+            } else {
+                (ErrorCode::None, None)
+            };
+            results.push(DescribeUserScramCredentialsResult {
+                user,
+                error_code,
+                error_message,
+                credential_infos,
+            });
+        }
+
+        let response = DescribeUserScramCredentialsResponse {
+            throttle_time_ms: throttle_time_ms(throttle),
+            error_code: ErrorCode::None,
+            error_message: None,
+            results,
+        };
+        Ok(protocol::encode_response(key, header, &response))
+    }
+
+    /// Create, update and delete SCRAM credentials.
+    ///
+    /// Errors are reported per user: the changes of a user are only applied
+    /// if all of them are valid.
+    pub(super) fn alter_user_scram_credentials(
+        &self,
+        header: &RequestHeader,
+        body: Bytes,
+        throttle: Duration,
+    ) -> Result<Bytes> {
+        let key = ApiKey::AlterUserScramCredentials;
+        let request: AlterUserScramCredentialsRequest =
+            protocol::decode_request(key, header, body)?;
+
+        let authorized = self.authorize(AclOperation::Alter, ResourceType::Cluster, CLUSTER_NAME);
+
+        // Changes of every user, in the order the users first appear. A
+        // change without a credential is a deletion.
+        let mut users: Vec<(String, std::result::Result<Vec<ScramChange>, ResultError>)> = vec![];
+        let deletions = request
+            .deletions
+            .into_iter()
+            .map(|deletion| (deletion.name, deletion.mechanism, None));
+        let upsertions = request
+            .upsertions
+            .into_iter()
+            .map(|upsertion| (upsertion.name.clone(), upsertion.mechanism, Some(upsertion)));
+        for (user, mechanism, upsertion) in deletions.chain(upsertions) {
+            let i = match users.iter().position(|(name, _)| *name == user) {
+                Some(i) => i,
+                None => {
+                    users.push((user.clone(), Ok(vec![])));
+                    users.len() - 1
+                }
+            };
+            if let Ok(changes) = &mut users[i].1 {
+                let change = if authorized {
+                    self.scram_change(&user, mechanism, upsertion, changes)
+                } else {
+                    let err = Error::ClusterAuthorizationFailed;
+                    Err((err.code(), err.to_string()))
+                };
+                match change {
+                    Ok(change) => changes.push(change),
+                    Err(err) => users[i].1 = Err(err),
+                }
+            }
+        }
+
+        let results = users
+            .into_iter()
+            .map(|(user, changes)| {
+                let res = changes.and_then(|changes| {
+                    for (mechanism, credential) in changes {
+                        let res = match credential {
+                            Some(credential) => {
+                                self.credentials.upsert(&user, mechanism, credential)
+                            }
+                            None => self.credentials.delete(&user, mechanism).map(|_| ()),
+                        };
+                        res.map_err(|err| (ErrorCode::KafkaStorageError, err.to_string()))?;
+                    }
+                    info!(%user, principal = %self.principal, "altered SCRAM credentials");
+                    Ok(())
+                });
+                let (error_code, error_message) = match res {
+                    Ok(()) => (ErrorCode::None, None),
+                    Err((error_code, msg)) => (error_code, Some(msg)),
+                };
+                AlterUserScramCredentialsResult {
+                    user,
+                    error_code,
+                    error_message,
+                }
+            })
+            .collect();
+
+        let response = AlterUserScramCredentialsResponse {
+            throttle_time_ms: throttle_time_ms(throttle),
+            results,
+        };
+        Ok(protocol::encode_response(key, header, &response))
+    }
+
+    // Validate a change of the credential of `user` for the mechanism of
+    // type `mechanism`, given the previous `changes` of the user.
+    fn scram_change(
+        &self,
+        user: &str,
+        mechanism: i8,
+        upsertion: Option<ScramCredentialUpsertion>,
+        changes: &[ScramChange],
+    ) -> std::result::Result<ScramChange, ResultError> {
+        if user.is_empty() {
+            return Err((ErrorCode::UnacceptableCredential, "empty user name".into()));
+        }
+        let mechanism = ScramMechanism::from_type_code(mechanism).ok_or_else(|| {
+            let msg = format!("unknown SCRAM mechanism type {}", mechanism);
+            (ErrorCode::UnsupportedSaslMechanism, msg)
+        })?;
+        if changes.iter().any(|(m, _)| *m == mechanism) {
+            let msg = format!("{} credential is altered more than once", mechanism);
+            return Err((ErrorCode::DuplicateResource, msg));
+        }
+
+        let upsertion = match upsertion {
+            Some(upsertion) => upsertion,
+            None if self.credentials.get(user, mechanism).is_none() => {
+                let msg = format!("user has no {} credential", mechanism);
+                return Err((ErrorCode::ResourceNotFound, msg));
+            }
+            None => return Ok((mechanism, None)),
+        };
+
+        let iterations = upsertion.iterations;
+        let (min, max) = (
+            ScramMechanism::MIN_ITERATIONS,
+            ScramMechanism::MAX_ITERATIONS,
+        );
+        if iterations < min as i32 || iterations > max as i32 {
+            let msg = format!("iterations must be between {} and {}", min, max);
+            return Err((ErrorCode::UnacceptableCredential, msg));
+        }
+        if upsertion.salt.is_empty() || upsertion.salted_password.is_empty() {
+            let msg = "empty salt or salted password".into();
+            return Err((ErrorCode::UnacceptableCredential, msg));
+        }
+
+        let credential = ScramCredential::from_salted_password(
+            mechanism,
+            upsertion.salt.to_vec(),
+            &upsertion.salted_password,
+            iterations as u32,
+        );
+        Ok((mechanism, Some(credential)))
+    }
+}
+
+/// Change of a SCRAM credential, `None` deleting it.
+type ScramChange = (ScramMechanism, Option<ScramCredential>);
@@ -1,29 +1,124 @@
+use rustls::ServerConfig;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
 use tokio::sync::{broadcast, mpsc, Semaphore};
 use tokio::time::{self, Duration};
-use tracing::{error, info};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, warn};
 
-use super::{connection::Connection, handler::Handler, shutdown::Shutdown};
+use super::authorizer::Authorizer;
+use super::connection_quotas::ConnectionQuotas;
+use super::dynamic_config::DynamicConfig;
+use super::endpoint::ListenerConfig;
+use super::metadata::MetadataCache;
+use super::metrics::Metrics;
+use super::principal::Principal;
+use super::purgatory::Purgatory;
+use super::quota::QuotaManager;
+use super::sasl::CredentialStore;
+#[cfg(unix)]
+use super::unix;
+use super::{
+    connection::{Connection, Socket},
+    handler::{Context, Handler},
+    shutdown::Shutdown,
+    tls, Config,
+};
+use crate::store::{LogManager, TopicPartition};
 use std::io::Result;
 
+/// Socket a `Listener` accepts connections on.
+#[derive(Debug)]
+pub enum Acceptor {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// Address of the client of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerAddr {
+    Inet(SocketAddr),
+    /// Client of the Unix domain socket, known by the credentials of its
+    /// process.
+    Unix {
+        uid: u32,
+        gid: u32,
+    },
+}
+
+impl PeerAddr {
+    /// IP address of the client. Clients of the Unix domain socket run on
+    /// the same host as the broker, so they are counted against the limits
+    /// of the loopback address, and match the ACLs of its host.
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            PeerAddr::Inet(addr) => addr.ip(),
+            PeerAddr::Unix { .. } => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        }
+    }
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Inet(addr) => addr.fmt(f),
+            PeerAddr::Unix { uid, gid } => write!(f, "unix:uid={},gid={}", uid, gid),
+        }
+    }
+}
+
 /// Server listener state. Created in the `run` call. It includes a `run` method
 /// which performs the TCP listening and initialization of per-connection state.
 #[derive(Debug)]
 pub struct Listener {
-    /// Shared database handle.
-    ///
-    /// Contains the key / value store as well as the broadcast channels for
-    /// pub/sub.
+    /// Shared partitions handle.
     ///
-    /// This is a wrapper around an `Arc`. This enables `db` to be cloned and
+    /// This is a wrapper around an `Arc`. This enables `logs` to be cloned and
     /// passed into the per connection state (`Handler`).
-    // db: Db,
+    pub logs: LogManager,
 
-    /// TCP listener supplied by the `run` caller.
-    pub listener: TcpListener,
+    /// Broker-wide metadata cache, shared with every `Handler`.
+    pub metadata: MetadataCache,
 
-    /// Limit the max number of connections.
+    /// SCRAM credentials of the users, shared with every `Handler`.
+    pub credentials: CredentialStore,
+
+    /// Authorizer of requests, shared with every `Handler`. `None` when
+    /// authorization is disabled.
+    pub authorizer: Option<Arc<dyn Authorizer>>,
+
+    /// Client quotas and usage, shared with every `Handler`.
+    pub quotas: QuotaManager,
+
+    /// Fetch requests parked until records are appended to their
+    /// partitions, shared with every `Handler`.
+    pub fetch_purgatory: Purgatory<TopicPartition>,
+
+    /// TCP listener supplied by the `run` caller, or the Unix domain socket
+    /// bound by `run`.
+    pub listener: Acceptor,
+
+    /// Name and security protocol of the listener, shared with every
+    /// `Handler`.
+    pub listener_config: Arc<ListenerConfig>,
+
+    /// Configuration of the TLS handshake of accepted connections, `None`
+    /// unless the security protocol of the listener uses TLS.
+    pub tls: Option<Arc<ServerConfig>>,
+
+    /// Server configuration supplied by the `run` caller, shared with every
+    /// `Handler`.
+    pub config: Arc<Config>,
+
+    /// Limit the max number of connections of the broker, shared by every
+    /// listener.
     ///
     /// A `Semaphore` is used to limit the max number of connections. Before
     /// attempting to accept a new connection, a permit is acquired from the
@@ -33,6 +128,28 @@ pub struct Listener {
     /// to the semaphore.
     pub limit_connections: Arc<Semaphore>,
 
+    /// Limit the max number of connections of the listener, within
+    /// `limit_connections`. Its permit is acquired first, so that a
+    /// listener at its own limit does not hold a permit of the broker.
+    pub limit_listener_connections: Arc<Semaphore>,
+
+    /// Number of permits of `limit_listener_connections`.
+    pub max_listener_connections: usize,
+
+    /// Limits on the connections of each client IP address. Connections
+    /// exceeding them are closed as soon as they are accepted, so that a
+    /// single client cannot take every permit of `limit_connections`.
+    pub connection_quotas: ConnectionQuotas,
+
+    /// Configuration changed at runtime, shared with every `Handler`.
+    pub dynamic_config: DynamicConfig,
+
+    /// Broker metrics, shared with every `Handler`. Connections are counted
+    /// by their handler, and the permits of `limit_listener_connections`
+    /// are exported alongside, as the listener holds one while it waits for
+    /// a connection.
+    pub metrics: Metrics,
+
     /// Broadcasts a shutdown signal to all active connections.
     ///
     /// The initial `shutdown` trigger is provided by the `run` caller. The
@@ -43,6 +160,10 @@ pub struct Listener {
     /// safe terminal state, and completes the task.
     pub notify_shutdown: broadcast::Sender<()>,
 
+    /// Broadcasts the end of the shutdown deadline to all active connections,
+    /// which are dropped on the spot, whatever they are doing.
+    pub notify_deadline: broadcast::Sender<()>,
+
     /// Used as part of the graceful shutdown process to wait for client
     /// connections to complete processing.
     ///
@@ -53,9 +174,8 @@ pub struct Listener {
     /// `shutdown_complete_tx`. When the listener shuts down, it drops the
     /// sender held by this `shutdown_complete_tx` field. Once all handler tasks
     /// complete, all clones of the `Sender` are also dropped. This results in
-    /// `shutdown_complete_rx.recv()` completing with `None`. At this point, it
-    /// is safe to exit the server process.
-    pub shutdown_complete_rx: mpsc::Receiver<()>,
+    /// the receiver held by `run` completing with `None`. At this point, it is
+    /// safe to exit the server process.
     pub shutdown_complete_tx: mpsc::Sender<()>,
 }
 
@@ -76,7 +196,13 @@ impl Listener {
     /// itself. One strategy for handling this is to implement a back off
     /// strategy, which is what we do here.
     pub async fn run(&mut self) -> Result<()> {
-        info!("accepting inbound connections");
+        let listener_name = &self.listener_config.name;
+        info!(listener = %listener_name, "accepting inbound connections");
+        self.metrics.add_listener(
+            listener_name,
+            self.limit_listener_connections.clone(),
+            self.max_listener_connections,
+        );
 
         loop {
             // Wait for a permit to become available
@@ -92,42 +218,147 @@ impl Listener {
             //
             // `acquire()` returns `Err` when the semaphore has been closed. We
             // don't ever close the sempahore, so `unwrap()` is safe.
+            self.limit_listener_connections
+                .acquire()
+                .await
+                .unwrap()
+                .forget();
             self.limit_connections.acquire().await.unwrap().forget();
 
             // Accept a new socket. This will attempt to perform error handling.
             // The `accept` method internally attempts to recover errors, so an
             // error here is non-recoverable.
-            let socket = self.accept().await?;
-
-            // Create the necessary per-connection handler state.
-            let mut handler = Handler {
-                // Get a handle to the shared database. Internally, this is an
-                // `Arc`, so a clone only increments the ref count.
-                // db: self.db.clone(),
-
-                // Initialize the connection state. This allocates read/write
-                // buffers to perform protocol frame parsing.
-                connection: Connection::new(socket),
-
-                // The connection state needs a handle to the max connections
-                // semaphore. When the handler is done processing the
-                // connection, a permit is added back to the semaphore.
-                limit_connections: self.limit_connections.clone(),
+            let (socket, peer_addr) = self.accept().await?;
 
-                // Receive shutdown notifications.
-                shutdown: Shutdown::new(self.notify_shutdown.subscribe()),
+            // Check the limits of the IP address of the client. Dropping the
+            // socket closes the connection, and the permits are given back as
+            // there is no handler to do it.
+            let ip_connection = match self.connection_quotas.open(peer_addr.ip()) {
+                Ok(ip_connection) => ip_connection,
+                Err(rejection) => {
+                    warn!(peer = %peer_addr, cause = %rejection, "connection rejected");
+                    self.limit_listener_connections.add_permits(1);
+                    self.limit_connections.add_permits(1);
+                    continue;
+                }
+            };
 
-                // Notifies the receiver half once all clones are
-                // dropped.
-                _shutdown_complete: self.shutdown_complete_tx.clone(),
+            // Get handles to the shared partitions, metadata, listener,
+            // credentials, authorizer, quotas, purgatory and configuration.
+            // Internally,
+            // these are `Arc`s, so a clone only increments the ref count.
+            // Clients are anonymous until they authenticate, but for clients
+            // of the Unix domain socket, which are the user of their process.
+            let principal = match peer_addr {
+                #[cfg(unix)]
+                PeerAddr::Unix { uid, gid } => unix::principal(uid, gid),
+                _ => Principal::anonymous(),
+            };
+            let mut context = Context {
+                logs: self.logs.clone(),
+                metadata: self.metadata.clone(),
+                listener: self.listener_config.clone(),
+                credentials: self.credentials.clone(),
+                authorizer: self.authorizer.clone(),
+                quotas: self.quotas.clone(),
+                fetch_purgatory: self.fetch_purgatory.clone(),
+                config: self.config.clone(),
+                dynamic_config: self.dynamic_config.clone(),
+                principal,
+                peer_addr,
+                metrics: self.metrics.clone(),
             };
+            let active_connection = self.metrics.open_connection(&self.listener_config.name);
+            let tls = self.tls.clone();
+            let limit_connections = self.limit_connections.clone();
+            let limit_listener_connections = self.limit_listener_connections.clone();
+            let shutdown = Shutdown::new(self.notify_shutdown.subscribe());
+            let shutdown_complete = self.shutdown_complete_tx.clone();
+            let mut deadline = Shutdown::new(self.notify_deadline.subscribe());
 
             // Spawn a new task to process the connections. Tokio tasks are like
             // asynchronous green threads and are executed concurrently.
             tokio::spawn(async move {
-                // Process the connection. If an error is encountered, log it.
-                if let Err(err) = handler.run().await {
-                    error!(cause = ?err, "connection error");
+                let process = async move {
+                    let config = context.config.clone();
+
+                    // Initialize the connection state. This allocates read/write
+                    // buffers to perform protocol frame parsing. TLS handshakes
+                    // are performed by the connection task, so that slow clients
+                    // do not hold back the listener.
+                    let connection = match tls {
+                        None => Connection::new(
+                            socket,
+                            config.max_request_size,
+                            config.write_stall_timeout,
+                        ),
+                        Some(tls) => {
+                            match handshake(tls, socket, config.connections_max_idle).await {
+                                Ok((stream, principal)) => {
+                                    context.principal = principal;
+                                    Connection::new(
+                                        stream,
+                                        config.max_request_size,
+                                        config.write_stall_timeout,
+                                    )
+                                }
+                                Err(err) => {
+                                    warn!(peer = %peer_addr, cause = %err, "TLS handshake failed");
+                                    // There is no handler to give the permits back.
+                                    limit_listener_connections.add_permits(1);
+                                    limit_connections.add_permits(1);
+                                    return;
+                                }
+                            }
+                        }
+                    };
+                    debug!(
+                        peer = %peer_addr,
+                        listener = %context.listener.name,
+                        principal = %context.principal,
+                        "connection established"
+                    );
+
+                    // Create the necessary per-connection handler state.
+                    let mut handler = Handler {
+                        context,
+                        connection,
+
+                        // The connection state needs a handle to the max connections
+                        // semaphores. When the handler is done processing the
+                        // connection, a permit is added back to each semaphore.
+                        limit_connections,
+                        limit_listener_connections,
+
+                        // Counts the connection against the limits of the IP
+                        // address of the client until the handler is dropped.
+                        _ip_connection: ip_connection,
+
+                        // Counts the connection in the metrics until the
+                        // handler is dropped.
+                        _active_connection: active_connection,
+
+                        // Receive shutdown notifications.
+                        shutdown,
+
+                        // Notifies the receiver half once all clones are
+                        // dropped.
+                        _shutdown_complete: shutdown_complete,
+                    };
+
+                    // Process the connection. If an error is encountered, log it.
+                    if let Err(err) = handler.run().await {
+                        error!(peer = %handler.context.peer_addr, cause = ?err, "connection error");
+                    }
+                };
+
+                // Connections still open when the shutdown deadline passes
+                // are dropped.
+                tokio::select! {
+                    _ = process => {}
+                    _ = deadline.recv() => {
+                        warn!(peer = %peer_addr, "connection dropped at the shutdown deadline");
+                    }
                 }
             });
         }
@@ -140,19 +371,19 @@ impl Listener {
     /// After the second failure, the task waits for 2 seconds. Each subsequent
     /// failure doubles the wait time. If accepting fails on the 6th try after
     /// waiting for 64 seconds, then this function returns with an error.
-    async fn accept(&mut self) -> Result<TcpStream> {
+    async fn accept(&mut self) -> Result<(Box<dyn Socket>, PeerAddr)> {
         let mut backoff = 1;
 
         // Try to accept a few times
         loop {
             // Perform the accept operation. If a socket is successfully
             // accepted, return it. Otherwise, save the error.
-            match self.listener.accept().await {
-                Ok((socket, _)) => return Ok(socket),
+            match accept(&self.listener).await {
+                Ok(accepted) => return Ok(accepted),
                 Err(err) => {
                     if backoff > 64 {
                         // Accept has failed too many times. Return the error.
-                        return Err(err.into());
+                        return Err(err);
                     }
                 }
             }
@@ -165,3 +396,53 @@ impl Listener {
         }
     }
 }
+
+// Accept a connection on `listener`. Clients of the Unix domain socket are
+// identified by the credentials of their process. Only errors of the
+// listener are returned: a client whose credentials cannot be read, e.g.
+// because it is already gone, is dropped and the next one accepted.
+async fn accept(listener: &Acceptor) -> Result<(Box<dyn Socket>, PeerAddr)> {
+    match listener {
+        Acceptor::Tcp(listener) => {
+            let (socket, addr) = listener.accept().await?;
+            Ok((Box::new(socket), PeerAddr::Inet(addr)))
+        }
+        #[cfg(unix)]
+        Acceptor::Unix(listener) => loop {
+            let (socket, _) = listener.accept().await?;
+            match socket.peer_cred() {
+                Ok(cred) => {
+                    let peer_addr = PeerAddr::Unix {
+                        uid: cred.uid(),
+                        gid: cred.gid(),
+                    };
+                    return Ok((Box::new(socket), peer_addr));
+                }
+                Err(err) => warn!(cause = %err, "failed to read the credentials of a client"),
+            }
+        },
+    }
+}
+
+// Perform the TLS handshake of a connection, returning the stream and the
+// principal of the client: the subject of its certificate, or the anonymous
+// user if it did not present one.
+async fn handshake<S: Socket + 'static>(
+    config: Arc<ServerConfig>,
+    socket: S,
+    timeout: Duration,
+) -> Result<(TlsStream<S>, Principal)> {
+    let acceptor = TlsAcceptor::from(config);
+    let stream = time::timeout(timeout, acceptor.accept(socket))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "handshake timed out"))??;
+
+    let principal = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .and_then(tls::principal)
+        .unwrap_or_else(Principal::anonymous);
+    Ok((stream, principal))
+}
@@ -0,0 +1,125 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+use crate::store::LogManager;
+
+/// A broker of the cluster, as advertised to clients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Broker {
+    pub node_id: i32,
+    /// Host and port clients should connect to, which may differ from the
+    /// address the broker is bound to.
+    pub host: String,
+    pub port: i32,
+    pub rack: Option<String>,
+}
+
+/// Leadership of a partition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionMetadata {
+    pub index: i32,
+    pub leader: i32,
+    pub leader_epoch: i32,
+    pub replicas: Vec<i32>,
+    pub isr: Vec<i32>,
+}
+
+/// Broker-wide view of the cluster: its brokers, and the topics and
+/// partitions they lead.
+///
+/// The cache is built from the partitions found in the log directory when
+/// the server starts, and updated every time a topic is created, so that
+/// Metadata requests do not have to go through the partitions. The broker
+/// is the only one of its cluster: it leads every partition and is its only
+/// replica.
+///
+/// `MetadataCache` is a wrapper around an `Arc`, clones share the same view.
+#[derive(Debug, Clone)]
+pub struct MetadataCache {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    cluster_id: String,
+    node_id: i32,
+    /// Advertised host and port of the broker, by listener name.
+    endpoints: HashMap<String, (String, i32)>,
+    // Number of partitions of every topic.
+    topics: RwLock<BTreeMap<String, i32>>,
+}
+
+impl MetadataCache {
+    /// Cache describing the broker `node_id`, advertised at `endpoints` by
+    /// listener name, and the partitions of `logs`.
+    pub fn new(
+        cluster_id: String,
+        node_id: i32,
+        endpoints: HashMap<String, (String, i32)>,
+        logs: &LogManager,
+    ) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                cluster_id,
+                node_id,
+                endpoints,
+                topics: RwLock::new(logs.topics()),
+            }),
+        }
+    }
+
+    pub fn cluster_id(&self) -> &str {
+        &self.shared.cluster_id
+    }
+
+    /// Id of the broker acting as controller.
+    pub fn controller_id(&self) -> i32 {
+        self.shared.node_id
+    }
+
+    /// Brokers as advertised to the clients of the listener `listener_name`.
+    /// Brokers without such a listener are left out.
+    pub fn brokers(&self, listener_name: &str) -> Vec<Broker> {
+        let shared = &*self.shared;
+        let endpoint = shared.endpoints.get(listener_name);
+        endpoint
+            .map(|(host, port)| Broker {
+                node_id: shared.node_id,
+                host: host.clone(),
+                port: *port,
+                rack: None,
+            })
+            .into_iter()
+            .collect()
+    }
+
+    /// Names of every topic, sorted.
+    pub fn topics(&self) -> Vec<String> {
+        self.shared.topics.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Partitions of `topic`, or `None` if the topic does not exist.
+    pub fn partitions(&self, topic: &str) -> Option<Vec<PartitionMetadata>> {
+        let count = *self.shared.topics.read().unwrap().get(topic)?;
+        let node_id = self.shared.node_id;
+        let partitions = (0..count)
+            .map(|index| PartitionMetadata {
+                index,
+                leader: node_id,
+                leader_epoch: 0,
+                replicas: vec![node_id],
+                isr: vec![node_id],
+            })
+            .collect();
+        Some(partitions)
+    }
+
+    /// Record that `topic` now has `num_partitions` partitions.
+    pub fn update_topic(&self, topic: &str, num_partitions: i32) {
+        self.shared
+            .topics
+            .write()
+            .unwrap()
+            .insert(topic.into(), num_partitions);
+    }
+}
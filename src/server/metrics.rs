@@ -0,0 +1,576 @@
+//! Broker metrics, exported over HTTP in the Prometheus text format.
+//!
+//! Requests, bytes and connections are recorded by the handlers as they go.
+//! Partition metrics are read from the partitions when the metrics are
+//! scraped, so that the partition writers do not have to report them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::time;
+use tracing::{debug, info, warn};
+
+use super::dynamic_config::DynamicConfig;
+use super::protocol::ApiKey;
+use super::shutdown::Shutdown;
+use crate::store::{LogManager, TopicPartition};
+
+/// Upper bounds of the buckets of request durations, in seconds.
+const BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// Maximum size of the head of an HTTP request.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// Time a scraper has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Metrics of the broker.
+///
+/// `Metrics` is a wrapper around an `Arc`: it is cheap to clone and every
+/// clone records to the same metrics.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    logs: LogManager,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // Durations of the requests, by API key.
+    requests: HashMap<i16, Histogram>,
+    // Bytes produced to and fetched from each topic.
+    bytes_in: BTreeMap<String, u64>,
+    bytes_out: BTreeMap<String, u64>,
+    // Connections open on each listener.
+    connections: BTreeMap<String, u64>,
+    // Connection limit of each listener, and its number of permits.
+    limits: BTreeMap<String, (Arc<Semaphore>, usize)>,
+    // Connection limit of the broker, whose number of permits is the
+    // current `max_connections`.
+    broker_limit: Option<(Arc<Semaphore>, DynamicConfig)>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // Number of observations in each bucket of `BUCKETS`, not cumulated.
+    // Observations larger than the last bucket are only counted in `count`.
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// A connection open on a listener, counted until it is dropped.
+#[derive(Debug)]
+pub struct ActiveConnection {
+    metrics: Metrics,
+    listener: String,
+}
+
+impl Metrics {
+    /// Metrics of a broker storing its partitions in `logs`.
+    pub fn new(logs: LogManager) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                logs,
+                state: Mutex::new(State::default()),
+            }),
+        }
+    }
+
+    /// Record a request to the API `key` which took `duration` to process.
+    pub fn record_request(&self, key: ApiKey, duration: Duration) {
+        let mut state = self.shared.state.lock().unwrap();
+        state
+            .requests
+            .entry(key as i16)
+            .or_default()
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Record `bytes` of records produced to `topic`.
+    pub fn record_bytes_in(&self, topic: &str, bytes: usize) {
+        let mut state = self.shared.state.lock().unwrap();
+        *state.bytes_in.entry(topic.to_owned()).or_default() += bytes as u64;
+    }
+
+    /// Record `bytes` of records fetched from `topic`.
+    pub fn record_bytes_out(&self, topic: &str, bytes: usize) {
+        let mut state = self.shared.state.lock().unwrap();
+        *state.bytes_out.entry(topic.to_owned()).or_default() += bytes as u64;
+    }
+
+    /// Count the listener `listener`, without any connection yet. The
+    /// permits taken from `limit`, out of `max_connections`, are exported
+    /// along with the connections.
+    pub fn add_listener(&self, listener: &str, limit: Arc<Semaphore>, max_connections: usize) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.connections.entry(listener.to_owned()).or_default();
+        state
+            .limits
+            .insert(listener.to_owned(), (limit, max_connections));
+    }
+
+    /// Export the permits taken from `limit`, the connection limit of the
+    /// broker shared by the listeners, out of the `max_connections` of
+    /// `config`.
+    pub fn set_broker_limit(&self, limit: Arc<Semaphore>, config: DynamicConfig) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.broker_limit = Some((limit, config));
+    }
+
+    /// Count a connection open on `listener` until the returned value is
+    /// dropped.
+    pub fn open_connection(&self, listener: &str) -> ActiveConnection {
+        let mut state = self.shared.state.lock().unwrap();
+        *state.connections.entry(listener.to_owned()).or_default() += 1;
+        ActiveConnection {
+            metrics: self.clone(),
+            listener: listener.to_owned(),
+        }
+    }
+
+    /// Every metric, in the Prometheus text format.
+    pub async fn render(&self) -> String {
+        let mut out = String::new();
+        self.render_recorded(&mut out);
+        self.render_partitions(&mut out).await;
+        out
+    }
+
+    fn render_recorded(&self, out: &mut String) {
+        let state = self.shared.state.lock().unwrap();
+
+        // API keys are sorted by name.
+        let mut requests: Vec<_> = state
+            .requests
+            .iter()
+            .filter_map(|(key, histogram)| {
+                ApiKey::from_i16(*key).map(|key| (format!("{:?}", key), histogram))
+            })
+            .collect();
+        requests.sort_by(|a, b| a.0.cmp(&b.0));
+        header(
+            out,
+            "fafka_request_duration_seconds",
+            "histogram",
+            "Time from reading a request to having its response ready, by API.",
+        );
+        for (api, histogram) in requests {
+            histogram.render(out, "fafka_request_duration_seconds", &[("api", &api)]);
+        }
+
+        header(
+            out,
+            "fafka_topic_bytes_in_total",
+            "counter",
+            "Bytes of records produced to a topic.",
+        );
+        for (topic, bytes) in &state.bytes_in {
+            sample(
+                out,
+                "fafka_topic_bytes_in_total",
+                &[("topic", topic)],
+                *bytes,
+            );
+        }
+        header(
+            out,
+            "fafka_topic_bytes_out_total",
+            "counter",
+            "Bytes of records fetched from a topic.",
+        );
+        for (topic, bytes) in &state.bytes_out {
+            sample(
+                out,
+                "fafka_topic_bytes_out_total",
+                &[("topic", topic)],
+                *bytes,
+            );
+        }
+
+        header(
+            out,
+            "fafka_connections_active",
+            "gauge",
+            "Connections open on a listener.",
+        );
+        for (listener, count) in &state.connections {
+            sample(
+                out,
+                "fafka_connections_active",
+                &[("listener", listener)],
+                *count,
+            );
+        }
+        header(
+            out,
+            "fafka_connection_permits_used",
+            "gauge",
+            "Permits taken from the connection limit of a listener, by its open connections \
+             and the next one it waits for.",
+        );
+        for (listener, (limit, max_connections)) in &state.limits {
+            sample(
+                out,
+                "fafka_connection_permits_used",
+                &[("listener", listener)],
+                max_connections.saturating_sub(limit.available_permits()),
+            );
+        }
+        if let Some((limit, config)) = &state.broker_limit {
+            header(
+                out,
+                "fafka_broker_connection_permits_used",
+                "gauge",
+                "Permits taken from the connection limit of the broker, by the open \
+                 connections of every listener and the next ones they wait for.",
+            );
+            let max_connections = config.config().max_connections;
+            sample::<&str, &str>(
+                out,
+                "fafka_broker_connection_permits_used",
+                &[],
+                max_connections.saturating_sub(limit.available_permits()),
+            );
+        }
+    }
+
+    async fn render_partitions(&self, out: &mut String) {
+        // Partitions closed in the meantime are left out.
+        let mut partitions = vec![];
+        for (tp, partition) in self.shared.logs.partitions() {
+            if let Ok(stats) = partition.stats().await {
+                partitions.push((tp, partition.end_offset(), stats));
+            }
+        }
+        let labels = |tp: &TopicPartition| {
+            [
+                ("topic", tp.topic.clone()),
+                ("partition", tp.partition.to_string()),
+            ]
+        };
+
+        header(
+            out,
+            "fafka_log_end_offset",
+            "gauge",
+            "Offset of the next record appended to a partition.",
+        );
+        for (tp, end_offset, _) in &partitions {
+            sample(out, "fafka_log_end_offset", &labels(tp), *end_offset);
+        }
+        header(
+            out,
+            "fafka_log_segments",
+            "gauge",
+            "Segments of a partition.",
+        );
+        for (tp, _, stats) in &partitions {
+            sample(out, "fafka_log_segments", &labels(tp), stats.segments);
+        }
+        header(
+            out,
+            "fafka_log_size_bytes",
+            "gauge",
+            "Size of the files of a partition on disk.",
+        );
+        for (tp, _, stats) in &partitions {
+            sample(out, "fafka_log_size_bytes", &labels(tp), stats.size);
+        }
+        header(
+            out,
+            "fafka_log_flush_duration_seconds",
+            "summary",
+            "Time spent flushing the active segment of a partition after appends.",
+        );
+        for (tp, _, stats) in &partitions {
+            let labels = labels(tp);
+            let flush_time = stats.flush_time.as_secs_f64();
+            sample(
+                out,
+                "fafka_log_flush_duration_seconds_sum",
+                &labels,
+                flush_time,
+            );
+            sample(
+                out,
+                "fafka_log_flush_duration_seconds_count",
+                &labels,
+                stats.flushes,
+            );
+        }
+    }
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        let mut state = self.metrics.shared.state.lock().unwrap();
+        if let Some(count) = state.connections.get_mut(&self.listener) {
+            *count -= 1;
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &[(&str, &str)]) {
+        let bucket = format!("{}_bucket", name);
+        let mut cumulated = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.buckets.iter()) {
+            cumulated += count;
+            let le = bound.to_string();
+            let labels: Vec<_> = labels
+                .iter()
+                .copied()
+                .chain(Some(("le", &le[..])))
+                .collect();
+            sample(out, &bucket, &labels, cumulated);
+        }
+        let labels_inf: Vec<_> = labels.iter().copied().chain(Some(("le", "+Inf"))).collect();
+        sample(out, &bucket, &labels_inf, self.count);
+        sample(out, &format!("{}_sum", name), labels, self.sum);
+        sample(out, &format!("{}_count", name), labels, self.count);
+    }
+}
+
+fn header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, metric_type);
+}
+
+fn sample<K, V>(out: &mut String, name: &str, labels: &[(K, V)], value: impl ToString)
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    out.push_str(name);
+    if !labels.is_empty() {
+        let labels: Vec<_> = labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key.as_ref(), escape(value.as_ref())))
+            .collect();
+        let _ = write!(out, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(out, " {}", value.to_string());
+}
+
+// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Serve the metrics on `listener` until the shutdown signal is received.
+/// `GET /metrics` requests are answered with the metrics, other requests
+/// with an error. Connections are closed after each response.
+pub async fn serve(listener: TcpListener, metrics: Metrics, mut shutdown: Shutdown) {
+    if let Ok(addr) = listener.local_addr() {
+        info!(%addr, "serving metrics");
+    }
+    let mut backoff = 1;
+    loop {
+        let res = tokio::select! {
+            res = listener.accept() => res,
+            _ = shutdown.recv() => return,
+        };
+        let socket = match res {
+            Ok((socket, _)) => {
+                backoff = 1;
+                socket
+            }
+            Err(err) => {
+                // Errors such as running out of file descriptors resolve
+                // over time. Back off like the listeners do, doubling the
+                // wait up to a minute, but keep serving the metrics.
+                warn!(cause = %err, backoff, "failed to accept metrics connection");
+                tokio::select! {
+                    _ = time::sleep(Duration::from_secs(backoff)) => {}
+                    _ = shutdown.recv() => return,
+                }
+                backoff = (backoff * 2).min(64);
+                continue;
+            }
+        };
+
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(err) = answer(socket, &metrics).await {
+                debug!(cause = %err, "metrics connection error");
+            }
+        });
+    }
+}
+
+// Read the head of an HTTP request and write the response.
+async fn answer(mut socket: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    let mut head = Vec::new();
+    let read = async {
+        let mut buf = [0; 1024];
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+            if head.len() > MAX_REQUEST_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "request too large",
+                ));
+            }
+            match socket.read(&mut buf).await? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => head.extend_from_slice(&buf[..n]),
+            }
+        }
+        Ok(())
+    };
+    time::timeout(REQUEST_TIMEOUT, read)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request timed out"))??;
+
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or("").split_whitespace();
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render().await),
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        status,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::connection_quotas::ConnectionQuotas;
+    use crate::server::Config;
+    use crate::store::segment::Record;
+    use crate::store::LogConfig;
+    use tempfile::tempdir;
+    use tokio::sync::broadcast;
+
+    #[tokio::test]
+    async fn test_render() {
+        let tmp_dir = tempdir().unwrap();
+        let logs = LogManager::open(tmp_dir.path(), LogConfig::default()).unwrap();
+        logs.create_topic("events", 1).unwrap();
+        let partition = logs.get(&TopicPartition::new("events", 0)).unwrap();
+        partition
+            .append(vec![Record::new(vec![0; 8])])
+            .await
+            .unwrap();
+
+        let limit_connections = Arc::new(Semaphore::new(20));
+        let config = Config {
+            log_dir: tmp_dir.path().to_owned(),
+            max_connections: 20,
+            ..Config::default()
+        };
+        let quotas =
+            ConnectionQuotas::new(usize::MAX, HashMap::new(), None, Duration::from_secs(1), 11);
+        let config = DynamicConfig::open(config, logs.clone(), quotas, limit_connections.clone())
+            .await
+            .unwrap();
+
+        let metrics = Metrics::new(logs);
+        metrics.set_broker_limit(limit_connections.clone(), config);
+        metrics.record_request(ApiKey::Metadata, Duration::from_millis(3));
+        metrics.record_request(ApiKey::Metadata, Duration::from_secs(10));
+        metrics.record_bytes_in("events", 100);
+        metrics.record_bytes_out("events", 40);
+        metrics.record_bytes_out("events", 60);
+        let limit = Arc::new(Semaphore::new(10));
+        metrics.add_listener("EXTERNAL", limit.clone(), 10);
+        limit.acquire_many(2).await.unwrap().forget();
+        limit_connections.acquire_many(3).await.unwrap().forget();
+        let connection = metrics.open_connection("INTERNAL");
+
+        let text = metrics.render().await;
+        let lines: Vec<_> = text.lines().collect();
+        for line in [
+            r#"fafka_request_duration_seconds_bucket{api="Metadata",le="0.0025"} 0"#,
+            r#"fafka_request_duration_seconds_bucket{api="Metadata",le="0.005"} 1"#,
+            r#"fafka_request_duration_seconds_bucket{api="Metadata",le="5"} 1"#,
+            r#"fafka_request_duration_seconds_bucket{api="Metadata",le="+Inf"} 2"#,
+            r#"fafka_request_duration_seconds_count{api="Metadata"} 2"#,
+            r#"fafka_topic_bytes_in_total{topic="events"} 100"#,
+            r#"fafka_topic_bytes_out_total{topic="events"} 100"#,
+            r#"fafka_connections_active{listener="EXTERNAL"} 0"#,
+            r#"fafka_connections_active{listener="INTERNAL"} 1"#,
+            r#"fafka_connection_permits_used{listener="EXTERNAL"} 2"#,
+            "fafka_broker_connection_permits_used 3",
+            r#"fafka_log_end_offset{topic="events",partition="0"} 1"#,
+            r#"fafka_log_segments{topic="events",partition="0"} 1"#,
+            r#"fafka_log_flush_duration_seconds_count{topic="events",partition="0"} 1"#,
+            "# TYPE fafka_request_duration_seconds histogram",
+        ]
+        .iter()
+        {
+            assert!(lines.contains(line), "{} not in\n{}", line, text);
+        }
+
+        drop(connection);
+        let text = metrics.render().await;
+        assert!(text.contains("fafka_connections_active{listener=\"INTERNAL\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let tmp_dir = tempdir().unwrap();
+        let logs = LogManager::open(tmp_dir.path(), LogConfig::default()).unwrap();
+        let metrics = Metrics::new(logs);
+        metrics.record_bytes_in("events", 1);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (notify_shutdown, _) = broadcast::channel(1);
+        let shutdown = Shutdown::new(notify_shutdown.subscribe());
+        let server = tokio::spawn(serve(listener, metrics, shutdown));
+
+        let get = |path: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\n\r\n# HELP "));
+        assert!(response.contains("fafka_topic_bytes_in_total{topic=\"events\"} 1\n"));
+        assert!(get("/").await.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        drop(notify_shutdown);
+        server.await.unwrap();
+    }
+}
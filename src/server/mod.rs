@@ -1,58 +1,422 @@
+mod authorizer;
+mod broker_config;
 mod connection;
+mod connection_quotas;
+mod dynamic_config;
+mod endpoint;
+mod error;
+mod frame;
 mod handler;
 mod listener;
+mod metadata;
+mod metrics;
+mod persist;
+mod principal;
+mod protocol;
+mod purgatory;
+mod quota;
+mod sasl;
 mod shutdown;
+mod tls;
+#[cfg(unix)]
+mod unix;
 
+use futures::future;
+use futures::stream::{Stream, StreamExt};
+use std::collections::HashMap;
 use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::sync::{broadcast, mpsc, Semaphore};
-use tracing::{error, info};
+use tokio::time;
+use tracing::{error, info, warn};
 
-use crate::server::listener::Listener;
+use crate::server::authorizer::{AclAuthorizer, Authorizer};
+use crate::server::connection_quotas::ConnectionQuotas;
+use crate::server::dynamic_config::DynamicConfig;
+use crate::server::listener::{Acceptor, Listener};
+use crate::server::metadata::MetadataCache;
+use crate::server::metrics::Metrics;
+use crate::server::purgatory::Purgatory;
+use crate::server::quota::QuotaManager;
+use crate::server::shutdown::Shutdown;
+use crate::store::{LogConfig, LogManager};
 use std::io::Result;
-/// Maximum number of concurrent connections the server will accept.
-///
-/// When this limit is reached, the server will stop accepting connections until
-/// an active connection terminates.
-///
-/// A real application will want to make this value configurable, but for this
-/// example, it is hard coded.
-///
-/// This is also set to a pretty low value to discourage using this in
-/// production (you'd think that all the disclaimers would make it obvious that
-/// this is not a serious project... but I thought that about mini-http as
-/// well).
-const MAX_CONNECTIONS: usize = 250;
-
-/// Accepts connections from the supplied listener. For each inbound connection,
-/// a task is spawned to handle that connection. The serer runs until the
-/// `shutdown` future completes, at which point the server shuts down
-/// gracefully.
+
+pub use broker_config::{BrokerConfig, Properties, Source, ENV_PREFIX};
+pub use dynamic_config::DYNAMIC_KEYS;
+pub use endpoint::{Endpoint, ListenerConfig, SecurityProtocol};
+pub use error::{Error, ErrorCode};
+pub use principal::Principal;
+pub use protocol::records::TimestampType;
+pub use sasl::{CredentialStore, Mechanism, ScramCredential, ScramMechanism};
+pub use tls::{ClientAuth, TlsConfig};
+
+/// Server configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Maximum number of connections open at the same time, all listeners
+    /// together (`max.connections`). When it is reached, the listeners stop
+    /// accepting connections until one is closed.
+    pub max_connections: usize,
+
+    /// Maximum number of connections open at the same time on specific
+    /// listeners, by listener name, within `max_connections`
+    /// (`listener.name.<name>.max.connections`).
+    pub listener_max_connections: HashMap<String, usize>,
+
+    /// Maximum number of connections open at the same time from an IP
+    /// address (`max.connections.per.ip`). Further connections are closed
+    /// as soon as they are accepted.
+    pub max_connections_per_ip: usize,
+
+    /// Maximum number of connections of specific IP addresses, instead of
+    /// `max_connections_per_ip` (`max.connections.per.ip.overrides`).
+    pub max_connections_per_ip_overrides: HashMap<IpAddr, usize>,
+
+    /// Maximum number of connections an IP address may create per second,
+    /// measured over the quota windows. Further connections are closed as
+    /// soon as they are accepted, until the rate goes back down.
+    pub max_connection_creation_rate: Option<f64>,
+
+    /// Maximum size of a request in bytes (`socket.request.max.bytes`).
+    /// Connections sending larger requests are closed.
+    pub max_request_size: usize,
+
+    /// Time after which connections with no request in flight and no byte
+    /// read or written are closed (`connections.max.idle.ms`).
+    pub connections_max_idle: Duration,
+
+    /// Time after which connections whose responses make no progress, i.e.
+    /// whose send buffer does not drain, are closed.
+    pub write_stall_timeout: Duration,
+
+    /// Unix domain socket to accept connections on as well as the listeners
+    /// (`unix.socket.path`). It is served as a PLAINTEXT listener named
+    /// `UNIX`, and its clients are the user `uid=<uid>,gid=<gid>` of their
+    /// process. Only supported on Unix.
+    pub unix_socket_path: Option<PathBuf>,
+
+    /// Permissions of the Unix domain socket, which decide who may connect
+    /// to it (`unix.socket.mode`).
+    pub unix_socket_mode: u32,
+
+    /// Port metrics are served on over HTTP, at `/metrics` in the
+    /// Prometheus text format (`metrics.port`). Metrics are not served when
+    /// unset.
+    pub metrics_port: Option<u16>,
+
+    /// Host metrics are served on, every interface when empty
+    /// (`metrics.host`). Properties files default it to `host.name`.
+    pub metrics_host: String,
+
+    /// TLS configuration of the SSL and SASL_SSL listeners.
+    pub tls: Option<TlsConfig>,
+
+    /// SASL mechanisms clients of the SASL_PLAINTEXT and SASL_SSL listeners
+    /// authenticate with (`sasl.enabled.mechanisms`). Clients must
+    /// authenticate before sending any request but ApiVersions.
+    pub sasl_enabled_mechanisms: Vec<Mechanism>,
+
+    /// Authorize requests with the ACLs managed by CreateAcls and DeleteAcls
+    /// requests. When disabled, every request is allowed.
+    pub authorizer_enabled: bool,
+
+    /// Principals allowed every operation, whatever the ACLs
+    /// (`super.users`).
+    pub super_users: Vec<Principal>,
+
+    /// Allow every operation on resources without any ACL
+    /// (`allow.everyone.if.no.acl.found`).
+    pub allow_everyone_if_no_acl_found: bool,
+
+    /// Number of samples client usage is tracked in for quotas
+    /// (`quota.window.num`).
+    pub quota_window_num: u32,
+
+    /// Time covered by each sample of client usage
+    /// (`quota.window.size.seconds`).
+    pub quota_window_size: Duration,
+
+    /// Time connections are given to answer their requests in flight once
+    /// the server is shutting down (`shutdown.timeout.ms`). Connections still
+    /// open afterwards are dropped.
+    pub shutdown_timeout: Duration,
+
+    /// Maximum number of requests of a connection processed at the same
+    /// time. Further requests are not read until a response is written.
+    pub max_in_flight_requests: usize,
+
+    /// Id of the broker in the cluster (`broker.id`).
+    pub broker_id: i32,
+
+    /// Id of the cluster, sent to clients in Metadata responses.
+    pub cluster_id: String,
+
+    /// Directory holding the partitions.
+    pub log_dir: PathBuf,
+
+    /// Configuration of the partitions.
+    pub log: LogConfig,
+
+    /// Time between two deletions of the segments beyond the retention
+    /// limits of their partition (`log.retention.check.interval.ms`).
+    pub log_retention_check_interval: Duration,
+
+    /// Create topics the first time records are produced to them
+    /// (`auto.create.topics.enable`).
+    pub auto_create_topics: bool,
+
+    /// Number of partitions of automatically created topics
+    /// (`num.partitions`).
+    pub num_partitions: i32,
+
+    /// Maximum size of a record batch in bytes (`message.max.bytes`).
+    pub max_message_bytes: usize,
+
+    /// Whether records keep the timestamp set by producers or get the time
+    /// at which they are appended (`log.message.timestamp.type`).
+    pub message_timestamp_type: TimestampType,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_connections: 250,
+            listener_max_connections: HashMap::new(),
+            max_connections_per_ip: usize::MAX,
+            max_connections_per_ip_overrides: HashMap::new(),
+            max_connection_creation_rate: None,
+            max_request_size: 100 * 1024 * 1024,
+            connections_max_idle: Duration::from_secs(10 * 60),
+            write_stall_timeout: Duration::from_secs(30),
+            shutdown_timeout: Duration::from_secs(30),
+            unix_socket_path: None,
+            unix_socket_mode: 0o660,
+            metrics_port: None,
+            metrics_host: "127.0.0.1".into(),
+            tls: None,
+            sasl_enabled_mechanisms: vec![],
+            authorizer_enabled: false,
+            super_users: vec![],
+            allow_everyone_if_no_acl_found: false,
+            quota_window_num: 11,
+            quota_window_size: Duration::from_secs(1),
+            max_in_flight_requests: 5,
+            broker_id: 0,
+            cluster_id: "fafka".into(),
+            log_dir: PathBuf::from("/tmp/fafka-logs"),
+            log: LogConfig::default(),
+            log_retention_check_interval: Duration::from_secs(5 * 60),
+            auto_create_topics: true,
+            num_partitions: 1,
+            max_message_bytes: 1024 * 1024 + 12,
+            message_timestamp_type: TimestampType::CreateTime,
+        }
+    }
+}
+
+/// Accepts connections from the supplied listeners, each bound to a socket.
+/// For each inbound connection, a task is spawned to handle that connection,
+/// according to the configuration of its listener. Connections to the Unix
+/// domain socket at `unix_socket_path` are handled the same way. The serer
+/// runs until the `shutdown` future completes, at which point the server
+/// shuts down gracefully: connections stop reading requests and answer the
+/// ones in flight, within `shutdown_timeout`, then the partitions are
+/// flushed to disk.
 ///
 /// `tokio::signal::ctrl_c()` can be used as the `shutdown` argument. This will
 /// listen for a SIGINT signal.
-pub async fn run(listener: TcpListener, shutdown: impl Future) -> Result<()> {
+///
+/// Every configuration yielded by `reload` replaces `config`, e.g. after the
+/// properties file changed. Only the keys in `DYNAMIC_KEYS` are taken into
+/// account, and values set by IncrementalAlterConfigs requests win over them.
+/// `futures::stream::pending()` can be used when the configuration is never
+/// reloaded.
+pub async fn run(
+    listeners: Vec<(ListenerConfig, TcpListener)>,
+    config: Config,
+    reload: impl Stream<Item = Config>,
+    shutdown: impl Future,
+) -> Result<()> {
+    validate_listeners(&listeners, &config)?;
+
     // When the provided `shutdown` future completes, we must send a shutdown
     // message to all active connections. We use a broadcast channel for this
     // purpose. The call below ignores the receiver of the broadcast pair, and when
     // a receiver is needed, the subscribe() method on the sender is used to create
     // one.
     let (notify_shutdown, _) = broadcast::channel(1);
-    let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel(1);
-
-    // Initialize the listener state
-    let mut server = Listener {
-        listener,
-        // db: Db::new(),
-        limit_connections: Arc::new(Semaphore::new(MAX_CONNECTIONS)),
-        notify_shutdown,
-        shutdown_complete_tx,
-        shutdown_complete_rx,
+    let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel(1);
+    // Dropped when the connections take longer than `shutdown_timeout` to
+    // finish processing, so that they are dropped as well.
+    let (notify_deadline, _) = broadcast::channel(1);
+
+    let logs = LogManager::open(&config.log_dir, config.log.clone())
+        .map_err(|err| io::Error::other(err.to_string()))?;
+
+    // Listeners are advertised at the address they are bound to, unless
+    // configured otherwise. Clients cannot connect to the wildcard address,
+    // so listeners bound to every interface are advertised at the name of
+    // the machine instead.
+    let mut endpoints = HashMap::new();
+    for (listener_config, listener) in &listeners {
+        let local_addr = listener.local_addr()?;
+        let host = match &listener_config.advertised_host {
+            Some(host) => host.clone(),
+            None if local_addr.ip().is_unspecified() => hostname()?,
+            None => local_addr.ip().to_string(),
+        };
+        let port = listener_config.advertised_port.unwrap_or(local_addr.port());
+        endpoints.insert(listener_config.name.clone(), (host, port as i32));
+    }
+    let tls = match &config.tls {
+        Some(tls) if listeners.iter().any(|(l, _)| l.security_protocol.is_tls()) => {
+            Some(tls.server_config()?)
+        }
+        _ => None,
     };
+    let metadata = MetadataCache::new(
+        config.cluster_id.clone(),
+        config.broker_id,
+        endpoints,
+        &logs,
+    );
+    let credentials = CredentialStore::open(&config.log_dir)?;
+    let authorizer = if config.authorizer_enabled {
+        let authorizer = AclAuthorizer::open(
+            &config.log_dir,
+            config.super_users.clone(),
+            config.allow_everyone_if_no_acl_found,
+        )?;
+        Some(Arc::new(authorizer) as Arc<dyn Authorizer>)
+    } else {
+        None
+    };
+    let quotas = QuotaManager::open(
+        &config.log_dir,
+        config.quota_window_size,
+        config.quota_window_num,
+    )?;
+    let connection_quotas = ConnectionQuotas::new(
+        config.max_connections_per_ip,
+        config.max_connections_per_ip_overrides.clone(),
+        config.max_connection_creation_rate,
+        config.quota_window_size,
+        config.quota_window_num,
+    );
+    // The listeners share the limit of the broker, resized when
+    // `max_connections` changes, and each has its own limit within it.
+    let limit_connections = Arc::new(Semaphore::new(config.max_connections));
+    // Values changed at runtime override the configuration, and are applied
+    // to the partitions and the connection limits.
+    let dynamic_config = DynamicConfig::open(
+        config.clone(),
+        logs.clone(),
+        connection_quotas.clone(),
+        limit_connections.clone(),
+    )
+    .await?;
+
+    // Fetch requests waiting for records. The purgatory wakes them all up
+    // when the shutdown signal is received.
+    let fetch_purgatory = Purgatory::new(
+        "fetch",
+        Shutdown::new(notify_shutdown.subscribe()),
+        shutdown_complete_tx.clone(),
+    );
+
+    // Segments beyond the retention limits are deleted periodically until
+    // the shutdown signal is received.
+    tokio::spawn(delete_expired_segments(
+        logs.clone(),
+        config.log_retention_check_interval,
+        Shutdown::new(notify_shutdown.subscribe()),
+    ));
+
+    // Metrics are served until the shutdown signal is received, without
+    // holding back the shutdown.
+    let metrics = Metrics::new(logs.clone());
+    metrics.set_broker_limit(limit_connections.clone(), dynamic_config.clone());
+    if let Some(port) = config.metrics_port {
+        let host = if config.metrics_host.is_empty() {
+            "0.0.0.0"
+        } else {
+            &config.metrics_host
+        };
+        let listener = TcpListener::bind((host, port)).await?;
+        let shutdown = Shutdown::new(notify_shutdown.subscribe());
+        tokio::spawn(metrics::serve(listener, metrics.clone(), shutdown));
+    }
+
+    // The Unix domain socket is served as one more listener, which is not
+    // advertised.
+    let mut acceptors: Vec<_> = listeners
+        .into_iter()
+        .map(|(listener_config, listener)| (listener_config, Acceptor::Tcp(listener)))
+        .collect();
+    #[cfg(unix)]
+    if let Some(path) = &config.unix_socket_path {
+        let listener = unix::bind(path, config.unix_socket_mode)?;
+        let listener_config = ListenerConfig::new(unix::LISTENER_NAME, SecurityProtocol::Plaintext);
+        acceptors.push((listener_config, Acceptor::Unix(listener)));
+    }
+
+    // Initialize the state of every listener.
+    let config = Arc::new(config);
+    let mut servers: Vec<_> = acceptors
+        .into_iter()
+        .map(|(listener_config, listener)| {
+            let max_listener_connections = config
+                .listener_max_connections
+                .get(&listener_config.name)
+                .copied()
+                .unwrap_or(Semaphore::MAX_PERMITS);
+            Listener {
+                listener,
+                tls: tls
+                    .clone()
+                    .filter(|_| listener_config.security_protocol.is_tls()),
+                listener_config: Arc::new(listener_config),
+                config: config.clone(),
+                logs: logs.clone(),
+                metadata: metadata.clone(),
+                credentials: credentials.clone(),
+                authorizer: authorizer.clone(),
+                quotas: quotas.clone(),
+                fetch_purgatory: fetch_purgatory.clone(),
+                limit_listener_connections: Arc::new(Semaphore::new(max_listener_connections)),
+                max_listener_connections,
+                limit_connections: limit_connections.clone(),
+                connection_quotas: connection_quotas.clone(),
+                dynamic_config: dynamic_config.clone(),
+                metrics: metrics.clone(),
+                notify_shutdown: notify_shutdown.clone(),
+                notify_deadline: notify_deadline.clone(),
+                shutdown_complete_tx: shutdown_complete_tx.clone(),
+            }
+        })
+        .collect();
 
-    // Concurrently run the server and listen for the `shutdown` signal. The
-    // server task runs until an error is encountered, so under normal
+    // Apply the reloaded configurations as they come. Reloading never
+    // completes the `select!` below.
+    let reloads = async {
+        futures::pin_mut!(reload);
+        while let Some(config) = reload.next().await {
+            match dynamic_config.reload(config).await {
+                Ok(()) => info!("reloaded configuration"),
+                Err(err) => error!(cause = %err, "failed to reload configuration"),
+            }
+        }
+        future::pending::<()>().await
+    };
+
+    // Concurrently run the listeners and listen for the `shutdown` signal.
+    // The listeners run until an error is encountered, so under normal
     // circumstances, this `select!` statement runs until the `shutdown` signal
     // is received.
     //
@@ -71,10 +435,10 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) -> Result<()> {
     //
     // https://docs.rs/tokio/*/tokio/macro.select.html
     tokio::select! {
-        res = server.run() => {
-            // If an error is received here, accepting connections from the TCP
-            // listener failed multiple times and the server is giving up and
-            // shutting down.
+        res = future::try_join_all(servers.iter_mut().map(Listener::run)) => {
+            // If an error is received here, accepting connections from one
+            // of the TCP listeners failed multiple times and the server is
+            // giving up and shutting down.
             //
             // Errors encountered when handling individual connections do not
             // bubble up to this point.
@@ -86,17 +450,13 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) -> Result<()> {
             // The shutdown signal has been received.
             info!("shutting down");
         }
+        _ = reloads => {}
     }
 
-    // Extract the `shutdown_complete` receiver and transmitter
-    // explicitly drop `shutdown_transmitter`. This is important, as the
-    // `.await` below would otherwise never complete.
-    let Listener {
-        mut shutdown_complete_rx,
-        shutdown_complete_tx,
-        notify_shutdown,
-        ..
-    } = server;
+    // Drop the listeners, and the clones of `notify_shutdown` and
+    // `shutdown_complete_tx` they hold. This is important, as the `.await`
+    // below would otherwise never complete.
+    drop(servers);
     // When `notify_shutdown` is dropped, all tasks which have `subscribe`d will
     // receive the shutdown signal and can exit
     drop(notify_shutdown);
@@ -104,10 +464,131 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) -> Result<()> {
     drop(shutdown_complete_tx);
 
     // Wait for all active connections to finish processing. As the `Sender`
-    // handle held by the listener has been dropped above, the only remaining
+    // handles held by the listeners have been dropped above, the only remaining
     // `Sender` instances are held by connection handler tasks. When those drop,
     // the `mpsc` channel will close and `recv()` will return `None`.
-    let _ = shutdown_complete_rx.recv().await;
+    //
+    // Connections answer the requests they have in flight first. Those which
+    // are not done by the deadline are dropped.
+    let timeout = config.shutdown_timeout;
+    if time::timeout(timeout, shutdown_complete_rx.recv())
+        .await
+        .is_err()
+    {
+        warn!(?timeout, "dropping remaining connections");
+        drop(notify_deadline);
+        let _ = shutdown_complete_rx.recv().await;
+    }
+
+    // No connection is accepted anymore, so the socket is removed even if
+    // closing the partitions fails.
+    #[cfg(unix)]
+    if let Some(path) = &config.unix_socket_path {
+        let _ = std::fs::remove_file(path);
+    }
+
+    // Appends sent by the connections are processed before the partitions
+    // are closed. The next start skips recovering them.
+    logs.shutdown()
+        .await
+        .map_err(|err| io::Error::other(err.to_string()))?;
+    info!("shutdown complete");
 
     Ok(())
 }
+
+// Delete the segments beyond the retention limits of the partitions every
+// `interval`, until `shutdown`.
+async fn delete_expired_segments(logs: LogManager, interval: Duration, mut shutdown: Shutdown) {
+    let mut interval = time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let deleted = logs.delete_expired().await;
+                if deleted > 0 {
+                    info!(deleted, "deleted expired segments");
+                }
+            }
+            _ = shutdown.recv() => return,
+        }
+    }
+}
+
+// Check that the listeners can be served with `config`.
+fn validate_listeners(listeners: &[(ListenerConfig, TcpListener)], config: &Config) -> Result<()> {
+    let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    if listeners.is_empty() && config.unix_socket_path.is_none() {
+        return invalid("no listener".into());
+    }
+    if config.unix_socket_path.is_some() {
+        #[cfg(not(unix))]
+        return invalid("Unix domain sockets are not supported on this platform".into());
+        #[cfg(unix)]
+        if listeners.iter().any(|(l, _)| l.name == unix::LISTENER_NAME) {
+            return invalid(format!("duplicate listener {}", unix::LISTENER_NAME));
+        }
+    }
+    for name in config.listener_max_connections.keys() {
+        #[cfg(unix)]
+        if *name == unix::LISTENER_NAME && config.unix_socket_path.is_some() {
+            continue;
+        }
+        if !listeners.iter().any(|(l, _)| l.name == *name) {
+            return invalid(format!("connection limit of unknown listener {}", name));
+        }
+    }
+    for (i, (listener, _)) in listeners.iter().enumerate() {
+        let protocol = listener.security_protocol;
+        if listeners[..i].iter().any(|(l, _)| l.name == listener.name) {
+            return invalid(format!("duplicate listener {}", listener.name));
+        }
+        if let Some(host) = &listener.advertised_host {
+            if is_wildcard(host) {
+                return invalid(format!(
+                    "listener {} cannot be advertised at the wildcard address {:?}",
+                    listener.name, host
+                ));
+            }
+        }
+        if protocol.is_tls() && config.tls.is_none() {
+            return invalid(format!(
+                "{} listener {} requires a TLS configuration",
+                protocol, listener.name
+            ));
+        }
+        if protocol.is_sasl() && config.sasl_enabled_mechanisms.is_empty() {
+            return invalid(format!(
+                "{} listener {} requires SASL mechanisms",
+                protocol, listener.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Whether `host` stands for every interface of the machine, which clients
+// cannot connect to.
+fn is_wildcard(host: &str) -> bool {
+    host.is_empty() || host.parse().is_ok_and(|ip: IpAddr| ip.is_unspecified())
+}
+
+// Name of the machine, advertised for the listeners bound to every
+// interface.
+#[cfg(target_os = "linux")]
+fn hostname() -> Result<String> {
+    let mut buf = [0u8; 256];
+    let res = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn hostname() -> Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "listeners bound to every interface require an advertised host",
+    ))
+}
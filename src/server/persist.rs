@@ -0,0 +1,49 @@
+//! Persistence of the state the broker keeps besides partitions, such as
+//! credentials and ACLs, each in its own file of the log directory.
+//!
+//! Values are serialized with bincode. Every change is written to a new
+//! file which then replaces the previous one, so that a crash leaves either
+//! the old or the new value. Files are only readable by the broker.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Read the value stored in `path`, or the default value if no value was
+/// stored yet.
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> io::Result<T> {
+    match fs::read(path) {
+        Ok(bytes) => bincode::deserialize(&bytes).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid file {}: {}", path.display(), err),
+            )
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Replace the value stored in `path` with `value`.
+pub fn save<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    let bytes = bincode::serialize(value).map_err(io::Error::other)?;
+
+    let tmp = path.with_extension("tmp");
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+
+    // Persist the rename itself.
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
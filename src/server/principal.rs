@@ -0,0 +1,54 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Identity of the client of a connection, as established when it connected.
+///
+/// Principals are written `type:name`, like Kafka principals. Clients
+/// authenticated with a certificate are users named after its subject,
+/// clients that did not authenticate are the anonymous user.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Principal {
+    pub principal_type: String,
+    pub name: String,
+}
+
+impl Principal {
+    /// Type of the principals of users.
+    pub const USER_TYPE: &'static str = "User";
+
+    /// Name of the user of connections that did not authenticate.
+    pub const ANONYMOUS: &'static str = "ANONYMOUS";
+
+    pub fn user(name: impl Into<String>) -> Self {
+        Self {
+            principal_type: Self::USER_TYPE.into(),
+            name: name.into(),
+        }
+    }
+
+    pub fn anonymous() -> Self {
+        Self::user(Self::ANONYMOUS)
+    }
+}
+
+impl fmt::Display for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.principal_type, self.name)
+    }
+}
+
+impl FromStr for Principal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((principal_type, name)) if !principal_type.is_empty() && !name.is_empty() => {
+                Ok(Self {
+                    principal_type: principal_type.into(),
+                    name: name.into(),
+                })
+            }
+            _ => Err(format!("invalid principal {:?}, expected type:name", s)),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! AlterClientQuotas (key 49): set or remove the quotas of entities.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::describe_client_quotas::EntityData;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlterClientQuotasRequest {
+    pub entries: Vec<EntryData>,
+    /// Whether the changes are only validated, not applied.
+    pub validate_only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryData {
+    pub entity: Vec<EntityData>,
+    pub ops: Vec<OpData>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpData {
+    pub key: String,
+    pub value: f64,
+    /// Whether the quota is removed instead of set to `value`.
+    pub remove: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlterClientQuotasResponse {
+    pub throttle_time_ms: i32,
+    /// Result of each entry, in the order of the request.
+    pub entries: Vec<EntryResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryResult {
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub entity: Vec<EntityData>,
+}
+
+impl Decode for AlterClientQuotasRequest {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self> {
+        let flexible = version >= 1;
+        let entries = get_array(buf, flexible, |buf| {
+            let entity = get_array(buf, flexible, |buf| {
+                let entity = EntityData {
+                    entity_type: get_string(buf, flexible)?,
+                    entity_name: get_nullable_string(buf, flexible)?,
+                };
+                if flexible {
+                    skip_tagged_fields(buf)?;
+                }
+                Ok(entity)
+            })?;
+            let ops = get_array(buf, flexible, |buf| {
+                let op = OpData {
+                    key: get_string(buf, flexible)?,
+                    value: get_f64(buf)?,
+                    remove: get_bool(buf)?,
+                };
+                if flexible {
+                    skip_tagged_fields(buf)?;
+                }
+                Ok(op)
+            })?;
+            if flexible {
+                skip_tagged_fields(buf)?;
+            }
+            Ok(EntryData { entity, ops })
+        })?;
+        let validate_only = get_bool(buf)?;
+        if flexible {
+            skip_tagged_fields(buf)?;
+        }
+        Ok(Self {
+            entries,
+            validate_only,
+        })
+    }
+}
+
+impl Encode for AlterClientQuotasResponse {
+    fn encode(&self, buf: &mut BytesMut, version: i16) {
+        let flexible = version >= 1;
+        buf.put_i32(self.throttle_time_ms);
+        put_array(buf, &self.entries, flexible, |buf, entry| {
+            buf.put_i16(entry.error_code.code());
+            put_nullable_string(buf, entry.error_message.as_deref(), flexible);
+            put_array(buf, &entry.entity, flexible, |buf, entity| {
+                put_string(buf, &entity.entity_type, flexible);
+                put_nullable_string(buf, entity.entity_name.as_deref(), flexible);
+                if flexible {
+                    put_empty_tagged_fields(buf);
+                }
+            });
+            if flexible {
+                put_empty_tagged_fields(buf);
+            }
+        });
+        if flexible {
+            put_empty_tagged_fields(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        let mut buf = BytesMut::new();
+        buf.put_i32(1);
+        buf.put_i32(1);
+        put_string(&mut buf, "client-id", false);
+        put_nullable_string(&mut buf, None, false);
+        buf.put_i32(1);
+        put_string(&mut buf, "producer_byte_rate", false);
+        buf.put_f64(1024.0);
+        put_bool(&mut buf, false);
+        put_bool(&mut buf, true);
+
+        let request = AlterClientQuotasRequest::decode(&mut buf.freeze(), 0).unwrap();
+        assert_eq!(
+            request,
+            AlterClientQuotasRequest {
+                entries: vec![EntryData {
+                    entity: vec![EntityData {
+                        entity_type: "client-id".into(),
+                        entity_name: None,
+                    }],
+                    ops: vec![OpData {
+                        key: "producer_byte_rate".into(),
+                        value: 1024.0,
+                        remove: false,
+                    }],
+                }],
+                validate_only: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = AlterClientQuotasResponse {
+            throttle_time_ms: 0,
+            entries: vec![EntryResult {
+                error_code: ErrorCode::None,
+                error_message: None,
+                entity: vec![EntityData {
+                    entity_type: "user".into(),
+                    entity_name: Some("a".into()),
+                }],
+            }],
+        };
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 1);
+        assert_eq!(&buf[..], b"\0\0\0\0\x02\0\0\0\x02\x05user\x02a\0\0\0");
+    }
+}
@@ -0,0 +1,147 @@
+//! AlterUserScramCredentials (key 51): create, update and delete the SCRAM
+//! credentials of users.
+//!
+//! Clients never send passwords: upsertions carry the password already
+//! salted and iterated, from which the broker derives the keys it stores.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlterUserScramCredentialsRequest {
+    pub deletions: Vec<ScramCredentialDeletion>,
+    pub upsertions: Vec<ScramCredentialUpsertion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScramCredentialDeletion {
+    pub name: String,
+    /// Type of the SCRAM mechanism, see `CredentialInfo`.
+    pub mechanism: i8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScramCredentialUpsertion {
+    pub name: String,
+    /// Type of the SCRAM mechanism, see `CredentialInfo`.
+    pub mechanism: i8,
+    pub iterations: i32,
+    pub salt: Bytes,
+    pub salted_password: Bytes,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlterUserScramCredentialsResponse {
+    pub throttle_time_ms: i32,
+    pub results: Vec<AlterUserScramCredentialsResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlterUserScramCredentialsResult {
+    pub user: String,
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+}
+
+impl Decode for AlterUserScramCredentialsRequest {
+    fn decode(buf: &mut Bytes, _version: i16) -> Result<Self> {
+        let deletions = get_array(buf, true, |buf| {
+            let deletion = ScramCredentialDeletion {
+                name: get_string(buf, true)?,
+                mechanism: get_i8(buf)?,
+            };
+            skip_tagged_fields(buf)?;
+            Ok(deletion)
+        })?;
+        let upsertions = get_array(buf, true, |buf| {
+            let upsertion = ScramCredentialUpsertion {
+                name: get_string(buf, true)?,
+                mechanism: get_i8(buf)?,
+                iterations: get_i32(buf)?,
+                salt: get_bytes(buf, true)?,
+                salted_password: get_bytes(buf, true)?,
+            };
+            skip_tagged_fields(buf)?;
+            Ok(upsertion)
+        })?;
+        skip_tagged_fields(buf)?;
+
+        Ok(Self {
+            deletions,
+            upsertions,
+        })
+    }
+}
+
+impl Encode for AlterUserScramCredentialsResponse {
+    fn encode(&self, buf: &mut BytesMut, _version: i16) {
+        buf.put_i32(self.throttle_time_ms);
+        put_array(buf, &self.results, true, |buf, result| {
+            put_string(buf, &result.user, true);
+            buf.put_i16(result.error_code.code());
+            put_nullable_string(buf, result.error_message.as_deref(), true);
+            put_empty_tagged_fields(buf);
+        });
+        put_empty_tagged_fields(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        let mut buf = BytesMut::new();
+        put_unsigned_varint(&mut buf, 2);
+        put_string(&mut buf, "alice", true);
+        buf.put_i8(2);
+        put_empty_tagged_fields(&mut buf);
+        put_unsigned_varint(&mut buf, 2);
+        put_string(&mut buf, "bob", true);
+        buf.put_i8(1);
+        buf.put_i32(8192);
+        put_bytes(&mut buf, b"salt", true);
+        put_bytes(&mut buf, b"salted", true);
+        put_empty_tagged_fields(&mut buf);
+        put_empty_tagged_fields(&mut buf);
+
+        let request = AlterUserScramCredentialsRequest::decode(&mut buf.freeze(), 0).unwrap();
+        assert_eq!(
+            request.deletions,
+            vec![ScramCredentialDeletion {
+                name: "alice".into(),
+                mechanism: 2,
+            }]
+        );
+        assert_eq!(
+            request.upsertions,
+            vec![ScramCredentialUpsertion {
+                name: "bob".into(),
+                mechanism: 1,
+                iterations: 8192,
+                salt: Bytes::from_static(b"salt"),
+                salted_password: Bytes::from_static(b"salted"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = AlterUserScramCredentialsResponse {
+            throttle_time_ms: 0,
+            results: vec![AlterUserScramCredentialsResult {
+                user: "bob".into(),
+                error_code: ErrorCode::ResourceNotFound,
+                error_message: None,
+            }],
+        };
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 0);
+        assert_eq!(&buf[..], b"\0\0\0\0\x02\x04bob\0\x5b\0\0\0");
+    }
+}
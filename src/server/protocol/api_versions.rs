@@ -0,0 +1,131 @@
+//! ApiVersions (key 18): the first request sent by clients, to learn which
+//! APIs and versions the broker supports.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{ApiKey, Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApiVersionsRequest {
+    /// Name of the client library (v3+).
+    pub client_software_name: Option<String>,
+    /// Version of the client library (v3+).
+    pub client_software_version: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiVersion {
+    pub api_key: i16,
+    pub min_version: i16,
+    pub max_version: i16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiVersionsResponse {
+    pub error_code: ErrorCode,
+    pub api_keys: Vec<ApiVersion>,
+    pub throttle_time_ms: i32,
+}
+
+impl ApiVersionsResponse {
+    /// Response listing every API supported by the broker.
+    pub fn supported(error_code: ErrorCode) -> Self {
+        let api_keys = ApiKey::ALL
+            .iter()
+            .map(|key| {
+                let (min_version, max_version) = key.versions();
+                ApiVersion {
+                    api_key: *key as i16,
+                    min_version,
+                    max_version,
+                }
+            })
+            .collect();
+
+        Self {
+            error_code,
+            api_keys,
+            throttle_time_ms: 0,
+        }
+    }
+}
+
+impl Decode for ApiVersionsRequest {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self> {
+        if version < 3 {
+            return Ok(Self::default());
+        }
+
+        let request = Self {
+            client_software_name: Some(get_string(buf, true)?),
+            client_software_version: Some(get_string(buf, true)?),
+        };
+        skip_tagged_fields(buf)?;
+        Ok(request)
+    }
+}
+
+impl Encode for ApiVersionsResponse {
+    fn encode(&self, buf: &mut BytesMut, version: i16) {
+        let flexible = version >= 3;
+
+        buf.put_i16(self.error_code.code());
+        put_array(buf, &self.api_keys, flexible, |buf, api| {
+            buf.put_i16(api.api_key);
+            buf.put_i16(api.min_version);
+            buf.put_i16(api.max_version);
+            if flexible {
+                put_empty_tagged_fields(buf);
+            }
+        });
+        if version >= 1 {
+            buf.put_i32(self.throttle_time_ms);
+        }
+        if flexible {
+            put_empty_tagged_fields(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        let mut buf = BytesMut::new();
+        put_string(&mut buf, "librdkafka", true);
+        put_string(&mut buf, "2.3.0", true);
+        put_empty_tagged_fields(&mut buf);
+
+        let request = ApiVersionsRequest::decode(&mut buf.freeze(), 3).unwrap();
+        assert_eq!(request.client_software_name.as_deref(), Some("librdkafka"));
+        assert_eq!(request.client_software_version.as_deref(), Some("2.3.0"));
+
+        let request = ApiVersionsRequest::decode(&mut Bytes::new(), 0).unwrap();
+        assert_eq!(request, ApiVersionsRequest::default());
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = ApiVersionsResponse {
+            error_code: ErrorCode::None,
+            api_keys: vec![ApiVersion {
+                api_key: 18,
+                min_version: 0,
+                max_version: 3,
+            }],
+            throttle_time_ms: 0,
+        };
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 0);
+        assert_eq!(&buf[..], &[0, 0, 0, 0, 0, 1, 0, 18, 0, 0, 0, 3]);
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 3);
+        assert_eq!(&buf[..], &[0, 0, 2, 0, 18, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,354 @@
+//! Primitive types of the Kafka protocol.
+//!
+//! See https://kafka.apache.org/protocol#protocol_types. Flexible versions of
+//! messages encode strings, bytes and arrays in their "compact" form, where
+//! lengths are unsigned varints holding the length plus one, and end
+//! structures with tagged fields.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::server::error::{Error, Result};
+
+fn ensure(buf: &Bytes, n: usize) -> Result<()> {
+    if buf.remaining() < n {
+        Err(Error::InvalidRequest(format!(
+            "expected {} more bytes, got {}",
+            n,
+            buf.remaining()
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn get_bool(buf: &mut Bytes) -> Result<bool> {
+    Ok(get_i8(buf)? != 0)
+}
+
+pub fn get_i8(buf: &mut Bytes) -> Result<i8> {
+    ensure(buf, 1)?;
+    Ok(buf.get_i8())
+}
+
+pub fn get_i16(buf: &mut Bytes) -> Result<i16> {
+    ensure(buf, 2)?;
+    Ok(buf.get_i16())
+}
+
+pub fn get_i32(buf: &mut Bytes) -> Result<i32> {
+    ensure(buf, 4)?;
+    Ok(buf.get_i32())
+}
+
+pub fn get_i64(buf: &mut Bytes) -> Result<i64> {
+    ensure(buf, 8)?;
+    Ok(buf.get_i64())
+}
+
+pub fn get_f64(buf: &mut Bytes) -> Result<f64> {
+    ensure(buf, 8)?;
+    Ok(buf.get_f64())
+}
+
+pub fn get_u32(buf: &mut Bytes) -> Result<u32> {
+    ensure(buf, 4)?;
+    Ok(buf.get_u32())
+}
+
+pub fn get_uuid(buf: &mut Bytes) -> Result<[u8; 16]> {
+    ensure(buf, 16)?;
+    let mut uuid = [0u8; 16];
+    buf.copy_to_slice(&mut uuid);
+    Ok(uuid)
+}
+
+pub fn get_unsigned_varint(buf: &mut Bytes) -> Result<u32> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let b = get_i8(buf)? as u8;
+        value |= ((b & 0x7f) as u32) << (i * 7);
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::InvalidRequest("varint is too long".into()))
+}
+
+pub fn get_unsigned_varlong(buf: &mut Bytes) -> Result<u64> {
+    let mut value = 0u64;
+    for i in 0..10 {
+        let b = get_i8(buf)? as u8;
+        value |= ((b & 0x7f) as u64) << (i * 7);
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::InvalidRequest("varlong is too long".into()))
+}
+
+pub fn get_varint(buf: &mut Bytes) -> Result<i32> {
+    let v = get_unsigned_varint(buf)?;
+    Ok(((v >> 1) as i32) ^ -((v & 1) as i32))
+}
+
+pub fn get_varlong(buf: &mut Bytes) -> Result<i64> {
+    let v = get_unsigned_varlong(buf)?;
+    Ok(((v >> 1) as i64) ^ -((v & 1) as i64))
+}
+
+// Length of a string, bytes or array. `None` stands for null.
+fn get_len(
+    buf: &mut Bytes,
+    flexible: bool,
+    standard: fn(&mut Bytes) -> Result<i32>,
+) -> Result<Option<usize>> {
+    let len = if flexible {
+        get_unsigned_varint(buf)? as i64 - 1
+    } else {
+        standard(buf)? as i64
+    };
+
+    if len < 0 {
+        Ok(None)
+    } else {
+        Ok(Some(len as usize))
+    }
+}
+
+pub fn get_nullable_bytes(buf: &mut Bytes, flexible: bool) -> Result<Option<Bytes>> {
+    match get_len(buf, flexible, get_i32)? {
+        None => Ok(None),
+        Some(len) => {
+            ensure(buf, len)?;
+            Ok(Some(buf.split_to(len)))
+        }
+    }
+}
+
+pub fn get_bytes(buf: &mut Bytes, flexible: bool) -> Result<Bytes> {
+    get_nullable_bytes(buf, flexible)?.ok_or_else(|| Error::InvalidRequest("null bytes".into()))
+}
+
+pub fn get_nullable_string(buf: &mut Bytes, flexible: bool) -> Result<Option<String>> {
+    let len = get_len(buf, flexible, |buf| get_i16(buf).map(i32::from))?;
+    match len {
+        None => Ok(None),
+        Some(len) => {
+            ensure(buf, len)?;
+            let bytes = buf.split_to(len);
+            String::from_utf8(bytes.to_vec())
+                .map(Some)
+                .map_err(|_| Error::InvalidRequest("string is not valid utf-8".into()))
+        }
+    }
+}
+
+pub fn get_string(buf: &mut Bytes, flexible: bool) -> Result<String> {
+    get_nullable_string(buf, flexible)?.ok_or_else(|| Error::InvalidRequest("null string".into()))
+}
+
+/// Decode an array whose elements are decoded by `f`. A null array is
+/// decoded as an empty one.
+pub fn get_array<T, F>(buf: &mut Bytes, flexible: bool, f: F) -> Result<Vec<T>>
+where
+    F: FnMut(&mut Bytes) -> Result<T>,
+{
+    Ok(get_nullable_array(buf, flexible, f)?.unwrap_or_default())
+}
+
+/// Decode an array whose elements are decoded by `f`, `None` standing for
+/// a null array.
+pub fn get_nullable_array<T, F>(buf: &mut Bytes, flexible: bool, mut f: F) -> Result<Option<Vec<T>>>
+where
+    F: FnMut(&mut Bytes) -> Result<T>,
+{
+    let len = match get_len(buf, flexible, get_i32)? {
+        None => return Ok(None),
+        Some(len) => len,
+    };
+    // Do not trust the length to preallocate: every element takes at least
+    // one byte.
+    let mut items = Vec::with_capacity(len.min(buf.remaining()));
+    for _ in 0..len {
+        items.push(f(buf)?);
+    }
+    Ok(Some(items))
+}
+
+/// Skip the tagged fields ending a structure. None of the tagged fields
+/// defined by the protocol are used by the broker.
+pub fn skip_tagged_fields(buf: &mut Bytes) -> Result<()> {
+    let count = get_unsigned_varint(buf)?;
+    for _ in 0..count {
+        let _tag = get_unsigned_varint(buf)?;
+        let size = get_unsigned_varint(buf)? as usize;
+        ensure(buf, size)?;
+        buf.advance(size);
+    }
+    Ok(())
+}
+
+pub fn put_bool(buf: &mut BytesMut, value: bool) {
+    buf.put_i8(value as i8);
+}
+
+pub fn put_uuid(buf: &mut BytesMut, uuid: &[u8; 16]) {
+    buf.put_slice(uuid);
+}
+
+pub fn put_unsigned_varint(buf: &mut BytesMut, mut value: u32) {
+    while value >= 0x80 {
+        buf.put_u8((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+pub fn put_unsigned_varlong(buf: &mut BytesMut, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+pub fn put_varint(buf: &mut BytesMut, value: i32) {
+    put_unsigned_varint(buf, ((value << 1) ^ (value >> 31)) as u32);
+}
+
+pub fn put_varlong(buf: &mut BytesMut, value: i64) {
+    put_unsigned_varlong(buf, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+fn put_len(buf: &mut BytesMut, len: Option<usize>, flexible: bool, short: bool) {
+    match (flexible, len) {
+        (true, None) => put_unsigned_varint(buf, 0),
+        (true, Some(len)) => put_unsigned_varint(buf, len as u32 + 1),
+        (false, None) if short => buf.put_i16(-1),
+        (false, None) => buf.put_i32(-1),
+        (false, Some(len)) if short => buf.put_i16(len as i16),
+        (false, Some(len)) => buf.put_i32(len as i32),
+    }
+}
+
+pub fn put_nullable_bytes(buf: &mut BytesMut, value: Option<&[u8]>, flexible: bool) {
+    put_len(buf, value.map(|v| v.len()), flexible, false);
+    if let Some(value) = value {
+        buf.put_slice(value);
+    }
+}
+
+pub fn put_bytes(buf: &mut BytesMut, value: &[u8], flexible: bool) {
+    put_nullable_bytes(buf, Some(value), flexible);
+}
+
+pub fn put_nullable_string(buf: &mut BytesMut, value: Option<&str>, flexible: bool) {
+    put_len(buf, value.map(|v| v.len()), flexible, true);
+    if let Some(value) = value {
+        buf.put_slice(value.as_bytes());
+    }
+}
+
+pub fn put_string(buf: &mut BytesMut, value: &str, flexible: bool) {
+    put_nullable_string(buf, Some(value), flexible);
+}
+
+/// Encode `items`, each element being encoded by `f`.
+pub fn put_array<T, F>(buf: &mut BytesMut, items: &[T], flexible: bool, mut f: F)
+where
+    F: FnMut(&mut BytesMut, &T),
+{
+    put_len(buf, Some(items.len()), flexible, false);
+    for item in items {
+        f(buf, item);
+    }
+}
+
+/// End a structure of a flexible message without any tagged field.
+pub fn put_empty_tagged_fields(buf: &mut BytesMut) {
+    put_unsigned_varint(buf, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip<T, E, D>(value: T, encode: E, decode: D) -> (T, usize)
+    where
+        E: Fn(&mut BytesMut, &T),
+        D: Fn(&mut Bytes) -> Result<T>,
+    {
+        let mut buf = BytesMut::new();
+        encode(&mut buf, &value);
+        let len = buf.len();
+        let mut buf = buf.freeze();
+        let decoded = decode(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        (decoded, len)
+    }
+
+    #[test]
+    fn test_varints() {
+        for value in [0, 1, -1, 63, -64, 64, i32::MAX, i32::MIN] {
+            let (decoded, _) = roundtrip(value, |b, v| put_varint(b, *v), get_varint);
+            assert_eq!(decoded, value);
+        }
+        for value in [0, 1, -1, i64::MAX, i64::MIN] {
+            let (decoded, _) = roundtrip(value, |b, v| put_varlong(b, *v), get_varlong);
+            assert_eq!(decoded, value);
+        }
+
+        // Zigzag encoding keeps small negative numbers small
+        assert_eq!(roundtrip(-1, |b, v| put_varint(b, *v), get_varint).1, 1);
+        assert_eq!(roundtrip(300, |b, v| put_varint(b, *v), get_varint).1, 2);
+    }
+
+    #[test]
+    fn test_strings() {
+        for flexible in [false, true] {
+            let (decoded, len) = roundtrip(
+                "fafka".to_string(),
+                |b, v| put_string(b, v, flexible),
+                |b| get_string(b, flexible),
+            );
+            assert_eq!(decoded, "fafka");
+            assert_eq!(len, if flexible { 6 } else { 7 });
+
+            let (decoded, _) = roundtrip(
+                None,
+                |b, v: &Option<String>| put_nullable_string(b, v.as_deref(), flexible),
+                |b| get_nullable_string(b, flexible),
+            );
+            assert_eq!(decoded, None);
+        }
+    }
+
+    #[test]
+    fn test_arrays() {
+        for flexible in [false, true] {
+            let (decoded, _) = roundtrip(
+                vec![1, 2, 3],
+                |b, v: &Vec<i32>| put_array(b, v, flexible, |b, i| b.put_i32(*i)),
+                |b| get_array(b, flexible, get_i32),
+            );
+            assert_eq!(decoded, vec![1, 2, 3]);
+        }
+
+        // Lengths larger than the buffer are rejected
+        let mut buf = Bytes::from_static(&[0, 0, 0, 10, 0, 0, 0, 1]);
+        assert!(get_array(&mut buf, false, get_i32).is_err());
+
+        // Null arrays
+        let mut buf = Bytes::from_static(&[0xff, 0xff, 0xff, 0xff, 0]);
+        assert_eq!(get_nullable_array(&mut buf, false, get_i32).unwrap(), None);
+        assert_eq!(get_nullable_array(&mut buf, true, get_i32).unwrap(), None);
+    }
+
+    #[test]
+    fn test_skip_tagged_fields() {
+        // Two tagged fields followed by an i16
+        let mut buf = Bytes::from_static(&[2, 0, 1, 0xff, 5, 2, 0xaa, 0xbb, 0, 7]);
+        skip_tagged_fields(&mut buf).unwrap();
+        assert_eq!(get_i16(&mut buf).unwrap(), 7);
+    }
+}
@@ -0,0 +1,127 @@
+//! CreateAcls (key 30): add access control lists.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateAclsRequest {
+    pub creations: Vec<AclCreation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclCreation {
+    pub resource_type: i8,
+    pub resource_name: String,
+    pub resource_pattern_type: i8,
+    pub principal: String,
+    pub host: String,
+    pub operation: i8,
+    pub permission_type: i8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateAclsResponse {
+    pub throttle_time_ms: i32,
+    /// Result of each creation, in the order of the request.
+    pub results: Vec<AclCreationResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclCreationResult {
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+}
+
+impl Decode for CreateAclsRequest {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self> {
+        let flexible = version >= 2;
+        let creations = get_array(buf, flexible, |buf| {
+            let creation = AclCreation {
+                resource_type: get_i8(buf)?,
+                resource_name: get_string(buf, flexible)?,
+                resource_pattern_type: get_i8(buf)?,
+                principal: get_string(buf, flexible)?,
+                host: get_string(buf, flexible)?,
+                operation: get_i8(buf)?,
+                permission_type: get_i8(buf)?,
+            };
+            if flexible {
+                skip_tagged_fields(buf)?;
+            }
+            Ok(creation)
+        })?;
+        if flexible {
+            skip_tagged_fields(buf)?;
+        }
+        Ok(Self { creations })
+    }
+}
+
+impl Encode for CreateAclsResponse {
+    fn encode(&self, buf: &mut BytesMut, version: i16) {
+        let flexible = version >= 2;
+        buf.put_i32(self.throttle_time_ms);
+        put_array(buf, &self.results, flexible, |buf, result| {
+            buf.put_i16(result.error_code.code());
+            put_nullable_string(buf, result.error_message.as_deref(), flexible);
+            if flexible {
+                put_empty_tagged_fields(buf);
+            }
+        });
+        if flexible {
+            put_empty_tagged_fields(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        let mut buf = BytesMut::new();
+        put_unsigned_varint(&mut buf, 2);
+        buf.put_i8(2);
+        put_string(&mut buf, "events", true);
+        buf.put_i8(3);
+        put_string(&mut buf, "User:alice", true);
+        put_string(&mut buf, "*", true);
+        buf.put_i8(3);
+        buf.put_i8(3);
+        put_empty_tagged_fields(&mut buf);
+        put_empty_tagged_fields(&mut buf);
+
+        let request = CreateAclsRequest::decode(&mut buf.freeze(), 2).unwrap();
+        assert_eq!(
+            request.creations,
+            vec![AclCreation {
+                resource_type: 2,
+                resource_name: "events".into(),
+                resource_pattern_type: 3,
+                principal: "User:alice".into(),
+                host: "*".into(),
+                operation: 3,
+                permission_type: 3,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = CreateAclsResponse {
+            throttle_time_ms: 0,
+            results: vec![AclCreationResult {
+                error_code: ErrorCode::None,
+                error_message: None,
+            }],
+        };
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 1);
+        assert_eq!(&buf[..], b"\0\0\0\0\0\0\0\x01\0\0\xff\xff");
+    }
+}
@@ -0,0 +1,170 @@
+//! DeleteAcls (key 31): delete the access control lists matching filters.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeleteAclsRequest {
+    pub filters: Vec<DeleteAclsFilter>,
+}
+
+/// Fields set to null, or to the ANY type, match any value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeleteAclsFilter {
+    pub resource_type_filter: i8,
+    pub resource_name_filter: Option<String>,
+    pub pattern_type_filter: i8,
+    pub principal_filter: Option<String>,
+    pub host_filter: Option<String>,
+    pub operation: i8,
+    pub permission_type: i8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeleteAclsResponse {
+    pub throttle_time_ms: i32,
+    /// Result of each filter, in the order of the request.
+    pub filter_results: Vec<DeleteAclsFilterResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeleteAclsFilterResult {
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub matching_acls: Vec<DeleteAclsMatchingAcl>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeleteAclsMatchingAcl {
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub resource_type: i8,
+    pub resource_name: String,
+    pub pattern_type: i8,
+    pub principal: String,
+    pub host: String,
+    pub operation: i8,
+    pub permission_type: i8,
+}
+
+impl Decode for DeleteAclsRequest {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self> {
+        let flexible = version >= 2;
+        let filters = get_array(buf, flexible, |buf| {
+            let filter = DeleteAclsFilter {
+                resource_type_filter: get_i8(buf)?,
+                resource_name_filter: get_nullable_string(buf, flexible)?,
+                pattern_type_filter: get_i8(buf)?,
+                principal_filter: get_nullable_string(buf, flexible)?,
+                host_filter: get_nullable_string(buf, flexible)?,
+                operation: get_i8(buf)?,
+                permission_type: get_i8(buf)?,
+            };
+            if flexible {
+                skip_tagged_fields(buf)?;
+            }
+            Ok(filter)
+        })?;
+        if flexible {
+            skip_tagged_fields(buf)?;
+        }
+        Ok(Self { filters })
+    }
+}
+
+impl Encode for DeleteAclsResponse {
+    fn encode(&self, buf: &mut BytesMut, version: i16) {
+        let flexible = version >= 2;
+        buf.put_i32(self.throttle_time_ms);
+        put_array(buf, &self.filter_results, flexible, |buf, result| {
+            buf.put_i16(result.error_code.code());
+            put_nullable_string(buf, result.error_message.as_deref(), flexible);
+            put_array(buf, &result.matching_acls, flexible, |buf, acl| {
+                buf.put_i16(acl.error_code.code());
+                put_nullable_string(buf, acl.error_message.as_deref(), flexible);
+                buf.put_i8(acl.resource_type);
+                put_string(buf, &acl.resource_name, flexible);
+                buf.put_i8(acl.pattern_type);
+                put_string(buf, &acl.principal, flexible);
+                put_string(buf, &acl.host, flexible);
+                buf.put_i8(acl.operation);
+                buf.put_i8(acl.permission_type);
+                if flexible {
+                    put_empty_tagged_fields(buf);
+                }
+            });
+            if flexible {
+                put_empty_tagged_fields(buf);
+            }
+        });
+        if flexible {
+            put_empty_tagged_fields(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        let mut buf = BytesMut::new();
+        put_unsigned_varint(&mut buf, 2);
+        buf.put_i8(2);
+        put_nullable_string(&mut buf, Some("events"), true);
+        buf.put_i8(1);
+        put_nullable_string(&mut buf, None, true);
+        put_nullable_string(&mut buf, None, true);
+        buf.put_i8(1);
+        buf.put_i8(1);
+        put_empty_tagged_fields(&mut buf);
+        put_empty_tagged_fields(&mut buf);
+
+        let request = DeleteAclsRequest::decode(&mut buf.freeze(), 3).unwrap();
+        assert_eq!(
+            request.filters,
+            vec![DeleteAclsFilter {
+                resource_type_filter: 2,
+                resource_name_filter: Some("events".into()),
+                pattern_type_filter: 1,
+                principal_filter: None,
+                host_filter: None,
+                operation: 1,
+                permission_type: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = DeleteAclsResponse {
+            throttle_time_ms: 0,
+            filter_results: vec![DeleteAclsFilterResult {
+                error_code: ErrorCode::None,
+                error_message: None,
+                matching_acls: vec![DeleteAclsMatchingAcl {
+                    error_code: ErrorCode::None,
+                    error_message: None,
+                    resource_type: 2,
+                    resource_name: "t".into(),
+                    pattern_type: 3,
+                    principal: "User:a".into(),
+                    host: "*".into(),
+                    operation: 3,
+                    permission_type: 3,
+                }],
+            }],
+        };
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 1);
+        assert_eq!(
+            &buf[..],
+            &b"\0\0\0\0\0\0\0\x01\0\0\xff\xff\0\0\0\x01\0\0\xff\xff\x02\0\x01t\x03\0\x06User:a\0\x01*\x03\x03"[..]
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! DescribeAcls (key 29): list the access control lists matching a filter.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+/// Fields set to null, or to the ANY type, match any value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribeAclsRequest {
+    pub resource_type_filter: i8,
+    pub resource_name_filter: Option<String>,
+    pub pattern_type_filter: i8,
+    pub principal_filter: Option<String>,
+    pub host_filter: Option<String>,
+    pub operation: i8,
+    pub permission_type: i8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribeAclsResponse {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub resources: Vec<DescribeAclsResource>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribeAclsResource {
+    pub resource_type: i8,
+    pub resource_name: String,
+    pub pattern_type: i8,
+    pub acls: Vec<AclDescription>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclDescription {
+    pub principal: String,
+    pub host: String,
+    pub operation: i8,
+    pub permission_type: i8,
+}
+
+impl Decode for DescribeAclsRequest {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self> {
+        let flexible = version >= 2;
+        let request = Self {
+            resource_type_filter: get_i8(buf)?,
+            resource_name_filter: get_nullable_string(buf, flexible)?,
+            pattern_type_filter: get_i8(buf)?,
+            principal_filter: get_nullable_string(buf, flexible)?,
+            host_filter: get_nullable_string(buf, flexible)?,
+            operation: get_i8(buf)?,
+            permission_type: get_i8(buf)?,
+        };
+        if flexible {
+            skip_tagged_fields(buf)?;
+        }
+        Ok(request)
+    }
+}
+
+impl Encode for DescribeAclsResponse {
+    fn encode(&self, buf: &mut BytesMut, version: i16) {
+        let flexible = version >= 2;
+        buf.put_i32(self.throttle_time_ms);
+        buf.put_i16(self.error_code.code());
+        put_nullable_string(buf, self.error_message.as_deref(), flexible);
+        put_array(buf, &self.resources, flexible, |buf, resource| {
+            buf.put_i8(resource.resource_type);
+            put_string(buf, &resource.resource_name, flexible);
+            buf.put_i8(resource.pattern_type);
+            put_array(buf, &resource.acls, flexible, |buf, acl| {
+                put_string(buf, &acl.principal, flexible);
+                put_string(buf, &acl.host, flexible);
+                buf.put_i8(acl.operation);
+                buf.put_i8(acl.permission_type);
+                if flexible {
+                    put_empty_tagged_fields(buf);
+                }
+            });
+            if flexible {
+                put_empty_tagged_fields(buf);
+            }
+        });
+        if flexible {
+            put_empty_tagged_fields(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        let mut buf = BytesMut::new();
+        buf.put_i8(1);
+        put_nullable_string(&mut buf, None, false);
+        buf.put_i8(2);
+        put_nullable_string(&mut buf, Some("User:alice"), false);
+        put_nullable_string(&mut buf, None, false);
+        buf.put_i8(1);
+        buf.put_i8(3);
+
+        let request = DescribeAclsRequest::decode(&mut buf.freeze(), 1).unwrap();
+        assert_eq!(
+            request,
+            DescribeAclsRequest {
+                resource_type_filter: 1,
+                resource_name_filter: None,
+                pattern_type_filter: 2,
+                principal_filter: Some("User:alice".into()),
+                host_filter: None,
+                operation: 1,
+                permission_type: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = DescribeAclsResponse {
+            throttle_time_ms: 0,
+            error_code: ErrorCode::None,
+            error_message: None,
+            resources: vec![DescribeAclsResource {
+                resource_type: 2,
+                resource_name: "t".into(),
+                pattern_type: 3,
+                acls: vec![AclDescription {
+                    principal: "User:a".into(),
+                    host: "*".into(),
+                    operation: 3,
+                    permission_type: 3,
+                }],
+            }],
+        };
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 2);
+        assert_eq!(
+            &buf[..],
+            &b"\0\0\0\0\0\0\0\x02\x02\x02t\x03\x02\x07User:a\x02*\x03\x03\0\0\0"[..]
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! DescribeClientQuotas (key 48): list the quotas of the entities matching
+//! a filter.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+/// Match the entities with this name.
+pub const MATCH_EXACT: i8 = 0;
+/// Match the default entities.
+pub const MATCH_DEFAULT: i8 = 1;
+/// Match the entities with any name, but not the default ones.
+pub const MATCH_ANY: i8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribeClientQuotasRequest {
+    pub components: Vec<ComponentFilter>,
+    /// Whether entities with components other than the ones of the filter
+    /// are excluded.
+    pub strict: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentFilter {
+    pub entity_type: String,
+    pub match_type: i8,
+    /// Name to match, for `MATCH_EXACT`.
+    pub match_name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DescribeClientQuotasResponse {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    /// `None` on error.
+    pub entries: Option<Vec<EntryData>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryData {
+    pub entity: Vec<EntityData>,
+    pub values: Vec<ValueData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityData {
+    pub entity_type: String,
+    /// `None` for the default entity.
+    pub entity_name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueData {
+    pub key: String,
+    pub value: f64,
+}
+
+impl Decode for DescribeClientQuotasRequest {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self> {
+        let flexible = version >= 1;
+        let components = get_array(buf, flexible, |buf| {
+            let component = ComponentFilter {
+                entity_type: get_string(buf, flexible)?,
+                match_type: get_i8(buf)?,
+                match_name: get_nullable_string(buf, flexible)?,
+            };
+            if flexible {
+                skip_tagged_fields(buf)?;
+            }
+            Ok(component)
+        })?;
+        let strict = get_bool(buf)?;
+        if flexible {
+            skip_tagged_fields(buf)?;
+        }
+        Ok(Self { components, strict })
+    }
+}
+
+impl Encode for DescribeClientQuotasResponse {
+    fn encode(&self, buf: &mut BytesMut, version: i16) {
+        let flexible = version >= 1;
+        buf.put_i32(self.throttle_time_ms);
+        buf.put_i16(self.error_code.code());
+        put_nullable_string(buf, self.error_message.as_deref(), flexible);
+        match &self.entries {
+            Some(entries) => put_array(buf, entries, flexible, |buf, entry| {
+                put_array(buf, &entry.entity, flexible, |buf, entity| {
+                    put_string(buf, &entity.entity_type, flexible);
+                    put_nullable_string(buf, entity.entity_name.as_deref(), flexible);
+                    if flexible {
+                        put_empty_tagged_fields(buf);
+                    }
+                });
+                put_array(buf, &entry.values, flexible, |buf, value| {
+                    put_string(buf, &value.key, flexible);
+                    buf.put_f64(value.value);
+                    if flexible {
+                        put_empty_tagged_fields(buf);
+                    }
+                });
+                if flexible {
+                    put_empty_tagged_fields(buf);
+                }
+            }),
+            None if flexible => put_unsigned_varint(buf, 0),
+            None => buf.put_i32(-1),
+        }
+        if flexible {
+            put_empty_tagged_fields(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        let mut buf = BytesMut::new();
+        put_unsigned_varint(&mut buf, 2);
+        put_string(&mut buf, "user", true);
+        buf.put_i8(MATCH_EXACT);
+        put_nullable_string(&mut buf, Some("alice"), true);
+        put_empty_tagged_fields(&mut buf);
+        put_bool(&mut buf, true);
+        put_empty_tagged_fields(&mut buf);
+
+        let request = DescribeClientQuotasRequest::decode(&mut buf.freeze(), 1).unwrap();
+        assert_eq!(
+            request,
+            DescribeClientQuotasRequest {
+                components: vec![ComponentFilter {
+                    entity_type: "user".into(),
+                    match_type: MATCH_EXACT,
+                    match_name: Some("alice".into()),
+                }],
+                strict: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = DescribeClientQuotasResponse {
+            throttle_time_ms: 0,
+            error_code: ErrorCode::None,
+            error_message: None,
+            entries: Some(vec![EntryData {
+                entity: vec![EntityData {
+                    entity_type: "user".into(),
+                    entity_name: None,
+                }],
+                values: vec![ValueData {
+                    key: "k".into(),
+                    value: 1.0,
+                }],
+            }]),
+        };
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 0);
+        assert_eq!(
+            &buf[..],
+            &b"\0\0\0\0\0\0\xff\xff\0\0\0\x01\0\0\0\x01\0\x04user\xff\xff\0\0\0\x01\0\x01k\x3f\xf0\0\0\0\0\0\0"[..]
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! DescribeConfigs (key 32): list the configuration of brokers and topics.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+/// Resource type of topic configurations.
+pub const RESOURCE_TOPIC: i8 = 2;
+/// Resource type of broker configurations.
+pub const RESOURCE_BROKER: i8 = 4;
+
+/// Where the value of a config comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i8)]
+pub enum ConfigSource {
+    /// Set for the topic at runtime.
+    DynamicTopic = 1,
+    /// Set for the broker at runtime.
+    DynamicBroker = 2,
+    /// Set in the configuration the broker was started or reloaded with.
+    StaticBroker = 4,
+    Default = 5,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribeConfigsRequest {
+    pub resources: Vec<DescribeConfigsResource>,
+    /// Whether the values the configs would have if they were not set are
+    /// returned as well.
+    pub include_synonyms: bool,
+    pub include_documentation: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribeConfigsResource {
+    pub resource_type: i8,
+    pub resource_name: String,
+    /// Configs to describe, `None` for all of them.
+    pub configuration_keys: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribeConfigsResponse {
+    pub throttle_time_ms: i32,
+    /// Result of each resource, in the order of the request.
+    pub results: Vec<DescribeConfigsResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribeConfigsResult {
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub resource_type: i8,
+    pub resource_name: String,
+    pub configs: Vec<DescribeConfigsResourceResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribeConfigsResourceResult {
+    pub name: String,
+    pub value: Option<String>,
+    /// Whether the config cannot be changed at runtime.
+    pub read_only: bool,
+    pub config_source: ConfigSource,
+    pub is_sensitive: bool,
+    /// Values of the config by order of precedence, the first one being the
+    /// value of the config itself.
+    pub synonyms: Vec<DescribeConfigsSynonym>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribeConfigsSynonym {
+    pub name: String,
+    pub value: Option<String>,
+    pub source: ConfigSource,
+}
+
+impl Decode for DescribeConfigsRequest {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self> {
+        let flexible = version >= 4;
+        let resources = get_array(buf, flexible, |buf| {
+            let resource = DescribeConfigsResource {
+                resource_type: get_i8(buf)?,
+                resource_name: get_string(buf, flexible)?,
+                configuration_keys: get_nullable_array(buf, flexible, |buf| {
+                    get_string(buf, flexible)
+                })?,
+            };
+            if flexible {
+                skip_tagged_fields(buf)?;
+            }
+            Ok(resource)
+        })?;
+        let include_synonyms = get_bool(buf)?;
+        let include_documentation = version >= 3 && get_bool(buf)?;
+        if flexible {
+            skip_tagged_fields(buf)?;
+        }
+        Ok(Self {
+            resources,
+            include_synonyms,
+            include_documentation,
+        })
+    }
+}
+
+impl Encode for DescribeConfigsResponse {
+    fn encode(&self, buf: &mut BytesMut, version: i16) {
+        let flexible = version >= 4;
+        buf.put_i32(self.throttle_time_ms);
+        put_array(buf, &self.results, flexible, |buf, result| {
+            buf.put_i16(result.error_code.code());
+            put_nullable_string(buf, result.error_message.as_deref(), flexible);
+            buf.put_i8(result.resource_type);
+            put_string(buf, &result.resource_name, flexible);
+            put_array(buf, &result.configs, flexible, |buf, config| {
+                put_string(buf, &config.name, flexible);
+                put_nullable_string(buf, config.value.as_deref(), flexible);
+                put_bool(buf, config.read_only);
+                buf.put_i8(config.config_source as i8);
+                put_bool(buf, config.is_sensitive);
+                put_array(buf, &config.synonyms, flexible, |buf, synonym| {
+                    put_string(buf, &synonym.name, flexible);
+                    put_nullable_string(buf, synonym.value.as_deref(), flexible);
+                    buf.put_i8(synonym.source as i8);
+                    if flexible {
+                        put_empty_tagged_fields(buf);
+                    }
+                });
+                if version >= 3 {
+                    // Unknown type, no documentation.
+                    buf.put_i8(0);
+                    put_nullable_string(buf, None, flexible);
+                }
+                if flexible {
+                    put_empty_tagged_fields(buf);
+                }
+            });
+            if flexible {
+                put_empty_tagged_fields(buf);
+            }
+        });
+        if flexible {
+            put_empty_tagged_fields(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        let mut buf = BytesMut::new();
+        buf.put_i32(2);
+        buf.put_i8(RESOURCE_BROKER);
+        put_string(&mut buf, "0", false);
+        buf.put_i32(-1);
+        buf.put_i8(RESOURCE_TOPIC);
+        put_string(&mut buf, "events", false);
+        buf.put_i32(1);
+        put_string(&mut buf, "segment.bytes", false);
+        put_bool(&mut buf, true);
+
+        let request = DescribeConfigsRequest::decode(&mut buf.freeze(), 1).unwrap();
+        assert_eq!(
+            request,
+            DescribeConfigsRequest {
+                resources: vec![
+                    DescribeConfigsResource {
+                        resource_type: RESOURCE_BROKER,
+                        resource_name: "0".into(),
+                        configuration_keys: None,
+                    },
+                    DescribeConfigsResource {
+                        resource_type: RESOURCE_TOPIC,
+                        resource_name: "events".into(),
+                        configuration_keys: Some(vec!["segment.bytes".into()]),
+                    },
+                ],
+                include_synonyms: true,
+                include_documentation: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = DescribeConfigsResponse {
+            throttle_time_ms: 0,
+            results: vec![DescribeConfigsResult {
+                error_code: ErrorCode::None,
+                error_message: None,
+                resource_type: RESOURCE_TOPIC,
+                resource_name: "t".into(),
+                configs: vec![DescribeConfigsResourceResult {
+                    name: "k".into(),
+                    value: Some("v".into()),
+                    read_only: false,
+                    config_source: ConfigSource::DynamicTopic,
+                    is_sensitive: false,
+                    synonyms: vec![],
+                }],
+            }],
+        };
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 4);
+        assert_eq!(
+            &buf[..],
+            b"\0\0\0\0\x02\0\0\0\x02\x02t\x02\x02k\x02v\0\x01\0\x01\0\0\0\0\0"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! DescribeUserScramCredentials (key 50): list the SCRAM mechanisms users
+//! have credentials for, without revealing the credentials.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribeUserScramCredentialsRequest {
+    /// Users to describe, `None` to describe every user.
+    pub users: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribeUserScramCredentialsResponse {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub results: Vec<DescribeUserScramCredentialsResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribeUserScramCredentialsResult {
+    pub user: String,
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub credential_infos: Vec<CredentialInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CredentialInfo {
+    /// Type of the SCRAM mechanism: 1 for SCRAM-SHA-256, 2 for
+    /// SCRAM-SHA-512.
+    pub mechanism: i8,
+    pub iterations: i32,
+}
+
+impl Decode for DescribeUserScramCredentialsRequest {
+    fn decode(buf: &mut Bytes, _version: i16) -> Result<Self> {
+        let users = get_nullable_array(buf, true, |buf| {
+            let name = get_string(buf, true)?;
+            skip_tagged_fields(buf)?;
+            Ok(name)
+        })?;
+        skip_tagged_fields(buf)?;
+        Ok(Self { users })
+    }
+}
+
+impl Encode for DescribeUserScramCredentialsResponse {
+    fn encode(&self, buf: &mut BytesMut, _version: i16) {
+        buf.put_i32(self.throttle_time_ms);
+        buf.put_i16(self.error_code.code());
+        put_nullable_string(buf, self.error_message.as_deref(), true);
+        put_array(buf, &self.results, true, |buf, result| {
+            put_string(buf, &result.user, true);
+            buf.put_i16(result.error_code.code());
+            put_nullable_string(buf, result.error_message.as_deref(), true);
+            put_array(buf, &result.credential_infos, true, |buf, info| {
+                buf.put_i8(info.mechanism);
+                buf.put_i32(info.iterations);
+                put_empty_tagged_fields(buf);
+            });
+            put_empty_tagged_fields(buf);
+        });
+        put_empty_tagged_fields(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        let mut buf = BytesMut::new();
+        put_unsigned_varint(&mut buf, 2);
+        put_string(&mut buf, "alice", true);
+        put_empty_tagged_fields(&mut buf);
+        put_empty_tagged_fields(&mut buf);
+
+        let request = DescribeUserScramCredentialsRequest::decode(&mut buf.freeze(), 0).unwrap();
+        assert_eq!(request.users, Some(vec!["alice".to_string()]));
+
+        let mut buf = BytesMut::new();
+        put_unsigned_varint(&mut buf, 0);
+        put_empty_tagged_fields(&mut buf);
+
+        let request = DescribeUserScramCredentialsRequest::decode(&mut buf.freeze(), 0).unwrap();
+        assert_eq!(request.users, None);
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = DescribeUserScramCredentialsResponse {
+            throttle_time_ms: 0,
+            error_code: ErrorCode::None,
+            error_message: None,
+            results: vec![DescribeUserScramCredentialsResult {
+                user: "bob".into(),
+                error_code: ErrorCode::None,
+                error_message: None,
+                credential_infos: vec![CredentialInfo {
+                    mechanism: 1,
+                    iterations: 4096,
+                }],
+            }],
+        };
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 0);
+        assert_eq!(
+            &buf[..],
+            b"\0\0\0\0\0\0\0\x02\x04bob\0\0\0\x02\x01\0\0\x10\0\0\0\0"
+        );
+    }
+}
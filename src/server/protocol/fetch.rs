@@ -0,0 +1,313 @@
+//! Fetch (key 1): read records from partitions.
+//!
+//! Fetch sessions (v7+) are not supported: every request is a full fetch
+//! listing all its partitions, and responses have a session id of 0, which
+//! tells clients to keep sending full fetches.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchRequest {
+    /// Id of the follower fetching, or -1 for consumers.
+    pub replica_id: i32,
+    /// Maximum time to wait for `min_bytes` to be available.
+    pub max_wait_ms: i32,
+    /// Minimum number of bytes to accumulate before answering.
+    pub min_bytes: i32,
+    /// Maximum number of bytes to return for the whole request.
+    pub max_bytes: i32,
+    /// 0 for READ_UNCOMMITTED, 1 for READ_COMMITTED.
+    pub isolation_level: i8,
+    /// Fetch session id (v7+).
+    pub session_id: i32,
+    /// Fetch session epoch (v7+).
+    pub session_epoch: i32,
+    pub topics: Vec<FetchTopic>,
+    /// Rack of the consumer (v11+).
+    pub rack_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchTopic {
+    pub name: String,
+    pub partitions: Vec<FetchPartition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchPartition {
+    pub index: i32,
+    pub fetch_offset: i64,
+    /// Maximum number of bytes to return for this partition.
+    pub partition_max_bytes: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchResponse {
+    pub throttle_time_ms: i32,
+    /// Top level error code (v7+).
+    pub error_code: ErrorCode,
+    /// Fetch session id (v7+).
+    pub session_id: i32,
+    pub responses: Vec<FetchableTopicResponse>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchableTopicResponse {
+    pub name: String,
+    pub partitions: Vec<PartitionData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionData {
+    pub index: i32,
+    pub error_code: ErrorCode,
+    pub high_watermark: i64,
+    /// Offset up to which transactions are decided (v4+).
+    pub last_stable_offset: i64,
+    /// Log start offset of the partition (v5+).
+    pub log_start_offset: i64,
+    /// Encoded record batches.
+    pub records: Option<Bytes>,
+}
+
+impl PartitionData {
+    pub fn error(index: i32, error_code: ErrorCode) -> Self {
+        Self {
+            index,
+            error_code,
+            high_watermark: -1,
+            last_stable_offset: -1,
+            log_start_offset: -1,
+            records: None,
+        }
+    }
+}
+
+impl Decode for FetchRequest {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self> {
+        let flexible = version >= 12;
+
+        let replica_id = get_i32(buf)?;
+        let max_wait_ms = get_i32(buf)?;
+        let min_bytes = get_i32(buf)?;
+        let max_bytes = get_i32(buf)?;
+        let isolation_level = get_i8(buf)?;
+        let (session_id, session_epoch) = if version >= 7 {
+            (get_i32(buf)?, get_i32(buf)?)
+        } else {
+            (0, -1)
+        };
+
+        let topics = get_array(buf, flexible, |buf| {
+            let name = get_string(buf, flexible)?;
+            let partitions = get_array(buf, flexible, |buf| {
+                let index = get_i32(buf)?;
+                if version >= 9 {
+                    let _current_leader_epoch = get_i32(buf)?;
+                }
+                let fetch_offset = get_i64(buf)?;
+                if version >= 12 {
+                    let _last_fetched_epoch = get_i32(buf)?;
+                }
+                if version >= 5 {
+                    let _log_start_offset = get_i64(buf)?;
+                }
+                let partition_max_bytes = get_i32(buf)?;
+                if flexible {
+                    skip_tagged_fields(buf)?;
+                }
+                Ok(FetchPartition {
+                    index,
+                    fetch_offset,
+                    partition_max_bytes,
+                })
+            })?;
+            if flexible {
+                skip_tagged_fields(buf)?;
+            }
+            Ok(FetchTopic { name, partitions })
+        })?;
+
+        if version >= 7 {
+            // Partitions to remove from the fetch session. There are no
+            // sessions, so they are ignored.
+            get_array(buf, flexible, |buf| {
+                get_string(buf, flexible)?;
+                get_array(buf, flexible, get_i32)?;
+                if flexible {
+                    skip_tagged_fields(buf)?;
+                }
+                Ok(())
+            })?;
+        }
+        let rack_id = if version >= 11 {
+            get_string(buf, flexible)?
+        } else {
+            String::new()
+        };
+        if flexible {
+            skip_tagged_fields(buf)?;
+        }
+
+        Ok(Self {
+            replica_id,
+            max_wait_ms,
+            min_bytes,
+            max_bytes,
+            isolation_level,
+            session_id,
+            session_epoch,
+            topics,
+            rack_id,
+        })
+    }
+}
+
+impl Encode for FetchResponse {
+    fn encode(&self, buf: &mut BytesMut, version: i16) {
+        let flexible = version >= 12;
+
+        buf.put_i32(self.throttle_time_ms);
+        if version >= 7 {
+            buf.put_i16(self.error_code.code());
+            buf.put_i32(self.session_id);
+        }
+        put_array(buf, &self.responses, flexible, |buf, topic| {
+            put_string(buf, &topic.name, flexible);
+            put_array(buf, &topic.partitions, flexible, |buf, partition| {
+                buf.put_i32(partition.index);
+                buf.put_i16(partition.error_code.code());
+                buf.put_i64(partition.high_watermark);
+                buf.put_i64(partition.last_stable_offset);
+                if version >= 5 {
+                    buf.put_i64(partition.log_start_offset);
+                }
+                // Aborted transactions: there are no transactions.
+                put_array(buf, &[] as &[()], flexible, |_, _| {});
+                if version >= 11 {
+                    // Preferred read replica: none, read from the leader.
+                    buf.put_i32(-1);
+                }
+                put_nullable_bytes(buf, partition.records.as_deref(), flexible);
+                if flexible {
+                    put_empty_tagged_fields(buf);
+                }
+            });
+            if flexible {
+                put_empty_tagged_fields(buf);
+            }
+        });
+        if flexible {
+            put_empty_tagged_fields(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_request(version: i16) -> Bytes {
+        let flexible = version >= 12;
+        let mut buf = BytesMut::new();
+        buf.put_i32(-1);
+        buf.put_i32(500);
+        buf.put_i32(1);
+        buf.put_i32(52_428_800);
+        buf.put_i8(0);
+        if version >= 7 {
+            buf.put_i32(0);
+            buf.put_i32(-1);
+        }
+        put_array(&mut buf, &[()], flexible, |buf, _| {
+            put_string(buf, "events", flexible);
+            put_array(buf, &[()], flexible, |buf, _| {
+                buf.put_i32(0);
+                if version >= 9 {
+                    buf.put_i32(-1);
+                }
+                buf.put_i64(42);
+                if version >= 12 {
+                    buf.put_i32(-1);
+                }
+                if version >= 5 {
+                    buf.put_i64(-1);
+                }
+                buf.put_i32(1_048_576);
+                if flexible {
+                    put_empty_tagged_fields(buf);
+                }
+            });
+            if flexible {
+                put_empty_tagged_fields(buf);
+            }
+        });
+        if version >= 7 {
+            put_array(&mut buf, &[] as &[()], flexible, |_, _| {});
+        }
+        if version >= 11 {
+            put_string(&mut buf, "", flexible);
+        }
+        if flexible {
+            put_empty_tagged_fields(&mut buf);
+        }
+        buf.freeze()
+    }
+
+    #[test]
+    fn test_decode_request() {
+        for version in 4..=12 {
+            let mut buf = encode_request(version);
+            let request = FetchRequest::decode(&mut buf, version).unwrap();
+            assert!(buf.is_empty(), "version {}", version);
+            assert_eq!(request.max_wait_ms, 500);
+            assert_eq!(request.max_bytes, 52_428_800);
+            assert_eq!(
+                request.topics,
+                vec![FetchTopic {
+                    name: "events".into(),
+                    partitions: vec![FetchPartition {
+                        index: 0,
+                        fetch_offset: 42,
+                        partition_max_bytes: 1_048_576,
+                    }],
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = FetchResponse {
+            throttle_time_ms: 0,
+            error_code: ErrorCode::None,
+            session_id: 0,
+            responses: vec![FetchableTopicResponse {
+                name: "t".into(),
+                partitions: vec![PartitionData {
+                    records: Some(Bytes::from_static(b"batch")),
+                    ..PartitionData::error(0, ErrorCode::None)
+                }],
+            }],
+        };
+
+        let mut v4 = BytesMut::new();
+        response.encode(&mut v4, 4);
+        // throttle time, topics, name, partitions, index, error, high
+        // watermark, last stable offset, aborted transactions, records
+        assert_eq!(v4.len(), 4 + 4 + 3 + 4 + 4 + 2 + 8 + 8 + 4 + 4 + 5);
+
+        let mut v12 = BytesMut::new();
+        response.encode(&mut v12, 12);
+        // adds the error code, session id, log start offset and preferred
+        // read replica, uses compact encodings and tagged fields
+        assert_eq!(
+            v12.len(),
+            4 + 2 + 4 + 1 + 2 + 1 + 4 + 2 + 8 + 8 + 8 + 1 + 4 + 6 + 1 + 1 + 1
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! IncrementalAlterConfigs (key 44): change some configs of brokers and
+//! topics, leaving the other ones as they are.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+/// Set the config to the value.
+pub const OP_SET: i8 = 0;
+/// Remove the config, which goes back to its default value.
+pub const OP_DELETE: i8 = 1;
+/// Add the value to a list config.
+pub const OP_APPEND: i8 = 2;
+/// Remove the value from a list config.
+pub const OP_SUBTRACT: i8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncrementalAlterConfigsRequest {
+    pub resources: Vec<AlterConfigsResource>,
+    /// Whether the changes are only validated, not applied.
+    pub validate_only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlterConfigsResource {
+    pub resource_type: i8,
+    pub resource_name: String,
+    pub configs: Vec<AlterableConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlterableConfig {
+    pub name: String,
+    pub config_operation: i8,
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncrementalAlterConfigsResponse {
+    pub throttle_time_ms: i32,
+    /// Result of each resource, in the order of the request.
+    pub responses: Vec<AlterConfigsResourceResponse>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlterConfigsResourceResponse {
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub resource_type: i8,
+    pub resource_name: String,
+}
+
+impl Decode for IncrementalAlterConfigsRequest {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self> {
+        let flexible = version >= 1;
+        let resources = get_array(buf, flexible, |buf| {
+            let resource_type = get_i8(buf)?;
+            let resource_name = get_string(buf, flexible)?;
+            let configs = get_array(buf, flexible, |buf| {
+                let config = AlterableConfig {
+                    name: get_string(buf, flexible)?,
+                    config_operation: get_i8(buf)?,
+                    value: get_nullable_string(buf, flexible)?,
+                };
+                if flexible {
+                    skip_tagged_fields(buf)?;
+                }
+                Ok(config)
+            })?;
+            if flexible {
+                skip_tagged_fields(buf)?;
+            }
+            Ok(AlterConfigsResource {
+                resource_type,
+                resource_name,
+                configs,
+            })
+        })?;
+        let validate_only = get_bool(buf)?;
+        if flexible {
+            skip_tagged_fields(buf)?;
+        }
+        Ok(Self {
+            resources,
+            validate_only,
+        })
+    }
+}
+
+impl Encode for IncrementalAlterConfigsResponse {
+    fn encode(&self, buf: &mut BytesMut, version: i16) {
+        let flexible = version >= 1;
+        buf.put_i32(self.throttle_time_ms);
+        put_array(buf, &self.responses, flexible, |buf, response| {
+            buf.put_i16(response.error_code.code());
+            put_nullable_string(buf, response.error_message.as_deref(), flexible);
+            buf.put_i8(response.resource_type);
+            put_string(buf, &response.resource_name, flexible);
+            if flexible {
+                put_empty_tagged_fields(buf);
+            }
+        });
+        if flexible {
+            put_empty_tagged_fields(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        let mut buf = BytesMut::new();
+        put_unsigned_varint(&mut buf, 2);
+        buf.put_i8(2);
+        put_string(&mut buf, "events", true);
+        put_unsigned_varint(&mut buf, 2);
+        put_string(&mut buf, "segment.bytes", true);
+        buf.put_i8(OP_SET);
+        put_nullable_string(&mut buf, Some("1024"), true);
+        put_empty_tagged_fields(&mut buf);
+        put_empty_tagged_fields(&mut buf);
+        put_bool(&mut buf, false);
+        put_empty_tagged_fields(&mut buf);
+
+        let request = IncrementalAlterConfigsRequest::decode(&mut buf.freeze(), 1).unwrap();
+        assert_eq!(
+            request,
+            IncrementalAlterConfigsRequest {
+                resources: vec![AlterConfigsResource {
+                    resource_type: 2,
+                    resource_name: "events".into(),
+                    configs: vec![AlterableConfig {
+                        name: "segment.bytes".into(),
+                        config_operation: OP_SET,
+                        value: Some("1024".into()),
+                    }],
+                }],
+                validate_only: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = IncrementalAlterConfigsResponse {
+            throttle_time_ms: 0,
+            responses: vec![AlterConfigsResourceResponse {
+                error_code: ErrorCode::None,
+                error_message: None,
+                resource_type: 4,
+                resource_name: "0".into(),
+            }],
+        };
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 0);
+        assert_eq!(&buf[..], b"\0\0\0\0\0\0\0\x01\0\0\xff\xff\x04\0\x010");
+    }
+}
@@ -0,0 +1,243 @@
+//! ListOffsets (key 2): find the offset of partitions at a point in time.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+/// Timestamp asking for the log end offset, or the last stable offset with
+/// `READ_COMMITTED`.
+pub const LATEST_TIMESTAMP: i64 = -1;
+
+/// Timestamp asking for the log start offset.
+pub const EARLIEST_TIMESTAMP: i64 = -2;
+
+/// Timestamp asking for the offset of the record with the largest timestamp
+/// (v7+).
+pub const MAX_TIMESTAMP: i64 = -3;
+
+/// Isolation levels of consumers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsolationLevel {
+    /// Read every record, including records of open transactions.
+    ReadUncommitted,
+    /// Read only records of committed transactions, up to the last stable
+    /// offset.
+    ReadCommitted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListOffsetsRequest {
+    pub replica_id: i32,
+    /// Isolation level (v2+).
+    pub isolation_level: IsolationLevel,
+    pub topics: Vec<ListOffsetsTopic>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListOffsetsTopic {
+    pub name: String,
+    pub partitions: Vec<ListOffsetsPartition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListOffsetsPartition {
+    pub partition_index: i32,
+    /// Timestamp to look up, or one of `LATEST_TIMESTAMP`,
+    /// `EARLIEST_TIMESTAMP` and `MAX_TIMESTAMP`.
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListOffsetsResponse {
+    pub throttle_time_ms: i32,
+    pub topics: Vec<ListOffsetsTopicResponse>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListOffsetsTopicResponse {
+    pub name: String,
+    pub partitions: Vec<ListOffsetsPartitionResponse>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListOffsetsPartitionResponse {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    /// Timestamp of the record at `offset`, -1 when looking up the earliest
+    /// or latest offset.
+    pub timestamp: i64,
+    pub offset: i64,
+    /// Leader epoch (v4+).
+    pub leader_epoch: i32,
+}
+
+impl ListOffsetsPartitionResponse {
+    pub fn error(partition_index: i32, error_code: ErrorCode) -> Self {
+        Self {
+            partition_index,
+            error_code,
+            timestamp: -1,
+            offset: -1,
+            leader_epoch: -1,
+        }
+    }
+}
+
+impl Decode for ListOffsetsRequest {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self> {
+        let flexible = version >= 6;
+
+        let replica_id = get_i32(buf)?;
+        let isolation_level = if version >= 2 && get_i8(buf)? == 1 {
+            IsolationLevel::ReadCommitted
+        } else {
+            IsolationLevel::ReadUncommitted
+        };
+        let topics = get_array(buf, flexible, |buf| {
+            let name = get_string(buf, flexible)?;
+            let partitions = get_array(buf, flexible, |buf| {
+                let partition_index = get_i32(buf)?;
+                if version >= 4 {
+                    let _current_leader_epoch = get_i32(buf)?;
+                }
+                let timestamp = get_i64(buf)?;
+                if flexible {
+                    skip_tagged_fields(buf)?;
+                }
+                Ok(ListOffsetsPartition {
+                    partition_index,
+                    timestamp,
+                })
+            })?;
+            if flexible {
+                skip_tagged_fields(buf)?;
+            }
+            Ok(ListOffsetsTopic { name, partitions })
+        })?;
+        if flexible {
+            skip_tagged_fields(buf)?;
+        }
+
+        Ok(Self {
+            replica_id,
+            isolation_level,
+            topics,
+        })
+    }
+}
+
+impl Encode for ListOffsetsResponse {
+    fn encode(&self, buf: &mut BytesMut, version: i16) {
+        let flexible = version >= 6;
+
+        if version >= 2 {
+            buf.put_i32(self.throttle_time_ms);
+        }
+        put_array(buf, &self.topics, flexible, |buf, topic| {
+            put_string(buf, &topic.name, flexible);
+            put_array(buf, &topic.partitions, flexible, |buf, partition| {
+                buf.put_i32(partition.partition_index);
+                buf.put_i16(partition.error_code.code());
+                buf.put_i64(partition.timestamp);
+                buf.put_i64(partition.offset);
+                if version >= 4 {
+                    buf.put_i32(partition.leader_epoch);
+                }
+                if flexible {
+                    put_empty_tagged_fields(buf);
+                }
+            });
+            if flexible {
+                put_empty_tagged_fields(buf);
+            }
+        });
+        if flexible {
+            put_empty_tagged_fields(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        for version in 1..=7 {
+            let flexible = version >= 6;
+            let mut buf = BytesMut::new();
+            buf.put_i32(-1);
+            if version >= 2 {
+                buf.put_i8(1);
+            }
+            put_array(&mut buf, &["events"], flexible, |buf, name| {
+                put_string(buf, name, flexible);
+                put_array(buf, &[EARLIEST_TIMESTAMP], flexible, |buf, timestamp| {
+                    buf.put_i32(3);
+                    if version >= 4 {
+                        buf.put_i32(-1);
+                    }
+                    buf.put_i64(*timestamp);
+                    if flexible {
+                        put_empty_tagged_fields(buf);
+                    }
+                });
+                if flexible {
+                    put_empty_tagged_fields(buf);
+                }
+            });
+            if flexible {
+                put_empty_tagged_fields(&mut buf);
+            }
+
+            let mut buf = buf.freeze();
+            let request = ListOffsetsRequest::decode(&mut buf, version).unwrap();
+            assert!(buf.is_empty(), "version {}", version);
+            assert_eq!(
+                request.isolation_level,
+                if version >= 2 {
+                    IsolationLevel::ReadCommitted
+                } else {
+                    IsolationLevel::ReadUncommitted
+                }
+            );
+            assert_eq!(
+                request.topics,
+                vec![ListOffsetsTopic {
+                    name: "events".into(),
+                    partitions: vec![ListOffsetsPartition {
+                        partition_index: 3,
+                        timestamp: EARLIEST_TIMESTAMP,
+                    }],
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = ListOffsetsResponse {
+            throttle_time_ms: 0,
+            topics: vec![ListOffsetsTopicResponse {
+                name: "t".into(),
+                partitions: vec![ListOffsetsPartitionResponse {
+                    partition_index: 0,
+                    error_code: ErrorCode::None,
+                    timestamp: -1,
+                    offset: 42,
+                    leader_epoch: 0,
+                }],
+            }],
+        };
+
+        let mut v1 = BytesMut::new();
+        response.encode(&mut v1, 1);
+        assert_eq!(v1.len(), 4 + 3 + 4 + 4 + 2 + 8 + 8);
+
+        let mut v7 = BytesMut::new();
+        response.encode(&mut v7, 7);
+        assert_eq!(v7.len(), 4 + 1 + 2 + 1 + 4 + 2 + 8 + 8 + 4 + 1 + 1 + 1);
+    }
+}
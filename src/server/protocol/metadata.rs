@@ -0,0 +1,253 @@
+//! Metadata (key 3): describe the brokers of the cluster and the topics and
+//! partitions they lead.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+/// Authorized operations sent when clients did not ask for them.
+pub const AUTHORIZED_OPERATIONS_OMITTED: i32 = i32::MIN;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataRequest {
+    /// Topics to describe, `None` for every topic.
+    pub topics: Option<Vec<String>>,
+    /// Whether topics that do not exist may be created (v4+).
+    pub allow_auto_topic_creation: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataResponse {
+    pub throttle_time_ms: i32,
+    pub brokers: Vec<MetadataResponseBroker>,
+    /// Cluster id (v2+).
+    pub cluster_id: Option<String>,
+    pub controller_id: i32,
+    pub topics: Vec<MetadataResponseTopic>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataResponseBroker {
+    pub node_id: i32,
+    pub host: String,
+    pub port: i32,
+    pub rack: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataResponseTopic {
+    pub error_code: ErrorCode,
+    pub name: String,
+    pub is_internal: bool,
+    pub partitions: Vec<MetadataResponsePartition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataResponsePartition {
+    pub error_code: ErrorCode,
+    pub partition_index: i32,
+    pub leader_id: i32,
+    /// Leader epoch (v7+).
+    pub leader_epoch: i32,
+    pub replica_nodes: Vec<i32>,
+    pub isr_nodes: Vec<i32>,
+}
+
+impl MetadataResponseTopic {
+    pub fn error(name: String, error_code: ErrorCode) -> Self {
+        Self {
+            error_code,
+            name,
+            is_internal: false,
+            partitions: vec![],
+        }
+    }
+}
+
+impl Decode for MetadataRequest {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self> {
+        let flexible = version >= 9;
+
+        let topics = get_nullable_array(buf, flexible, |buf| {
+            if version >= 10 {
+                let _topic_id = get_uuid(buf)?;
+            }
+            // Topics can be looked up by id only from v12, and topic ids are
+            // not supported: a null name matches no topic.
+            let name = get_nullable_string(buf, flexible)?.unwrap_or_default();
+            if flexible {
+                skip_tagged_fields(buf)?;
+            }
+            Ok(name)
+        })?;
+        let allow_auto_topic_creation = if version >= 4 { get_bool(buf)? } else { true };
+        if (8..=10).contains(&version) {
+            let _include_cluster_authorized_operations = get_bool(buf)?;
+        }
+        if version >= 8 {
+            let _include_topic_authorized_operations = get_bool(buf)?;
+        }
+        if flexible {
+            skip_tagged_fields(buf)?;
+        }
+
+        Ok(Self {
+            topics,
+            allow_auto_topic_creation,
+        })
+    }
+}
+
+impl Encode for MetadataResponse {
+    fn encode(&self, buf: &mut BytesMut, version: i16) {
+        let flexible = version >= 9;
+
+        if version >= 3 {
+            buf.put_i32(self.throttle_time_ms);
+        }
+        put_array(buf, &self.brokers, flexible, |buf, broker| {
+            buf.put_i32(broker.node_id);
+            put_string(buf, &broker.host, flexible);
+            buf.put_i32(broker.port);
+            put_nullable_string(buf, broker.rack.as_deref(), flexible);
+            if flexible {
+                put_empty_tagged_fields(buf);
+            }
+        });
+        if version >= 2 {
+            put_nullable_string(buf, self.cluster_id.as_deref(), flexible);
+        }
+        buf.put_i32(self.controller_id);
+        put_array(buf, &self.topics, flexible, |buf, topic| {
+            buf.put_i16(topic.error_code.code());
+            put_string(buf, &topic.name, flexible);
+            if version >= 10 {
+                // Topic ids are not supported, send the null id.
+                put_uuid(buf, &[0; 16]);
+            }
+            put_bool(buf, topic.is_internal);
+            put_array(buf, &topic.partitions, flexible, |buf, partition| {
+                buf.put_i16(partition.error_code.code());
+                buf.put_i32(partition.partition_index);
+                buf.put_i32(partition.leader_id);
+                if version >= 7 {
+                    buf.put_i32(partition.leader_epoch);
+                }
+                put_array(buf, &partition.replica_nodes, flexible, |buf, id| {
+                    buf.put_i32(*id)
+                });
+                put_array(buf, &partition.isr_nodes, flexible, |buf, id| {
+                    buf.put_i32(*id)
+                });
+                if version >= 5 {
+                    // Offline replicas: the only replica is the leader.
+                    put_array(buf, &[] as &[i32], flexible, |_, _| {});
+                }
+                if flexible {
+                    put_empty_tagged_fields(buf);
+                }
+            });
+            if version >= 8 {
+                buf.put_i32(AUTHORIZED_OPERATIONS_OMITTED);
+            }
+            if flexible {
+                put_empty_tagged_fields(buf);
+            }
+        });
+        if (8..=10).contains(&version) {
+            buf.put_i32(AUTHORIZED_OPERATIONS_OMITTED);
+        }
+        if flexible {
+            put_empty_tagged_fields(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        for version in 1..=12 {
+            let flexible = version >= 9;
+            let mut buf = BytesMut::new();
+            put_array(&mut buf, &["events"], flexible, |buf, name| {
+                if version >= 10 {
+                    put_uuid(buf, &[0; 16]);
+                }
+                put_string(buf, name, flexible);
+                if flexible {
+                    put_empty_tagged_fields(buf);
+                }
+            });
+            if version >= 4 {
+                put_bool(&mut buf, false);
+            }
+            if (8..=10).contains(&version) {
+                put_bool(&mut buf, false);
+            }
+            if version >= 8 {
+                put_bool(&mut buf, false);
+            }
+            if flexible {
+                put_empty_tagged_fields(&mut buf);
+            }
+
+            let mut buf = buf.freeze();
+            let request = MetadataRequest::decode(&mut buf, version).unwrap();
+            assert!(buf.is_empty(), "version {}", version);
+            assert_eq!(request.topics, Some(vec!["events".to_string()]));
+            assert_eq!(request.allow_auto_topic_creation, version < 4);
+        }
+
+        // A null array asks for every topic
+        let mut buf = Bytes::from_static(&[0xff, 0xff, 0xff, 0xff, 1]);
+        let request = MetadataRequest::decode(&mut buf, 4).unwrap();
+        assert_eq!(request.topics, None);
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = MetadataResponse {
+            throttle_time_ms: 0,
+            brokers: vec![MetadataResponseBroker {
+                node_id: 0,
+                host: "localhost".into(),
+                port: 9092,
+                rack: None,
+            }],
+            cluster_id: Some("fafka".into()),
+            controller_id: 0,
+            topics: vec![MetadataResponseTopic {
+                error_code: ErrorCode::None,
+                name: "t".into(),
+                is_internal: false,
+                partitions: vec![MetadataResponsePartition {
+                    error_code: ErrorCode::None,
+                    partition_index: 0,
+                    leader_id: 0,
+                    leader_epoch: 0,
+                    replica_nodes: vec![0],
+                    isr_nodes: vec![0],
+                }],
+            }],
+        };
+
+        let mut v1 = BytesMut::new();
+        response.encode(&mut v1, 1);
+        let broker = 4 + 11 + 4 + 2;
+        let partition = 2 + 4 + 4 + 8 + 8;
+        let topic = 2 + 3 + 1 + 4 + partition;
+        assert_eq!(v1.len(), 4 + broker + 4 + 4 + topic);
+
+        let mut v12 = BytesMut::new();
+        response.encode(&mut v12, 12);
+        let broker = 4 + 10 + 4 + 1 + 1;
+        let partition = 2 + 4 + 4 + 4 + 5 + 5 + 1 + 1;
+        let topic = 2 + 2 + 16 + 1 + 1 + partition + 4 + 1;
+        assert_eq!(v12.len(), 4 + 1 + broker + 6 + 4 + 1 + topic + 1);
+    }
+}
@@ -0,0 +1,176 @@
+//! Kafka binary protocol.
+//!
+//! Each API is implemented in its own module, which defines the request and
+//! response messages of the API for every version supported by the broker.
+//! See https://kafka.apache.org/protocol for the definition of the messages.
+
+pub mod alter_client_quotas;
+pub mod alter_user_scram_credentials;
+pub mod api_versions;
+pub mod codec;
+pub mod create_acls;
+pub mod delete_acls;
+pub mod describe_acls;
+pub mod describe_client_quotas;
+pub mod describe_configs;
+pub mod describe_user_scram_credentials;
+pub mod fetch;
+pub mod incremental_alter_configs;
+pub mod list_offsets;
+pub mod metadata;
+pub mod produce;
+pub mod records;
+pub mod sasl_authenticate;
+pub mod sasl_handshake;
+
+use bytes::{Bytes, BytesMut};
+
+use super::error::Result;
+use super::frame::RequestHeader;
+
+/// Identifies the API of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i16)]
+pub enum ApiKey {
+    Produce = 0,
+    Fetch = 1,
+    ListOffsets = 2,
+    Metadata = 3,
+    SaslHandshake = 17,
+    ApiVersions = 18,
+    DescribeAcls = 29,
+    CreateAcls = 30,
+    DeleteAcls = 31,
+    DescribeConfigs = 32,
+    SaslAuthenticate = 36,
+    IncrementalAlterConfigs = 44,
+    DescribeClientQuotas = 48,
+    AlterClientQuotas = 49,
+    DescribeUserScramCredentials = 50,
+    AlterUserScramCredentials = 51,
+}
+
+impl ApiKey {
+    /// Every API implemented by the broker.
+    pub const ALL: &'static [ApiKey] = &[
+        ApiKey::Produce,
+        ApiKey::Fetch,
+        ApiKey::ListOffsets,
+        ApiKey::Metadata,
+        ApiKey::SaslHandshake,
+        ApiKey::ApiVersions,
+        ApiKey::DescribeAcls,
+        ApiKey::CreateAcls,
+        ApiKey::DeleteAcls,
+        ApiKey::DescribeConfigs,
+        ApiKey::SaslAuthenticate,
+        ApiKey::IncrementalAlterConfigs,
+        ApiKey::DescribeClientQuotas,
+        ApiKey::AlterClientQuotas,
+        ApiKey::DescribeUserScramCredentials,
+        ApiKey::AlterUserScramCredentials,
+    ];
+
+    pub fn from_i16(key: i16) -> Option<ApiKey> {
+        ApiKey::ALL.iter().copied().find(|k| *k as i16 == key)
+    }
+
+    /// Oldest and newest versions of the API supported by the broker.
+    pub fn versions(self) -> (i16, i16) {
+        match self {
+            // Older versions of Produce use the v0 and v1 message formats.
+            ApiKey::Produce => (3, 9),
+            // Fetch v13+ identifies topics by id instead of name.
+            ApiKey::Fetch => (4, 12),
+            ApiKey::ListOffsets => (1, 7),
+            ApiKey::Metadata => (1, 12),
+            // SaslHandshake v0 sends the tokens of the exchange without
+            // request headers, instead of in SaslAuthenticate requests.
+            ApiKey::SaslHandshake => (1, 1),
+            ApiKey::ApiVersions => (0, 3),
+            // v0 of the ACL APIs predates prefixed patterns.
+            ApiKey::DescribeAcls => (1, 3),
+            ApiKey::CreateAcls => (1, 3),
+            ApiKey::DeleteAcls => (1, 3),
+            // v0 does not tell where the values of configs come from.
+            ApiKey::DescribeConfigs => (1, 4),
+            ApiKey::SaslAuthenticate => (0, 2),
+            ApiKey::IncrementalAlterConfigs => (0, 1),
+            ApiKey::DescribeClientQuotas => (0, 1),
+            ApiKey::AlterClientQuotas => (0, 1),
+            ApiKey::DescribeUserScramCredentials => (0, 0),
+            ApiKey::AlterUserScramCredentials => (0, 0),
+        }
+    }
+
+    pub fn supports(self, version: i16) -> bool {
+        let (min, max) = self.versions();
+        min <= version && version <= max
+    }
+
+    /// Whether `version` of the API is a flexible version. Flexible versions
+    /// use compact strings, bytes and arrays, and tagged fields.
+    pub fn is_flexible(self, version: i16) -> bool {
+        let first_flexible = match self {
+            ApiKey::Produce => 9,
+            ApiKey::Fetch => 12,
+            ApiKey::ListOffsets => 6,
+            ApiKey::Metadata => 9,
+            ApiKey::SaslHandshake => i16::MAX,
+            ApiKey::ApiVersions => 3,
+            ApiKey::DescribeAcls => 2,
+            ApiKey::CreateAcls => 2,
+            ApiKey::DeleteAcls => 2,
+            ApiKey::DescribeConfigs => 4,
+            ApiKey::SaslAuthenticate => 2,
+            ApiKey::IncrementalAlterConfigs => 1,
+            ApiKey::DescribeClientQuotas => 1,
+            ApiKey::AlterClientQuotas => 1,
+            ApiKey::DescribeUserScramCredentials => 0,
+            ApiKey::AlterUserScramCredentials => 0,
+        };
+        version >= first_flexible
+    }
+}
+
+/// A message that can be decoded from a request body.
+pub trait Decode: Sized {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self>;
+}
+
+/// A message that can be encoded in a response body.
+pub trait Encode {
+    fn encode(&self, buf: &mut BytesMut, version: i16);
+}
+
+/// Decode the body of the request described by `header`.
+///
+/// Requests of flexible versions use the second version of the request
+/// header, which ends with tagged fields. They are not parsed with the rest
+/// of the header and are skipped here.
+pub fn decode_request<T: Decode>(
+    key: ApiKey,
+    header: &RequestHeader,
+    mut body: Bytes,
+) -> Result<T> {
+    if key.is_flexible(header.api_version) {
+        codec::skip_tagged_fields(&mut body)?;
+    }
+    T::decode(&mut body, header.api_version)
+}
+
+/// Encode the body of the response to the request described by `header`.
+///
+/// Responses of flexible versions use the second version of the response
+/// header, which ends with tagged fields. The correlation id is written by
+/// `Connection`, so only the tagged fields are added here. ApiVersions
+/// responses always use the first version of the header, so that clients can
+/// parse them before knowing which versions the broker supports.
+pub fn encode_response<T: Encode>(key: ApiKey, header: &RequestHeader, response: &T) -> Bytes {
+    let mut buf = BytesMut::new();
+    if key.is_flexible(header.api_version) && key != ApiKey::ApiVersions {
+        codec::put_empty_tagged_fields(&mut buf);
+    }
+    response.encode(&mut buf, header.api_version);
+    buf.freeze()
+}
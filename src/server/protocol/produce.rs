@@ -0,0 +1,225 @@
+//! Produce (key 0): append record batches to partitions.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProduceRequest {
+    pub transactional_id: Option<String>,
+    /// Number of acknowledgments required before answering: 0 for none, 1
+    /// for the leader only, -1 for every in-sync replica.
+    pub acks: i16,
+    pub timeout_ms: i32,
+    pub topics: Vec<TopicProduceData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicProduceData {
+    pub name: String,
+    pub partitions: Vec<PartitionProduceData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionProduceData {
+    pub index: i32,
+    /// Encoded record batches.
+    pub records: Option<Bytes>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProduceResponse {
+    pub responses: Vec<TopicProduceResponse>,
+    pub throttle_time_ms: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicProduceResponse {
+    pub name: String,
+    pub partitions: Vec<PartitionProduceResponse>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionProduceResponse {
+    pub index: i32,
+    pub error_code: ErrorCode,
+    pub base_offset: i64,
+    /// Append time of the records if the topic uses `LogAppendTime`
+    /// timestamps, -1 otherwise.
+    pub log_append_time_ms: i64,
+    /// Log start offset of the partition (v5+).
+    pub log_start_offset: i64,
+    /// Message describing the error (v8+).
+    pub error_message: Option<String>,
+}
+
+impl PartitionProduceResponse {
+    pub fn error(index: i32, error_code: ErrorCode, error_message: Option<String>) -> Self {
+        Self {
+            index,
+            error_code,
+            base_offset: -1,
+            log_append_time_ms: -1,
+            log_start_offset: -1,
+            error_message,
+        }
+    }
+}
+
+impl Decode for ProduceRequest {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self> {
+        let flexible = version >= 9;
+
+        let transactional_id = get_nullable_string(buf, flexible)?;
+        let acks = get_i16(buf)?;
+        let timeout_ms = get_i32(buf)?;
+        let topics = get_array(buf, flexible, |buf| {
+            let name = get_string(buf, flexible)?;
+            let partitions = get_array(buf, flexible, |buf| {
+                let partition = PartitionProduceData {
+                    index: get_i32(buf)?,
+                    records: get_nullable_bytes(buf, flexible)?,
+                };
+                if flexible {
+                    skip_tagged_fields(buf)?;
+                }
+                Ok(partition)
+            })?;
+            if flexible {
+                skip_tagged_fields(buf)?;
+            }
+            Ok(TopicProduceData { name, partitions })
+        })?;
+        if flexible {
+            skip_tagged_fields(buf)?;
+        }
+
+        Ok(Self {
+            transactional_id,
+            acks,
+            timeout_ms,
+            topics,
+        })
+    }
+}
+
+impl Encode for ProduceResponse {
+    fn encode(&self, buf: &mut BytesMut, version: i16) {
+        let flexible = version >= 9;
+
+        put_array(buf, &self.responses, flexible, |buf, topic| {
+            put_string(buf, &topic.name, flexible);
+            put_array(buf, &topic.partitions, flexible, |buf, partition| {
+                buf.put_i32(partition.index);
+                buf.put_i16(partition.error_code.code());
+                buf.put_i64(partition.base_offset);
+                buf.put_i64(partition.log_append_time_ms);
+                if version >= 5 {
+                    buf.put_i64(partition.log_start_offset);
+                }
+                if version >= 8 {
+                    // Errors are reported for the whole partition, never
+                    // for individual records.
+                    put_array(buf, &[] as &[()], flexible, |_, _| {});
+                    put_nullable_string(buf, partition.error_message.as_deref(), flexible);
+                }
+                if flexible {
+                    put_empty_tagged_fields(buf);
+                }
+            });
+            if flexible {
+                put_empty_tagged_fields(buf);
+            }
+        });
+        buf.put_i32(self.throttle_time_ms);
+        if flexible {
+            put_empty_tagged_fields(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        for version in [3, 9] {
+            let flexible = version >= 9;
+            let mut buf = BytesMut::new();
+            put_nullable_string(&mut buf, None, flexible);
+            buf.put_i16(-1);
+            buf.put_i32(30_000);
+            put_array(&mut buf, &[()], flexible, |buf, _| {
+                put_string(buf, "events", flexible);
+                put_array(buf, &[()], flexible, |buf, _| {
+                    buf.put_i32(2);
+                    put_bytes(buf, b"batch", flexible);
+                    if flexible {
+                        put_empty_tagged_fields(buf);
+                    }
+                });
+                if flexible {
+                    put_empty_tagged_fields(buf);
+                }
+            });
+            if flexible {
+                put_empty_tagged_fields(&mut buf);
+            }
+
+            let mut buf = buf.freeze();
+            let request = ProduceRequest::decode(&mut buf, version).unwrap();
+            assert!(buf.is_empty());
+            assert_eq!(
+                request,
+                ProduceRequest {
+                    transactional_id: None,
+                    acks: -1,
+                    timeout_ms: 30_000,
+                    topics: vec![TopicProduceData {
+                        name: "events".into(),
+                        partitions: vec![PartitionProduceData {
+                            index: 2,
+                            records: Some(Bytes::from_static(b"batch")),
+                        }],
+                    }],
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = ProduceResponse {
+            responses: vec![TopicProduceResponse {
+                name: "t".into(),
+                partitions: vec![PartitionProduceResponse {
+                    index: 0,
+                    error_code: ErrorCode::None,
+                    base_offset: 5,
+                    log_append_time_ms: -1,
+                    log_start_offset: 0,
+                    error_message: None,
+                }],
+            }],
+            throttle_time_ms: 0,
+        };
+
+        let mut v3 = BytesMut::new();
+        response.encode(&mut v3, 3);
+        // topics, name, partitions, index, error, base offset, append time,
+        // throttle time
+        assert_eq!(v3.len(), 4 + 3 + 4 + 4 + 2 + 8 + 8 + 4);
+
+        let mut v9 = BytesMut::new();
+        response.encode(&mut v9, 9);
+        // compact arrays and strings, log start offset, record errors, error
+        // message and tagged fields
+        assert_eq!(
+            v9.len(),
+            1 + 2 + 1 + 4 + 2 + 8 + 8 + 8 + 1 + 1 + 1 + 1 + 4 + 1
+        );
+    }
+}
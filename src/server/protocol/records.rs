@@ -0,0 +1,556 @@
+//! Record batches, the format in which clients produce and fetch records.
+//!
+//! Only the v2 format (magic 2) is supported, without compression. See
+//! https://kafka.apache.org/documentation/#recordbatch
+//!
+//! The store assigns an offset to every record, so records are not stored in
+//! their batch: each record is stored on its own in the compact format of
+//! `Record::encode_stored`, and batches are rebuilt when records are read.
+
+use std::fmt;
+use std::str::FromStr;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use crate::server::error::{Error, Result};
+use crate::store::index::Entry;
+
+/// Magic byte of the v2 record batch format.
+pub const MAGIC: i8 = 2;
+
+/// Size of the batch fields preceding `batch_length`, which are not counted
+/// in it.
+const LOG_OVERHEAD: usize = 12;
+
+/// Size of a batch header, up to and including the record count.
+const HEADER_SIZE: usize = 61;
+
+/// Offset of the `attributes` field, where the CRC coverage starts.
+const CRC_START: usize = 21;
+
+const COMPRESSION_MASK: i16 = 0x07;
+const TIMESTAMP_TYPE_MASK: i16 = 0x08;
+const TRANSACTIONAL_MASK: i16 = 0x10;
+const CONTROL_MASK: i16 = 0x20;
+
+/// Where the timestamps of records come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampType {
+    /// Timestamps are set by the producer.
+    CreateTime,
+    /// Timestamps are the time at which the broker appended the records.
+    LogAppendTime,
+}
+
+impl FromStr for TimestampType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "CreateTime" => Ok(TimestampType::CreateTime),
+            "LogAppendTime" => Ok(TimestampType::LogAppendTime),
+            _ => Err(format!(
+                "invalid timestamp type {:?}, expected CreateTime or LogAppendTime",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TimestampType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub key: String,
+    pub value: Option<Bytes>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Offset of the record relative to the base offset of its batch.
+    pub offset_delta: i32,
+    /// Timestamp of the record in milliseconds since the unix epoch.
+    pub timestamp: i64,
+    pub key: Option<Bytes>,
+    pub value: Option<Bytes>,
+    pub headers: Vec<Header>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordBatch {
+    pub base_offset: i64,
+    pub partition_leader_epoch: i32,
+    pub attributes: i16,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub base_sequence: i32,
+    pub records: Vec<Record>,
+}
+
+impl TimestampType {
+    pub fn name(self) -> &'static str {
+        match self {
+            TimestampType::CreateTime => "CreateTime",
+            TimestampType::LogAppendTime => "LogAppendTime",
+        }
+    }
+
+    fn from_attributes(attributes: i16) -> Self {
+        if attributes & TIMESTAMP_TYPE_MASK == 0 {
+            TimestampType::CreateTime
+        } else {
+            TimestampType::LogAppendTime
+        }
+    }
+}
+
+impl Record {
+    /// Encode the record to be stored. The offset delta is not stored, the
+    /// store keeps the offset of every record.
+    pub fn encode_stored(&self, timestamp_type: TimestampType) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        let attributes = match timestamp_type {
+            TimestampType::CreateTime => 0,
+            TimestampType::LogAppendTime => TIMESTAMP_TYPE_MASK as i8,
+        };
+        buf.put_i8(attributes);
+        put_varlong(&mut buf, self.timestamp);
+        self.put_body(&mut buf);
+        buf.to_vec()
+    }
+
+    /// Decode a record encoded by `encode_stored`. Its offset delta is left
+    /// to 0.
+    pub fn decode_stored(mut buf: Bytes) -> Result<(Self, TimestampType)> {
+        let timestamp_type = TimestampType::from_attributes(get_i8(&mut buf)? as i16);
+        let timestamp = get_varlong(&mut buf)?;
+        let record = Self::get_body(&mut buf, 0, timestamp)?;
+        Ok((record, timestamp_type))
+    }
+
+    fn put_body(&self, buf: &mut BytesMut) {
+        put_varint_bytes(buf, self.key.as_deref());
+        put_varint_bytes(buf, self.value.as_deref());
+        put_varint(buf, self.headers.len() as i32);
+        for header in &self.headers {
+            put_varint_bytes(buf, Some(header.key.as_bytes()));
+            put_varint_bytes(buf, header.value.as_deref());
+        }
+    }
+
+    fn get_body(buf: &mut Bytes, offset_delta: i32, timestamp: i64) -> Result<Self> {
+        let key = get_varint_bytes(buf)?;
+        let value = get_varint_bytes(buf)?;
+
+        let count = get_varint(buf)?;
+        if count < 0 {
+            return Err(Error::CorruptMessage("negative header count".into()));
+        }
+        let mut headers = Vec::with_capacity((count as usize).min(buf.remaining()));
+        for _ in 0..count {
+            let key = get_varint_bytes(buf)?
+                .ok_or_else(|| Error::CorruptMessage("null header key".into()))?;
+            let key = String::from_utf8(key.to_vec())
+                .map_err(|_| Error::CorruptMessage("header key is not valid utf-8".into()))?;
+            let value = get_varint_bytes(buf)?;
+            headers.push(Header { key, value });
+        }
+
+        Ok(Self {
+            offset_delta,
+            timestamp,
+            key,
+            value,
+            headers,
+        })
+    }
+}
+
+impl RecordBatch {
+    /// Batch of `records`, whose offset deltas and timestamps are already
+    /// set, with the attributes of a non-transactional batch.
+    pub fn new(base_offset: i64, timestamp_type: TimestampType, records: Vec<Record>) -> Self {
+        let attributes = match timestamp_type {
+            TimestampType::CreateTime => 0,
+            TimestampType::LogAppendTime => TIMESTAMP_TYPE_MASK,
+        };
+
+        Self {
+            base_offset,
+            partition_leader_epoch: -1,
+            attributes,
+            producer_id: -1,
+            producer_epoch: -1,
+            base_sequence: -1,
+            records,
+        }
+    }
+
+    pub fn timestamp_type(&self) -> TimestampType {
+        TimestampType::from_attributes(self.attributes)
+    }
+
+    /// Decode and validate every batch in `buf`, as sent in a produce
+    /// request.
+    ///
+    /// Batches that cannot be decoded or whose CRC does not match are
+    /// rejected with `CorruptMessage`. Compressed batches are rejected with
+    /// `UnsupportedCompressionType`, transactional and control batches, and
+    /// batches whose offset deltas are not consecutive, with `InvalidRecord`.
+    pub fn decode_all(mut buf: Bytes) -> Result<Vec<RecordBatch>> {
+        let mut batches = vec![];
+        while buf.has_remaining() {
+            batches.push(Self::decode(&mut buf).map_err(corrupt)?);
+        }
+        if batches.is_empty() {
+            return Err(Error::InvalidRecord("no record batch".into()));
+        }
+        Ok(batches)
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<RecordBatch> {
+        if buf.remaining() < HEADER_SIZE {
+            return Err(Error::CorruptMessage("truncated batch header".into()));
+        }
+        let size = LOG_OVERHEAD + (&buf[8..LOG_OVERHEAD]).get_i32().max(0) as usize;
+        if size < HEADER_SIZE || size > buf.remaining() {
+            return Err(Error::CorruptMessage(format!(
+                "invalid batch size {}",
+                size
+            )));
+        }
+        let mut batch = buf.split_to(size);
+
+        let base_offset = get_i64(&mut batch)?;
+        let _batch_length = get_i32(&mut batch)?;
+        let partition_leader_epoch = get_i32(&mut batch)?;
+        let magic = get_i8(&mut batch)?;
+        if magic != MAGIC {
+            return Err(Error::CorruptMessage(format!(
+                "unsupported magic {}",
+                magic
+            )));
+        }
+        let crc = get_u32(&mut batch)?;
+        if crc != crc32c::crc32c(&batch) {
+            return Err(Error::CorruptMessage("crc mismatch".into()));
+        }
+
+        let attributes = get_i16(&mut batch)?;
+        if attributes & COMPRESSION_MASK != 0 {
+            return Err(Error::UnsupportedCompressionType);
+        }
+        if attributes & (TRANSACTIONAL_MASK | CONTROL_MASK) != 0 {
+            return Err(Error::InvalidRecord(
+                "transactional and control batches are not supported".into(),
+            ));
+        }
+
+        let last_offset_delta = get_i32(&mut batch)?;
+        let base_timestamp = get_i64(&mut batch)?;
+        let _max_timestamp = get_i64(&mut batch)?;
+        let producer_id = get_i64(&mut batch)?;
+        let producer_epoch = get_i16(&mut batch)?;
+        let base_sequence = get_i32(&mut batch)?;
+        let count = get_i32(&mut batch)?;
+        if count <= 0 || count.checked_sub(1) != Some(last_offset_delta) {
+            return Err(Error::InvalidRecord(format!(
+                "{} records with a last offset delta of {}",
+                count, last_offset_delta
+            )));
+        }
+
+        let mut records = Vec::with_capacity((count as usize).min(batch.remaining()));
+        for expected_delta in 0..count {
+            let len = get_varint(&mut batch)?;
+            if len < 0 || len as usize > batch.remaining() {
+                return Err(Error::CorruptMessage(format!(
+                    "invalid record size {}",
+                    len
+                )));
+            }
+            let mut record = batch.split_to(len as usize);
+
+            let _attributes = get_i8(&mut record)?;
+            let timestamp_delta = get_varlong(&mut record)?;
+            let offset_delta = get_varint(&mut record)?;
+            if offset_delta != expected_delta {
+                return Err(Error::InvalidRecord(format!(
+                    "offset delta {}, expected {}",
+                    offset_delta, expected_delta
+                )));
+            }
+            let timestamp = base_timestamp.wrapping_add(timestamp_delta);
+            records.push(Record::get_body(&mut record, offset_delta, timestamp)?);
+            if record.has_remaining() {
+                return Err(Error::CorruptMessage("trailing bytes in record".into()));
+            }
+        }
+        if batch.has_remaining() {
+            return Err(Error::CorruptMessage("trailing bytes in batch".into()));
+        }
+
+        Ok(RecordBatch {
+            base_offset,
+            partition_leader_epoch,
+            attributes,
+            producer_id,
+            producer_epoch,
+            base_sequence,
+            records,
+        })
+    }
+
+    /// Encode the batch, computing its length and CRC.
+    pub fn encode(&self, buf: &mut BytesMut) {
+        let start = buf.len();
+        let base_timestamp = self.records.iter().map(|r| r.timestamp).min().unwrap_or(-1);
+        let max_timestamp = self.records.iter().map(|r| r.timestamp).max().unwrap_or(-1);
+        let last_offset_delta = self.records.last().map(|r| r.offset_delta).unwrap_or(0);
+
+        buf.put_i64(self.base_offset);
+        buf.put_i32(0); // batch length, set below
+        buf.put_i32(self.partition_leader_epoch);
+        buf.put_i8(MAGIC);
+        buf.put_u32(0); // crc, set below
+        buf.put_i16(self.attributes);
+        buf.put_i32(last_offset_delta);
+        buf.put_i64(base_timestamp);
+        buf.put_i64(max_timestamp);
+        buf.put_i64(self.producer_id);
+        buf.put_i16(self.producer_epoch);
+        buf.put_i32(self.base_sequence);
+        buf.put_i32(self.records.len() as i32);
+
+        let mut record_buf = BytesMut::new();
+        for record in &self.records {
+            record_buf.clear();
+            record_buf.put_i8(0);
+            put_varlong(&mut record_buf, record.timestamp - base_timestamp);
+            put_varint(&mut record_buf, record.offset_delta);
+            record.put_body(&mut record_buf);
+
+            put_varint(buf, record_buf.len() as i32);
+            buf.put_slice(&record_buf);
+        }
+
+        let batch_length = (buf.len() - start - LOG_OVERHEAD) as i32;
+        buf[start + 8..start + LOG_OVERHEAD].copy_from_slice(&batch_length.to_be_bytes());
+        let crc = crc32c::crc32c(&buf[start + CRC_START..]);
+        buf[start + CRC_START - 4..start + CRC_START].copy_from_slice(&crc.to_be_bytes());
+    }
+}
+
+/// Rebuild batches from records read from the store. Consecutive records
+/// with the same timestamp type are grouped in a single batch.
+///
+/// `entries` describe the records stored in `data`, in offset order. The
+/// timestamp of records stored with `LogAppendTime` is their append time,
+/// kept in their index entry.
+pub fn batches_from_stored(entries: &[Entry], data: Bytes) -> Result<Vec<RecordBatch>> {
+    let mut batches: Vec<RecordBatch> = vec![];
+    let mut position = 0;
+    for entry in entries {
+        let size = entry.size as usize;
+        if position + size > data.len() {
+            return Err(Error::CorruptMessage(format!(
+                "record {} is not in the data read",
+                entry.offset
+            )));
+        }
+        let stored = data.slice(position..position + size);
+        position += size;
+
+        let (mut record, timestamp_type) = Record::decode_stored(stored)?;
+        if timestamp_type == TimestampType::LogAppendTime {
+            record.timestamp = entry.timestamp;
+        }
+
+        match batches.last_mut() {
+            Some(batch) if batch.timestamp_type() == timestamp_type => {
+                record.offset_delta = (entry.offset as i64 - batch.base_offset) as i32;
+                batch.records.push(record);
+            }
+            _ => {
+                batches.push(RecordBatch::new(
+                    entry.offset as i64,
+                    timestamp_type,
+                    vec![record],
+                ));
+            }
+        }
+    }
+    Ok(batches)
+}
+
+// Record fields are truncated when the primitives decoding them fail: report
+// it as a corrupt batch rather than as a malformed request.
+fn corrupt(err: Error) -> Error {
+    match err {
+        Error::InvalidRequest(msg) => Error::CorruptMessage(msg),
+        err => err,
+    }
+}
+
+// Bytes prefixed with their length as a varint, -1 standing for null.
+fn get_varint_bytes(buf: &mut Bytes) -> Result<Option<Bytes>> {
+    let len = get_varint(buf)?;
+    if len < 0 {
+        return Ok(None);
+    }
+    if len as usize > buf.remaining() {
+        return Err(Error::CorruptMessage(format!("invalid field size {}", len)));
+    }
+    Ok(Some(buf.split_to(len as usize)))
+}
+
+fn put_varint_bytes(buf: &mut BytesMut, value: Option<&[u8]>) {
+    match value {
+        None => put_varint(buf, -1),
+        Some(value) => {
+            put_varint(buf, value.len() as i32);
+            buf.put_slice(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(offset_delta: i32, timestamp: i64, value: &'static [u8]) -> Record {
+        Record {
+            offset_delta,
+            timestamp,
+            key: None,
+            value: Some(Bytes::from_static(value)),
+            headers: vec![],
+        }
+    }
+
+    fn encode(batch: &RecordBatch) -> BytesMut {
+        let mut buf = BytesMut::new();
+        batch.encode(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut first = record(0, 1_000, b"first");
+        first.key = Some(Bytes::from_static(b"key"));
+        first.headers.push(Header {
+            key: "trace".into(),
+            value: None,
+        });
+        let batch = RecordBatch::new(
+            0,
+            TimestampType::CreateTime,
+            vec![first, record(1, 990, b"second")],
+        );
+        let other = RecordBatch::new(2, TimestampType::CreateTime, vec![record(0, 5, b"")]);
+
+        let mut buf = encode(&batch);
+        buf.extend_from_slice(&encode(&other));
+        let decoded = RecordBatch::decode_all(buf.freeze()).unwrap();
+        assert_eq!(decoded, vec![batch, other]);
+    }
+
+    #[test]
+    fn test_stored_record() {
+        let record = record(3, 1_000, b"value");
+        let stored = record.encode_stored(TimestampType::LogAppendTime);
+        let (decoded, timestamp_type) = Record::decode_stored(Bytes::from(stored)).unwrap();
+        assert_eq!(timestamp_type, TimestampType::LogAppendTime);
+        assert_eq!(
+            decoded,
+            Record {
+                offset_delta: 0,
+                ..record
+            }
+        );
+    }
+
+    #[test]
+    fn test_batches_from_stored() {
+        let mut data = vec![];
+        let mut entries = vec![];
+        let stored = [
+            (TimestampType::CreateTime, 10),
+            (TimestampType::CreateTime, 11),
+            (TimestampType::LogAppendTime, 12),
+        ];
+        for (offset, &(timestamp_type, timestamp)) in stored.iter().enumerate() {
+            let encoded = record(0, timestamp, b"value").encode_stored(timestamp_type);
+            entries.push(Entry {
+                timestamp: 100,
+                ..Entry::new(offset as u32 + 5, encoded.len() as u32, data.len() as u32)
+            });
+            data.extend(encoded);
+        }
+
+        let batches = batches_from_stored(&entries, Bytes::from(data)).unwrap();
+        assert_eq!(
+            batches,
+            vec![
+                RecordBatch::new(
+                    5,
+                    TimestampType::CreateTime,
+                    vec![record(0, 10, b"value"), record(1, 11, b"value")]
+                ),
+                RecordBatch::new(
+                    7,
+                    TimestampType::LogAppendTime,
+                    vec![record(0, 100, b"value")]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        let batch = RecordBatch::new(0, TimestampType::CreateTime, vec![record(0, 1, b"value")]);
+
+        let mut buf = encode(&batch);
+        let last = buf.len() - 1;
+        buf[last] ^= 0xff;
+        assert!(matches!(
+            RecordBatch::decode_all(buf.freeze()),
+            Err(Error::CorruptMessage(_))
+        ));
+
+        let buf = encode(&batch);
+        assert!(matches!(
+            RecordBatch::decode_all(buf.freeze().slice(..20)),
+            Err(Error::CorruptMessage(_))
+        ));
+
+        let compressed = RecordBatch {
+            attributes: 1,
+            ..batch.clone()
+        };
+        assert!(matches!(
+            RecordBatch::decode_all(encode(&compressed).freeze()),
+            Err(Error::UnsupportedCompressionType)
+        ));
+
+        let gap = RecordBatch::new(
+            0,
+            TimestampType::CreateTime,
+            vec![record(0, 1, b"a"), record(2, 1, b"b")],
+        );
+        assert!(matches!(
+            RecordBatch::decode_all(encode(&gap).freeze()),
+            Err(Error::InvalidRecord(_))
+        ));
+
+        assert!(matches!(
+            RecordBatch::decode_all(Bytes::new()),
+            Err(Error::InvalidRecord(_))
+        ));
+    }
+}
@@ -0,0 +1,97 @@
+//! SaslAuthenticate (key 36): carry the tokens of a SASL exchange, once a
+//! mechanism has been picked with SaslHandshake.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaslAuthenticateRequest {
+    /// Token sent by the client, defined by the mechanism.
+    pub auth_bytes: Bytes,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaslAuthenticateResponse {
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    /// Token sent by the broker, defined by the mechanism.
+    pub auth_bytes: Bytes,
+    /// Time after which the client must authenticate again, 0 when the
+    /// session does not expire (v1+).
+    pub session_lifetime_ms: i64,
+}
+
+impl SaslAuthenticateResponse {
+    pub fn error(error_code: ErrorCode, error_message: String) -> Self {
+        Self {
+            error_code,
+            error_message: Some(error_message),
+            auth_bytes: Bytes::new(),
+            session_lifetime_ms: 0,
+        }
+    }
+}
+
+impl Decode for SaslAuthenticateRequest {
+    fn decode(buf: &mut Bytes, version: i16) -> Result<Self> {
+        let flexible = version >= 2;
+
+        let auth_bytes = get_bytes(buf, flexible)?;
+        if flexible {
+            skip_tagged_fields(buf)?;
+        }
+        Ok(Self { auth_bytes })
+    }
+}
+
+impl Encode for SaslAuthenticateResponse {
+    fn encode(&self, buf: &mut BytesMut, version: i16) {
+        let flexible = version >= 2;
+
+        buf.put_i16(self.error_code.code());
+        put_nullable_string(buf, self.error_message.as_deref(), flexible);
+        put_bytes(buf, &self.auth_bytes, flexible);
+        if version >= 1 {
+            buf.put_i64(self.session_lifetime_ms);
+        }
+        if flexible {
+            put_empty_tagged_fields(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        let mut buf = BytesMut::new();
+        put_bytes(&mut buf, b"\0user\0secret", true);
+        put_empty_tagged_fields(&mut buf);
+
+        let request = SaslAuthenticateRequest::decode(&mut buf.freeze(), 2).unwrap();
+        assert_eq!(request.auth_bytes, Bytes::from_static(b"\0user\0secret"));
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = SaslAuthenticateResponse {
+            error_code: ErrorCode::None,
+            error_message: None,
+            auth_bytes: Bytes::from_static(b"v=1"),
+            session_lifetime_ms: 0,
+        };
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 0);
+        assert_eq!(&buf[..], b"\x00\x00\xff\xff\x00\x00\x00\x03v=1");
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 2);
+        assert_eq!(&buf[..], b"\x00\x00\x00\x04v=1\0\0\0\0\0\0\0\0\0");
+    }
+}
@@ -0,0 +1,64 @@
+//! SaslHandshake (key 17): pick the SASL mechanism a client authenticates
+//! with. The exchange itself is carried by SaslAuthenticate requests.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::codec::*;
+use super::{Decode, Encode};
+use crate::server::error::{ErrorCode, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaslHandshakeRequest {
+    /// Name of the mechanism, e.g. `SCRAM-SHA-256`.
+    pub mechanism: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaslHandshakeResponse {
+    pub error_code: ErrorCode,
+    /// Mechanisms enabled on the broker.
+    pub mechanisms: Vec<String>,
+}
+
+impl Decode for SaslHandshakeRequest {
+    fn decode(buf: &mut Bytes, _version: i16) -> Result<Self> {
+        Ok(Self {
+            mechanism: get_string(buf, false)?,
+        })
+    }
+}
+
+impl Encode for SaslHandshakeResponse {
+    fn encode(&self, buf: &mut BytesMut, _version: i16) {
+        buf.put_i16(self.error_code.code());
+        put_array(buf, &self.mechanisms, false, |buf, mechanism| {
+            put_string(buf, mechanism, false);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_request() {
+        let mut buf = BytesMut::new();
+        put_string(&mut buf, "PLAIN", false);
+
+        let request = SaslHandshakeRequest::decode(&mut buf.freeze(), 1).unwrap();
+        assert_eq!(request.mechanism, "PLAIN");
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = SaslHandshakeResponse {
+            error_code: ErrorCode::UnsupportedSaslMechanism,
+            mechanisms: vec!["PLAIN".into()],
+        };
+
+        let mut buf = BytesMut::new();
+        response.encode(&mut buf, 1);
+        assert_eq!(&buf[..], b"\x00\x21\x00\x00\x00\x01\x00\x05PLAIN");
+    }
+}
@@ -0,0 +1,475 @@
+//! Purgatory of delayed operations.
+//!
+//! Some requests cannot be answered right away: a fetch waits for records to
+//! be appended, a produce with `acks=all` waits for the replicas to catch
+//! up, a group join waits for the other members. Such requests are parked
+//! in a purgatory as `DelayedOperation`s until they can be completed or their
+//! timeout fires.
+//!
+//! Operations watch keys, such as the partitions they read. Whatever may let
+//! them complete, such as an append to a partition, calls `check_and_complete`
+//! with its key, which wakes up the operations watching it so that they try
+//! to complete again. Timeouts are kept in a hierarchical timing wheel
+//! advanced by a reaper task, which also wakes every parked operation up when
+//! the server shuts down.
+
+mod timer;
+
+use std::collections::{HashMap, HashSet};
+use std::future::{self, Future};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::time::{self, Duration, Instant};
+use tracing::debug;
+
+use super::shutdown::Shutdown;
+use timer::Timer;
+
+/// Duration of a tick of the timing wheel, in milliseconds.
+const TICK_MS: u64 = 1;
+
+/// Number of buckets of every wheel of the timing wheel.
+const WHEEL_SIZE: usize = 20;
+
+/// A request parked until it can be completed, or until its deadline.
+pub trait DelayedOperation: Send {
+    /// Type of the keys watched by the operation.
+    type Key;
+
+    /// Result of the operation.
+    type Output;
+
+    /// Keys whose completion may let the operation complete.
+    fn keys(&self) -> Vec<Self::Key>;
+
+    /// Time after which the operation completes whatever its state.
+    fn deadline(&self) -> Instant;
+
+    /// Try to complete the operation, returning `None` when it has to wait.
+    fn try_complete(&mut self) -> impl Future<Output = Option<Self::Output>> + Send;
+
+    /// Complete the operation once its deadline passed, or when the server
+    /// shuts down.
+    fn on_expiration(&mut self) -> impl Future<Output = Self::Output> + Send;
+}
+
+/// Why a parked operation was woken up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wakeup {
+    /// One of the keys it watches was completed.
+    Completed,
+    /// Its deadline passed.
+    Expired,
+    /// The server is shutting down.
+    Shutdown,
+}
+
+/// Operations parked until a key they watch is completed or their deadline
+/// passes.
+///
+/// `Purgatory` is a wrapper around an `Arc`, clones share the same
+/// operations.
+#[derive(Debug)]
+pub struct Purgatory<K> {
+    shared: Arc<Shared<K>>,
+}
+
+#[derive(Debug)]
+struct Shared<K> {
+    name: &'static str,
+    state: Mutex<State<K>>,
+    // Wakes the reaper up when an operation expires before the ones it is
+    // waiting for.
+    reaper: Notify,
+    // Origin of the times of the timing wheel.
+    start: Instant,
+}
+
+#[derive(Debug)]
+struct State<K> {
+    // Ids of the parked operations, by expiration. Operations completed
+    // before their deadline are left in the timer and skipped when they
+    // expire.
+    timer: Timer<u64>,
+    operations: HashMap<u64, Operation<K>>,
+    // Ids of the operations watching each key.
+    watchers: HashMap<K, HashSet<u64>>,
+    next_id: u64,
+    shutdown: bool,
+}
+
+#[derive(Debug)]
+struct Operation<K> {
+    keys: Vec<K>,
+    wakeup: oneshot::Sender<Wakeup>,
+}
+
+/// Registration of an operation in a purgatory, until it is woken up.
+///
+/// Dropping a `Watch` removes the operation from the purgatory.
+#[derive(Debug)]
+pub struct Watch<'a, K: Eq + Hash> {
+    purgatory: &'a Purgatory<K>,
+    id: Option<u64>,
+    wakeup: oneshot::Receiver<Wakeup>,
+}
+
+impl<K> Clone for Purgatory<K> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash + Send + 'static> Purgatory<K> {
+    /// Create a purgatory and spawn its reaper task, which expires the
+    /// operations and wakes them all up when `shutdown` is received.
+    /// `shutdown_complete` is dropped once they are.
+    pub fn new(
+        name: &'static str,
+        shutdown: Shutdown,
+        shutdown_complete: mpsc::Sender<()>,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            name,
+            state: Mutex::new(State {
+                timer: Timer::new(TICK_MS, WHEEL_SIZE, 0),
+                operations: HashMap::new(),
+                watchers: HashMap::new(),
+                next_id: 0,
+                shutdown: false,
+            }),
+            reaper: Notify::new(),
+            start: Instant::now(),
+        });
+        tokio::spawn(reap(shared.clone(), shutdown, shutdown_complete));
+        Self { shared }
+    }
+}
+
+impl<K: Clone + Eq + Hash> Purgatory<K> {
+    /// Number of parked operations.
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().unwrap().operations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Complete `operation`, parking it until it can be completed.
+    ///
+    /// The operation first tries to complete right away. When it cannot, it
+    /// watches its keys and tries once more, so that a key completed in
+    /// between is not missed, then waits to be woken up. It tries again
+    /// every time one of its keys is completed, and completes whatever its
+    /// state once its deadline passes or the server shuts down.
+    pub async fn complete<O>(&self, mut operation: O) -> O::Output
+    where
+        O: DelayedOperation<Key = K>,
+    {
+        if let Some(output) = operation.try_complete().await {
+            return output;
+        }
+
+        loop {
+            let watch = self.watch(operation.keys(), operation.deadline());
+            if let Some(output) = operation.try_complete().await {
+                return output;
+            }
+            match watch.wait().await {
+                Wakeup::Completed => {}
+                Wakeup::Expired | Wakeup::Shutdown => return operation.on_expiration().await,
+            }
+        }
+    }
+
+    /// Park an operation watching `keys` until one of them is completed or
+    /// `deadline` passes.
+    pub fn watch(&self, keys: Vec<K>, deadline: Instant) -> Watch<'_, K> {
+        let (tx, rx) = oneshot::channel();
+        let mut watch = Watch {
+            purgatory: self,
+            id: None,
+            wakeup: rx,
+        };
+
+        let mut state = self.shared.state.lock().unwrap();
+        if state.shutdown {
+            let _ = tx.send(Wakeup::Shutdown);
+            return watch;
+        }
+
+        let id = state.next_id;
+        let expiration = self.shared.expiration_ms(deadline);
+        let next_expiration = state.timer.next_expiration();
+        if state.timer.add(expiration, id).is_err() {
+            let _ = tx.send(Wakeup::Expired);
+            return watch;
+        }
+        state.next_id += 1;
+        for key in &keys {
+            state.watchers.entry(key.clone()).or_default().insert(id);
+        }
+        state.operations.insert(id, Operation { keys, wakeup: tx });
+        watch.id = Some(id);
+
+        if next_expiration.is_none_or(|next| expiration < next) {
+            self.shared.reaper.notify_one();
+        }
+        watch
+    }
+
+    /// Wake up the operations watching `key`, returning how many were.
+    pub fn check_and_complete(&self, key: &K) -> usize {
+        let mut state = self.shared.state.lock().unwrap();
+        let ids = match state.watchers.remove(key) {
+            Some(ids) => ids,
+            None => return 0,
+        };
+
+        let mut completed = 0;
+        for id in ids {
+            if let Some(wakeup) = state.remove(id) {
+                let _ = wakeup.send(Wakeup::Completed);
+                completed += 1;
+            }
+        }
+        completed
+    }
+}
+
+impl<K: Eq + Hash> Watch<'_, K> {
+    /// Wait for the operation to be woken up.
+    pub async fn wait(mut self) -> Wakeup {
+        // The sender is only dropped once the operation is woken up, or on
+        // shutdown when the reaper is gone.
+        (&mut self.wakeup).await.unwrap_or(Wakeup::Shutdown)
+    }
+}
+
+impl<K: Eq + Hash> Drop for Watch<'_, K> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.purgatory.shared.state.lock().unwrap().remove(id);
+        }
+    }
+}
+
+impl<K> Shared<K> {
+    // Milliseconds from the start of the purgatory to `deadline`, rounded
+    // up so that operations never expire early.
+    fn expiration_ms(&self, deadline: Instant) -> u64 {
+        let elapsed = deadline.saturating_duration_since(self.start);
+        elapsed.as_nanos().div_ceil(1_000_000) as u64
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
+
+impl<K: Eq + Hash> State<K> {
+    // Remove an operation, returning its wakeup sender unless it was already
+    // woken up.
+    fn remove(&mut self, id: u64) -> Option<oneshot::Sender<Wakeup>> {
+        let operation = self.operations.remove(&id)?;
+        for key in &operation.keys {
+            if let Some(ids) = self.watchers.get_mut(key) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.watchers.remove(key);
+                }
+            }
+        }
+        Some(operation.wakeup)
+    }
+}
+
+// Expire the operations of a purgatory as their deadlines pass, until the
+// shutdown signal is received. Every parked operation is then woken up, and
+// operations parked later are woken up right away.
+async fn reap<K: Eq + Hash>(
+    shared: Arc<Shared<K>>,
+    mut shutdown: Shutdown,
+    _shutdown_complete: mpsc::Sender<()>,
+) {
+    loop {
+        let next_expiration = shared.state.lock().unwrap().timer.next_expiration();
+        let expired = async {
+            match next_expiration {
+                Some(ms) => time::sleep_until(shared.start + Duration::from_millis(ms)).await,
+                None => future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            _ = expired => {
+                let now = shared.now_ms();
+                let mut state = shared.state.lock().unwrap();
+                for id in state.timer.poll(now) {
+                    if let Some(wakeup) = state.remove(id) {
+                        let _ = wakeup.send(Wakeup::Expired);
+                    }
+                }
+            }
+            _ = shared.reaper.notified() => {}
+            _ = shutdown.recv() => break,
+        }
+    }
+
+    let mut state = shared.state.lock().unwrap();
+    state.shutdown = true;
+    state.timer.drain();
+    state.watchers.clear();
+    debug!(
+        purgatory = shared.name,
+        operations = state.operations.len(),
+        "draining purgatory"
+    );
+    for (_, operation) in state.operations.drain() {
+        let _ = operation.wakeup.send(Wakeup::Shutdown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::broadcast;
+
+    fn purgatory() -> (
+        Purgatory<&'static str>,
+        broadcast::Sender<()>,
+        mpsc::Receiver<()>,
+    ) {
+        let (notify_shutdown, _) = broadcast::channel(1);
+        let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel(1);
+        let shutdown = Shutdown::new(notify_shutdown.subscribe());
+        let purgatory = Purgatory::new("test", shutdown, shutdown_complete_tx);
+        (purgatory, notify_shutdown, shutdown_complete_rx)
+    }
+
+    // Completes once `available` reaches `needed`, returning the number of
+    // attempts.
+    struct Counter<'a> {
+        available: &'a AtomicUsize,
+        needed: usize,
+        deadline: Instant,
+        attempts: usize,
+    }
+
+    impl DelayedOperation for Counter<'_> {
+        type Key = &'static str;
+        type Output = (usize, bool);
+
+        fn keys(&self) -> Vec<&'static str> {
+            vec!["a", "b"]
+        }
+
+        fn deadline(&self) -> Instant {
+            self.deadline
+        }
+
+        async fn try_complete(&mut self) -> Option<(usize, bool)> {
+            self.attempts += 1;
+            (self.available.load(Ordering::SeqCst) >= self.needed).then_some((self.attempts, true))
+        }
+
+        async fn on_expiration(&mut self) -> (usize, bool) {
+            (self.attempts, false)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_complete_by_key() {
+        let (purgatory, _notify_shutdown, _rx) = purgatory();
+        let available = AtomicUsize::new(0);
+        let operation = Counter {
+            available: &available,
+            needed: 2,
+            deadline: Instant::now() + Duration::from_secs(10),
+            attempts: 0,
+        };
+
+        let complete = async {
+            for _ in 0..2 {
+                while purgatory.is_empty() {
+                    tokio::task::yield_now().await;
+                }
+                available.fetch_add(1, Ordering::SeqCst);
+                assert_eq!(purgatory.check_and_complete(&"c"), 0);
+                assert_eq!(purgatory.check_and_complete(&"b"), 1);
+            }
+        };
+        let (output, _) = tokio::join!(purgatory.complete(operation), complete);
+
+        // Tried right away, once more after watching, then after each key
+        // completion.
+        assert_eq!(output, (4, true));
+        assert!(purgatory.is_empty());
+        assert_eq!(purgatory.check_and_complete(&"a"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_expiration() {
+        let (purgatory, _notify_shutdown, _rx) = purgatory();
+        let available = AtomicUsize::new(0);
+        let start = Instant::now();
+        let operation = Counter {
+            available: &available,
+            needed: 1,
+            deadline: start + Duration::from_millis(50),
+            attempts: 0,
+        };
+
+        assert_eq!(purgatory.complete(operation).await, (2, false));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(purgatory.is_empty());
+
+        // Operations whose deadline passed are woken up right away
+        let watch = purgatory.watch(vec!["a"], start);
+        assert_eq!(watch.wait().await, Wakeup::Expired);
+    }
+
+    #[tokio::test]
+    async fn test_many_operations() {
+        let (purgatory, _notify_shutdown, _rx) = purgatory();
+        let now = Instant::now();
+
+        // Operations on the same key with deadlines spread over the wheels
+        let watches: Vec<_> = (0..100_000u64)
+            .map(|i| purgatory.watch(vec!["a"], now + Duration::from_millis(100 + i % 5_000)))
+            .collect();
+        assert_eq!(purgatory.len(), 100_000);
+
+        // Dropping a watch removes its operation
+        let mut watches = watches.into_iter();
+        watches.next();
+        assert_eq!(purgatory.len(), 99_999);
+
+        assert_eq!(purgatory.check_and_complete(&"a"), 99_999);
+        for watch in watches {
+            assert_eq!(watch.wait().await, Wakeup::Completed);
+        }
+        assert!(purgatory.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let (purgatory, notify_shutdown, mut shutdown_complete) = purgatory();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let parked = purgatory.watch(vec!["a"], deadline);
+
+        drop(notify_shutdown);
+        assert_eq!(parked.wait().await, Wakeup::Shutdown);
+        assert_eq!(shutdown_complete.recv().await, None);
+
+        // Once drained, operations are not parked anymore
+        let watch = purgatory.watch(vec!["a"], deadline);
+        assert!(purgatory.is_empty());
+        assert_eq!(watch.wait().await, Wakeup::Shutdown);
+    }
+}
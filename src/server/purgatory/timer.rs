@@ -0,0 +1,258 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::mem;
+
+/// A hierarchical timing wheel.
+///
+/// The first wheel has `wheel_size` buckets of `tick_ms` milliseconds each.
+/// Items expiring beyond the span of a wheel go to an overflow wheel whose
+/// buckets span a whole turn of the wheel below it, and so on. Adding an item
+/// is O(1) whatever its expiration, and items are only moved to a finer wheel
+/// when the bucket holding them expires, so the timer can hold a very large
+/// number of items cheaply.
+///
+/// Buckets waiting for their expiration are kept in a priority queue: the
+/// timer never ticks through empty buckets, it jumps to the next bucket to
+/// expire. Times are milliseconds, from any origin chosen by the caller.
+#[derive(Debug)]
+pub struct Timer<T> {
+    tick_ms: u64,
+    wheel_size: usize,
+    // Wheels from the finest to the coarsest. Overflow wheels are created
+    // when needed.
+    wheels: Vec<Wheel<T>>,
+    // Expiration, wheel and slot of the buckets holding items. Entries whose
+    // bucket was flushed or reused since are skipped.
+    queue: BinaryHeap<Reverse<(u64, usize, usize)>>,
+    len: usize,
+}
+
+#[derive(Debug)]
+struct Wheel<T> {
+    tick_ms: u64,
+    // Time spanned by the whole wheel.
+    interval_ms: u64,
+    // Current time of the wheel, rounded down to a multiple of its tick.
+    current_ms: u64,
+    buckets: Vec<Bucket<T>>,
+}
+
+#[derive(Debug)]
+struct Bucket<T> {
+    // Start of the tick the bucket currently holds, `None` when empty.
+    expiration_ms: Option<u64>,
+    items: Vec<(u64, T)>,
+}
+
+impl<T> Wheel<T> {
+    fn new(tick_ms: u64, wheel_size: usize, start_ms: u64) -> Self {
+        Self {
+            tick_ms,
+            interval_ms: tick_ms * wheel_size as u64,
+            current_ms: start_ms - start_ms % tick_ms,
+            buckets: (0..wheel_size)
+                .map(|_| Bucket {
+                    expiration_ms: None,
+                    items: vec![],
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<T> Timer<T> {
+    pub fn new(tick_ms: u64, wheel_size: usize, start_ms: u64) -> Self {
+        assert!(tick_ms > 0 && wheel_size > 1);
+        Self {
+            tick_ms,
+            wheel_size,
+            wheels: vec![Wheel::new(tick_ms, wheel_size, start_ms)],
+            queue: BinaryHeap::new(),
+            len: 0,
+        }
+    }
+
+    /// Number of items waiting for their expiration.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Add `item`, expiring at `expiration_ms`. The item is given back if it
+    /// has already expired, i.e. it expires within the current tick.
+    pub fn add(&mut self, expiration_ms: u64, item: T) -> Result<(), T> {
+        let mut level = 0;
+        loop {
+            if level == self.wheels.len() {
+                let lower = &self.wheels[level - 1];
+                let wheel = Wheel::new(lower.interval_ms, self.wheel_size, lower.current_ms);
+                self.wheels.push(wheel);
+            }
+
+            let wheel = &mut self.wheels[level];
+            if expiration_ms < wheel.current_ms + wheel.tick_ms {
+                // Only possible for the first wheel: items only overflow
+                // when they expire after a whole turn of the wheel below.
+                return Err(item);
+            }
+            if expiration_ms < wheel.current_ms + wheel.interval_ms {
+                let virtual_id = expiration_ms / wheel.tick_ms;
+                let slot = (virtual_id % self.wheel_size as u64) as usize;
+                let bucket = &mut wheel.buckets[slot];
+                bucket.items.push((expiration_ms, item));
+
+                let bucket_expiration = virtual_id * wheel.tick_ms;
+                if bucket.expiration_ms != Some(bucket_expiration) {
+                    bucket.expiration_ms = Some(bucket_expiration);
+                    self.queue.push(Reverse((bucket_expiration, level, slot)));
+                }
+                self.len += 1;
+                return Ok(());
+            }
+            level += 1;
+        }
+    }
+
+    /// Time at which the next bucket expires. It may hold no item anymore,
+    /// in which case `poll` returns nothing at that time.
+    pub fn next_expiration(&self) -> Option<u64> {
+        self.queue
+            .peek()
+            .map(|Reverse((expiration, _, _))| *expiration)
+    }
+
+    /// Advance the timer to `now_ms`, returning every item expired by then.
+    pub fn poll(&mut self, now_ms: u64) -> Vec<T> {
+        let mut expired = vec![];
+        while let Some(&Reverse((expiration, level, slot))) = self.queue.peek() {
+            if expiration > now_ms {
+                break;
+            }
+            self.queue.pop();
+
+            let bucket = &mut self.wheels[level].buckets[slot];
+            if bucket.expiration_ms != Some(expiration) {
+                continue;
+            }
+            bucket.expiration_ms = None;
+            let items = mem::take(&mut bucket.items);
+            self.len -= items.len();
+
+            // Items of a coarse bucket are moved to finer wheels, the ones
+            // expiring within the current tick are returned.
+            self.advance(expiration);
+            for (expiration_ms, item) in items {
+                if let Err(item) = self.add(expiration_ms, item) {
+                    expired.push(item);
+                }
+            }
+        }
+        // Every bucket left expires after `now_ms`, so items added from now
+        // on cannot land in one of them from an earlier turn of its wheel.
+        self.advance(now_ms);
+        expired
+    }
+
+    /// Remove every item, expired or not.
+    pub fn drain(&mut self) -> Vec<T> {
+        self.queue.clear();
+        self.len = 0;
+        self.wheels
+            .iter_mut()
+            .flat_map(|wheel| wheel.buckets.iter_mut())
+            .flat_map(|bucket| {
+                bucket.expiration_ms = None;
+                mem::take(&mut bucket.items)
+            })
+            .map(|(_, item)| item)
+            .collect()
+    }
+
+    fn advance(&mut self, time_ms: u64) {
+        for wheel in &mut self.wheels {
+            if time_ms >= wheel.current_ms + wheel.tick_ms {
+                wheel.current_ms = time_ms - time_ms % wheel.tick_ms;
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn levels(&self) -> usize {
+        self.wheels.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiration() {
+        let mut timer = Timer::new(1, 20, 0);
+        assert_eq!(timer.add(0, "expired"), Err("expired"));
+        timer.add(5, "a").unwrap();
+        timer.add(5, "b").unwrap();
+        timer.add(19, "c").unwrap();
+        assert_eq!(timer.len(), 3);
+        assert_eq!(timer.next_expiration(), Some(5));
+
+        assert!(timer.poll(4).is_empty());
+        assert_eq!(timer.poll(5), vec!["a", "b"]);
+        assert_eq!(timer.poll(100), vec!["c"]);
+        assert!(timer.is_empty());
+    }
+
+    #[test]
+    fn test_overflow_wheels() {
+        let mut timer = Timer::new(1, 20, 0);
+        for expiration in [25, 450, 8_001, 8_000_000] {
+            timer.add(expiration, expiration).unwrap();
+        }
+        assert_eq!(timer.levels(), 6);
+
+        // Items are returned at their own expiration, not their bucket's
+        let mut expired = vec![];
+        for now in 0..=8_001 {
+            for item in timer.poll(now) {
+                assert_eq!(item, now);
+                expired.push(item);
+            }
+        }
+        assert_eq!(expired, vec![25, 450, 8_001]);
+        assert_eq!(timer.len(), 1);
+        assert_eq!(timer.poll(8_000_000), vec![8_000_000]);
+    }
+
+    #[test]
+    fn test_poll_late() {
+        let mut timer = Timer::new(1, 20, 1_000);
+        for i in 0..1_000 {
+            timer.add(1_001 + i * 37, i).unwrap();
+        }
+
+        // Polling long after the expirations returns everything at once,
+        // and the timer keeps working from the new time.
+        let mut expired = timer.poll(1_000_000);
+        expired.sort_unstable();
+        assert_eq!(expired, (0..1_000).collect::<Vec<_>>());
+        assert_eq!(timer.add(1_000_000, 0), Err(0));
+        timer.add(1_000_010, 1).unwrap();
+        assert_eq!(timer.poll(1_000_010), vec![1]);
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut timer = Timer::new(1, 20, 0);
+        timer.add(10, 1).unwrap();
+        timer.add(10_000, 2).unwrap();
+
+        let mut drained = timer.drain();
+        drained.sort_unstable();
+        assert_eq!(drained, vec![1, 2]);
+        assert!(timer.is_empty());
+        assert!(timer.poll(20_000).is_empty());
+    }
+}
@@ -0,0 +1,388 @@
+//! Client quotas.
+//!
+//! Clients are throttled when they produce or fetch more bytes per second,
+//! or use more request handler time, than their quota allows. Usage is
+//! tracked in sliding windows of `quota_window_num` samples of
+//! `quota_window_size` each. A client over its quota is told to back off for
+//! a time proportional to how far over its quota it is, and its response is
+//! delayed by that time.
+//!
+//! Quotas are set at runtime with AlterClientQuotas requests, for users,
+//! client ids or both, and persisted in a file of the log directory. Like in
+//! Kafka, the quota of a client is the one of the most specific entity
+//! matching its user and client id, default entities matching any user or
+//! client id.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tracing::info;
+
+use super::persist;
+
+/// Name of the file holding the quotas, in the log directory.
+const FILE_NAME: &str = "quotas";
+
+/// A resource usage limited by quotas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum QuotaType {
+    /// Bytes of records produced per second.
+    ProducerByteRate,
+    /// Bytes of records fetched per second.
+    ConsumerByteRate,
+    /// Percentage of the time of one thread spent handling requests.
+    RequestPercentage,
+}
+
+impl QuotaType {
+    pub const ALL: [QuotaType; 3] = [
+        QuotaType::ProducerByteRate,
+        QuotaType::ConsumerByteRate,
+        QuotaType::RequestPercentage,
+    ];
+
+    /// Name of the quota in DescribeClientQuotas and AlterClientQuotas
+    /// requests.
+    pub fn name(self) -> &'static str {
+        match self {
+            QuotaType::ProducerByteRate => "producer_byte_rate",
+            QuotaType::ConsumerByteRate => "consumer_byte_rate",
+            QuotaType::RequestPercentage => "request_percentage",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        QuotaType::ALL.iter().copied().find(|t| t.name() == name)
+    }
+}
+
+impl fmt::Display for QuotaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Name of a component of a quota entity.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum EntityName {
+    /// Matches any user or client id without a more specific quota.
+    Default,
+    Name(String),
+}
+
+/// Users and client ids a quota applies to. At least one of the components
+/// is set.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct QuotaEntity {
+    pub user: Option<EntityName>,
+    pub client_id: Option<EntityName>,
+}
+
+impl QuotaEntity {
+    /// Type of the user component in requests.
+    pub const USER: &'static str = "user";
+
+    /// Type of the client id component in requests.
+    pub const CLIENT_ID: &'static str = "client-id";
+}
+
+/// Quotas of an entity.
+pub type Quotas = BTreeMap<QuotaType, f64>;
+
+/// Quotas of every entity and usage of clients, shared by the connections.
+///
+/// The manager is a wrapper around an `Arc`, clones share the same quotas.
+#[derive(Debug, Clone)]
+pub struct QuotaManager {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    path: PathBuf,
+    window_size: Duration,
+    window_num: u32,
+    quotas: Mutex<BTreeMap<QuotaEntity, Quotas>>,
+    /// Usage of every client with a quota, by quota type, user and client
+    /// id. Components the quota of the client does not depend on are empty,
+    /// so that the clients sharing a quota share their usage.
+    rates: Mutex<HashMap<(QuotaType, String, String), Rate>>,
+}
+
+impl QuotaManager {
+    /// Open the quotas stored in `dir`, creating the directory if needed.
+    /// Usage is tracked in `window_num` windows of `window_size`.
+    pub fn open(dir: impl AsRef<Path>, window_size: Duration, window_num: u32) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let path = dir.as_ref().join(FILE_NAME);
+
+        let quotas: BTreeMap<QuotaEntity, Quotas> = persist::load(&path)?;
+        info!(path = %path.display(), entities = quotas.len(), "loaded quotas");
+
+        Ok(Self {
+            shared: Arc::new(Shared {
+                path,
+                window_size,
+                window_num: window_num.max(2),
+                quotas: Mutex::new(quotas),
+                rates: Mutex::new(HashMap::new()),
+            }),
+        })
+    }
+
+    /// Record `value` to the usage of the client `client_id` of `user`, and
+    /// return how long it is throttled for. Clients without a quota are
+    /// never throttled.
+    ///
+    /// Byte rates are recorded in bytes, request time in percents of a
+    /// second.
+    pub fn record(
+        &self,
+        quota_type: QuotaType,
+        user: &str,
+        client_id: &str,
+        value: f64,
+    ) -> Duration {
+        let (bound, key) = match self.quota(quota_type, user, client_id) {
+            Some(quota) => quota,
+            None => return Duration::ZERO,
+        };
+
+        let shared = &*self.shared;
+        let now = Instant::now();
+        let mut rates = shared.rates.lock().unwrap();
+        if !rates.contains_key(&key) {
+            // Forget about the clients which have been idle for a whole
+            // window.
+            rates.retain(|_, rate| !rate.is_idle(now, shared.window()));
+        }
+        let rate = rates.entry(key).or_default();
+        rate.record(value, now, shared.window_size, shared.window());
+
+        // Throttling the client for this time would bring its rate back to
+        // the quota, were the window to grow as much.
+        let (total, elapsed) = rate.measure(now, shared.window_size, shared.window_num);
+        let rate = total / elapsed.as_secs_f64();
+        if rate <= bound {
+            return Duration::ZERO;
+        }
+        elapsed.mul_f64((rate - bound) / bound).min(shared.window())
+    }
+
+    /// Every entity with quotas.
+    pub fn describe(&self) -> Vec<(QuotaEntity, Quotas)> {
+        let quotas = self.shared.quotas.lock().unwrap();
+        quotas
+            .iter()
+            .map(|(entity, quotas)| (entity.clone(), quotas.clone()))
+            .collect()
+    }
+
+    /// Set the quotas of `entity`, removing the ones without value.
+    pub fn alter(
+        &self,
+        entity: &QuotaEntity,
+        changes: &[(QuotaType, Option<f64>)],
+    ) -> io::Result<()> {
+        let mut quotas = self.shared.quotas.lock().unwrap();
+        let mut updated = quotas.clone();
+        let entity_quotas = updated.entry(entity.clone()).or_default();
+        for (quota_type, value) in changes {
+            match value {
+                Some(value) => entity_quotas.insert(*quota_type, *value),
+                None => entity_quotas.remove(quota_type),
+            };
+        }
+        if entity_quotas.is_empty() {
+            updated.remove(entity);
+        }
+        persist::save(&self.shared.path, &updated)?;
+        *quotas = updated;
+        Ok(())
+    }
+
+    // Quota of type `quota_type` of the client, and the key of the usage it
+    // bounds.
+    fn quota(
+        &self,
+        quota_type: QuotaType,
+        user: &str,
+        client_id: &str,
+    ) -> Option<(f64, (QuotaType, String, String))> {
+        let quotas = self.shared.quotas.lock().unwrap();
+        if quotas.is_empty() {
+            return None;
+        }
+
+        let name = |name: &str| Some(EntityName::Name(name.into()));
+        let default = || Some(EntityName::Default);
+        let entities = [
+            (name(user), name(client_id)),
+            (name(user), default()),
+            (name(user), None),
+            (default(), name(client_id)),
+            (default(), default()),
+            (default(), None),
+            (None, name(client_id)),
+            (None, default()),
+        ];
+        entities.iter().find_map(|(user_name, client_name)| {
+            let entity = QuotaEntity {
+                user: user_name.clone(),
+                client_id: client_name.clone(),
+            };
+            let bound = *quotas.get(&entity)?.get(&quota_type)?;
+            let user = if entity.user.is_some() { user } else { "" };
+            let client_id = if entity.client_id.is_some() {
+                client_id
+            } else {
+                ""
+            };
+            Some((bound, (quota_type, user.into(), client_id.into())))
+        })
+    }
+}
+
+impl Shared {
+    // Time covered by the samples of a rate.
+    fn window(&self) -> Duration {
+        self.window_size * self.window_num
+    }
+}
+
+/// Values recorded over a sliding window, as samples covering
+/// `window_size` each.
+#[derive(Debug, Default)]
+pub(super) struct Rate {
+    /// Start and total of the samples, oldest first.
+    samples: VecDeque<(Instant, f64)>,
+}
+
+impl Rate {
+    pub(super) fn record(
+        &mut self,
+        value: f64,
+        now: Instant,
+        window_size: Duration,
+        window: Duration,
+    ) {
+        while let Some((start, _)) = self.samples.front() {
+            if now.duration_since(*start) < window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        match self.samples.back_mut() {
+            Some((start, total)) if now.duration_since(*start) < window_size => *total += value,
+            _ => self.samples.push_back((now, value)),
+        }
+    }
+
+    // Total of the samples and the time they cover. Until the window fills
+    // up, the rate is computed over all windows but the current one, so that
+    // the first values recorded do not make the rate spike.
+    pub(super) fn measure(
+        &self,
+        now: Instant,
+        window_size: Duration,
+        window_num: u32,
+    ) -> (f64, Duration) {
+        let total = self.samples.iter().map(|(_, value)| value).sum();
+        let elapsed = self
+            .samples
+            .front()
+            .map_or(Duration::ZERO, |(start, _)| now.duration_since(*start));
+        (total, elapsed.max(window_size * (window_num - 1)))
+    }
+
+    pub(super) fn is_idle(&self, now: Instant, window: Duration) -> bool {
+        self.samples
+            .back()
+            .is_none_or(|(start, _)| now.duration_since(*start) >= window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(name: &str) -> QuotaEntity {
+        QuotaEntity {
+            user: Some(EntityName::Name(name.into())),
+            client_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_throttle() {
+        let dir = tempfile::tempdir().unwrap();
+        let window_size = Duration::from_millis(50);
+        let quotas = QuotaManager::open(dir.path(), window_size, 11).unwrap();
+        let rate = QuotaType::ProducerByteRate;
+        quotas
+            .alter(&user("alice"), &[(rate, Some(1000.0))])
+            .unwrap();
+
+        // Half a second worth of quota is allowed at once, until the
+        // windows fill up.
+        assert_eq!(quotas.record(rate, "alice", "", 500.0), Duration::ZERO);
+        assert_eq!(
+            quotas.record(rate, "alice", "", 250.0),
+            Duration::from_millis(250)
+        );
+
+        // Other users and quota types have no quota.
+        assert_eq!(quotas.record(rate, "bob", "", 1e9), Duration::ZERO);
+        let other = QuotaType::ConsumerByteRate;
+        assert_eq!(quotas.record(other, "alice", "", 1e9), Duration::ZERO);
+
+        // The usage is forgotten once out of the window.
+        tokio::time::sleep(window_size * 11).await;
+        assert_eq!(quotas.record(rate, "alice", "", 0.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let quotas = QuotaManager::open(dir.path(), Duration::from_secs(1), 11).unwrap();
+        let rate = QuotaType::RequestPercentage;
+        let entities = [
+            (Some(EntityName::Default), None, 1.0),
+            (None, Some(EntityName::Name("app".into())), 2.0),
+            (
+                Some(EntityName::Name("alice".into())),
+                Some(EntityName::Default),
+                3.0,
+            ),
+        ];
+        for (user, client_id, value) in entities.iter() {
+            let entity = QuotaEntity {
+                user: user.clone(),
+                client_id: client_id.clone(),
+            };
+            quotas.alter(&entity, &[(rate, Some(*value))]).unwrap();
+        }
+
+        let quota = |user, client_id| quotas.quota(rate, user, client_id).unwrap();
+        assert_eq!(quota("alice", "app").0, 3.0);
+        assert_eq!(
+            quota("alice", "app").1,
+            (rate, "alice".into(), "app".into())
+        );
+        assert_eq!(quota("bob", "app").0, 1.0);
+        assert_eq!(quota("bob", "app").1, (rate, "bob".into(), "".into()));
+
+        // Removing the last quota of an entity removes the entity.
+        quotas.alter(&user("bob"), &[(rate, Some(4.0))]).unwrap();
+        quotas.alter(&user("bob"), &[(rate, None)]).unwrap();
+        let quotas = QuotaManager::open(dir.path(), Duration::from_secs(1), 11).unwrap();
+        assert_eq!(quotas.describe().len(), 3);
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tracing::info;
+
+use super::scram::{ScramCredential, ScramMechanism};
+use crate::server::persist;
+
+/// Name of the file holding the credentials, in the log directory.
+const FILE_NAME: &str = "credentials";
+
+type Credentials = BTreeMap<String, BTreeMap<ScramMechanism, ScramCredential>>;
+
+/// SCRAM credentials of the users of the broker, persisted in a file of
+/// the log directory.
+///
+/// The store is a wrapper around an `Arc`, clones share the same
+/// credentials.
+#[derive(Debug, Clone)]
+pub struct CredentialStore {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    path: PathBuf,
+    credentials: Mutex<Credentials>,
+}
+
+impl CredentialStore {
+    /// Open the credentials stored in `dir`, creating the directory if
+    /// needed. The store is empty if no credential was stored yet.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let path = dir.as_ref().join(FILE_NAME);
+
+        let credentials: Credentials = persist::load(&path)?;
+        info!(path = %path.display(), users = credentials.len(), "loaded credentials");
+
+        Ok(Self {
+            shared: Arc::new(Shared {
+                path,
+                credentials: Mutex::new(credentials),
+            }),
+        })
+    }
+
+    /// Credential of `user` for `mechanism`, if it has one.
+    pub fn get(&self, user: &str, mechanism: ScramMechanism) -> Option<ScramCredential> {
+        let credentials = self.shared.credentials.lock().unwrap();
+        credentials.get(user)?.get(&mechanism).cloned()
+    }
+
+    /// Mechanisms `user` has a credential for, with their iterations.
+    pub fn describe(&self, user: &str) -> Vec<(ScramMechanism, u32)> {
+        let credentials = self.shared.credentials.lock().unwrap();
+        credentials
+            .get(user)
+            .into_iter()
+            .flatten()
+            .map(|(mechanism, credential)| (*mechanism, credential.iterations))
+            .collect()
+    }
+
+    /// Every user with at least one credential, sorted by name.
+    pub fn users(&self) -> Vec<String> {
+        let credentials = self.shared.credentials.lock().unwrap();
+        credentials.keys().cloned().collect()
+    }
+
+    /// Set the credential of `user` for `mechanism`.
+    pub fn upsert(
+        &self,
+        user: &str,
+        mechanism: ScramMechanism,
+        credential: ScramCredential,
+    ) -> io::Result<()> {
+        let mut credentials = self.shared.credentials.lock().unwrap();
+        let mut updated = credentials.clone();
+        updated
+            .entry(user.into())
+            .or_default()
+            .insert(mechanism, credential);
+        persist::save(&self.shared.path, &updated)?;
+        *credentials = updated;
+        Ok(())
+    }
+
+    /// Delete the credential of `user` for `mechanism`. Returns whether the
+    /// user had one.
+    pub fn delete(&self, user: &str, mechanism: ScramMechanism) -> io::Result<bool> {
+        let mut credentials = self.shared.credentials.lock().unwrap();
+        if credentials
+            .get(user)
+            .is_none_or(|c| !c.contains_key(&mechanism))
+        {
+            return Ok(false);
+        }
+
+        let mut updated = credentials.clone();
+        let user_credentials = updated.get_mut(user).unwrap();
+        user_credentials.remove(&mechanism);
+        if user_credentials.is_empty() {
+            updated.remove(user);
+        }
+        persist::save(&self.shared.path, &updated)?;
+        *credentials = updated;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CredentialStore::open(dir.path()).unwrap();
+        assert!(store.users().is_empty());
+
+        let credential = ScramCredential::new(ScramMechanism::Sha256, "secret", 4096);
+        store
+            .upsert("alice", ScramMechanism::Sha256, credential.clone())
+            .unwrap();
+        store
+            .upsert(
+                "alice",
+                ScramMechanism::Sha512,
+                ScramCredential::new(ScramMechanism::Sha512, "secret", 8192),
+            )
+            .unwrap();
+        store
+            .upsert("bob", ScramMechanism::Sha256, credential.clone())
+            .unwrap();
+        assert!(store.delete("bob", ScramMechanism::Sha256).unwrap());
+        assert!(!store.delete("bob", ScramMechanism::Sha256).unwrap());
+
+        let store = CredentialStore::open(dir.path()).unwrap();
+        assert_eq!(store.users(), vec!["alice".to_string()]);
+        assert_eq!(
+            store.describe("alice"),
+            vec![
+                (ScramMechanism::Sha256, 4096),
+                (ScramMechanism::Sha512, 8192)
+            ]
+        );
+        assert_eq!(store.get("alice", ScramMechanism::Sha256), Some(credential));
+        assert_eq!(store.get("bob", ScramMechanism::Sha256), None);
+    }
+
+    #[test]
+    fn test_corrupt_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join(FILE_NAME),
+            b"\xff\xff\xff\xff\xff\xff\xff\xff\xff",
+        )
+        .unwrap();
+        assert!(CredentialStore::open(dir.path()).is_err());
+    }
+}
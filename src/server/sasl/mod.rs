@@ -0,0 +1,195 @@
+//! SASL authentication of clients.
+//!
+//! Clients pick a mechanism with a SaslHandshake request, then send the
+//! tokens of the exchange in SaslAuthenticate requests until the broker
+//! establishes their principal. The PLAIN and SCRAM mechanisms are both
+//! verified against the SCRAM credentials of the `CredentialStore`, so that
+//! the broker never stores passwords.
+
+mod credentials;
+mod scram;
+
+pub use credentials::CredentialStore;
+pub use scram::{ScramCredential, ScramMechanism, ScramServer};
+
+use std::fmt;
+use std::io;
+use std::str::{self, FromStr};
+
+use tokio::task;
+
+use super::error::{Error, Result};
+use super::principal::Principal;
+
+/// SASL mechanism a client authenticates with (`sasl.enabled.mechanisms`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mechanism {
+    /// User name and password sent in clear text (RFC 4616). It should only
+    /// be enabled on TLS listeners.
+    Plain,
+    /// Salted challenge response (RFC 5802).
+    Scram(ScramMechanism),
+}
+
+impl Mechanism {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mechanism::Plain => "PLAIN",
+            Mechanism::Scram(mechanism) => mechanism.name(),
+        }
+    }
+}
+
+impl FromStr for Mechanism {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s == "PLAIN" {
+            return Ok(Mechanism::Plain);
+        }
+        let mechanism = ScramMechanism::from_name(s).map(Mechanism::Scram);
+        mechanism.ok_or_else(|| {
+            let expected = "expected PLAIN, SCRAM-SHA-256 or SCRAM-SHA-512";
+            format!("invalid SASL mechanism {:?}, {}", s, expected)
+        })
+    }
+}
+
+impl fmt::Display for Mechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Outcome of a token of the client.
+#[derive(Debug)]
+pub enum Step {
+    /// The exchange goes on with this token of the broker.
+    Challenge(Vec<u8>),
+    /// The client is authenticated. The last token of the broker is sent
+    /// along.
+    Done {
+        response: Vec<u8>,
+        principal: Principal,
+    },
+}
+
+/// Broker side of the SASL exchange of a connection.
+#[derive(Debug)]
+pub enum Authenticator {
+    Plain(CredentialStore),
+    Scram(Box<ScramServer>),
+}
+
+impl Authenticator {
+    pub fn new(mechanism: Mechanism, credentials: &CredentialStore) -> Self {
+        match mechanism {
+            Mechanism::Plain => Authenticator::Plain(credentials.clone()),
+            Mechanism::Scram(mechanism) => {
+                Authenticator::Scram(Box::new(ScramServer::new(mechanism, credentials.clone())))
+            }
+        }
+    }
+
+    /// Process the next token of the client. Errors end the exchange.
+    pub async fn step(&mut self, token: &[u8]) -> Result<Step> {
+        match self {
+            Authenticator::Plain(credentials) => {
+                // Salting the password keeps a core busy for milliseconds,
+                // which would hold up the other connections of the worker.
+                let (credentials, token) = (credentials.clone(), token.to_vec());
+                let principal =
+                    task::spawn_blocking(move || authenticate_plain(&credentials, &token))
+                        .await
+                        .map_err(io::Error::other)??;
+                Ok(Step::Done {
+                    response: vec![],
+                    principal,
+                })
+            }
+            Authenticator::Scram(server) => server.step(token),
+        }
+    }
+}
+
+// The single message of PLAIN is `authzid NUL authcid NUL passwd`. The
+// password is checked against any SCRAM credential of the user.
+fn authenticate_plain(credentials: &CredentialStore, token: &[u8]) -> Result<Principal> {
+    let message = str::from_utf8(token)
+        .map_err(|_| Error::SaslAuthenticationFailed("message is not valid utf-8".into()))?;
+    let fields: Vec<_> = message.split('\0').collect();
+    let (authzid, user, password) = match fields[..] {
+        [authzid, user, password] if !user.is_empty() => (authzid, user, password),
+        _ => {
+            return Err(Error::SaslAuthenticationFailed(
+                "invalid PLAIN message".into(),
+            ))
+        }
+    };
+    if !authzid.is_empty() && authzid != user {
+        return Err(Error::SaslAuthenticationFailed(
+            "authorization id must be the user name".into(),
+        ));
+    }
+
+    let verified = ScramMechanism::ALL.iter().any(|mechanism| {
+        credentials
+            .get(user, *mechanism)
+            .is_some_and(|credential| credential.verify(*mechanism, password))
+    });
+    if !verified {
+        return Err(Error::SaslAuthenticationFailed(
+            "invalid credentials".into(),
+        ));
+    }
+    Ok(Principal::user(user))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_plain() {
+        let dir = tempfile::tempdir().unwrap();
+        let credentials = CredentialStore::open(dir.path()).unwrap();
+        let credential = ScramCredential::new(ScramMechanism::Sha512, "secret", 4096);
+        credentials
+            .upsert("alice", ScramMechanism::Sha512, credential)
+            .unwrap();
+
+        for token in [&b"\0alice\0secret"[..], b"alice\0alice\0secret"].iter() {
+            let mut authenticator = Authenticator::new(Mechanism::Plain, &credentials);
+            match authenticator.step(token).await.unwrap() {
+                Step::Done { principal, .. } => assert_eq!(principal, Principal::user("alice")),
+                step => panic!("unexpected step {:?}", step),
+            }
+        }
+
+        for token in [
+            &b"\0alice\0wrong"[..],
+            b"\0bob\0secret",
+            b"bob\0alice\0secret",
+            b"\0alice",
+            b"\0\0secret",
+        ]
+        .iter()
+        {
+            let mut authenticator = Authenticator::new(Mechanism::Plain, &credentials);
+            assert!(matches!(
+                authenticator.step(token).await,
+                Err(Error::SaslAuthenticationFailed(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_mechanism() {
+        assert_eq!("PLAIN".parse(), Ok(Mechanism::Plain));
+        assert_eq!(
+            "SCRAM-SHA-512".parse(),
+            Ok(Mechanism::Scram(ScramMechanism::Sha512))
+        );
+        assert!("GSSAPI".parse::<Mechanism>().is_err());
+    }
+}
@@ -0,0 +1,528 @@
+//! SCRAM-SHA-256 and SCRAM-SHA-512 (RFC 5802, RFC 7677).
+//!
+//! The broker never stores passwords: a credential holds a random salt,
+//! the number of iterations and two keys derived from the salted password.
+//! They are enough to verify the proof sent by a client and to prove to the
+//! client that the broker knows its credential, but not to impersonate it.
+
+use std::fmt;
+use std::num::NonZeroU32;
+use std::str;
+use std::sync::OnceLock;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hmac, pbkdf2};
+use serde::{Deserialize, Serialize};
+
+use super::credentials::CredentialStore;
+use super::Step;
+use crate::server::error::{Error, Result};
+use crate::server::principal::Principal;
+
+/// Size of the random salts of credentials, and of the nonces of the broker.
+const RANDOM_SIZE: usize = 24;
+
+/// Hash function of a SCRAM mechanism.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ScramMechanism {
+    Sha256,
+    Sha512,
+}
+
+impl ScramMechanism {
+    pub const ALL: &'static [ScramMechanism] = &[ScramMechanism::Sha256, ScramMechanism::Sha512];
+
+    /// Bounds of the number of iterations of credentials, the ones enforced
+    /// by Kafka.
+    pub const MIN_ITERATIONS: u32 = 4096;
+    pub const MAX_ITERATIONS: u32 = 16384;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScramMechanism::Sha256 => "SCRAM-SHA-256",
+            ScramMechanism::Sha512 => "SCRAM-SHA-512",
+        }
+    }
+
+    /// Type of the mechanism in the messages of the protocol.
+    pub fn type_code(self) -> i8 {
+        match self {
+            ScramMechanism::Sha256 => 1,
+            ScramMechanism::Sha512 => 2,
+        }
+    }
+
+    pub fn from_type_code(code: i8) -> Option<Self> {
+        ScramMechanism::ALL
+            .iter()
+            .copied()
+            .find(|m| m.type_code() == code)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        ScramMechanism::ALL
+            .iter()
+            .copied()
+            .find(|m| m.name() == name)
+    }
+
+    /// `Hi(password, salt, iterations)`: the password salted and iterated
+    /// with PBKDF2.
+    pub fn salted_password(self, password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+        let (algorithm, len) = match self {
+            ScramMechanism::Sha256 => (pbkdf2::PBKDF2_HMAC_SHA256, digest::SHA256_OUTPUT_LEN),
+            ScramMechanism::Sha512 => (pbkdf2::PBKDF2_HMAC_SHA512, digest::SHA512_OUTPUT_LEN),
+        };
+        let mut salted_password = vec![0; len];
+        let iterations = NonZeroU32::new(iterations.max(1)).unwrap();
+        pbkdf2::derive(algorithm, iterations, salt, password, &mut salted_password);
+        salted_password
+    }
+
+    fn hash(self, data: &[u8]) -> Vec<u8> {
+        let algorithm = match self {
+            ScramMechanism::Sha256 => &digest::SHA256,
+            ScramMechanism::Sha512 => &digest::SHA512,
+        };
+        digest::digest(algorithm, data).as_ref().to_vec()
+    }
+
+    fn hmac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        let algorithm = match self {
+            ScramMechanism::Sha256 => hmac::HMAC_SHA256,
+            ScramMechanism::Sha512 => hmac::HMAC_SHA512,
+        };
+        let key = hmac::Key::new(algorithm, key);
+        hmac::sign(&key, data).as_ref().to_vec()
+    }
+}
+
+impl fmt::Display for ScramMechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// SCRAM credential of a user for one mechanism.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScramCredential {
+    pub salt: Vec<u8>,
+    /// `H(HMAC(SaltedPassword, "Client Key"))`, verifies client proofs.
+    pub stored_key: Vec<u8>,
+    /// `HMAC(SaltedPassword, "Server Key")`, signs the final message of the
+    /// broker.
+    pub server_key: Vec<u8>,
+    pub iterations: u32,
+}
+
+impl ScramCredential {
+    /// Credential of `password`, salted with a random salt.
+    pub fn new(mechanism: ScramMechanism, password: &str, iterations: u32) -> Self {
+        let salt = random_bytes();
+        let salted_password = mechanism.salted_password(password.as_bytes(), &salt, iterations);
+        Self::from_salted_password(mechanism, salt, &salted_password, iterations)
+    }
+
+    /// Credential of a password salted with `salt`, as sent by clients in
+    /// AlterUserScramCredentials requests.
+    pub fn from_salted_password(
+        mechanism: ScramMechanism,
+        salt: Vec<u8>,
+        salted_password: &[u8],
+        iterations: u32,
+    ) -> Self {
+        let client_key = mechanism.hmac(salted_password, b"Client Key");
+        Self {
+            salt,
+            stored_key: mechanism.hash(&client_key),
+            server_key: mechanism.hmac(salted_password, b"Server Key"),
+            iterations,
+        }
+    }
+
+    /// Whether the credential was derived from `password`.
+    pub fn verify(&self, mechanism: ScramMechanism, password: &str) -> bool {
+        let salted_password =
+            mechanism.salted_password(password.as_bytes(), &self.salt, self.iterations);
+        let client_key = mechanism.hmac(&salted_password, b"Client Key");
+        constant_time_eq(&mechanism.hash(&client_key), &self.stored_key)
+    }
+}
+
+// The keys are as sensitive as the password for other SCRAM servers, they
+// are kept out of logs.
+impl fmt::Debug for ScramCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScramCredential")
+            .field("iterations", &self.iterations)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Broker side of a SCRAM exchange.
+///
+/// The client sends its first message, with its user name and a nonce. The
+/// broker answers with the salt and iterations of the credential of the
+/// user, and its nonce appended to the one of the client. The client then
+/// sends a proof that it knows the salted password, which the broker
+/// answers with its signature of the exchange.
+///
+/// Users without a credential go through the same exchange with a made up
+/// one, and fail at the proof like a wrong password, so that clients cannot
+/// find out which users exist.
+#[derive(Debug)]
+pub struct ScramServer {
+    mechanism: ScramMechanism,
+    credentials: CredentialStore,
+    server_nonce: String,
+    state: State,
+}
+
+#[derive(Debug)]
+enum State {
+    ReceivedNothing,
+    SentServerFirst {
+        user: String,
+        credential: ScramCredential,
+        gs2_header: String,
+        client_first_bare: String,
+        server_first: String,
+        nonce: String,
+    },
+    Done,
+}
+
+impl ScramServer {
+    pub fn new(mechanism: ScramMechanism, credentials: CredentialStore) -> Self {
+        Self {
+            mechanism,
+            credentials,
+            server_nonce: BASE64.encode(random_bytes()),
+            state: State::ReceivedNothing,
+        }
+    }
+
+    /// Process the next message of the client.
+    pub fn step(&mut self, token: &[u8]) -> Result<Step> {
+        let message = str::from_utf8(token).map_err(|_| failed("message is not valid utf-8"))?;
+        match std::mem::replace(&mut self.state, State::Done) {
+            State::ReceivedNothing => self.client_first(message),
+            State::SentServerFirst {
+                user,
+                credential,
+                gs2_header,
+                client_first_bare,
+                server_first,
+                nonce,
+            } => {
+                let client_final = parse_client_final(message, &gs2_header, &nonce)?;
+                let auth_message = format!(
+                    "{},{},{}",
+                    client_first_bare, server_first, client_final.without_proof
+                );
+
+                let client_signature = self
+                    .mechanism
+                    .hmac(&credential.stored_key, auth_message.as_bytes());
+                if client_final.proof.len() != client_signature.len() {
+                    return Err(invalid_credentials());
+                }
+                let client_key: Vec<u8> = client_final
+                    .proof
+                    .iter()
+                    .zip(&client_signature)
+                    .map(|(a, b)| a ^ b)
+                    .collect();
+                if !constant_time_eq(&self.mechanism.hash(&client_key), &credential.stored_key) {
+                    return Err(invalid_credentials());
+                }
+
+                let server_signature = self
+                    .mechanism
+                    .hmac(&credential.server_key, auth_message.as_bytes());
+                Ok(Step::Done {
+                    response: format!("v={}", BASE64.encode(server_signature)).into_bytes(),
+                    principal: Principal::user(user),
+                })
+            }
+            State::Done => Err(Error::IllegalSaslState(
+                "the exchange is already complete".into(),
+            )),
+        }
+    }
+
+    fn client_first(&mut self, message: &str) -> Result<Step> {
+        // gs2-header: channel binding flag, then authorization id.
+        let mut parts = message.splitn(3, ',');
+        let cbind = parts.next().unwrap_or_default();
+        let authzid = parts.next().ok_or_else(|| failed("invalid gs2 header"))?;
+        let client_first_bare = parts.next().ok_or_else(|| failed("invalid gs2 header"))?;
+        if cbind != "n" && cbind != "y" {
+            return Err(failed("channel binding is not supported"));
+        }
+        let gs2_header = &message[..message.len() - client_first_bare.len()];
+
+        let mut attributes = client_first_bare.split(',');
+        let user = attributes
+            .next()
+            .and_then(|a| a.strip_prefix("n="))
+            .ok_or_else(|| failed("missing user name"))
+            .and_then(decode_name)?;
+        let client_nonce = attributes
+            .next()
+            .and_then(|a| a.strip_prefix("r="))
+            .filter(|nonce| !nonce.is_empty())
+            .ok_or_else(|| failed("missing nonce"))?;
+        if !authzid.is_empty() {
+            let authzid = authzid.strip_prefix("a=").map(decode_name).transpose()?;
+            if authzid.as_ref() != Some(&user) {
+                return Err(failed("authorization id must be the user name"));
+            }
+        }
+
+        let credential = self
+            .credentials
+            .get(&user, self.mechanism)
+            .unwrap_or_else(|| unknown_user_credential(self.mechanism, &user));
+        let nonce = format!("{}{}", client_nonce, self.server_nonce);
+        let server_first = format!(
+            "r={},s={},i={}",
+            nonce,
+            BASE64.encode(&credential.salt),
+            credential.iterations
+        );
+
+        let challenge = server_first.clone().into_bytes();
+        self.state = State::SentServerFirst {
+            user,
+            credential,
+            gs2_header: gs2_header.into(),
+            client_first_bare: client_first_bare.into(),
+            server_first,
+            nonce,
+        };
+        Ok(Step::Challenge(challenge))
+    }
+}
+
+struct ClientFinal<'a> {
+    without_proof: &'a str,
+    proof: Vec<u8>,
+}
+
+fn parse_client_final<'a>(
+    message: &'a str,
+    gs2_header: &str,
+    nonce: &str,
+) -> Result<ClientFinal<'a>> {
+    let (without_proof, proof) = message
+        .rsplit_once(",p=")
+        .ok_or_else(|| failed("missing proof"))?;
+    let proof = BASE64
+        .decode(proof)
+        .map_err(|_| failed("proof is not valid base64"))?;
+
+    let mut attributes = without_proof.split(',');
+    let channel_binding = attributes.next().and_then(|a| a.strip_prefix("c="));
+    if channel_binding != Some(&BASE64.encode(gs2_header)) {
+        return Err(failed("invalid channel binding"));
+    }
+    if attributes.next().and_then(|a| a.strip_prefix("r=")) != Some(nonce) {
+        return Err(failed("invalid nonce"));
+    }
+
+    Ok(ClientFinal {
+        without_proof,
+        proof,
+    })
+}
+
+// Names escape ',' and '=' as "=2C" and "=3D".
+fn decode_name(name: &str) -> Result<String> {
+    let mut decoded = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(i) = rest.find('=') {
+        decoded.push_str(&rest[..i]);
+        match rest.get(i..i + 3) {
+            Some("=2C") => decoded.push(','),
+            Some("=3D") => decoded.push('='),
+            _ => return Err(failed("invalid user name")),
+        }
+        rest = &rest[i + 3..];
+    }
+    decoded.push_str(rest);
+    Ok(decoded)
+}
+
+// Made up credential of a user without one. Its salt is derived from the
+// name of the user, so that it is the same in every exchange like the salt
+// of an actual credential, and its keys are random, so that no proof
+// matches them.
+fn unknown_user_credential(mechanism: ScramMechanism, user: &str) -> ScramCredential {
+    static SALT_KEY: OnceLock<hmac::Key> = OnceLock::new();
+    let key = SALT_KEY.get_or_init(|| hmac::Key::new(hmac::HMAC_SHA256, &random_bytes()));
+    let tag = hmac::sign(key, format!("{},{}", mechanism, user).as_bytes());
+    ScramCredential {
+        salt: tag.as_ref()[..RANDOM_SIZE].to_vec(),
+        stored_key: mechanism.hash(&random_bytes()),
+        server_key: random_bytes(),
+        iterations: ScramMechanism::MIN_ITERATIONS,
+    }
+}
+
+fn random_bytes() -> Vec<u8> {
+    let mut bytes = vec![0; RANDOM_SIZE];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("the system random number generator failed");
+    bytes
+}
+
+// Compare secrets in a time that does not depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn failed(msg: &str) -> Error {
+    Error::SaslAuthenticationFailed(msg.into())
+}
+
+fn invalid_credentials() -> Error {
+    failed("invalid credentials")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(credential: ScramCredential) -> (tempfile::TempDir, CredentialStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CredentialStore::open(dir.path()).unwrap();
+        store
+            .upsert("user", ScramMechanism::Sha256, credential)
+            .unwrap();
+        (dir, store)
+    }
+
+    // Test vector of RFC 7677.
+    fn rfc_credential() -> ScramCredential {
+        let salt = BASE64.decode("W22ZaJ0SNY7soEsUEjb6gQ==").unwrap();
+        let salted_password = ScramMechanism::Sha256.salted_password(b"pencil", &salt, 4096);
+        ScramCredential::from_salted_password(ScramMechanism::Sha256, salt, &salted_password, 4096)
+    }
+
+    #[test]
+    fn test_rfc_exchange() {
+        let (_dir, store) = store(rfc_credential());
+        let mut server = ScramServer::new(ScramMechanism::Sha256, store);
+        server.server_nonce = "%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0".into();
+
+        let step = server.step(b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO").unwrap();
+        let expected = "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                        s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+        assert!(matches!(step, Step::Challenge(challenge) if challenge == expected.as_bytes()));
+
+        let client_final = "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                            p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=";
+        match server.step(client_final.as_bytes()).unwrap() {
+            Step::Done {
+                response,
+                principal,
+            } => {
+                assert_eq!(
+                    response,
+                    b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=".to_vec()
+                );
+                assert_eq!(principal, Principal::user("user"));
+            }
+            step => panic!("unexpected step {:?}", step),
+        }
+
+        // The exchange cannot go on.
+        assert!(server.step(client_final.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_proof() {
+        let (_dir, store) = store(rfc_credential());
+        let mut server = ScramServer::new(ScramMechanism::Sha256, store);
+        server.server_nonce = "%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0".into();
+
+        server.step(b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO").unwrap();
+        let client_final = "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                            p=AAAAAApWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=";
+        assert!(matches!(
+            server.step(client_final.as_bytes()),
+            Err(Error::SaslAuthenticationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_client_first() {
+        let (_dir, store) = store(rfc_credential());
+        for message in [
+            &b"p=tls-unique,,n=user,r=abc"[..],
+            b"n,a=admin,n=user,r=abc",
+            b"n,,n=user",
+            b"n=user,r=abc",
+        ]
+        .iter()
+        {
+            let mut server = ScramServer::new(ScramMechanism::Sha256, store.clone());
+            assert!(server.step(message).is_err(), "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn test_unknown_user() {
+        let (_dir, store) = store(rfc_credential());
+        let salt = |mechanism, message: &[u8]| {
+            let mut server = ScramServer::new(mechanism, store.clone());
+            let challenge = match server.step(message).unwrap() {
+                Step::Challenge(challenge) => String::from_utf8(challenge).unwrap(),
+                step => panic!("unexpected step {:?}", step),
+            };
+            assert!(challenge.ends_with(",i=4096"), "{}", challenge);
+
+            // The exchange fails at the proof, like with a wrong password.
+            let nonce = challenge.split(',').next().unwrap();
+            let client_final = format!(
+                "c=biws,{},p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=",
+                nonce
+            );
+            assert!(matches!(
+                server.step(client_final.as_bytes()),
+                Err(Error::SaslAuthenticationFailed(msg)) if msg == "invalid credentials"
+            ));
+            challenge.split(',').nth(1).unwrap().to_string()
+        };
+
+        // The salt of a user does not change between exchanges.
+        let nobody = salt(ScramMechanism::Sha256, b"n,,n=nobody,r=abc");
+        assert_eq!(salt(ScramMechanism::Sha256, b"n,,n=nobody,r=def"), nobody);
+        assert_ne!(salt(ScramMechanism::Sha256, b"n,,n=somebody,r=abc"), nobody);
+        assert_ne!(salt(ScramMechanism::Sha512, b"n,,n=nobody,r=abc"), nobody);
+        // The mechanism must match the one of the credential.
+        salt(ScramMechanism::Sha512, b"n,,n=user,r=abc");
+    }
+
+    #[test]
+    fn test_decode_name() {
+        assert_eq!(decode_name("a=2Cb=3Dc").unwrap(), "a,b=c");
+        assert!(decode_name("a=2").is_err());
+        assert!(decode_name("a=41").is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        let credential = ScramCredential::new(ScramMechanism::Sha512, "secret", 4096);
+        assert!(credential.verify(ScramMechanism::Sha512, "secret"));
+        assert!(!credential.verify(ScramMechanism::Sha512, "Secret"));
+        assert_eq!(
+            format!("{:?}", credential),
+            "ScramCredential { iterations: 4096, .. }"
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use std::fmt::{self, Write as _};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::pem::{self, PemObject};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use x509_parser::objects::{oid2abbrev, oid_registry};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use super::principal::Principal;
+
+/// Whether clients of a TLS listener authenticate with a certificate
+/// (`ssl.client.auth`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// Clients are not asked for a certificate.
+    None,
+    /// Clients may present a certificate. Clients that do not are
+    /// anonymous.
+    Requested,
+    /// Clients must present a certificate.
+    Required,
+}
+
+/// TLS configuration of a listener.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// PEM file holding the certificate chain of the broker, starting with
+    /// its own certificate.
+    pub cert_path: PathBuf,
+
+    /// PEM file holding the private key of the broker.
+    pub key_path: PathBuf,
+
+    /// PEM file holding the certificates of the CAs client certificates are
+    /// verified against. Required unless `client_auth` is `None`.
+    pub client_ca_path: Option<PathBuf>,
+
+    pub client_auth: ClientAuth,
+}
+
+impl FromStr for ClientAuth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ClientAuth::None),
+            "requested" => Ok(ClientAuth::Requested),
+            "required" => Ok(ClientAuth::Required),
+            _ => Err(format!(
+                "invalid client auth {:?}, expected none, requested or required",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ClientAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ClientAuth::None => "none",
+            ClientAuth::Requested => "requested",
+            ClientAuth::Required => "required",
+        })
+    }
+}
+
+impl TlsConfig {
+    /// Configuration authenticating the broker with the certificate and key
+    /// stored in `cert_path` and `key_path`, and not clients.
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            client_ca_path: None,
+            client_auth: ClientAuth::None,
+        }
+    }
+
+    /// Load the certificates and the key, and build the configuration of
+    /// the handshakes of the listener.
+    pub fn server_config(&self) -> io::Result<Arc<ServerConfig>> {
+        let certs = load_certs(&self.cert_path)?;
+        let key = PrivateKeyDer::from_pem_file(&self.key_path)
+            .map_err(|err| pem_error(&self.key_path, err))?;
+
+        let provider = Arc::new(crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid_config)?;
+
+        let builder = match self.client_auth {
+            ClientAuth::None => builder.with_no_client_auth(),
+            client_auth => {
+                let path = self.client_ca_path.as_ref().ok_or_else(|| {
+                    invalid_config("client authentication requires a CA certificate file")
+                })?;
+                let mut roots = RootCertStore::empty();
+                for cert in load_certs(path)? {
+                    roots.add(cert).map_err(invalid_config)?;
+                }
+
+                let verifier = client_verifier(Arc::new(roots), provider, client_auth)?;
+                builder.with_client_cert_verifier(verifier)
+            }
+        };
+
+        let config = builder
+            .with_single_cert(certs, key)
+            .map_err(invalid_config)?;
+        Ok(Arc::new(config))
+    }
+}
+
+/// Principal of a client that authenticated with `certificate`: the user
+/// named after the subject of the certificate, in the RFC 2253 format used
+/// by Kafka, e.g. `User:CN=client,O=fafka`.
+pub fn principal(certificate: &CertificateDer<'_>) -> Option<Principal> {
+    let (_, certificate) = X509Certificate::from_der(certificate).ok()?;
+
+    // RFC 2253 lists the relative distinguished names from the last one.
+    let mut name = String::new();
+    let rdns: Vec<_> = certificate.subject().iter_rdn().collect();
+    for rdn in rdns.iter().rev() {
+        if !name.is_empty() {
+            name.push(',');
+        }
+        for (i, attribute) in rdn.iter().enumerate() {
+            if i > 0 {
+                name.push('+');
+            }
+            match oid2abbrev(attribute.attr_type(), oid_registry()) {
+                Ok(abbrev) => name.push_str(abbrev),
+                Err(_) => name.push_str(&attribute.attr_type().to_id_string()),
+            }
+            name.push('=');
+            match attribute.as_str() {
+                Ok(value) => escape(value, &mut name),
+                // Values that are not strings are written as the hex of
+                // their encoding.
+                Err(_) => {
+                    name.push('#');
+                    for byte in attribute.as_slice() {
+                        let _ = write!(name, "{:02x}", byte);
+                    }
+                }
+            }
+        }
+    }
+    Some(Principal::user(name))
+}
+
+fn client_verifier(
+    roots: Arc<RootCertStore>,
+    provider: Arc<CryptoProvider>,
+    client_auth: ClientAuth,
+) -> io::Result<Arc<dyn rustls::server::danger::ClientCertVerifier>> {
+    let builder = WebPkiClientVerifier::builder_with_provider(roots, provider);
+    let builder = match client_auth {
+        ClientAuth::Requested => builder.allow_unauthenticated(),
+        _ => builder,
+    };
+    builder.build().map_err(invalid_config)
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| pem_error(path, err))?;
+    if certs.is_empty() {
+        return Err(invalid_config(format!(
+            "no certificate found in {}",
+            path.display()
+        )));
+    }
+    Ok(certs)
+}
+
+// Escape the characters of an attribute value that are special in RFC 2253
+// distinguished names.
+fn escape(value: &str, name: &mut String) {
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        let special = matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';')
+            || (i == 0 && (c == '#' || c == ' '))
+            || (i == last && c == ' ');
+        if special {
+            name.push('\\');
+        }
+        name.push(c);
+    }
+}
+
+fn pem_error(path: &Path, err: pem::Error) -> io::Error {
+    invalid_config(format!("{}: {}", path.display(), err))
+}
+
+fn invalid_config(err: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{self, Config, ListenerConfig, SecurityProtocol};
+    use futures::stream;
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+        ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+    use rustls::pki_types::ServerName;
+    use rustls::ClientConfig;
+    use std::convert::{TryFrom, TryInto};
+    use std::fs;
+    use std::net::SocketAddr;
+    use tempfile::{tempdir, TempDir};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::oneshot;
+    use tokio::time::{self, Duration, Instant};
+    use tokio_rustls::client::TlsStream;
+    use tokio_rustls::TlsConnector;
+
+    struct Pki {
+        dir: TempDir,
+        ca: Certificate,
+        ca_key: KeyPair,
+    }
+
+    impl Pki {
+        // A CA, and a certificate it signs for the broker, written to PEM
+        // files.
+        fn new() -> Self {
+            let ca_key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec![]).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "fafka ca");
+            let ca = params.self_signed(&ca_key).unwrap();
+
+            let dir = tempdir().unwrap();
+            fs::write(dir.path().join("ca.pem"), ca.pem()).unwrap();
+            let pki = Self { dir, ca, ca_key };
+
+            let params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            let (cert, key) = pki.sign(params);
+            fs::write(pki.dir.path().join("broker.pem"), cert.pem()).unwrap();
+            fs::write(pki.dir.path().join("broker.key"), key.serialize_pem()).unwrap();
+            pki
+        }
+
+        fn sign(&self, params: CertificateParams) -> (Certificate, KeyPair) {
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+            (cert, key)
+        }
+
+        fn client(&self) -> (Certificate, KeyPair) {
+            let mut params = CertificateParams::new(vec![]).unwrap();
+            params.distinguished_name = DistinguishedName::new();
+            params
+                .distinguished_name
+                .push(DnType::OrganizationName, "fafka");
+            params.distinguished_name.push(DnType::CommonName, "client");
+            params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+            self.sign(params)
+        }
+
+        fn tls_config(&self, client_auth: ClientAuth) -> TlsConfig {
+            let path = self.dir.path();
+            TlsConfig {
+                client_ca_path: Some(path.join("ca.pem")),
+                client_auth,
+                ..TlsConfig::new(path.join("broker.pem"), path.join("broker.key"))
+            }
+        }
+    }
+
+    async fn start_server(tls: TlsConfig, log_dir: &TempDir) -> (SocketAddr, oneshot::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config {
+            log_dir: log_dir.path().to_owned(),
+            tls: Some(tls),
+            ..Config::default()
+        };
+        let (stop, stopped) = oneshot::channel();
+        let listener_config = ListenerConfig::new("SSL", SecurityProtocol::Ssl);
+        tokio::spawn(server::run(
+            vec![(listener_config, listener)],
+            config,
+            stream::pending(),
+            stopped,
+        ));
+        (addr, stop)
+    }
+
+    async fn connect(
+        addr: SocketAddr,
+        pki: &Pki,
+        client: Option<(Certificate, KeyPair)>,
+    ) -> TlsStream<TcpStream> {
+        let mut roots = RootCertStore::empty();
+        roots.add(pki.ca.der().clone()).unwrap();
+        let builder =
+            ClientConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+        let config = match client {
+            Some((cert, key)) => {
+                let key = PrivateKeyDer::try_from(key.serialize_der()).unwrap();
+                builder
+                    .with_client_auth_cert(vec![cert.der().clone()], key)
+                    .unwrap()
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        TlsConnector::from(Arc::new(config))
+            .connect(server_name, socket)
+            .await
+            .unwrap()
+    }
+
+    // Send an ApiVersions request and read the correlation id of the
+    // response.
+    async fn api_versions(stream: &mut TlsStream<TcpStream>) -> io::Result<i32> {
+        let mut frame = vec![];
+        frame.extend_from_slice(&10_i32.to_be_bytes());
+        frame.extend_from_slice(&18_i16.to_be_bytes());
+        frame.extend_from_slice(&0_i16.to_be_bytes());
+        frame.extend_from_slice(&7_i32.to_be_bytes());
+        frame.extend_from_slice(&(-1_i16).to_be_bytes());
+        stream.write_all(&frame).await?;
+
+        let size = stream.read_i32().await?;
+        let mut response = vec![0; size as usize];
+        stream.read_exact(&mut response).await?;
+        Ok(i32::from_be_bytes(response[..4].try_into().unwrap()))
+    }
+
+    #[test]
+    fn test_principal() {
+        let pki = Pki::new();
+        let (cert, _) = pki.client();
+        assert_eq!(
+            principal(cert.der()).unwrap().to_string(),
+            "User:CN=client,O=fafka"
+        );
+
+        let mut params = CertificateParams::new(vec![]).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, " a,b+c ");
+        let (cert, _) = pki.sign(params);
+        assert_eq!(principal(cert.der()).unwrap().name, r"CN=\ a\,b\+c\ ");
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let pki = Pki::new();
+        assert!(pki.tls_config(ClientAuth::Required).server_config().is_ok());
+
+        let config = TlsConfig {
+            client_ca_path: None,
+            ..pki.tls_config(ClientAuth::Required)
+        };
+        assert!(config.server_config().is_err());
+
+        // The key is not a certificate
+        let config = TlsConfig::new(pki.dir.path().join("broker.key"), "missing.key");
+        assert!(config.server_config().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_client_auth() {
+        let pki = Pki::new();
+        let log_dir = tempdir().unwrap();
+
+        let (addr, _stop) = start_server(pki.tls_config(ClientAuth::Required), &log_dir).await;
+        let mut stream = connect(addr, &pki, Some(pki.client())).await;
+        assert_eq!(api_versions(&mut stream).await.unwrap(), 7);
+
+        // Clients without a certificate are rejected once the handshake
+        // completes on the broker side.
+        let mut stream = connect(addr, &pki, None).await;
+        assert!(api_versions(&mut stream).await.is_err());
+
+        let (addr, _stop) = start_server(pki.tls_config(ClientAuth::Requested), &log_dir).await;
+        let mut stream = connect(addr, &pki, None).await;
+        assert_eq!(api_versions(&mut stream).await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_deadline() {
+        let pki = Pki::new();
+        let log_dir = tempdir().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config {
+            log_dir: log_dir.path().to_owned(),
+            tls: Some(pki.tls_config(ClientAuth::None)),
+            shutdown_timeout: Duration::from_millis(200),
+            ..Config::default()
+        };
+        let (stop, stopped) = oneshot::channel::<()>();
+        let listener_config = ListenerConfig::new("SSL", SecurityProtocol::Ssl);
+        let server = tokio::spawn(server::run(
+            vec![(listener_config, listener)],
+            config,
+            stream::pending(),
+            stopped,
+        ));
+
+        // A client which never completes its handshake is dropped once the
+        // deadline passes, instead of holding back the shutdown.
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+        let started = Instant::now();
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(socket.read(&mut [0; 1]).await.unwrap(), 0);
+    }
+}
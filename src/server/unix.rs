@@ -0,0 +1,96 @@
+//! Unix domain socket listener.
+//!
+//! Clients running on the same host as the broker, such as sidecars, can
+//! connect to a socket file instead of a TCP port. Who may connect is decided
+//! by the permissions of the file, and clients are identified by the
+//! credentials of their process.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use tokio::net::UnixListener;
+
+use super::principal::Principal;
+
+/// Name of the listener of the Unix domain socket. It is not advertised in
+/// Metadata responses, which only hold TCP endpoints.
+pub const LISTENER_NAME: &str = "UNIX";
+
+/// Bind the socket at `path`, with the permissions `mode`. A socket left at
+/// `path` by a previous run is replaced, but a socket another process still
+/// accepts connections on, or any other file, is an error.
+///
+/// The file is created before its permissions are set, so the directory
+/// holding it should not be writable by everyone either.
+pub fn bind(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use", path.display()),
+                ));
+            }
+            fs::remove_file(path)?
+        }
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+/// Principal of the clients whose process runs as `uid` and `gid`, the user
+/// named `uid=<uid>,gid=<gid>`.
+pub fn principal(uid: u32, gid: u32) -> Principal {
+    Principal::user(format!("uid={},gid={}", uid, gid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_bind() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("broker.sock");
+
+        let listener = bind(&path, 0o600).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // A socket in use is kept
+        let err = bind(&path, 0o660).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(UnixStream::connect(&path).is_ok());
+
+        // The socket of a previous run is replaced
+        drop(listener);
+        bind(&path, 0o660).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+
+        // Other files are kept
+        let path = dir.path().join("file");
+        fs::write(&path, "data").unwrap();
+        assert!(bind(&path, 0o600).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"data");
+    }
+
+    #[test]
+    fn test_principal() {
+        assert_eq!(principal(1000, 100).to_string(), "User:uid=1000,gid=100");
+    }
+}
@@ -0,0 +1,47 @@
+/// Configuration of partition logs.
+///
+/// Field names follow the matching Kafka topic configurations.
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// Maximum size of a segment in bytes (`segment.bytes`). The active
+    /// segment is rolled when an append would make it larger.
+    pub segment_bytes: u32,
+
+    /// Maximum age of a segment in milliseconds (`segment.ms`). The active
+    /// segment is rolled on the next append once it is older, even if it is
+    /// not full, so that retention can eventually delete it.
+    pub segment_ms: u64,
+
+    /// Maximum random jitter in milliseconds subtracted from `segment_ms`
+    /// (`segment.jitter.ms`). Avoids rolling the segments of every partition
+    /// at the same time.
+    pub segment_jitter_ms: u64,
+
+    /// Allocate the whole `segment_bytes` on disk when a log file is created
+    /// (`preallocate`). The file is trimmed to its real size when the segment
+    /// is rolled or closed.
+    pub preallocate: bool,
+
+    /// Maximum age of the records in milliseconds (`retention.ms`). Segments
+    /// whose records are all older are deleted, but the active one. Records
+    /// are kept whatever their age when `None`.
+    pub retention_ms: Option<u64>,
+
+    /// Maximum size of a partition in bytes (`retention.bytes`). The oldest
+    /// segments are deleted, but the active one, while the partition is
+    /// larger. The size is not limited when `None`.
+    pub retention_bytes: Option<u64>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            segment_bytes: 1024 * 1024 * 1024,
+            segment_ms: 7 * 24 * 60 * 60 * 1000,
+            segment_jitter_ms: 0,
+            preallocate: false,
+            retention_ms: Some(7 * 24 * 60 * 60 * 1000),
+            retention_bytes: None,
+        }
+    }
+}
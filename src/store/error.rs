@@ -0,0 +1,72 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Errors returned by the store.
+#[derive(Debug)]
+pub enum StoreError {
+    /// The segment does not have enough room left for the records.
+    SegmentFull,
+
+    /// The requested offset is not in the log.
+    OffsetOutOfRange(u32),
+
+    /// A record could not be decoded or is invalid.
+    CorruptRecord(String),
+
+    /// A caller-supplied offset would create a gap or a duplicate in the log.
+    InvalidOffset { expected: u32, actual: u32 },
+
+    /// The index does not match the log it describes.
+    IndexMismatch(String),
+
+    /// The topic name cannot be used to name a partition directory.
+    InvalidTopic(String),
+
+    /// The file was written with a format version this broker does not know.
+    UnsupportedFormat(u16),
+
+    /// The caller asked for something the store does not do, e.g. appending
+    /// no records.
+    InvalidRequest(String),
+
+    /// An I/O error occurred while accessing the log.
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, StoreError>;
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::SegmentFull => write!(f, "segment is full"),
+            StoreError::OffsetOutOfRange(offset) => write!(f, "offset {} is out of range", offset),
+            StoreError::CorruptRecord(msg) => write!(f, "corrupt record: {}", msg),
+            StoreError::InvalidOffset { expected, actual } => {
+                write!(f, "invalid offset {}, expected {}", actual, expected)
+            }
+            StoreError::IndexMismatch(msg) => write!(f, "index mismatch: {}", msg),
+            StoreError::InvalidTopic(topic) => write!(f, "invalid topic name {:?}", topic),
+            StoreError::UnsupportedFormat(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            StoreError::InvalidRequest(msg) => write!(f, "invalid request: {}", msg),
+            StoreError::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            StoreError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        StoreError::Io(err)
+    }
+}
@@ -0,0 +1,84 @@
+use super::{Result, StoreError};
+
+/// Files written before headers were introduced. They contain raw data only
+/// and their index entries are serialized with bincode's default
+/// configuration, without a timestamp.
+pub const LEGACY_VERSION: u16 = 0;
+
+/// Version written in the header of every new segment file. Index entries
+/// are explicitly encoded as little-endian integers and carry the time at
+/// which the record was appended.
+pub const CURRENT_VERSION: u16 = 1;
+
+/// Size of the header at the beginning of segment files:
+/// 4 bytes of magic, 2 bytes of version and 2 reserved bytes.
+pub const HEADER_SIZE: usize = 8;
+
+/// Kind of a segment file. Each kind has its own magic bytes so that a log
+/// cannot be mistaken for an index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Log,
+    Index,
+}
+
+impl FileKind {
+    fn magic(self) -> &'static [u8; 4] {
+        match self {
+            FileKind::Log => b"FFLG",
+            FileKind::Index => b"FFIX",
+        }
+    }
+}
+
+/// Encode the header of a `kind` file written with format `version`.
+pub fn encode_header(kind: FileKind, version: u16) -> [u8; HEADER_SIZE] {
+    let mut header = [0u8; HEADER_SIZE];
+    header[..4].copy_from_slice(kind.magic());
+    header[4..6].copy_from_slice(&version.to_le_bytes());
+    header
+}
+
+/// Decode the header found at the beginning of a `kind` file.
+///
+/// Returns `None` if the file does not start with a header, which is the
+/// case of files written with the legacy format.
+pub fn decode_header(kind: FileKind, bytes: &[u8]) -> Result<Option<u16>> {
+    if bytes.len() < HEADER_SIZE || &bytes[..4] != kind.magic() {
+        return Ok(None);
+    }
+
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version > CURRENT_VERSION {
+        return Err(StoreError::UnsupportedFormat(version));
+    }
+
+    Ok(Some(version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let header = encode_header(FileKind::Index, CURRENT_VERSION);
+
+        assert_eq!(
+            decode_header(FileKind::Index, &header).unwrap(),
+            Some(CURRENT_VERSION)
+        );
+        assert_eq!(decode_header(FileKind::Log, &header).unwrap(), None);
+        assert_eq!(decode_header(FileKind::Index, &header[..4]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_future_version() {
+        let header = encode_header(FileKind::Log, CURRENT_VERSION + 1);
+
+        assert!(matches!(
+            decode_header(FileKind::Log, &header),
+            Err(StoreError::UnsupportedFormat(_))
+        ));
+    }
+}
@@ -1,21 +1,24 @@
 use std::path::PathBuf;
 
 use std::collections::HashMap;
-use std::io::Result;
 
 use bincode;
 use rayon::prelude::*;
-use serde::{Deserialize, Serialize};
 
-use super::Store;
+use super::format::{FileKind, LEGACY_VERSION};
+use super::{Result, Store, StoreError};
 
-const ENTRY_SIZE: usize = 12;
+/// Timestamp of entries read from files that do not store timestamps.
+pub const NO_TIMESTAMP: i64 = -1;
 
-#[derive(Eq, Ord, Debug, PartialEq, PartialOrd, Serialize, Deserialize, Clone, Copy)]
+#[derive(Eq, Ord, Debug, PartialEq, PartialOrd, Clone, Copy)]
 pub struct Entry {
     pub offset: u32,
     pub start: u32,
     pub size: u32,
+    // Time at which the record was appended, in milliseconds since the unix
+    // epoch.
+    pub timestamp: i64,
 }
 
 impl Entry {
@@ -24,6 +27,57 @@ impl Entry {
             offset,
             size,
             start,
+            timestamp: NO_TIMESTAMP,
+        }
+    }
+
+    // Size of an encoded entry in a file of the given format version.
+    pub fn encoded_size(version: u16) -> usize {
+        match version {
+            LEGACY_VERSION => 12,
+            _ => 20,
+        }
+    }
+
+    pub fn encode(&self, version: u16) -> Result<Vec<u8>> {
+        match version {
+            // Legacy entries were serialized with bincode's default
+            // configuration, which encodes a struct as its fields.
+            LEGACY_VERSION => bincode::serialize(&(self.offset, self.start, self.size))
+                .map_err(|err| StoreError::IndexMismatch(err.to_string())),
+            _ => Ok([
+                &self.offset.to_le_bytes()[..],
+                &self.start.to_le_bytes()[..],
+                &self.size.to_le_bytes()[..],
+                &self.timestamp.to_le_bytes()[..],
+            ]
+            .concat()),
+        }
+    }
+
+    pub fn decode(version: u16, bytes: &[u8]) -> Result<Self> {
+        match version {
+            LEGACY_VERSION => {
+                let (offset, start, size) = bincode::deserialize(bytes)
+                    .map_err(|err| StoreError::IndexMismatch(err.to_string()))?;
+                Ok(Self::new(offset, size, start))
+            }
+            _ => {
+                let u32_at = |i: usize| {
+                    let mut b = [0u8; 4];
+                    b.copy_from_slice(&bytes[i..i + 4]);
+                    u32::from_le_bytes(b)
+                };
+                let mut timestamp = [0u8; 8];
+                timestamp.copy_from_slice(&bytes[12..20]);
+
+                Ok(Self {
+                    offset: u32_at(0),
+                    start: u32_at(4),
+                    size: u32_at(8),
+                    timestamp: i64::from_le_bytes(timestamp),
+                })
+            }
         }
     }
 }
@@ -47,7 +101,11 @@ impl Index {
         let mut i = Self {
             start_offset,
             last_offset: 0,
-            store: Store::new(path.join(format!("{}.index", start_offset)), max_size)?,
+            store: Store::new(
+                path.join(format!("{}.index", start_offset)),
+                max_size,
+                FileKind::Index,
+            )?,
             entries: HashMap::new(),
             max_size,
         };
@@ -58,36 +116,92 @@ impl Index {
     }
 
     pub fn append(&mut self, entries: Vec<Entry>) -> Result<()> {
-        // Adding Entries to memory index and updating last_offset
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        // Serializing Entries. Entries are written with the version of the
+        // file, even if it is not the current one.
+        let version = self.store.version();
+        let buf = entries
+            .par_iter()
+            .map(|e| e.encode(version))
+            .collect::<Result<Vec<_>>>()?
+            .concat();
+        self.store.append(&buf[..])?;
+
+        // Adding Entries to memory index and updating last_offset, once they
+        // are written so that a failed append leaves the index untouched.
         let mut last_offset = entries[0].offset;
-        for e in entries.clone() {
+        for e in entries {
             self.entries.insert(e.offset, e);
             if e.offset > last_offset {
                 last_offset = e.offset;
             }
         }
         self.last_offset = last_offset;
+        Ok(())
+    }
 
-        // Serializing Entries
-        let buf = entries
-            .par_iter()
-            .map(|e| bincode::serialize(&e).unwrap())
-            .reduce(|| vec![], |a, b| [a, b].concat());
+    pub fn flush(&mut self) -> Result<()> {
+        self.store.flush()
+    }
 
-        self.store.append(&buf[..])
+    pub fn sync(&mut self) -> Result<()> {
+        self.store.sync()
     }
 
-    pub fn flush(&self) -> std::io::Result<()> {
-        self.store.flush()
+    // Truncate the index file of the segment starting at `start_offset`
+    // after its last complete entry pointing to records stored in the first
+    // `log_size` bytes of the log. Entries are written after their records,
+    // so a crash can only leave a torn entry or entries whose records were
+    // not written, at the end of the file. Returns the number of entries
+    // dropped, a torn one included.
+    pub fn recover(path: PathBuf, start_offset: u32, log_size: u32) -> Result<u32> {
+        let mut store = Store::new(
+            path.join(format!("{}.index", start_offset)),
+            u32::MAX,
+            FileKind::Index,
+        )?;
+        let bytes = store.read_all()?;
+        let entry_size = Entry::encoded_size(store.version());
+
+        let mut valid = 0;
+        for chunk in bytes.chunks_exact(entry_size) {
+            let e = Entry::decode(store.version(), chunk)?;
+            if e.start as u64 + e.size as u64 > log_size as u64 {
+                break;
+            }
+            valid += 1;
+        }
+
+        let total = bytes.len().div_ceil(entry_size);
+        if valid < total {
+            store.set_size((valid * entry_size) as u32)?;
+            store.trim()?;
+            store.sync()?;
+        }
+        Ok((total - valid) as u32)
     }
 
     pub fn read(&mut self) -> Result<()> {
         let bytes = self.store.read_all()?;
+        let version = self.store.version();
+        let entry_size = Entry::encoded_size(version);
         let n = bytes.len();
+        if n % entry_size != 0 {
+            return Err(StoreError::IndexMismatch(format!(
+                "index size {} is not a multiple of the entry size {}",
+                n, entry_size
+            )));
+        }
         if n > 0 {
-            for i in (0..n).step_by(ENTRY_SIZE) {
-                let e: Entry = bincode::deserialize(&bytes[i..i + ENTRY_SIZE]).unwrap();
+            for i in (0..n).step_by(entry_size) {
+                let e = Entry::decode(version, &bytes[i..i + entry_size])?;
                 self.entries.insert(e.offset, e);
+                if e.offset > self.last_offset {
+                    self.last_offset = e.offset;
+                }
             }
         }
 
@@ -97,11 +211,46 @@ impl Index {
     pub fn get(&self, offset: u32) -> Option<&Entry> {
         self.entries.get(&offset)
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // All entries, sorted by offset.
+    pub fn entries(&self) -> Vec<Entry> {
+        let mut entries: Vec<Entry> = self.entries.values().copied().collect();
+        entries.sort();
+        entries
+    }
+
+    pub fn version(&self) -> u16 {
+        self.store.version()
+    }
+
+    // Format version of the index of the segment starting at `start_offset`,
+    // which is also the version of its log, without reading its entries.
+    pub fn read_version(path: PathBuf, start_offset: u32) -> Result<u16> {
+        let store = Store::new(
+            path.join(format!("{}.index", start_offset)),
+            u32::MAX,
+            FileKind::Index,
+        )?;
+        Ok(store.version())
+    }
+
+    // Position right after the last indexed record, i.e. the end of the log
+    // data described by this index.
+    pub fn end_position(&self) -> u32 {
+        self.get(self.last_offset)
+            .map(|e| e.start + e.size)
+            .unwrap_or(0)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::format::{CURRENT_VERSION, HEADER_SIZE};
     use std::fs;
     use std::iter::FromIterator;
 
@@ -126,17 +275,42 @@ mod tests {
 
         let bytes = indices
             .par_iter()
-            .map(|i| bincode::serialize(&i).unwrap())
-            .reduce(|| vec![], |a, b| [a, b].concat());
+            .map(|i| i.encode(CURRENT_VERSION).unwrap())
+            .reduce(Vec::new, |a, b| [a, b].concat());
 
         let mut buf = BufReader::new(index.store.file);
-        buf.seek(SeekFrom::Start(0)).unwrap();
+        buf.seek(SeekFrom::Start(HEADER_SIZE as u64)).unwrap();
 
         let mut written = vec![];
         buf.read_to_end(&mut written).unwrap();
         assert_eq!(bytes, written);
     }
 
+    #[test]
+    fn test_read_legacy() {
+        let tmp_dir = create_tmp_folder();
+        let legacy: Vec<u8> = [(0_u32, 0_u32, 1024_u32), (1, 1024, 16)]
+            .iter()
+            .flat_map(|e| bincode::serialize(e).unwrap())
+            .collect();
+        fs::write(tmp_dir.join("0.index"), &legacy).unwrap();
+
+        let mut index = Index::new(tmp_dir.clone(), 0, 2048).unwrap();
+        assert_eq!(index.version(), LEGACY_VERSION);
+        assert_eq!(
+            index.entries(),
+            vec![Entry::new(0, 1024, 0), Entry::new(1, 16, 1024)]
+        );
+
+        // New entries keep the legacy encoding
+        index.append(vec![Entry::new(2, 8, 1040)]).unwrap();
+        index.flush().unwrap();
+
+        let index = Index::new(tmp_dir, 0, 2048).unwrap();
+        assert_eq!(index.last_offset, 2);
+        assert_eq!(index.get(2), Some(&Entry::new(2, 8, 1040)));
+    }
+
     #[test]
     fn test_read() {
         let tmp_dir = create_tmp_folder();
@@ -149,7 +323,7 @@ mod tests {
 
         index.read().unwrap();
 
-        let mut read = Vec::from_iter(index.entries.values().map(|a| *a).collect::<Vec<Entry>>());
+        let mut read = Vec::from_iter(index.entries.values().copied().collect::<Vec<Entry>>());
 
         read.sort();
         indices.sort();
@@ -1,8 +1,6 @@
 use std::path::PathBuf;
 
-use std::io::Result;
-
-use super::Store;
+use super::{format::FileKind, Result, Store};
 
 #[derive(Debug)]
 pub struct Log {
@@ -12,22 +10,60 @@ pub struct Log {
 }
 
 impl Log {
-    pub fn new(path: PathBuf, start_offset: u32, max_size: u32) -> Result<Self> {
+    // Open the log of the segment starting at `start_offset`, written with
+    // format `version`, the version of the index of the segment.
+    pub fn new(path: PathBuf, start_offset: u32, max_size: u32, version: u16) -> Result<Self> {
         Ok(Self {
             start_offset,
-            store: Store::new(path.join(format!("{}.log", start_offset)), max_size)?,
+            store: Store::with_version(
+                path.join(format!("{}.log", start_offset)),
+                max_size,
+                FileKind::Log,
+                version,
+            )?,
         })
     }
 
-    pub fn append(&self, buf: &[u8]) -> Result<()> {
+    pub fn append(&mut self, buf: &[u8]) -> Result<()> {
         self.store.append(buf)
     }
 
-    pub fn flush(&self) -> std::io::Result<()> {
+    pub fn flush(&mut self) -> Result<()> {
         self.store.flush()
     }
 
-    pub fn read(&mut self, start: u32, size: u32) -> std::io::Result<Vec<u8>> {
+    pub fn sync(&mut self) -> Result<()> {
+        self.store.sync()
+    }
+
+    // Number of bytes written in the log file. This is the position at which
+    // the next record will be written.
+    pub fn size(&self) -> u32 {
+        self.store.size()
+    }
+
+    pub fn version(&self) -> u16 {
+        self.store.version()
+    }
+
+    // Size the log file may grow to, fixed when the segment is created.
+    pub fn max_size(&self) -> u32 {
+        self.store.max_size()
+    }
+
+    pub fn set_size(&mut self, size: u32) -> Result<()> {
+        self.store.set_size(size)
+    }
+
+    pub fn preallocate(&mut self) -> Result<()> {
+        self.store.preallocate()
+    }
+
+    pub fn trim(&mut self) -> Result<()> {
+        self.store.trim()
+    }
+
+    pub fn read(&mut self, start: u32, size: u32) -> Result<Vec<u8>> {
         self.store.read(start, size)
     }
 }
@@ -35,6 +71,7 @@ impl Log {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::format::{encode_header, CURRENT_VERSION, HEADER_SIZE, LEGACY_VERSION};
     use std::fs;
 
     use tempfile::tempdir;
@@ -54,7 +91,7 @@ mod tests {
 
         for index in 0..5 {
             let expected_file = tmp_dir.clone().join(format!("{}.log", index));
-            let log = Log::new(tmp_dir.clone(), index, 1).unwrap();
+            let log = Log::new(tmp_dir.clone(), index, 1, CURRENT_VERSION).unwrap();
 
             assert!(expected_file.as_path().exists());
             assert_eq!(log.start_offset, index);
@@ -64,7 +101,7 @@ mod tests {
     #[test]
     fn test_write() {
         let tmp_dir = create_tmp_folder();
-        let log = Log::new(tmp_dir, 0, 2048).unwrap();
+        let mut log = Log::new(tmp_dir, 0, 2048, CURRENT_VERSION).unwrap();
 
         let seq: Vec<u8> = (0_u8..255_u8).collect();
         log.append(seq.as_slice()).unwrap();
@@ -75,14 +112,31 @@ mod tests {
 
         let mut written = vec![];
         buf.read_to_end(&mut written).unwrap();
-        assert_eq!(seq, written);
+        assert_eq!(
+            &written[..HEADER_SIZE],
+            encode_header(FileKind::Log, CURRENT_VERSION)
+        );
+        assert_eq!(seq, &written[HEADER_SIZE..]);
+    }
+
+    #[test]
+    fn test_read_legacy() {
+        let tmp_dir = create_tmp_folder();
+        // Legacy logs hold raw data, which may look like a header.
+        let seq: Vec<u8> = b"FFLG".iter().copied().chain(0_u8..251_u8).collect();
+        fs::write(tmp_dir.join("0.log"), &seq).unwrap();
+
+        let mut log = Log::new(tmp_dir, 0, 2048, LEGACY_VERSION).unwrap();
+        assert_eq!(log.version(), LEGACY_VERSION);
+        assert_eq!(log.size(), 255);
+        assert_eq!(log.read(10, 5).unwrap(), seq[10..15].to_vec());
     }
 
     #[test]
     #[should_panic]
     fn test_greedy_write() {
         let tmp_dir = create_tmp_folder();
-        let log = Log::new(tmp_dir, 0, 100).unwrap();
+        let mut log = Log::new(tmp_dir, 0, 100, CURRENT_VERSION).unwrap();
 
         let seq: Vec<u8> = (0_u8..255_u8).collect();
         log.append(seq.as_slice()).unwrap();
@@ -91,7 +145,7 @@ mod tests {
     #[test]
     fn test_read() {
         let tmp_dir = create_tmp_folder();
-        let mut log = Log::new(tmp_dir, 0, 2048).unwrap();
+        let mut log = Log::new(tmp_dir, 0, 2048, CURRENT_VERSION).unwrap();
         let seq: Vec<u8> = (0_u8..255_u8).collect();
 
         log.append(seq.as_slice()).unwrap();
@@ -103,7 +157,7 @@ mod tests {
     #[should_panic]
     fn test_greedy_read() {
         let tmp_dir = create_tmp_folder();
-        let mut log = Log::new(tmp_dir, 0, 256).unwrap();
+        let mut log = Log::new(tmp_dir, 0, 256, CURRENT_VERSION).unwrap();
         let seq: Vec<u8> = (0_u8..255_u8).collect();
 
         log.append(seq.as_slice()).unwrap();
@@ -114,14 +168,14 @@ mod tests {
     #[bench]
     fn bench_write(b: &mut Bencher) {
         let tmp_dir = create_tmp_folder();
-        let log = Log::new(tmp_dir, 0, 1024e+9 as u32).unwrap();
+        let mut log = Log::new(tmp_dir, 0, 1024e+9 as u32, CURRENT_VERSION).unwrap();
 
         let seq: Vec<u8> = vec![255_u8; 2048];
 
         b.iter(|| {
             // Inner closure, the actual test
             for _ in 1..1000 {
-                black_box(log.append(seq.as_slice()).unwrap());
+                log.append(black_box(seq.as_slice())).unwrap();
             }
             log.flush()
         });
@@ -130,7 +184,7 @@ mod tests {
     #[bench]
     fn bench_read(b: &mut Bencher) {
         let tmp_dir = create_tmp_folder();
-        let mut log = Log::new(tmp_dir, 0, 1024e+9 as u32).unwrap();
+        let mut log = Log::new(tmp_dir, 0, 1024e+9 as u32, CURRENT_VERSION).unwrap();
 
         let seq: Vec<u8> = vec![255_u8; 2048000];
         log.append(seq.as_slice()).unwrap();
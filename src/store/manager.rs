@@ -0,0 +1,400 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use futures::future;
+use tracing::{info, warn};
+
+use super::partition::{self, Partition};
+use super::{LogConfig, Result, StoreError};
+
+/// Maximum length of a topic name. Partition directories are named
+/// `<topic>-<partition>`, which must fit in a file name.
+const MAX_TOPIC_LENGTH: usize = 249;
+
+/// File written in the log directory once every partition has been flushed
+/// to disk by `LogManager::shutdown`.
+const CLEAN_SHUTDOWN_FILE: &str = ".clean_shutdown";
+
+/// Identifies a partition of a topic.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TopicPartition {
+    pub topic: String,
+    pub partition: i32,
+}
+
+impl TopicPartition {
+    pub fn new(topic: impl Into<String>, partition: i32) -> Self {
+        Self {
+            topic: topic.into(),
+            partition,
+        }
+    }
+
+    // Parse the name of a partition directory. The topic name may itself
+    // contain dashes, so the partition is what follows the last one.
+    fn from_dir_name(name: &str) -> Option<Self> {
+        let (topic, partition) = name.rsplit_once('-')?;
+        let partition = partition.parse().ok().filter(|p| *p >= 0)?;
+        if is_valid_topic(topic) {
+            Some(Self::new(topic, partition))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for TopicPartition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.topic, self.partition)
+    }
+}
+
+/// Whether `topic` can be used as a topic name: up to 249 ASCII
+/// alphanumerics, `.`, `_` or `-`, and neither `.` nor `..`.
+pub fn is_valid_topic(topic: &str) -> bool {
+    !topic.is_empty()
+        && topic.len() <= MAX_TOPIC_LENGTH
+        && topic != "."
+        && topic != ".."
+        && topic
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+}
+
+/// Owns the partitions stored in a log directory.
+///
+/// Each partition is stored in its own `<topic>-<partition>` directory. The
+/// partitions found in the directory are opened by `LogManager::open`, new
+/// ones are created by `create_topic`.
+///
+/// Partitions use the configuration of their topic if it has one, the
+/// default configuration otherwise. Both can be replaced at runtime with
+/// `reconfigure`.
+///
+/// `LogManager` is a wrapper around an `Arc`: it is cheap to clone and every
+/// clone shares the same partitions.
+#[derive(Debug, Clone)]
+pub struct LogManager {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    dir: PathBuf,
+    configs: Mutex<Configs>,
+    partitions: Mutex<HashMap<TopicPartition, Partition>>,
+}
+
+#[derive(Debug)]
+struct Configs {
+    default: LogConfig,
+    topics: HashMap<String, LogConfig>,
+}
+
+impl Configs {
+    fn get(&self, topic: &str) -> &LogConfig {
+        self.topics.get(topic).unwrap_or(&self.default)
+    }
+}
+
+impl LogManager {
+    /// Open every partition stored in `dir`, creating the directory if
+    /// needed.
+    ///
+    /// Unless the directory was closed by `shutdown`, the partitions are
+    /// recovered first, see `Segment::recover`.
+    pub fn open(dir: impl AsRef<Path>, config: LogConfig) -> Result<Self> {
+        let dir = dir.as_ref().to_owned();
+        fs::create_dir_all(&dir)?;
+
+        // The marker is removed right away, so that a crash from now on is
+        // noticed by the next start.
+        let marker = dir.join(CLEAN_SHUTDOWN_FILE);
+        let clean = marker.exists();
+        if clean {
+            fs::remove_file(&marker)?;
+        }
+
+        let mut partitions = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let name = entry.file_name();
+            if let Some(tp) = name.to_str().and_then(TopicPartition::from_dir_name) {
+                if !clean {
+                    let dropped = partition::recover(&entry.path())?;
+                    if dropped > 0 {
+                        warn!(partition = %tp, dropped, "dropped index entries of unwritten records");
+                    }
+                }
+                let partition = Partition::open(entry.path(), config.clone())?;
+                partitions.insert(tp, partition);
+            }
+        }
+        info!(
+            dir = %dir.display(),
+            partitions = partitions.len(),
+            recovered = !clean,
+            "loaded logs"
+        );
+
+        Ok(Self {
+            shared: Arc::new(Shared {
+                dir,
+                configs: Mutex::new(Configs {
+                    default: config,
+                    topics: HashMap::new(),
+                }),
+                partitions: Mutex::new(partitions),
+            }),
+        })
+    }
+
+    /// Handle to the partition `tp`, if it exists.
+    pub fn get(&self, tp: &TopicPartition) -> Option<Partition> {
+        self.shared.partitions.lock().unwrap().get(tp).cloned()
+    }
+
+    /// Create the partitions `0..num_partitions` of `topic`. Partitions that
+    /// already exist are kept as they are.
+    pub fn create_topic(&self, topic: &str, num_partitions: i32) -> Result<()> {
+        if !is_valid_topic(topic) {
+            return Err(StoreError::InvalidTopic(topic.into()));
+        }
+
+        let mut partitions = self.shared.partitions.lock().unwrap();
+        for partition in 0..num_partitions {
+            let tp = TopicPartition::new(topic, partition);
+            if partitions.contains_key(&tp) {
+                continue;
+            }
+            let path = self.shared.dir.join(tp.to_string());
+            let handle = Partition::open(path, self.config(topic))?;
+            partitions.insert(tp, handle);
+        }
+        info!(topic, num_partitions, "created topic");
+        Ok(())
+    }
+
+    /// Configuration of the partitions of `topic`.
+    pub fn config(&self, topic: &str) -> LogConfig {
+        self.shared.configs.lock().unwrap().get(topic).clone()
+    }
+
+    /// Replace the default configuration, and the configurations of the
+    /// topics which have their own, then apply them to every partition.
+    pub async fn reconfigure(
+        &self,
+        default: LogConfig,
+        topics: HashMap<String, LogConfig>,
+    ) -> Result<()> {
+        // Partitions created from now on get the new configuration, the
+        // other ones are reconfigured below.
+        let configs = Configs { default, topics };
+        *self.shared.configs.lock().unwrap() = configs;
+
+        let partitions: Vec<_> = self
+            .shared
+            .partitions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(tp, partition)| (tp.topic.clone(), partition.clone()))
+            .collect();
+        for (topic, partition) in partitions {
+            partition.reconfigure(self.config(&topic)).await?;
+        }
+        Ok(())
+    }
+
+    /// Delete the segments of every partition which are beyond the
+    /// retention limits of its configuration, see
+    /// `Partition::delete_expired`. Partitions failing to do so are logged
+    /// and skipped. Returns the number of deleted segments.
+    pub async fn delete_expired(&self) -> usize {
+        let mut deleted = 0;
+        for (tp, partition) in self.partitions() {
+            match partition.delete_expired().await {
+                Ok(count) => deleted += count,
+                Err(err) => {
+                    warn!(partition = %tp, cause = %err, "failed to delete expired segments")
+                }
+            }
+        }
+        deleted
+    }
+
+    /// Close every partition, see `Partition::close`, then record that the
+    /// directory was shut down cleanly so that the next `open` does not
+    /// recover it. Partitions cannot be used afterwards.
+    pub async fn shutdown(&self) -> Result<()> {
+        let partitions: Vec<_> = self
+            .shared
+            .partitions
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        future::try_join_all(partitions.iter().map(Partition::close)).await?;
+
+        fs::File::create(self.shared.dir.join(CLEAN_SHUTDOWN_FILE))?.sync_all()?;
+        info!(dir = %self.shared.dir.display(), "closed logs");
+        Ok(())
+    }
+
+    /// Handles to every partition, sorted by topic and partition.
+    pub fn partitions(&self) -> Vec<(TopicPartition, Partition)> {
+        let mut partitions: Vec<_> = self
+            .shared
+            .partitions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(tp, partition)| (tp.clone(), partition.clone()))
+            .collect();
+        partitions.sort_by(|a, b| a.0.cmp(&b.0));
+        partitions
+    }
+
+    /// Every topic with the number of its partitions, sorted by name.
+    pub fn topics(&self) -> BTreeMap<String, i32> {
+        let partitions = self.shared.partitions.lock().unwrap();
+        let mut topics = BTreeMap::new();
+        for tp in partitions.keys() {
+            let count = topics.entry(tp.topic.clone()).or_insert(0);
+            *count = (*count).max(tp.partition + 1);
+        }
+        topics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::segment::Record;
+
+    use tempfile::tempdir;
+
+    fn create_tmp_folder() -> PathBuf {
+        let tmp_dir = tempdir().unwrap().path().to_owned();
+        fs::create_dir_all(tmp_dir.clone()).unwrap();
+        tmp_dir
+    }
+
+    #[test]
+    fn test_topic_names() {
+        assert!(is_valid_topic("orders.v1_eu-west"));
+        assert!(!is_valid_topic(""));
+        assert!(!is_valid_topic(".."));
+        assert!(!is_valid_topic("a/b"));
+        assert!(!is_valid_topic(&"a".repeat(250)));
+
+        assert_eq!(
+            TopicPartition::from_dir_name("my-topic-3"),
+            Some(TopicPartition::new("my-topic", 3))
+        );
+        assert_eq!(TopicPartition::from_dir_name("my-topic"), None);
+        assert_eq!(TopicPartition::from_dir_name("topic-x"), None);
+    }
+
+    #[tokio::test]
+    async fn test_create_and_reopen() {
+        let tmp_dir = create_tmp_folder();
+        let logs = LogManager::open(&tmp_dir, LogConfig::default()).unwrap();
+        assert!(logs.topics().is_empty());
+
+        logs.create_topic("events", 2).unwrap();
+        assert!(matches!(
+            logs.create_topic("a/b", 1),
+            Err(StoreError::InvalidTopic(_))
+        ));
+
+        let partition = logs.get(&TopicPartition::new("events", 1)).unwrap();
+        partition
+            .append(vec![Record::new(vec![1; 8])])
+            .await
+            .unwrap();
+        assert!(logs.get(&TopicPartition::new("events", 2)).is_none());
+        logs.shutdown().await.unwrap();
+        assert!(tmp_dir.join(CLEAN_SHUTDOWN_FILE).exists());
+        assert!(partition
+            .append(vec![Record::new(vec![2; 8])])
+            .await
+            .is_err());
+
+        let logs = LogManager::open(&tmp_dir, LogConfig::default()).unwrap();
+        assert!(!tmp_dir.join(CLEAN_SHUTDOWN_FILE).exists());
+        assert_eq!(
+            logs.topics().into_iter().collect::<Vec<_>>(),
+            vec![("events".into(), 2)]
+        );
+        let partition = logs.get(&TopicPartition::new("events", 1)).unwrap();
+        assert_eq!(partition.end_offset(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reconfigure() {
+        let tmp_dir = create_tmp_folder();
+        let logs = LogManager::open(&tmp_dir, LogConfig::default()).unwrap();
+        logs.create_topic("small", 1).unwrap();
+        logs.create_topic("large", 1).unwrap();
+
+        // Topics without a configuration of their own use the default one.
+        let small = LogConfig {
+            segment_bytes: 16,
+            ..LogConfig::default()
+        };
+        let topics = vec![("small".to_string(), small)].into_iter().collect();
+        logs.reconfigure(LogConfig::default(), topics)
+            .await
+            .unwrap();
+        assert_eq!(logs.config("small").segment_bytes, 16);
+        assert_eq!(logs.config("large").segment_bytes, 1024 * 1024 * 1024);
+        logs.create_topic("small", 2).unwrap();
+
+        for tp in [("small", 0), ("small", 1), ("large", 0)].iter() {
+            let partition = logs.get(&TopicPartition::new(tp.0, tp.1)).unwrap();
+            for _ in 0..3 {
+                partition
+                    .append(vec![Record::new(vec![1; 8])])
+                    .await
+                    .unwrap();
+            }
+        }
+
+        // Records of the small topic were spread over two segments, the
+        // partition created after the change included.
+        let segments = |tp: &str| {
+            fs::read_dir(tmp_dir.join(tp))
+                .unwrap()
+                .filter(|entry| {
+                    let path = entry.as_ref().unwrap().path();
+                    path.extension().is_some_and(|e| e == "log")
+                })
+                .count()
+        };
+        assert_eq!(segments("small-0"), 2);
+        assert_eq!(segments("small-1"), 2);
+        assert_eq!(segments("large-0"), 1);
+
+        // Only the active segments are left.
+        let small = LogConfig {
+            retention_bytes: Some(0),
+            ..logs.config("small")
+        };
+        let topics = vec![("small".to_string(), small)].into_iter().collect();
+        logs.reconfigure(LogConfig::default(), topics)
+            .await
+            .unwrap();
+        assert_eq!(logs.delete_expired().await, 2);
+        assert_eq!(segments("small-0"), 1);
+        let partition = logs.get(&TopicPartition::new("small", 0)).unwrap();
+        assert_eq!(partition.start_offset(), 2);
+    }
+}
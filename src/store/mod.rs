@@ -1,69 +1,215 @@
+pub mod config;
+pub mod error;
+pub mod format;
 pub mod index;
 pub mod log;
+pub mod manager;
+pub mod partition;
 pub mod segment;
+pub mod upgrade;
 
 use std::fs::{File, OpenOptions};
 use std::path::PathBuf;
 
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::io::{Error, ErrorKind, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use std::sync::{Arc, Mutex};
+pub use config::LogConfig;
+pub use error::{Result, StoreError};
+use format::FileKind;
+pub use manager::{LogManager, TopicPartition};
 
 #[derive(Debug)]
 struct Store {
     file: File,
     max_size: u32,
-    writer: Arc<Mutex<BufWriter<File>>>,
+    // Format version of the file, read from its header.
+    version: u16,
+    // Size of the header. Positions used by `append` and `read` are relative
+    // to the end of the header, so that they do not depend on the version.
+    header_size: u32,
+    // Number of bytes written so far. Tracked in memory so the writer does
+    // not have to flush its buffer to learn its position.
+    size: u32,
+    // Only the partition writer appends to a store, so the writer does not
+    // need to be shared.
+    writer: BufWriter<File>,
     reader: BufReader<File>,
 }
 
 impl Store {
-    pub fn new(path: PathBuf, max_size: u32) -> Result<Self> {
+    // Open the `kind` file at `path`. New files are written with the current
+    // format version, existing files keep the version they were created with,
+    // read from their header.
+    pub fn new(path: PathBuf, max_size: u32, kind: FileKind) -> Result<Self> {
+        Self::open(path, max_size, kind, None)
+    }
+
+    // Open the `kind` file at `path`, written with format `version`. Legacy
+    // logs hold raw data, which may start with anything, so the version of a
+    // log is the one of its index rather than guessed from its first bytes.
+    pub fn with_version(
+        path: PathBuf,
+        max_size: u32,
+        kind: FileKind,
+        version: u16,
+    ) -> Result<Self> {
+        Self::open(path, max_size, kind, Some(version))
+    }
+
+    fn open(path: PathBuf, max_size: u32, kind: FileKind, version: Option<u16>) -> Result<Self> {
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(path)?;
+            .truncate(false)
+            .open(&path)?;
 
+        let mut reader = BufReader::new(file.try_clone()?);
         let mut writer = BufWriter::new(file.try_clone()?);
-        writer.seek(SeekFrom::End(0))?;
+        let len = writer.seek(SeekFrom::End(0))? as u32;
+
+        let (version, header_size) = match version {
+            Some(format::LEGACY_VERSION) => (format::LEGACY_VERSION, 0),
+            _ if len == 0 => {
+                let version = version.unwrap_or(format::CURRENT_VERSION);
+                writer.write_all(&format::encode_header(kind, version))?;
+                writer.flush()?;
+                (version, format::HEADER_SIZE as u32)
+            }
+            expected => {
+                let mut header = Vec::with_capacity(format::HEADER_SIZE);
+                reader.seek(SeekFrom::Start(0))?;
+                reader
+                    .by_ref()
+                    .take(format::HEADER_SIZE as u64)
+                    .read_to_end(&mut header)?;
+                match (format::decode_header(kind, &header)?, expected) {
+                    (Some(version), Some(expected)) if version != expected => {
+                        return Err(StoreError::IndexMismatch(format!(
+                            "{} has version {}, expected {}",
+                            path.display(),
+                            version,
+                            expected
+                        )))
+                    }
+                    (None, Some(expected)) => {
+                        return Err(StoreError::IndexMismatch(format!(
+                            "{} has no header, expected version {}",
+                            path.display(),
+                            expected
+                        )))
+                    }
+                    (Some(version), _) => (version, format::HEADER_SIZE as u32),
+                    (None, None) => (format::LEGACY_VERSION, 0),
+                }
+            }
+        };
 
         Ok(Self {
-            file: file.try_clone()?,
+            file,
             max_size,
-            writer: Arc::new(Mutex::new(writer)),
-            reader: BufReader::new(file),
+            version,
+            header_size,
+            size: len.saturating_sub(header_size),
+            writer,
+            reader,
         })
     }
 
-    pub fn append(&self, buf: &[u8]) -> Result<()> {
-        let mut writer = self.writer.lock().unwrap();
-        let position = writer.seek(SeekFrom::Current(0))? as u32;
-        if position + buf.len() as u32 >= self.max_size {
-            Err(Error::new(ErrorKind::UnexpectedEof, ""))
+    pub fn append(&mut self, buf: &[u8]) -> Result<()> {
+        if self.size as u64 + buf.len() as u64 > self.max_size as u64 {
+            Err(StoreError::SegmentFull)
         } else {
-            writer.write_all(buf)
+            if let Err(err) = self.writer.write_all(buf) {
+                // Part of `buf` may have been written. Rewind to the end of
+                // the data, so that the next append is written where its
+                // position says.
+                let _ = self.set_size(self.size);
+                return Err(err.into());
+            }
+            self.size += buf.len() as u32;
+            Ok(())
         }
     }
 
-    pub fn flush(&self) -> std::io::Result<()> {
-        let mut writer = self.writer.lock().unwrap();
-        writer.flush()
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    // Flush the writer and wait for the file to reach the disk.
+    pub fn sync(&mut self) -> Result<()> {
+        self.flush()?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn max_size(&self) -> u32 {
+        self.max_size
+    }
+
+    // Move the end of the data to `size`. Anything written after it is
+    // ignored and will be overwritten by the next append.
+    pub fn set_size(&mut self, size: u32) -> Result<()> {
+        self.writer
+            .seek(SeekFrom::Start((self.header_size + size) as u64))?;
+        self.size = size;
+        Ok(())
+    }
+
+    // Reserve `max_size` bytes of data on disk.
+    pub fn preallocate(&mut self) -> Result<()> {
+        let len = self.header_size as u64 + self.max_size as u64;
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::io::AsRawFd;
+
+            let res = unsafe { libc::fallocate(self.file.as_raw_fd(), 0, 0, len as libc::off_t) };
+            if res != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        self.file.set_len(len)?;
+
+        Ok(())
+    }
+
+    // Truncate the file to the end of the data, releasing any preallocated
+    // space.
+    pub fn trim(&mut self) -> Result<()> {
+        self.flush()?;
+        self.file.set_len((self.header_size + self.size) as u64)?;
+        Ok(())
     }
 
     // TODO Remove mut! This is declaired as mutable because
     // we use the seek function on the BufReader. We don't really
-    // need to keep track of the position inside a file between two reads,
-    // and Arc<Mutex<BufWriter>> is thread safe.
-    pub fn read(&mut self, start: u32, size: u32) -> std::io::Result<Vec<u8>> {
-        if start + size >= self.max_size {
-            Err(Error::new(ErrorKind::UnexpectedEof, ""))
+    // need to keep track of the position inside a file between two reads.
+    pub fn read(&mut self, start: u32, size: u32) -> Result<Vec<u8>> {
+        // Reads are driven by the index, so reading past the end of the file
+        // means that the index points to records that were never written.
+        if start + size > self.size {
+            Err(StoreError::IndexMismatch(format!(
+                "cannot read {} bytes at position {} of a {} bytes file",
+                size, start, self.size
+            )))
         } else {
             let mut buf = vec![0u8; size as usize];
-            self.reader.seek(SeekFrom::Start(start as u64))?;
-            let n = self.reader.read(&mut buf)?;
-            buf.truncate(n);
+            self.reader
+                .seek(SeekFrom::Start((self.header_size + start) as u64))?;
+            // A single read may return less than asked, e.g. for large
+            // reads, which would silently drop the end of the records.
+            self.reader.read_exact(&mut buf)?;
             Ok(buf)
         }
     }
@@ -71,9 +217,17 @@ impl Store {
     // TODO Remove mut!
     pub fn read_all(&mut self) -> Result<Vec<u8>> {
         let mut buf = vec![];
-        self.reader.seek(SeekFrom::Start(0))?;
+        self.reader.seek(SeekFrom::Start(self.header_size as u64))?;
         let n = self.reader.read_to_end(&mut buf)?;
         buf.truncate(n);
         Ok(buf)
     }
 }
+
+// Current time in milliseconds since the unix epoch.
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,1005 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use rand::Rng;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task;
+use tracing::{debug, error, info};
+
+use super::index::Entry;
+use super::segment::{AppendInfo, Record, Segment};
+use super::{now, LogConfig, Result, StoreError};
+
+/// Number of commands that can be queued before `Partition` callers have to
+/// wait for the writer to catch up.
+const COMMAND_BUFFER: usize = 1024;
+
+/// Requests sent by `Partition` handles to the partition writer.
+enum Command {
+    Append {
+        records: Vec<Record>,
+        reply: oneshot::Sender<Result<AppendInfo>>,
+    },
+    Read {
+        from_offset: u32,
+        to_offset: u32,
+        max_bytes: u32,
+        reply: oneshot::Sender<Result<(Vec<Entry>, Vec<u8>)>>,
+    },
+    FindTimestamp {
+        timestamp: i64,
+        reply: oneshot::Sender<Option<Entry>>,
+    },
+    MaxTimestamp {
+        reply: oneshot::Sender<Option<Entry>>,
+    },
+    Reconfigure {
+        config: LogConfig,
+    },
+    DeleteExpired {
+        reply: oneshot::Sender<Result<usize>>,
+    },
+    Close {
+        reply: oneshot::Sender<Result<()>>,
+    },
+    Stats {
+        reply: oneshot::Sender<PartitionStats>,
+    },
+}
+
+/// Statistics of a partition, see `Partition::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PartitionStats {
+    /// Number of segments.
+    pub segments: usize,
+    /// Size of the log and index files, in bytes. Space preallocated for
+    /// the active segment is included.
+    pub size: u64,
+    /// Number of times the active segment was flushed, once per append.
+    pub flushes: u64,
+    /// Total time spent flushing the active segment.
+    pub flush_time: Duration,
+}
+
+/// Handle to a partition.
+///
+/// A partition is a sequence of segments, the last one being the active
+/// segment records are appended to. It is owned by a single writer task. The
+/// writer receives commands over a channel, assigns offsets, writes the log
+/// and the index, rolls the active segment when it is full or too old, and
+/// publishes the new log end offset through a `watch` channel. Because the
+/// writer is the only owner of the segments, appends from concurrent
+/// producers are serialized without any lock, and offsets are assigned in a
+/// single place.
+///
+/// The file I/O of every command runs on the blocking threads of the runtime,
+/// so partitions do not each hold a thread and the number of threads stays
+/// bounded by the runtime however many partitions are open.
+///
+/// The oldest segments are deleted by the writer once they are beyond the
+/// retention limits, which moves the log start offset forward.
+///
+/// `Partition` is cheap to clone: each clone sends its commands to the same
+/// writer. The writer stops once every handle has been dropped, or when the
+/// partition is closed.
+#[derive(Debug, Clone)]
+pub struct Partition {
+    commands: mpsc::Sender<Command>,
+    start_offset: watch::Receiver<u32>,
+    end_offset: watch::Receiver<u32>,
+}
+
+/// State owned by the partition writer task.
+struct Writer {
+    path: PathBuf,
+    config: LogConfig,
+    // Segments sorted by start offset. The last one is the active segment.
+    segments: Vec<Segment>,
+    // Jitter subtracted from `segment_ms` for the active segment. Drawn
+    // again every time a segment is rolled.
+    roll_jitter_ms: u64,
+    commands: mpsc::Receiver<Command>,
+    start_offset: watch::Sender<u32>,
+    end_offset: watch::Sender<u32>,
+    flushes: u64,
+    flush_time: Duration,
+    // Set when flushing the active segment failed after records were written
+    // to it. Later appends are rejected, so that a producer retrying the
+    // failed append does not write its records twice.
+    failed: Option<String>,
+}
+
+impl Partition {
+    /// Open the partition stored in `path` and spawn its writer. Must be
+    /// called from within a tokio runtime.
+    ///
+    /// Every segment found in `path` is opened. If there is none, an empty
+    /// segment starting at offset 0 is created.
+    pub fn open(path: PathBuf, config: LogConfig) -> Result<Self> {
+        fs::create_dir_all(&path)?;
+
+        let mut segments = vec![];
+        for start_offset in segment_offsets(&path)? {
+            segments.push(Segment::new(path.clone(), start_offset, &config)?);
+        }
+        if segments.is_empty() {
+            segments.push(Segment::new(path.clone(), 0, &config)?);
+        }
+
+        let start_offset = segments.first().map(|s| s.start_offset()).unwrap_or(0);
+        let end_offset = segments.last().map(|s| s.next_offset()).unwrap_or(0);
+        let (commands_tx, commands_rx) = mpsc::channel(COMMAND_BUFFER);
+        let (start_offset_tx, start_offset_rx) = watch::channel(start_offset);
+        let (end_offset_tx, end_offset_rx) = watch::channel(end_offset);
+
+        let writer = Writer {
+            path: path.clone(),
+            roll_jitter_ms: jitter(&config),
+            config,
+            segments,
+            commands: commands_rx,
+            start_offset: start_offset_tx,
+            end_offset: end_offset_tx,
+            flushes: 0,
+            flush_time: Duration::ZERO,
+            failed: None,
+        };
+        tokio::spawn(writer.run());
+
+        Ok(Self {
+            commands: commands_tx,
+            start_offset: start_offset_rx,
+            end_offset: end_offset_rx,
+        })
+    }
+
+    /// Append `records` to the partition. Records are assigned consecutive
+    /// offsets, starting from the current log end offset.
+    ///
+    /// If the records are written but cannot be flushed, the error is
+    /// returned and every later append fails, as retrying would write the
+    /// records twice.
+    pub async fn append(&self, records: Vec<Record>) -> Result<AppendInfo> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Append { records, reply }).await?;
+        response.await.map_err(|_| writer_stopped())?
+    }
+
+    /// Read the records between `from_offset` and `to_offset` (inclusive).
+    pub async fn read(&self, from_offset: u32, to_offset: u32) -> Result<(Vec<Entry>, Vec<u8>)> {
+        self.read_with_limit(from_offset, to_offset, u32::MAX).await
+    }
+
+    /// Read the records from `from_offset` whose total size does not exceed
+    /// `max_bytes`. The first record is returned even if it is larger, so
+    /// that readers can always make progress.
+    pub async fn read_bytes(
+        &self,
+        from_offset: u32,
+        max_bytes: u32,
+    ) -> Result<(Vec<Entry>, Vec<u8>)> {
+        self.read_with_limit(from_offset, u32::MAX, max_bytes).await
+    }
+
+    async fn read_with_limit(
+        &self,
+        from_offset: u32,
+        to_offset: u32,
+        max_bytes: u32,
+    ) -> Result<(Vec<Entry>, Vec<u8>)> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Read {
+            from_offset,
+            to_offset,
+            max_bytes,
+            reply,
+        })
+        .await?;
+        response.await.map_err(|_| writer_stopped())?
+    }
+
+    /// Index entry of the first record appended at or after `timestamp`, in
+    /// milliseconds since the unix epoch. `None` if every record was
+    /// appended before.
+    pub async fn find_timestamp(&self, timestamp: i64) -> Result<Option<Entry>> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::FindTimestamp { timestamp, reply })
+            .await?;
+        response.await.map_err(|_| writer_stopped())
+    }
+
+    /// Index entry of the record with the latest append time. `None` if the
+    /// partition is empty.
+    pub async fn max_timestamp(&self) -> Result<Option<Entry>> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::MaxTimestamp { reply }).await?;
+        response.await.map_err(|_| writer_stopped())
+    }
+
+    /// Replace the configuration of the partition. Segments are rolled
+    /// according to the new configuration from the next append, but the
+    /// active segment keeps the size it was created with.
+    pub async fn reconfigure(&self, config: LogConfig) -> Result<()> {
+        self.send(Command::Reconfigure { config }).await
+    }
+
+    /// Delete the oldest segments while they are beyond the retention limits
+    /// of the configuration, see `LogConfig::retention_ms` and
+    /// `LogConfig::retention_bytes`. The active segment is never deleted.
+    /// Returns the number of deleted segments.
+    pub async fn delete_expired(&self) -> Result<usize> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::DeleteExpired { reply }).await?;
+        response.await.map_err(|_| writer_stopped())?
+    }
+
+    /// Flush the segments, wait for them to reach the disk and stop the
+    /// writer. Commands sent before are processed first, commands sent
+    /// afterwards by any handle fail.
+    pub async fn close(&self) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Close { reply }).await?;
+        response.await.map_err(|_| writer_stopped())?
+    }
+
+    /// Number of segments, size on disk and flushes of the partition.
+    pub async fn stats(&self) -> Result<PartitionStats> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Stats { reply }).await?;
+        response.await.map_err(|_| writer_stopped())
+    }
+
+    /// Offset of the first record stored in the partition.
+    pub fn start_offset(&self) -> u32 {
+        *self.start_offset.borrow()
+    }
+
+    /// Offset that will be assigned to the next appended record.
+    pub fn end_offset(&self) -> u32 {
+        *self.end_offset.borrow()
+    }
+
+    /// Subscribe to log end offset changes. The receiver is notified every
+    /// time records are appended to the partition.
+    pub fn subscribe(&self) -> watch::Receiver<u32> {
+        self.end_offset.clone()
+    }
+
+    async fn send(&self, command: Command) -> Result<()> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| writer_stopped())
+    }
+}
+
+impl Writer {
+    /// Process commands until every `Partition` handle has been dropped or
+    /// the partition is closed.
+    async fn run(mut self) {
+        let mut close_reply = None;
+        while let Some(command) = self.commands.recv().await {
+            if let Command::Close { reply } = command {
+                self.commands.close();
+                close_reply = Some(reply);
+                break;
+            }
+            // Commands read and write the segment files, which blocks.
+            self = match task::spawn_blocking(move || {
+                self.process(command);
+                self
+            })
+            .await
+            {
+                Ok(writer) => writer,
+                Err(err) => {
+                    error!(cause = ?err, "partition writer failed");
+                    return;
+                }
+            };
+        }
+
+        debug!("partition writer stopped");
+        let res = task::spawn_blocking(move || self.close())
+            .await
+            .unwrap_or_else(|err| Err(StoreError::Io(Error::other(err))));
+        match close_reply {
+            Some(reply) => {
+                let _ = reply.send(res);
+            }
+            None => {
+                if let Err(err) = res {
+                    error!(cause = ?err, "failed to flush partition");
+                }
+            }
+        }
+    }
+
+    fn process(&mut self, command: Command) {
+        match command {
+            Command::Append { records, reply } => {
+                let _ = reply.send(self.append(records));
+            }
+            Command::Read {
+                from_offset,
+                to_offset,
+                max_bytes,
+                reply,
+            } => {
+                let _ = reply.send(self.read(from_offset, to_offset, max_bytes));
+            }
+            Command::FindTimestamp { timestamp, reply } => {
+                let entry = self
+                    .segments
+                    .iter()
+                    .find_map(|s| s.find_timestamp(timestamp));
+                let _ = reply.send(entry);
+            }
+            Command::MaxTimestamp { reply } => {
+                // Segments are sorted, so the first one holding the
+                // maximum wins ties.
+                let entry = self.segments.iter().filter_map(|s| s.max_timestamp()).fold(
+                    None,
+                    |max: Option<Entry>, e| match max {
+                        Some(max) if max.timestamp >= e.timestamp => Some(max),
+                        _ => Some(e),
+                    },
+                );
+                let _ = reply.send(entry);
+            }
+            Command::Reconfigure { config } => {
+                self.roll_jitter_ms = jitter(&config);
+                self.config = config;
+            }
+            Command::DeleteExpired { reply } => {
+                let _ = reply.send(self.delete_expired());
+            }
+            Command::Stats { reply } => {
+                let _ = reply.send(self.stats());
+            }
+            // Handled by `run`.
+            Command::Close { .. } => unreachable!(),
+        }
+    }
+
+    // Release the space preallocated for the active segment, then sync every
+    // segment. Segments rolled earlier were flushed but may not have reached
+    // the disk yet.
+    fn close(&mut self) -> Result<()> {
+        self.active().trim()?;
+        for segment in &mut self.segments {
+            segment.sync()?;
+        }
+        Ok(())
+    }
+
+    fn active(&mut self) -> &mut Segment {
+        // There is always at least one segment.
+        self.segments.last_mut().unwrap()
+    }
+
+    fn append(&mut self, records: Vec<Record>) -> Result<AppendInfo> {
+        if let Some(cause) = &self.failed {
+            return Err(StoreError::Io(Error::other(format!(
+                "partition failed to flush: {}",
+                cause
+            ))));
+        }
+
+        let size: u64 = records.iter().map(|r| r.data.len() as u64).sum();
+        if self.should_roll(size) {
+            self.roll()?;
+        }
+
+        let active = self.active();
+        let info = active.append(records)?;
+        // Flushing makes the records visible to readers before the new end
+        // offset is published. If it fails, the records were still assigned
+        // their offsets, so the end offset is published anyway and the
+        // partition is marked failed instead.
+        let started = Instant::now();
+        let flushed = active.flush();
+        let end_offset = active.next_offset();
+        self.flushes += 1;
+        self.flush_time += started.elapsed();
+        self.end_offset.send_replace(end_offset);
+        if let Err(err) = flushed {
+            error!(cause = ?err, "failed to flush partition, rejecting appends");
+            self.failed = Some(err.to_string());
+            return Err(err);
+        }
+
+        Ok(info)
+    }
+
+    // Whether the active segment must be rolled before appending `size`
+    // bytes. An empty segment is never rolled: records that do not fit in an
+    // empty segment are rejected by the segment itself. The active segment
+    // may have been created before `segment_bytes` was raised, in which case
+    // it cannot grow past its own size.
+    fn should_roll(&mut self, size: u64) -> bool {
+        let max_age = self.config.segment_ms.saturating_sub(self.roll_jitter_ms) as i64;
+        let segment_bytes = self.config.segment_bytes;
+        let active = self.active();
+        let segment_bytes = segment_bytes.min(active.max_size()) as u64;
+
+        match active.rolling_timestamp() {
+            None => false,
+            Some(timestamp) => {
+                active.size() as u64 + size > segment_bytes || now() - timestamp >= max_age
+            }
+        }
+    }
+
+    fn stats(&self) -> PartitionStats {
+        // Files that cannot be read are left out rather than failing the
+        // whole command.
+        let size = self
+            .segments
+            .iter()
+            .flat_map(|s| {
+                let start_offset = s.start_offset();
+                vec![
+                    self.path.join(format!("{}.log", start_offset)),
+                    self.path.join(format!("{}.index", start_offset)),
+                ]
+            })
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+
+        PartitionStats {
+            segments: self.segments.len(),
+            size,
+            flushes: self.flushes,
+            flush_time: self.flush_time,
+        }
+    }
+
+    // Delete the oldest segments, but the active one, while their records
+    // are all older than `retention_ms` or the partition would still hold
+    // `retention_bytes` without them.
+    fn delete_expired(&mut self) -> Result<usize> {
+        let now = now();
+        let mut size: u64 = self.segments.iter().map(|s| s.size() as u64).sum();
+        let mut deleted = 0;
+        while self.segments.len() > 1 {
+            let segment = &self.segments[0];
+            let expired = match self.config.retention_ms {
+                Some(retention_ms) => {
+                    now.saturating_sub(self.largest_timestamp(segment)) > retention_ms as i64
+                }
+                None => false,
+            };
+            let oversized = match self.config.retention_bytes {
+                Some(retention_bytes) => size - segment.size() as u64 >= retention_bytes,
+                None => false,
+            };
+            if !expired && !oversized {
+                break;
+            }
+
+            // The segment is gone for readers before its files are
+            // removed.
+            let segment = self.segments.remove(0);
+            size -= segment.size() as u64;
+            let start_offset = segment.start_offset();
+            drop(segment);
+            self.start_offset
+                .send_replace(self.segments[0].start_offset());
+            fs::remove_file(self.path.join(format!("{}.log", start_offset)))?;
+            fs::remove_file(self.path.join(format!("{}.index", start_offset)))?;
+            deleted += 1;
+            info!(path = %self.path.display(), start_offset, expired, "deleted segment");
+        }
+        Ok(deleted)
+    }
+
+    // Latest append time of the records of `segment`. Legacy segments,
+    // whose index does not store timestamps, fall back to the last time
+    // their log was modified.
+    fn largest_timestamp(&self, segment: &Segment) -> i64 {
+        if let Some(entry) = segment.max_timestamp() {
+            return entry.timestamp;
+        }
+        let path = self.path.join(format!("{}.log", segment.start_offset()));
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |modified| modified.as_millis() as i64)
+    }
+
+    // Close the active segment and start a new one at the log end offset.
+    fn roll(&mut self) -> Result<()> {
+        let active = self.active();
+        active.trim()?;
+        let start_offset = active.next_offset();
+
+        let segment = Segment::new(self.path.clone(), start_offset, &self.config)?;
+        self.segments.push(segment);
+        self.roll_jitter_ms = jitter(&self.config);
+
+        info!(path = %self.path.display(), start_offset, "rolled new segment");
+        Ok(())
+    }
+
+    // Read from the segment holding `from_offset`. Records stored in the
+    // following segments are not returned.
+    fn read(
+        &mut self,
+        from_offset: u32,
+        to_offset: u32,
+        max_bytes: u32,
+    ) -> Result<(Vec<Entry>, Vec<u8>)> {
+        let segment = self
+            .segments
+            .iter_mut()
+            .find(|s| from_offset < s.next_offset());
+
+        match segment {
+            Some(segment) if from_offset >= segment.start_offset() => {
+                let to_offset = to_offset.min(segment.last_offset_within(from_offset, max_bytes));
+                segment.read(from_offset, to_offset)
+            }
+            // Reading from the log end offset returns nothing.
+            None if from_offset == *self.end_offset.borrow() => Ok((vec![], vec![])),
+            _ => Err(StoreError::OffsetOutOfRange(from_offset)),
+        }
+    }
+}
+
+/// Repair the segments of the partition stored in `path` after a crash,
+/// before it is opened. Returns the number of index entries dropped, see
+/// `Segment::recover`.
+pub fn recover(path: &Path) -> Result<u32> {
+    let mut dropped = 0;
+    for start_offset in segment_offsets(path)? {
+        dropped += Segment::recover(path.to_owned(), start_offset)?;
+    }
+    Ok(dropped)
+}
+
+// Start offsets of the segments stored in `path`, sorted.
+fn segment_offsets(path: &Path) -> Result<Vec<u32>> {
+    let mut offsets = vec![];
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "log") {
+            if let Some(offset) = path.file_stem().and_then(|s| s.to_str()?.parse().ok()) {
+                offsets.push(offset);
+            }
+        }
+    }
+    offsets.sort_unstable();
+    Ok(offsets)
+}
+
+// Draw the jitter of a new active segment.
+fn jitter(config: &LogConfig) -> u64 {
+    if config.segment_jitter_ms == 0 {
+        0
+    } else {
+        rand::thread_rng().gen_range(0..config.segment_jitter_ms)
+    }
+}
+
+fn writer_stopped() -> StoreError {
+    StoreError::Io(Error::new(
+        ErrorKind::BrokenPipe,
+        "partition writer stopped",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::format::{CURRENT_VERSION, HEADER_SIZE};
+    use std::fs;
+    use std::io::Write;
+    use std::thread;
+
+    use tempfile::tempdir;
+    use tokio::time;
+
+    fn create_tmp_folder() -> PathBuf {
+        let tmp_dir = tempdir().unwrap().path().to_owned();
+        fs::create_dir_all(tmp_dir.clone()).unwrap();
+        tmp_dir
+    }
+
+    #[tokio::test]
+    async fn test_append_assigns_offsets() {
+        let tmp_dir = create_tmp_folder();
+        let partition = Partition::open(tmp_dir, LogConfig::default()).unwrap();
+
+        let records = vec![Record::new(vec![1; 10]), Record::new(vec![2; 20])];
+        assert_eq!(partition.append(records).await.unwrap().base_offset, 0);
+        let records = vec![Record::new(vec![3; 30])];
+        let info = partition.append(records).await.unwrap();
+        assert_eq!(info.base_offset, 2);
+        assert_eq!(partition.end_offset(), 3);
+
+        let (entries, data) = partition.read(1, 2).await.unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (e.offset, e.start, e.size))
+                .collect::<Vec<_>>(),
+            vec![(1, 10, 20), (2, 30, 30)]
+        );
+        assert_eq!(entries[1].timestamp, info.log_append_time);
+        assert_eq!(data, [vec![2; 20], vec![3; 30]].concat());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_appends() {
+        let tmp_dir = create_tmp_folder();
+        let partition = Partition::open(tmp_dir, LogConfig::default()).unwrap();
+
+        let tasks: Vec<_> = (0..10)
+            .map(|i| {
+                let partition = partition.clone();
+                tokio::spawn(async move { partition.append(vec![Record::new(vec![i; 4])]).await })
+            })
+            .collect();
+
+        let mut offsets = vec![];
+        for task in tasks {
+            offsets.push(task.await.unwrap().unwrap().base_offset);
+        }
+        offsets.sort_unstable();
+
+        assert_eq!(offsets, (0..10).collect::<Vec<u32>>());
+        assert_eq!(partition.end_offset(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let tmp_dir = create_tmp_folder();
+        let partition = Partition::open(tmp_dir, LogConfig::default()).unwrap();
+        let mut end_offset = partition.subscribe();
+
+        partition
+            .append(vec![Record::new(vec![0; 8])])
+            .await
+            .unwrap();
+
+        end_offset.changed().await.unwrap();
+        assert_eq!(*end_offset.borrow(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reopen() {
+        let tmp_dir = create_tmp_folder();
+        let partition = Partition::open(tmp_dir.clone(), LogConfig::default()).unwrap();
+        partition
+            .append(vec![Record::new(vec![0; 8]), Record::new(vec![1; 8])])
+            .await
+            .unwrap();
+        partition.close().await.unwrap();
+
+        let partition = Partition::open(tmp_dir, LogConfig::default()).unwrap();
+        assert_eq!(partition.end_offset(), 2);
+        let records = vec![Record::new(vec![2; 8])];
+        assert_eq!(partition.append(records).await.unwrap().base_offset, 2);
+    }
+
+    #[tokio::test]
+    async fn test_roll_on_size() {
+        let tmp_dir = create_tmp_folder();
+        let config = LogConfig {
+            segment_bytes: 20,
+            ..LogConfig::default()
+        };
+        let partition = Partition::open(tmp_dir.clone(), config.clone()).unwrap();
+
+        for i in 0..5 {
+            partition
+                .append(vec![Record::new(vec![i; 8])])
+                .await
+                .unwrap();
+        }
+
+        // Two records per segment
+        assert!(tmp_dir.join("0.log").exists());
+        assert!(tmp_dir.join("2.log").exists());
+        assert!(tmp_dir.join("4.log").exists());
+
+        // Reads stop at the end of a segment
+        let (entries, data) = partition.read(1, 4).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(data, vec![1; 8]);
+        let (_, data) = partition.read(3, 3).await.unwrap();
+        assert_eq!(data, vec![3; 8]);
+
+        // Records larger than a segment are rejected
+        let records = vec![Record::new(vec![0; 21])];
+        assert!(matches!(
+            partition.append(records).await,
+            Err(StoreError::SegmentFull)
+        ));
+
+        partition.close().await.unwrap();
+        let partition = Partition::open(tmp_dir, config).unwrap();
+        assert_eq!(partition.end_offset(), 5);
+        let (_, data) = partition.read(2, 2).await.unwrap();
+        assert_eq!(data, vec![2; 8]);
+    }
+
+    #[tokio::test]
+    async fn test_roll_on_age() {
+        let tmp_dir = create_tmp_folder();
+        let config = LogConfig {
+            segment_ms: 0,
+            ..LogConfig::default()
+        };
+        let partition = Partition::open(tmp_dir.clone(), config).unwrap();
+
+        partition
+            .append(vec![Record::new(vec![0; 8])])
+            .await
+            .unwrap();
+        assert!(!tmp_dir.join("1.log").exists());
+        partition
+            .append(vec![Record::new(vec![1; 8])])
+            .await
+            .unwrap();
+        assert!(tmp_dir.join("1.log").exists());
+    }
+
+    #[tokio::test]
+    async fn test_delete_expired() {
+        let tmp_dir = create_tmp_folder();
+        let config = LogConfig {
+            segment_bytes: 20,
+            ..LogConfig::default()
+        };
+        let partition = Partition::open(tmp_dir.clone(), config.clone()).unwrap();
+        for i in 0..7 {
+            partition
+                .append(vec![Record::new(vec![i; 8])])
+                .await
+                .unwrap();
+        }
+        assert_eq!(partition.delete_expired().await.unwrap(), 0);
+
+        // The oldest segments go until the others fit in `retention_bytes`.
+        let by_size = LogConfig {
+            retention_bytes: Some(40),
+            ..config.clone()
+        };
+        partition.reconfigure(by_size).await.unwrap();
+        assert_eq!(partition.delete_expired().await.unwrap(), 1);
+        assert!(!tmp_dir.join("0.log").exists());
+        assert!(!tmp_dir.join("0.index").exists());
+        assert_eq!(partition.start_offset(), 2);
+        assert!(matches!(
+            partition.read(1, 1).await,
+            Err(StoreError::OffsetOutOfRange(1))
+        ));
+
+        // The active segment is kept, whatever its age.
+        time::sleep(Duration::from_millis(10)).await;
+        let by_age = LogConfig {
+            retention_ms: Some(0),
+            ..config.clone()
+        };
+        partition.reconfigure(by_age).await.unwrap();
+        assert_eq!(partition.delete_expired().await.unwrap(), 2);
+        assert_eq!(partition.start_offset(), 6);
+        let (_, data) = partition.read(6, 6).await.unwrap();
+        assert_eq!(data, vec![6; 8]);
+
+        partition.close().await.unwrap();
+        let partition = Partition::open(tmp_dir, config).unwrap();
+        assert_eq!(partition.start_offset(), 6);
+        assert_eq!(partition.end_offset(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_read_out_of_range() {
+        let tmp_dir = create_tmp_folder();
+        let partition = Partition::open(tmp_dir, LogConfig::default()).unwrap();
+        partition
+            .append(vec![Record::new(vec![0; 8])])
+            .await
+            .unwrap();
+
+        let (entries, _) = partition.read(1, 10).await.unwrap();
+        assert!(entries.is_empty());
+        assert!(matches!(
+            partition.read(2, 10).await,
+            Err(StoreError::OffsetOutOfRange(2))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_bytes() {
+        let tmp_dir = create_tmp_folder();
+        let partition = Partition::open(tmp_dir, LogConfig::default()).unwrap();
+        let records = (0..4).map(|i| Record::new(vec![i; 10])).collect();
+        partition.append(records).await.unwrap();
+
+        let (entries, data) = partition.read_bytes(1, 25).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(data, [vec![1; 10], vec![2; 10]].concat());
+
+        // The first record is returned even if it does not fit
+        let (entries, _) = partition.read_bytes(3, 5).await.unwrap();
+        assert_eq!(entries.len(), 1);
+
+        let (entries, _) = partition.read_bytes(4, 100).await.unwrap();
+        assert!(entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_timestamps() {
+        let tmp_dir = create_tmp_folder();
+        let config = LogConfig {
+            segment_bytes: 20,
+            ..LogConfig::default()
+        };
+        let partition = Partition::open(tmp_dir, config).unwrap();
+        assert_eq!(partition.max_timestamp().await.unwrap(), None);
+
+        let mut times = vec![];
+        for i in 0..3 {
+            let records = vec![Record::new(vec![i; 8]), Record::new(vec![i; 8])];
+            times.push(partition.append(records).await.unwrap().log_append_time);
+            thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        let entry = partition.find_timestamp(times[1]).await.unwrap().unwrap();
+        assert_eq!(entry.offset, 2);
+        let entry = partition.find_timestamp(0).await.unwrap().unwrap();
+        assert_eq!(entry.offset, 0);
+        assert_eq!(partition.find_timestamp(times[2] + 1).await.unwrap(), None);
+
+        let entry = partition.max_timestamp().await.unwrap().unwrap();
+        assert_eq!((entry.offset, entry.timestamp), (4, times[2]));
+    }
+
+    #[tokio::test]
+    async fn test_preallocate() {
+        let tmp_dir = create_tmp_folder();
+        let config = LogConfig {
+            segment_bytes: 4096,
+            preallocate: true,
+            ..LogConfig::default()
+        };
+        let log_len = |offset: u32| {
+            fs::metadata(tmp_dir.join(format!("{}.log", offset)))
+                .unwrap()
+                .len()
+        };
+
+        let partition = Partition::open(tmp_dir.clone(), config.clone()).unwrap();
+        assert!(log_len(0) >= 4096);
+
+        partition
+            .append(vec![Record::new(vec![0; 8])])
+            .await
+            .unwrap();
+        // Closing waits for the log to be trimmed, which would otherwise race
+        // with the appends below.
+        partition.close().await.unwrap();
+        assert_eq!(log_len(0), (HEADER_SIZE + 8) as u64);
+
+        // Reopening a trimmed log does not lose its end
+        let partition = Partition::open(tmp_dir.clone(), config).unwrap();
+        assert_eq!(partition.end_offset(), 1);
+        partition
+            .append(vec![Record::new(vec![1; 8])])
+            .await
+            .unwrap();
+        let (_, data) = partition.read(0, 1).await.unwrap();
+        assert_eq!(data, [vec![0; 8], vec![1; 8]].concat());
+    }
+
+    #[tokio::test]
+    async fn test_trim_on_roll() {
+        let tmp_dir = create_tmp_folder();
+        let config = LogConfig {
+            segment_bytes: 4096,
+            segment_ms: 0,
+            preallocate: true,
+            ..LogConfig::default()
+        };
+        let partition = Partition::open(tmp_dir.clone(), config).unwrap();
+
+        partition
+            .append(vec![Record::new(vec![0; 8])])
+            .await
+            .unwrap();
+        partition
+            .append(vec![Record::new(vec![1; 8])])
+            .await
+            .unwrap();
+
+        let len = fs::metadata(tmp_dir.join("0.log")).unwrap().len();
+        assert_eq!(len, (HEADER_SIZE + 8) as u64);
+        assert!(fs::metadata(tmp_dir.join("1.log")).unwrap().len() >= 4096);
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let tmp_dir = create_tmp_folder();
+        let config = LogConfig {
+            segment_bytes: 20,
+            ..LogConfig::default()
+        };
+        let partition = Partition::open(tmp_dir, config).unwrap();
+        assert_eq!(partition.stats().await.unwrap().flushes, 0);
+
+        for i in 0..3 {
+            partition
+                .append(vec![Record::new(vec![i; 8])])
+                .await
+                .unwrap();
+        }
+        let stats = partition.stats().await.unwrap();
+        assert_eq!(stats.segments, 2);
+        assert_eq!(stats.flushes, 3);
+        // Two headers per segment, the records and their index entries
+        let size = 4 * HEADER_SIZE + 3 * 8 + 3 * Entry::encoded_size(CURRENT_VERSION);
+        assert_eq!(stats.size, size as u64);
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let tmp_dir = create_tmp_folder();
+        let partition = Partition::open(tmp_dir, LogConfig::default()).unwrap();
+        let other = partition.clone();
+        partition
+            .append(vec![Record::new(vec![0; 8])])
+            .await
+            .unwrap();
+
+        partition.close().await.unwrap();
+        assert!(other.append(vec![Record::new(vec![1; 8])]).await.is_err());
+        assert!(other.close().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_recover() {
+        let tmp_dir = create_tmp_folder();
+        let config = LogConfig {
+            segment_bytes: 20,
+            ..LogConfig::default()
+        };
+        let partition = Partition::open(tmp_dir.clone(), config.clone()).unwrap();
+        for i in 0..4 {
+            partition
+                .append(vec![Record::new(vec![i; 8])])
+                .await
+                .unwrap();
+        }
+        partition.close().await.unwrap();
+        assert_eq!(recover(&tmp_dir).unwrap(), 0);
+
+        // A crash left a torn index entry, and the last record out of the log
+        let index = tmp_dir.join("2.index");
+        let len = fs::metadata(&index).unwrap().len();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&index)
+            .unwrap()
+            .write_all(&[0; 7])
+            .unwrap();
+        let log = fs::OpenOptions::new()
+            .write(true)
+            .open(tmp_dir.join("2.log"))
+            .unwrap();
+        log.set_len((HEADER_SIZE + 12) as u64).unwrap();
+
+        assert_eq!(recover(&tmp_dir).unwrap(), 2);
+        assert_eq!(fs::metadata(&index).unwrap().len(), len - 20);
+        let partition = Partition::open(tmp_dir, config).unwrap();
+        assert_eq!(partition.end_offset(), 3);
+        let (_, data) = partition.read(2, 2).await.unwrap();
+        assert_eq!(data, vec![2; 8]);
+    }
+}
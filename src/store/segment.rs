@@ -1,9 +1,46 @@
 use std::cmp;
-use std::io::Result;
 use std::path::PathBuf;
 
 // use rayon::prelude::*;
-use super::{index::Entry, index::Index, log::Log};
+use super::index::{Entry, Index, NO_TIMESTAMP};
+use super::{log::Log, now, LogConfig, Result, StoreError};
+
+/// A record to append to a segment.
+///
+/// The offset is usually left to the segment, which assigns the next one.
+/// Callers that already know the offset of a record (e.g. when copying a log)
+/// can supply it, in which case it must be the offset the segment would
+/// have assigned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub offset: Option<u32>,
+    pub data: Vec<u8>,
+}
+
+impl Record {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { offset: None, data }
+    }
+
+    pub fn with_offset(offset: u32, data: Vec<u8>) -> Self {
+        Self {
+            offset: Some(offset),
+            data,
+        }
+    }
+}
+
+/// Outcome of a successful `Segment::append`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppendInfo {
+    /// Offset assigned to the first appended record.
+    pub base_offset: u32,
+    /// Offset assigned to the last appended record.
+    pub last_offset: u32,
+    /// Time at which the records were appended, in milliseconds since the
+    /// unix epoch.
+    pub log_append_time: i64,
+}
 
 struct LogChunk {
     pub entries: Vec<Entry>,
@@ -11,30 +48,91 @@ struct LogChunk {
     pub size: u32,
 }
 
-struct Segment {
+#[derive(Debug)]
+pub struct Segment {
     start_offset: u32,
     log: Log,
     index: Index,
+    // Time at which the segment was opened, used as the age of segments
+    // whose index does not store timestamps.
+    opened_at: i64,
 }
 
 impl Segment {
-    pub fn new(path: PathBuf, start_offset: u32) -> Result<Self> {
-        let log = Log::new(path.clone(), start_offset, 1024e+9 as u32)?;
-        let index = Index::new(path.clone(), start_offset, 1024e+9 as u32)?;
+    pub fn new(path: PathBuf, start_offset: u32, config: &LogConfig) -> Result<Self> {
+        let index = Index::new(path.clone(), start_offset, u32::MAX)?;
+        let mut log = Log::new(path, start_offset, config.segment_bytes, index.version())?;
+
+        if config.preallocate && index.is_empty() {
+            log.preallocate()?;
+        }
+        // The index is the source of truth for the end of the log: the log
+        // file may be preallocated, or hold records whose index entries
+        // were never written.
+        log.set_size(index.end_position())?;
 
         Ok(Self {
             start_offset,
             log,
             index,
+            opened_at: now(),
         })
     }
 
-    // Not parrallel any failure will lead to data loss as
-    // a record might be written in the log but not in the index
-    pub fn append(&mut self, index: Vec<Entry>, records: &[u8]) -> Result<()> {
-        self.log.append(records)?;
-        self.index.append(index)?;
-        Ok(())
+    // Assign offsets to `records`, write them at the end of the log and
+    // index them.
+    pub fn append(&mut self, records: Vec<Record>) -> Result<AppendInfo> {
+        if records.is_empty() {
+            return Err(StoreError::InvalidRequest("no records to append".into()));
+        }
+
+        let base_offset = self.next_offset();
+        let log_append_time = now();
+        let mut position = self.log.size();
+        let mut index = Vec::with_capacity(records.len());
+
+        // Validate every record before writing anything so that a rejected
+        // batch leaves the segment untouched.
+        for (offset, record) in (base_offset..).zip(records.iter()) {
+            match record.offset {
+                Some(actual) if actual != offset => {
+                    return Err(StoreError::InvalidOffset {
+                        expected: offset,
+                        actual,
+                    });
+                }
+                _ => {}
+            }
+
+            let size = record.data.len() as u32;
+            index.push(Entry {
+                timestamp: log_append_time,
+                ..Entry::new(offset, size, position)
+            });
+            position += size;
+        }
+
+        let last_offset = base_offset + records.len() as u32 - 1;
+        let data: Vec<u8> = records.into_iter().flat_map(|r| r.data).collect();
+
+        // A failed write leaves the index as it was, but may have moved the
+        // end of the log. The log is rewound, so that the positions of the
+        // next append match where its records are written.
+        let log_size = self.log.size();
+        if let Err(err) = self
+            .log
+            .append(&data)
+            .and_then(|()| self.index.append(index))
+        {
+            self.log.set_size(log_size)?;
+            return Err(err);
+        }
+
+        Ok(AppendInfo {
+            base_offset,
+            last_offset,
+            log_append_time,
+        })
     }
 
     pub fn flush(&mut self) -> Result<()> {
@@ -43,52 +141,153 @@ impl Segment {
         Ok(())
     }
 
+    // Flush the segment and wait for its files to reach the disk.
+    pub fn sync(&mut self) -> Result<()> {
+        self.log.sync()?;
+        self.index.sync()?;
+        Ok(())
+    }
+
+    // Repair the segment starting at `start_offset` after a crash, before it
+    // is opened: index entries pointing past the end of the log are dropped.
+    // The log of a preallocated segment spans its whole size, so its entries
+    // are only dropped when they point past it. Returns the number of entries
+    // dropped.
+    pub fn recover(path: PathBuf, start_offset: u32) -> Result<u32> {
+        let version = Index::read_version(path.clone(), start_offset)?;
+        let log_size = Log::new(path.clone(), start_offset, u32::MAX, version)?.size();
+        Index::recover(path, start_offset, log_size)
+    }
+
+    // Offset that will be assigned to the next appended record.
+    pub fn next_offset(&self) -> u32 {
+        if self.index.is_empty() {
+            self.start_offset
+        } else {
+            self.index.last_offset + 1
+        }
+    }
+
+    // Size of the log file, i.e. the position of the next appended record.
+    pub fn size(&self) -> u32 {
+        self.log.size()
+    }
+
+    // Size the log may grow to, the `segment_bytes` of the config the
+    // segment was created with.
+    pub fn max_size(&self) -> u32 {
+        self.log.max_size()
+    }
+
+    pub fn start_offset(&self) -> u32 {
+        self.start_offset
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    // Time from which the age of the segment is computed: the append time of
+    // its first record. `None` if the segment is empty.
+    pub fn rolling_timestamp(&self) -> Option<i64> {
+        if self.is_empty() {
+            return None;
+        }
+
+        match self.index.get(self.start_offset) {
+            Some(e) if e.timestamp != NO_TIMESTAMP => Some(e.timestamp),
+            _ => Some(self.opened_at),
+        }
+    }
+
+    // Flush the segment and release the space preallocated for its log.
+    // Called when the segment stops being the active one.
+    pub fn trim(&mut self) -> Result<()> {
+        self.index.flush()?;
+        self.log.trim()
+    }
+
+    // First record appended at or after `timestamp`. Records of legacy
+    // segments, whose index does not store timestamps, are never found.
+    pub fn find_timestamp(&self, timestamp: i64) -> Option<Entry> {
+        self.timestamped_entries()
+            .find(|e| e.timestamp >= timestamp)
+    }
+
+    // Record with the latest append time, the first one if several records
+    // were appended at that time.
+    pub fn max_timestamp(&self) -> Option<Entry> {
+        self.timestamped_entries()
+            .fold(None, |max: Option<Entry>, e| match max {
+                Some(max) if max.timestamp >= e.timestamp => Some(max),
+                _ => Some(e),
+            })
+    }
+
+    // Entries that store a timestamp, in offset order.
+    fn timestamped_entries(&self) -> impl Iterator<Item = Entry> + '_ {
+        (self.start_offset..self.next_offset())
+            .filter_map(move |offset| self.index.get(offset).copied())
+            .filter(|e| e.timestamp != NO_TIMESTAMP)
+    }
+
+    // Last offset from `from_offset` such that the records up to it take at
+    // most `max_bytes`. The first record is always included, whatever its
+    // size, so that readers can make progress.
+    pub fn last_offset_within(&self, from_offset: u32, max_bytes: u32) -> u32 {
+        let mut size = self.index.get(from_offset).map_or(0, |e| e.size as u64);
+        let mut offset = from_offset;
+        while offset < self.index.last_offset {
+            match self.index.get(offset + 1) {
+                Some(e) if size + e.size as u64 <= max_bytes as u64 => size += e.size as u64,
+                _ => break,
+            }
+            offset += 1;
+        }
+        offset
+    }
+
     // TODO: Remove mut!
     // Returning a vec might not be optimal. We may need to tweak this
     // function when implementing clients.
     pub fn read(&mut self, from_offset: u32, to_offset: u32) -> Result<(Vec<Entry>, Vec<u8>)> {
+        // Reading from the end of the segment is valid and returns nothing.
+        if from_offset < self.start_offset || from_offset > self.next_offset() {
+            return Err(StoreError::OffsetOutOfRange(from_offset));
+        }
+
         let mut current_offset = from_offset;
         let end_offset = cmp::min(to_offset, self.index.last_offset);
-        let mut current_chunk = LogChunk {
-            entries: vec![],
-            start: 0,
-            size: 0,
-        };
-
         let mut chunks: Vec<LogChunk> = vec![];
 
+        // Group contiguous entries so that each chunk is read from the log
+        // with a single call.
         while current_offset <= end_offset {
-            match self.index.get(current_offset) {
-                Some(i) => {
-                    if (i.start == current_chunk.start + current_chunk.size)
-                        || current_chunk.start == 0 && current_chunk.size == 0
-                    {
-                        current_chunk.size += i.size;
-                        current_chunk.entries.push(*i);
-                    } else {
-                        chunks.push(current_chunk);
-                        current_chunk = LogChunk {
-                            entries: vec![],
-                            start: i.start,
-                            size: i.size,
-                        };
+            if let Some(i) = self.index.get(current_offset) {
+                match chunks.last_mut() {
+                    Some(chunk) if chunk.start + chunk.size == i.start => {
+                        chunk.size += i.size;
+                        chunk.entries.push(*i);
                     }
+                    _ => chunks.push(LogChunk {
+                        entries: vec![*i],
+                        start: i.start,
+                        size: i.size,
+                    }),
                 }
-                None => {}
-            };
+            }
             current_offset += 1;
         }
 
-        chunks.push(current_chunk);
         // TODO fix log.read mutability / or add a way to clone/copy
         // a Log to paralellize!
         // chunks.par_iter().map(|chunk| {
         //     self.log.read(chunk.start, chunk.size).unwrap()
         // });
-        let data: Vec<u8> = chunks
-            .iter()
-            .map(|chunk| self.log.read(chunk.start, chunk.size).unwrap())
-            .fold(vec![], |acc, chunk| [acc, chunk].concat());
+        let mut data: Vec<u8> = vec![];
+        for chunk in chunks.iter() {
+            data.extend(self.log.read(chunk.start, chunk.size)?);
+        }
 
         // Oh my
         let indices: Vec<Entry> = chunks
@@ -118,7 +317,7 @@ mod tests {
 
         for i in 0..5 {
             let expected_file = tmp_dir.clone();
-            let segment = Segment::new(tmp_dir.clone(), i).unwrap();
+            let segment = Segment::new(tmp_dir.clone(), i, &LogConfig::default()).unwrap();
 
             assert!(expected_file.as_path().exists());
             assert_eq!(segment.start_offset, i);
@@ -129,24 +328,101 @@ mod tests {
     fn test_read_write() {
         let tmp_dir = create_tmp_folder();
 
-        let index: Vec<Entry> = vec![
+        let mut index: Vec<Entry> = vec![
             Entry::new(0, 100, 0),
             Entry::new(1, 102, 100),
             Entry::new(2, 50, 202),
         ];
 
         let seq = (0_u8..252_u8).collect::<Vec<u8>>();
-        let records: &[u8] = seq.as_ref();
-        let mut segment = Segment::new(tmp_dir, 0).unwrap();
+        let records = vec![
+            Record::new(seq[0..100].to_vec()),
+            Record::new(seq[100..202].to_vec()),
+            Record::new(seq[202..].to_vec()),
+        ];
+        let mut segment = Segment::new(tmp_dir, 0, &LogConfig::default()).unwrap();
 
-        segment.append(index.clone(), records).unwrap();
+        let info = segment.append(records).unwrap();
         segment.flush().unwrap();
 
+        assert_eq!(info.base_offset, 0);
+        assert_eq!(info.last_offset, 2);
+
+        for e in index.iter_mut() {
+            e.timestamp = info.log_append_time;
+        }
+
         let (entries, written) = segment.read(0, 2).unwrap();
-        assert_eq!(written, records.to_vec());
+        assert_eq!(written, seq);
         assert_eq!(entries, index);
     }
 
+    #[test]
+    fn test_append_assigns_offsets() {
+        let tmp_dir = create_tmp_folder();
+        let mut segment = Segment::new(tmp_dir, 10, &LogConfig::default()).unwrap();
+
+        let info = segment.append(vec![Record::new(vec![0; 8])]).unwrap();
+        assert_eq!((info.base_offset, info.last_offset), (10, 10));
+
+        let info = segment
+            .append(vec![Record::new(vec![1; 4]), Record::new(vec![2; 4])])
+            .unwrap();
+        assert_eq!((info.base_offset, info.last_offset), (11, 12));
+        assert_eq!(segment.next_offset(), 13);
+        assert_eq!(segment.size(), 16);
+
+        assert!(matches!(
+            segment.append(vec![]),
+            Err(StoreError::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_append_supplied_offsets() {
+        let tmp_dir = create_tmp_folder();
+        let mut segment = Segment::new(tmp_dir, 0, &LogConfig::default()).unwrap();
+
+        let records = vec![
+            Record::with_offset(0, vec![0; 8]),
+            Record::with_offset(1, vec![1; 8]),
+        ];
+        assert_eq!(segment.append(records).unwrap().last_offset, 1);
+
+        // Duplicate
+        let records = vec![Record::with_offset(1, vec![1; 8])];
+        assert!(segment.append(records).is_err());
+
+        // Gap
+        let records = vec![Record::new(vec![2; 8]), Record::with_offset(4, vec![4; 8])];
+        assert!(segment.append(records).is_err());
+
+        // Rejected batches are not written
+        assert_eq!(segment.next_offset(), 2);
+        assert_eq!(segment.size(), 16);
+    }
+
+    #[test]
+    fn test_read_out_of_range() {
+        let tmp_dir = create_tmp_folder();
+        let mut segment = Segment::new(tmp_dir, 5, &LogConfig::default()).unwrap();
+        segment.append(vec![Record::new(vec![0; 8])]).unwrap();
+        segment.flush().unwrap();
+
+        assert!(matches!(
+            segment.read(4, 5),
+            Err(StoreError::OffsetOutOfRange(4))
+        ));
+        assert!(matches!(
+            segment.read(7, 8),
+            Err(StoreError::OffsetOutOfRange(7))
+        ));
+
+        let (entries, data) = segment.read(6, 10).unwrap();
+        assert!(entries.is_empty());
+        assert!(data.is_empty());
+    }
+
     //#[bench]
     //fn bench_write(b: &mut Bencher) {
     //}
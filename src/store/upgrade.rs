@@ -0,0 +1,212 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tracing::info;
+
+use super::format::{FileKind, CURRENT_VERSION};
+use super::index::Index;
+use super::log::Log;
+use super::{Result, Store, StoreError};
+
+/// Extension of the files written while a segment is being upgraded.
+const UPGRADE_SUFFIX: &str = "upgrade";
+
+/// Outcome of `upgrade_log_dir`.
+#[derive(Debug, Default)]
+pub struct UpgradeReport {
+    /// Segments rewritten to the current format, identified by the path of
+    /// their index.
+    pub upgraded: Vec<PathBuf>,
+    /// Number of segments that were already using the current format.
+    pub up_to_date: usize,
+}
+
+/// Rewrite every segment found under `log_dir` with the current format.
+///
+/// This must be run while the broker is stopped. Each segment is rewritten
+/// into temporary files which are then renamed over the original ones, the
+/// log first and the index last. If the upgrade is interrupted, running it
+/// again completes the segments that were left half renamed.
+pub fn upgrade_log_dir(log_dir: &Path) -> Result<UpgradeReport> {
+    let mut report = UpgradeReport::default();
+    let mut dirs = vec![log_dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+
+            let start_offset = match segment_offset(&path) {
+                Some(start_offset) => start_offset,
+                None => continue,
+            };
+
+            if upgrade_segment(&dir, start_offset)? {
+                info!(segment = %path.display(), "upgraded segment");
+                report.upgraded.push(path);
+            } else {
+                report.up_to_date += 1;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+// Start offset of the segment whose index is `path`, if `path` is an index.
+fn segment_offset(path: &Path) -> Option<u32> {
+    if path.extension()? != "index" {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+// Upgrade the segment starting at `start_offset` in `dir`. Returns `false` if
+// the segment already uses the current format.
+fn upgrade_segment(dir: &Path, start_offset: u32) -> Result<bool> {
+    let log_path = dir.join(format!("{}.log", start_offset));
+    let index_path = dir.join(format!("{}.index", start_offset));
+    let log_tmp = log_path.with_extension(format!("log.{}", UPGRADE_SUFFIX));
+    let index_tmp = index_path.with_extension(format!("index.{}", UPGRADE_SUFFIX));
+
+    // A previous run was interrupted after the log was renamed: the log is
+    // already upgraded and only the index is left.
+    if index_tmp.exists() && !log_tmp.exists() {
+        fs::rename(&index_tmp, &index_path)?;
+        return Ok(true);
+    }
+
+    // The log has the version of its index.
+    let index = Index::new(dir.to_path_buf(), start_offset, u32::MAX)?;
+    if index.version() == CURRENT_VERSION {
+        return Ok(false);
+    }
+    let mut log = Log::new(dir.to_path_buf(), start_offset, u32::MAX, index.version())?;
+
+    // Positions in the index are relative to the end of the header, so the
+    // log data and the entries can be copied as is.
+    let mut new_log = create(&log_tmp, FileKind::Log)?;
+    new_log.append(&log.read(0, log.size())?)?;
+    new_log.sync()?;
+    // The original log is replaced below, so a short copy would lose records.
+    if new_log.size() != log.size() {
+        return Err(StoreError::Io(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "copied {} bytes of a {} bytes log",
+                new_log.size(),
+                log.size()
+            ),
+        )));
+    }
+
+    let mut new_index = create(&index_tmp, FileKind::Index)?;
+    let buf = index
+        .entries()
+        .iter()
+        .map(|e| e.encode(CURRENT_VERSION))
+        .collect::<Result<Vec<_>>>()?
+        .concat();
+    new_index.append(&buf)?;
+    new_index.sync()?;
+
+    fs::rename(&log_tmp, &log_path)?;
+    fs::rename(&index_tmp, &index_path)?;
+
+    Ok(true)
+}
+
+// Create an empty `kind` file at `path`, replacing the leftovers of an
+// interrupted upgrade.
+fn create(path: &Path, kind: FileKind) -> Result<Store> {
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Store::new(path.to_path_buf(), u32::MAX, kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::format::LEGACY_VERSION;
+    use crate::store::index::Entry;
+
+    use tempfile::tempdir;
+
+    fn create_tmp_folder() -> PathBuf {
+        let tmp_dir = tempdir().unwrap().path().to_owned();
+        fs::create_dir_all(tmp_dir.clone()).unwrap();
+        tmp_dir
+    }
+
+    // Write a legacy segment containing `n` records of 8 bytes.
+    fn write_legacy_segment(dir: &Path, start_offset: u32, n: u32) {
+        let log: Vec<u8> = (0..n).flat_map(|i| vec![i as u8; 8]).collect();
+        let index: Vec<u8> = (0..n)
+            .flat_map(|i| {
+                Entry::new(start_offset + i, 8, i * 8)
+                    .encode(LEGACY_VERSION)
+                    .unwrap()
+            })
+            .collect();
+
+        fs::write(dir.join(format!("{}.log", start_offset)), log).unwrap();
+        fs::write(dir.join(format!("{}.index", start_offset)), index).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let tmp_dir = create_tmp_folder();
+        let partition_dir = tmp_dir.join("topic-0");
+        fs::create_dir_all(&partition_dir).unwrap();
+        write_legacy_segment(&partition_dir, 0, 3);
+        write_legacy_segment(&partition_dir, 3, 2);
+
+        let report = upgrade_log_dir(&tmp_dir).unwrap();
+        assert_eq!(report.upgraded.len(), 2);
+        assert_eq!(report.up_to_date, 0);
+
+        let mut log = Log::new(partition_dir.clone(), 3, u32::MAX, CURRENT_VERSION).unwrap();
+        let index = Index::new(partition_dir.clone(), 3, u32::MAX).unwrap();
+        assert_eq!(log.version(), CURRENT_VERSION);
+        assert_eq!(index.version(), CURRENT_VERSION);
+        assert_eq!(
+            index.entries(),
+            vec![Entry::new(3, 8, 0), Entry::new(4, 8, 8)]
+        );
+        assert_eq!(log.read(8, 8).unwrap(), vec![1; 8]);
+
+        // Nothing left to do
+        let report = upgrade_log_dir(&tmp_dir).unwrap();
+        assert!(report.upgraded.is_empty());
+        assert_eq!(report.up_to_date, 2);
+        assert!(!partition_dir.join("0.log.upgrade").exists());
+    }
+
+    #[test]
+    fn test_resume_interrupted_upgrade() {
+        let tmp_dir = create_tmp_folder();
+        write_legacy_segment(&tmp_dir, 0, 2);
+
+        // Simulate an upgrade that stopped right after renaming the log.
+        upgrade_segment(&tmp_dir, 0).unwrap();
+        let index = fs::read(tmp_dir.join("0.index")).unwrap();
+        fs::write(tmp_dir.join("0.index.upgrade"), index).unwrap();
+        fs::write(
+            tmp_dir.join("0.index"),
+            Entry::new(0, 8, 0).encode(LEGACY_VERSION).unwrap(),
+        )
+        .unwrap();
+
+        let report = upgrade_log_dir(&tmp_dir).unwrap();
+        assert_eq!(report.upgraded.len(), 1);
+
+        let index = Index::new(tmp_dir, 0, u32::MAX).unwrap();
+        assert_eq!(index.version(), CURRENT_VERSION);
+        assert_eq!(index.last_offset, 1);
+    }
+}